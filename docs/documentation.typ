@@ -134,4 +134,24 @@ Because of this, the tensor product dimensions are explicitely dependend only on
 
 === from hw to host
 TBD (to be defined)
-ideally, some ack on when the computation is ready and the address where the result is stored
\ No newline at end of file
+ideally, some ack on when the computation is ready and the address where the result is stored
+= Sparse block storage
+
+On the host, a block is stored as a CSR `SparseBlock` when at most a fraction of its entries are non-zero (0.25 by default, `CpuExecutor::sparse_density` to change it). Only exact zeros are dropped, so the storage never changes the result. Padded, diagonal and monomial blocks keep their own storage in both runs.
+
+`just bench-sparse` contracts each circuit twice: once with dense blocks only (density 0) and once with the default density. It reports the fastest of 3 runs. The numbers below were measured on 1 CPU with a release build. The deviation between the two results was 0 for every circuit.
+
+#table(
+  columns: 7,
+  [circuit], [qubits], [gates], [out density], [dense], [sparse], [speedup],
+  [full-adder.txt], [4], [7], [0.0625], [164 µs], [131 µs], [1.26x],
+  [q3-01.txt], [3], [3], [0.1250], [99 µs], [97 µs], [1.02x],
+  [q5-01.txt], [5], [23], [1.0000], [480 µs], [465 µs], [1.03x],
+  [qft:8], [8], [40], [1.0000], [14.4 ms], [10.0 ms], [1.44x],
+  [qft:10], [10], [60], [1.0000], [324 ms], [165 ms], [1.96x],
+  [adder:4], [10], [25], [0.0010], [1.12 ms], [1.06 ms], [1.06x],
+  [bv:10], [11], [33], [0.0312], [4.09 s], [1.41 s], [2.89x],
+  [ghz:10], [10], [10], [0.0020], [2.58 ms], [0.45 ms], [5.70x],
+)
+
+The small textual circuits are within noise, as thread startup dominates. On the larger circuits, the sparse intermediates of the first contraction steps give the speedup, even when the final matrix is dense.
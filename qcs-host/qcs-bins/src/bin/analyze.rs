@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use qcs_circuit_parser::parse_program;
+use qcs_core::analysis::CircuitMetrics;
+
+#[derive(Debug, Clone, Parser)]
+struct Cli {
+    /// Input circuit files
+    #[clap(required = true)]
+    input: Vec<PathBuf>,
+
+    /// Print the moment decomposition of the circuit
+    #[clap(short, long, default_value = "false")]
+    moments: bool,
+}
+
+fn main() {
+    let args = Cli::parse();
+
+    for input in args.input {
+        let circuit = parse_program(&input).unwrap();
+        let metrics = CircuitMetrics::from(&circuit);
+
+        print!("Circuit {}:\n{}", input.display(), metrics);
+        if args.moments {
+            println!("Moments:");
+            for (i, moment) in metrics.moments.iter().enumerate() {
+                let gates = moment
+                    .iter()
+                    .map(|&ix| circuit.gates[ix].to_string())
+                    .collect::<Vec<_>>();
+                println!("  {:03}: {}", i, gates.join(" "));
+            }
+        }
+    }
+}
@@ -1,16 +1,29 @@
 use std::path::PathBuf;
 
+use clap::Parser;
 use qcs_bins::{BinFile, Matmul, TECompatible};
-use qcs_core::model::gates::U;
+use qcs_core::model::{fixed::FixedFormat, gates::U};
+
+#[derive(Debug, Clone, Parser)]
+struct Cli {
+    /// Fixed-point format of the golden vectors, as the parameters W,I[,Q[,O]] of an ap_fixed
+    #[clap(short, long)]
+    fixed: Option<FixedFormat>,
+}
 
 fn main() {
+    let args = Cli::parse();
+    if let Some(format) = args.fixed {
+        println!("Format: {}", format);
+    }
+
     let mut bfile = BinFile::new(PathBuf::from("golden-vectors.dat")).unwrap();
 
-    let left = U::new(1.0, 2.0, 3.0, 0).right_te(1);
+    let left = U::new(1.0, 2.0, 3.0, 0).right_te(1).fixed(args.fixed);
     println!("InL {}", left);
-    let right = U::new(1.0, 2.0, 3.0, 0).left_te(1);
+    let right = U::new(1.0, 2.0, 3.0, 0).left_te(1).fixed(args.fixed);
     println!("InR {}", right);
-    let out = Matmul::new(left.compute(), right.compute());
+    let out = Matmul::new(left.compute(), right.compute()).fixed(args.fixed);
     println!("Out {}", out);
 
     bfile.add(left).unwrap();
@@ -1,13 +1,18 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
+use qcs_bins::{operation_plan, NO_STATE_TENSORS};
 use qcs_circuit_parser::parse_program;
 use qcs_core::{
     compiler::QcfFile,
-    contractions::{TensorKind, TensorNetwork},
+    contractions::{
+        forest::{ContractionForest, Placement},
+        optimizer::AnnealingOptimizer,
+        strategy::Strategy,
+        TensorKind, TensorNetwork,
+    },
     executor::{CpuExecutor, InstructionLike},
-    model::{blocks::BlockLike, gates::QuantumGate, TensorProduct},
-    op_tree,
+    model::{blocks::SpannedBlock, cache::GateCache, fixed::FixedFormat, gates::QuantumGate},
     scheduler::OperationPlan,
 };
 
@@ -15,6 +20,31 @@ use qcs_core::{
 struct Cli {
     input: PathBuf,
     output: PathBuf,
+
+    /// Contraction order strategy (greedy, flops, peak)
+    #[clap(short = 'S', long, default_value = "greedy")]
+    strategy: Strategy,
+
+    /// Refine the contraction tree with simulated annealing for a number of iterations
+    #[clap(short, long)]
+    anneal: Option<usize>,
+
+    /// Maximum rank of the contractions, the pieces left are combined on the host
+    #[clap(short = 'r', long)]
+    max_rank: Option<u8>,
+
+    /// Emulate the fixed-point datapath on the host, as the parameters W,I[,Q[,O]] of an ap_fixed
+    #[clap(short, long)]
+    fixed: Option<FixedFormat>,
+
+    /// Check every intermediate block for unitarity, and with --fixed its deviation from the
+    /// floating-point run
+    #[clap(short = 'd', long)]
+    diagnostics: bool,
+
+    /// Print the hits and misses of the gate cache
+    #[clap(long)]
+    cache_stats: bool,
 }
 
 fn compile_plan(mut plan: OperationPlan, output: PathBuf) {
@@ -30,37 +60,86 @@ fn compile_plan(mut plan: OperationPlan, output: PathBuf) {
     }
 }
 
+/// Return the path of the QCF file of a piece, numbered when more than one
+/// piece goes to the accelerator.
+fn piece_path(output: &Path, index: usize, pieces: usize) -> PathBuf {
+    if pieces == 1 {
+        return output.to_path_buf();
+    }
+    let stem = output.file_stem().unwrap().to_string_lossy();
+    output.with_file_name(format!("{}-{}.qcf", stem, index))
+}
+
 fn main() {
     let args = Cli::parse();
     let circuit = parse_program(args.input).unwrap();
 
-    let tensor_net = TensorNetwork::from(circuit.clone());
+    let mut tensor_net = TensorNetwork::from(circuit.clone());
+    if let Some(max_rank) = args.max_rank {
+        tensor_net = tensor_net.max_rank(max_rank);
+    }
     println!("Tensor Network:\n{}", tensor_net);
-    let contracted_nodes = tensor_net.contract().into_iter();
+    let contracted_nodes = match args.anneal {
+        Some(iterations) => {
+            AnnealingOptimizer::new()
+                .strategy(args.strategy.clone())
+                .iterations(iterations)
+                .optimize(tensor_net)
+                .tensors
+        }
+        None => tensor_net.contract_with(&args.strategy),
+    };
+    let forest = ContractionForest::new(contracted_nodes, args.max_rank);
+    println!("{}", forest);
 
+    let pieces = forest.accelerator_pieces();
     let mut blocks = Vec::new();
+    let mut index = 0;
 
-    for node in contracted_nodes {
-        match node {
-            TensorKind::Contraction(contr) => {
-                let opt = op_tree::Operation::from_contraction(*contr, false);
-                let plan = OperationPlan::from(opt);
+    for (placement, node) in forest.pieces() {
+        match (placement, node) {
+            (Placement::Accelerator, TensorKind::Contraction(contr)) => {
+                let span = contr.span.filled();
+                let plan = operation_plan(*contr.clone());
                 println!("Contraction plan:\n{}", &plan);
-                compile_plan(plan.clone(), args.output.clone());
-                println!("Compiled to binary file {}", args.output.display());
+                let output = piece_path(&args.output, index, pieces);
+                compile_plan(plan.clone(), output.clone());
+                println!("Compiled to binary file {}", output.display());
+                index += 1;
                 let exec = CpuExecutor::new();
                 let start = std::time::Instant::now();
-                blocks.extend(exec.execute(plan));
+                let mut results = match (args.fixed, args.diagnostics) {
+                    // the floating-point run is the reference of the quantized one
+                    (Some(format), true) => {
+                        let (results, diagnostics) =
+                            exec.diagnose_against(plan.clone().fixed_point(format), plan);
+                        println!("{}", diagnostics);
+                        results
+                    }
+                    (None, true) => {
+                        let (results, diagnostics) = exec.diagnose(plan);
+                        println!("{}", diagnostics);
+                        results
+                    }
+                    (Some(format), false) => exec.execute(plan.fixed_point(format)),
+                    (None, false) => exec.execute(plan),
+                };
+                let block = results.pop().unwrap();
+                blocks.push(SpannedBlock::new(block, span));
                 println!("CPU Execution Time: {:?}", start.elapsed());
             }
-            TensorKind::Gate(g) => blocks.push((*g).block()),
+            (_, TensorKind::Gate(g)) => blocks.push(g.spanned_block()),
+            (_, TensorKind::State(_)) => unreachable!("{}", NO_STATE_TENSORS),
+            (Placement::Host, TensorKind::Contraction(contr)) => {
+                unreachable!("Contraction {} exceeds the maximum rank", contr)
+            }
         }
     }
 
-    let eval = blocks.into_iter().fold(None, |acc, block| match acc {
-        None => Some(block),
-        Some(acc) => Some(acc.tensor_product(block)),
-    });
+    if args.cache_stats {
+        println!("Gate cache: {}", GateCache::global().stats());
+    }
 
+    let eval = ContractionForest::combine(blocks);
     println!("Final Block:\n{}", eval.unwrap().into_block());
 }
@@ -1,51 +1,227 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use qcs_bins::{contraction_plan, NO_STATE_TENSORS};
 use qcs_circuit_parser::parse_program;
 use qcs_core::{
-    contractions::{TensorKind, TensorNetwork},
+    contractions::{
+        einsum::parse_path, forest::ContractionForest, optimizer::AnnealingOptimizer, state,
+        strategy::Strategy, tree::ContractionTree, TensorKind, TensorNetwork,
+    },
     executor::CpuExecutor,
-    model::{gates::QuantumGate, QRegister, Qubit, TensorProduct},
-    scheduler::ContractionPlan,
+    model::{
+        blocks::SpannedBlock,
+        scalar::{deviation, Precision, Scalar},
+        tensor::IndexTensor,
+        QRegister, Qubit,
+    },
 };
 
 #[derive(Debug, Clone, Parser)]
 struct Cli {
     input: PathBuf,
+
+    /// Contraction order strategy (greedy, flops, peak)
+    #[clap(short = 'S', long, default_value = "greedy")]
+    strategy: Strategy,
+
+    /// Refine the contraction tree with simulated annealing for a number of iterations
+    #[clap(short, long)]
+    anneal: Option<usize>,
+
+    /// Maximum rank of the contractions, the pieces left are combined on the host
+    #[clap(short = 'r', long)]
+    max_rank: Option<u8>,
+
+    /// Contract the input state into the network, keeping the intermediate results at vector size
+    #[clap(short = 'v', long, conflicts_with = "compare_precision")]
+    state_vector: bool,
+
+    /// Export the contraction tree with the times of the reference contraction of its nodes, as
+    /// DOT, JSON or SVG depending on the extension
+    #[clap(short = 't', long, value_parser = tree_path)]
+    tree: Option<PathBuf>,
+
+    /// Contract index tensors, that keep only the lanes the gates act on, instead of spanned blocks
+    #[clap(short = 'i', long, conflicts_with = "compare_precision")]
+    index_tensors: bool,
+
+    /// Export the network as an einsum equation with the shapes of the tensors, for opt_einsum or cotengra
+    #[clap(short = 'e', long)]
+    einsum: Option<PathBuf>,
+
+    /// Write the data of the tensors of the einsum equation as .npy files in a directory
+    #[clap(long)]
+    npy: Option<PathBuf>,
+
+    /// Contract along a path of pairs of tensors, as returned by opt_einsum, instead of the strategy
+    #[clap(short = 'p', long, conflicts_with_all = ["strategy", "anneal", "max_rank"])]
+    path: Option<PathBuf>,
+
+    /// Precision of the blocks of the contraction (single, double)
+    #[clap(short = 'P', long, default_value = "double")]
+    precision: Precision,
+
+    /// Run the contraction in the other precision too, and report the deviation between the two
+    #[clap(long)]
+    compare_precision: bool,
+
+    /// Check every intermediate block for unitarity, and in single precision its deviation from a
+    /// double precision run
+    #[clap(short = 'd', long, conflicts_with_all = ["index_tensors", "state_vector"])]
+    diagnostics: bool,
 }
 
 fn main() {
     let args = Cli::parse();
+    if args.precision == Precision::Single && (args.state_vector || args.index_tensors) {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "the state vector and the index tensors are only contracted in double precision",
+            )
+            .exit();
+    }
     let circuit = parse_program(args.input).unwrap();
 
-    let tensor_net = TensorNetwork::from(circuit.clone());
+    let mut tensor_net = TensorNetwork::from(circuit.clone());
+    if let Some(max_rank) = args.max_rank {
+        tensor_net = tensor_net.max_rank(max_rank);
+    }
     println!("Tensor Network:\n{}", tensor_net);
-    let contracted_nodes = tensor_net.contract().into_iter();
+    if let Some(path) = &args.einsum {
+        std::fs::write(path, tensor_net.to_einsum().to_text()).unwrap();
+    }
+    if let Some(dir) = &args.npy {
+        tensor_net.to_einsum().write_npy(dir).unwrap();
+    }
+    let contracted_nodes = match (&args.path, args.anneal) {
+        (Some(path), _) => {
+            let path = parse_path(&std::fs::read_to_string(path).unwrap()).unwrap();
+            tensor_net.to_einsum().contract_path(&path).unwrap()
+        }
+        (None, Some(iterations)) => {
+            AnnealingOptimizer::new()
+                .strategy(args.strategy.clone())
+                .iterations(iterations)
+                .optimize(tensor_net)
+                .tensors
+        }
+        (None, None) => tensor_net.contract_with(&args.strategy),
+    };
+    let forest = ContractionForest::new(contracted_nodes, args.max_rank);
+    println!("{}", forest);
+
+    if let Some(path) = &args.tree {
+        let tree = ContractionTree::new(&forest.trees).measure_reference();
+        let contents = match path.extension().and_then(|e| e.to_str()) {
+            Some("dot") | Some("gv") => tree.to_dot(),
+            Some("json") => tree.to_json(),
+            Some("svg") => tree.to_svg(),
+            _ => unreachable!("The extension is checked by the parser"),
+        };
+        std::fs::write(path, contents).unwrap();
+    }
+
+    let inr = QRegister::from((0..circuit.n_qubits).map(|_| Qubit::zero()));
+    if args.state_vector {
+        let start = std::time::Instant::now();
+        let qstate = state::apply(forest.trees, inr).expect(NO_STATE_TENSORS);
+        println!("CPU Execution Time: {:?}", start.elapsed());
+        println!("{}", qstate.distr());
+        return;
+    }
+
+    let run = |precision, trees| match precision {
+        Precision::Single => run::<f32>(trees, &inr, args.index_tensors, args.diagnostics),
+        Precision::Double => run::<f64>(trees, &inr, args.index_tensors, args.diagnostics),
+    };
+    let Some((eval, qstate)) = run(args.precision, forest.trees.clone()) else {
+        return;
+    };
+    println!("{}", qstate.distr());
 
+    if args.compare_precision {
+        let other = match args.precision {
+            Precision::Single => Precision::Double,
+            Precision::Double => Precision::Single,
+        };
+        if let Some((other_eval, other_qstate)) = run(other, forest.trees) {
+            println!(
+                "Deviation from {} precision: matrix {:.3e}, state {:.3e}",
+                other,
+                deviation(eval.as_ref(), other_eval.as_ref()),
+                (qstate.qubits - other_qstate.qubits).norm()
+            );
+        }
+    }
+}
+
+/// Parse the path of the exported tree, whose extension selects the format.
+fn tree_path(path: &str) -> Result<PathBuf, String> {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("dot" | "gv" | "json" | "svg") => Ok(PathBuf::from(path)),
+        _ => Err("expected a .dot, .gv, .json or .svg file".to_string()),
+    }
+}
+
+/// Contract the pieces of the forest with blocks of precision T and apply the
+/// circuit to the input register, returning the matrix of the circuit and the
+/// output state widened to double precision. With diagnostics, the
+/// intermediate blocks are checked for unitarity, and in single precision
+/// compared with the ones of a double precision run.
+/// Index tensors are always contracted in double precision, and the arguments
+/// only select them with T = f64.
+fn run<T: Scalar>(
+    trees: Vec<TensorKind>,
+    input: &QRegister,
+    index_tensors: bool,
+    diagnostics: bool,
+) -> Option<(SpannedBlock, QRegister)> {
     let mut blocks = Vec::new();
 
-    for node in contracted_nodes {
+    for node in trees {
         match node {
+            TensorKind::Contraction(contr) if index_tensors => {
+                let plan = contraction_plan::<IndexTensor>(*contr);
+                println!("Contraction plan:\n{}", &plan);
+                let exec = CpuExecutor::new();
+                let start = std::time::Instant::now();
+                blocks.extend(
+                    exec.execute(plan)
+                        .into_iter()
+                        .map(|tensor| SpannedBlock::from(tensor).into_precision()),
+                );
+                println!("CPU Execution Time: {:?}", start.elapsed());
+            }
+            TensorKind::Contraction(contr) if diagnostics => {
+                let plan = contraction_plan::<SpannedBlock<T>>(*contr.clone());
+                println!("Contraction plan:\n{}", &plan);
+                let exec = CpuExecutor::new();
+                let (results, diagnostics) = match T::PRECISION {
+                    Precision::Double => exec.diagnose(plan),
+                    Precision::Single => {
+                        let reference = contraction_plan::<SpannedBlock>(*contr);
+                        exec.diagnose_against(plan, reference)
+                    }
+                };
+                println!("{}", diagnostics);
+                blocks.extend(results);
+            }
             TensorKind::Contraction(contr) => {
-                let plan = ContractionPlan::from(*contr);
+                let plan = contraction_plan::<SpannedBlock<T>>(*contr);
                 println!("Contraction plan:\n{}", &plan);
                 let exec = CpuExecutor::new();
                 let start = std::time::Instant::now();
                 blocks.extend(exec.execute(plan));
                 println!("CPU Execution Time: {:?}", start.elapsed());
             }
-            TensorKind::Gate(g) => blocks.push((*g).spanned_block()),
+            TensorKind::Gate(g) => blocks.push(SpannedBlock::from(*g)),
+            TensorKind::State(_) => unreachable!("{}", NO_STATE_TENSORS),
         }
     }
 
-    let eval = blocks.into_iter().fold(None, |acc, block| match acc {
-        None => Some(block),
-        Some(acc) => Some(acc.tensor_product(block)),
-    });
-
-    let inr = QRegister::from((0..circuit.n_qubits).map(|_| Qubit::zero()));
-    if let Some(eval) = eval {
-        let qstate_2 = eval.into_block() * inr;
-        println!("{}", qstate_2.distr());
-    }
+    let eval = ContractionForest::combine(blocks)?;
+    let qstate = eval.clone().into_block() * input.cast::<T>();
+    Some((eval.into_precision(), qstate.cast()))
 }
@@ -1,7 +1,9 @@
 use std::path::PathBuf;
 
+use clap::Parser;
 use qcs_bins::{BinFile, TECompatible, TE};
 use qcs_core::model::{
+    fixed::FixedFormat,
     gates::{
         Fredkin, Gate, Hadamard, Identity, PauliX, PauliY, PauliZ, Phase, Swap, Toffoli, CH, CP,
         CRX, CRY, CRZ, CU, CX, CY, CZ, RX, RY, RZ, SX, U, U1, U2, U3,
@@ -9,7 +11,19 @@ use qcs_core::model::{
     TensorProduct,
 };
 
+#[derive(Debug, Clone, Parser)]
+struct Cli {
+    /// Fixed-point format of the golden vectors, as the parameters W,I[,Q[,O]] of an ap_fixed
+    #[clap(short, long)]
+    fixed: Option<FixedFormat>,
+}
+
 fn main() {
+    let args = Cli::parse();
+    if let Some(format) = args.fixed {
+        println!("Format: {}", format);
+    }
+
     let gates = vec![
         Gate::from(Hadamard::new(0)),
         Gate::from(Identity::new(0)),
@@ -45,25 +59,25 @@ fn main() {
     let mut bfile = BinFile::new(PathBuf::from("golden-vectors.dat")).unwrap();
 
     for (gate, i) in gates.into_iter().zip(0..) {
-        let te1 = gate.left_te(1);
+        let te1 = gate.left_te(1).fixed(args.fixed);
         println!("{}: {}", i * 4, te1);
         bfile.add(te1).unwrap();
 
-        let te2 = gate.left_te(2);
+        let te2 = gate.left_te(2).fixed(args.fixed);
         println!("{}: {}", i * 4 + 1, te2);
         bfile.add(te2).unwrap();
 
-        let te3 = gate.right_te(1);
+        let te3 = gate.right_te(1).fixed(args.fixed);
         println!("{}: {}", i * 4 + 2, te3);
         bfile.add(te3).unwrap();
 
-        let te4 = gate.right_te(2);
+        let te4 = gate.right_te(2).fixed(args.fixed);
         println!("{}: {}", i * 4 + 3, te4);
         bfile.add(te4).unwrap();
     }
 
     let big_te = U::new(1.0, 2.0, 3.0, 0).tensor_product(U::new(1.0, 2.0, 3.0, 0));
-    let big_te = TE::new(big_te.clone().into_matrix(), big_te.into_matrix());
+    let big_te = TE::new(big_te.clone().into_matrix(), big_te.into_matrix()).fixed(args.fixed);
     println!("big: {}", big_te);
     bfile.add(big_te).unwrap();
 
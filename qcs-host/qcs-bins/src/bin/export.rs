@@ -1,20 +1,26 @@
 use std::{
     path::{Path, PathBuf},
     time::Duration,
-    vec::IntoIter,
 };
 
 use clap::Parser;
+use qcs_bins::{contraction_plan, NO_STATE_TENSORS};
 use qcs_circuit_parser::parse_program;
 use qcs_core::{
-    contractions::{TensorKind, TensorNetwork},
+    analysis::CircuitMetrics,
+    contractions::{
+        forest::{ContractionForest, Placement},
+        optimizer::AnnealingOptimizer,
+        strategy::{ContractionStrategy, Strategy},
+        TensorKind, TensorNetwork,
+    },
+    cost::CostEstimate,
     executor::CpuExecutor,
     model::{
-        blocks::Block,
+        blocks::{Block, SpannedBlock},
         gates::{Gate, QuantumGate},
-        QRegister, Qubit, TensorProduct,
+        QRegister, Qubit,
     },
-    scheduler::ContractionPlan,
 };
 use rusqlite::{Connection, Result};
 use tracing::{debug, info, instrument, trace};
@@ -33,10 +39,28 @@ struct Cli {
     /// Skip inserting contractions into the database
     #[clap(short, long, default_value = "false")]
     skip_insert: bool,
+
+    /// Contraction order strategy (greedy, flops, peak)
+    #[clap(short = 'S', long, default_value = "greedy")]
+    strategy: Strategy,
+
+    /// Refine the contraction tree with simulated annealing for a number of iterations
+    #[clap(short, long)]
+    anneal: Option<usize>,
+
+    /// Maximum rank of the contractions, the pieces left are combined on the host
+    #[clap(short = 'r', long)]
+    max_rank: Option<u8>,
 }
 
 const SCHEMA: &str = include_str!("../../../schema.sql");
 
+/// The columns added to the tables of the schema after their creation, with
+/// their definitions. `CREATE TABLE IF NOT EXISTS` leaves the tables of an
+/// existing database untouched, so these are added with `ALTER TABLE`.
+const ADDED_COLUMNS: &[(&str, &str, &str)] =
+    &[("programs", "strategy", "TEXT NOT NULL DEFAULT 'greedy'")];
+
 fn main() -> Result<()> {
     tracing_subscriber::registry()
         .with(fmt::layer())
@@ -50,36 +74,64 @@ fn main() -> Result<()> {
     debug!("Connected to database");
 
     conn.execute_batch(SCHEMA)?;
+    conn.add_missing_columns()?;
 
     for input in args.input {
         let _outer_span = tracing::info_span!("", ?input).entered();
-        let program_id = conn.insert_program(&input)?;
+        let program_id = conn.insert_program(&input, &args.strategy)?;
         let circuit = parse_program(input).unwrap();
+        conn.insert_metrics(&CircuitMetrics::from(&circuit), program_id)?;
 
         let inputs: Vec<QRegister> = vec![
             vec![Qubit::zero(); circuit.n_qubits].into(),
             vec![Qubit::one(); circuit.n_qubits].into(),
-            vec![
-                Qubit::new((1.0 / 2.0_f64.sqrt()).into(), (1.0 / 2.0_f64.sqrt()).into());
-                circuit.n_qubits
-            ]
-            .into(),
+            QRegister::uniform(circuit.n_qubits),
         ];
 
-        let tensor_net = TensorNetwork::from(circuit.clone());
+        let mut tensor_net = TensorNetwork::from(circuit.clone());
+        if let Some(max_rank) = args.max_rank {
+            tensor_net = tensor_net.max_rank(max_rank);
+        }
         debug!("Starting building tensor network");
         let start = std::time::Instant::now();
-        let contracted_nodes: IntoIter<TensorKind> = tensor_net.contract().into_iter();
+        let contracted_nodes = match args.anneal {
+            Some(iterations) => {
+                AnnealingOptimizer::new()
+                    .strategy(args.strategy.clone())
+                    .iterations(iterations)
+                    .optimize(tensor_net)
+                    .tensors
+            }
+            None => tensor_net.contract_with(&args.strategy),
+        };
         let tree_building_time = start.elapsed();
         info!(
             time_us = tree_building_time.as_micros(),
             "Finished building tensor network"
         );
+        let forest = ContractionForest::new(contracted_nodes, args.max_rank);
+        for (i, (placement, piece)) in forest.pieces().enumerate() {
+            debug!(piece = i, span = %piece.span(), %placement, "Placed piece");
+        }
+        info!(
+            accelerator = forest.accelerator_pieces(),
+            host = forest.host_pieces(),
+            "Split contraction forest"
+        );
+
+        // store the predicted cost, to compare it with the measured time
+        let cost = forest
+            .pieces()
+            .filter(|(placement, _)| *placement == Placement::Accelerator)
+            .map(|(_, piece)| CostEstimate::try_from(piece).expect(NO_STATE_TENSORS))
+            .sum::<CostEstimate>();
+        debug!(flops = cost.flops(), "Estimated contraction cost");
+        conn.insert_cost_estimate(&cost, program_id)?;
 
         if !args.skip_insert {
             debug!("Inserting contractions into database");
             let start = std::time::Instant::now();
-            for node in contracted_nodes.clone() {
+            for node in forest.trees.iter().cloned() {
                 conn.insert_contraction(node, program_id)?;
             }
             let insertion_time = start.elapsed();
@@ -91,10 +143,10 @@ fn main() -> Result<()> {
 
         let mut blocks = Vec::new();
         let mut cpu_time = Duration::new(0, 0);
-        for node in contracted_nodes {
+        for node in forest.trees {
             match node {
                 TensorKind::Contraction(contr) => {
-                    let plan = ContractionPlan::from(*contr);
+                    let plan = contraction_plan::<SpannedBlock>(*contr);
                     let exec = CpuExecutor::new();
                     debug!("Starting contraction");
                     let start = std::time::Instant::now();
@@ -103,6 +155,7 @@ fn main() -> Result<()> {
                     info!(time_us = cpu_time.as_micros(), "Finished contraction");
                 }
                 TensorKind::Gate(g) => blocks.push((*g).spanned_block()),
+                TensorKind::State(_) => unreachable!("{}", NO_STATE_TENSORS),
             }
         }
 
@@ -115,10 +168,7 @@ fn main() -> Result<()> {
         )?;
 
         debug!("Combining individual blocks into a single block");
-        let eval = blocks.into_iter().fold(None, |acc, block| match acc {
-            None => Some(block),
-            Some(acc) => Some(acc.tensor_product(block)),
-        });
+        let eval = ContractionForest::combine(blocks);
 
         debug!("Starting measuring experiment time (input -> output)");
         if let Some(eval) = eval {
@@ -171,9 +221,12 @@ impl IntoByte for QRegister {
 }
 
 trait QuantumDB {
+    fn add_missing_columns(&self) -> Result<()>;
     fn insert_gate(&self, gate: Gate) -> Result<i64>;
     fn insert_contraction(&self, contr: TensorKind, program_id: i64) -> Result<i64>;
-    fn insert_program(&self, program: &Path) -> Result<i64>;
+    fn insert_program(&self, program: &Path, strategy: &Strategy) -> Result<i64>;
+    fn insert_metrics(&self, metrics: &CircuitMetrics, program_id: i64) -> Result<()>;
+    fn insert_cost_estimate(&self, cost: &CostEstimate, program_id: i64) -> Result<()>;
     fn update_contraction_time(
         &self,
         id: i64,
@@ -189,6 +242,26 @@ trait QuantumDB {
 }
 
 impl QuantumDB for Connection {
+    fn add_missing_columns(&self) -> Result<()> {
+        for (table, column, definition) in ADDED_COLUMNS {
+            let mut stmt = self.prepare(&format!("PRAGMA table_info(`{}`)", table))?;
+            let columns = stmt
+                .query_map([], |row| row.get::<usize, String>(1))?
+                .collect::<Result<Vec<_>>>()?;
+            if !columns.iter().any(|c| c == column) {
+                self.execute(
+                    &format!(
+                        "ALTER TABLE `{}` ADD COLUMN `{}` {}",
+                        table, column, definition
+                    ),
+                    [],
+                )?;
+                debug!(table, column, "Added missing column");
+            }
+        }
+        Ok(())
+    }
+
     #[instrument(skip(self), level = "debug", fields(gate = %gate.to_string(), rank = gate.rank()))]
     fn insert_gate(&self, gate: Gate) -> Result<i64> {
         let name = gate.to_string();
@@ -235,22 +308,87 @@ impl QuantumDB for Connection {
                 trace!(program_id, "Inserted contraction");
                 Ok(id)
             }
+            TensorKind::State(_) => unreachable!("{}", NO_STATE_TENSORS),
         }
     }
 
     #[instrument(skip(self), level = "debug")]
-    fn insert_program(&self, program: &Path) -> Result<i64> {
+    fn insert_program(&self, program: &Path, strategy: &Strategy) -> Result<i64> {
         let filename = program.file_name().unwrap().to_str().unwrap();
         let text = std::fs::read_to_string(program).unwrap();
         self.execute(
-            "INSERT INTO programs (filename, text) VALUES (?1, ?2)",
-            (&filename, &text),
+            "INSERT INTO programs (filename, text, strategy) VALUES (?1, ?2, ?3)",
+            (&filename, &text, strategy.name()),
         )?;
         trace!("Inserted program");
         let id = self.last_insert_rowid();
         Ok(id)
     }
 
+    #[instrument(skip(self, metrics), level = "debug", fields(depth = metrics.depth()))]
+    fn insert_metrics(&self, metrics: &CircuitMetrics, program_id: i64) -> Result<()> {
+        let idle_lanes = metrics
+            .idle_lanes
+            .iter()
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>();
+        self.execute(
+            "INSERT INTO circuit_metrics (program_id, n_qubits, n_gates, depth, two_qubit_depth, max_span_width, avg_span_width, idle_lanes) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            (
+                &program_id,
+                &metrics.n_qubits,
+                &metrics.n_gates,
+                &metrics.depth(),
+                &metrics.two_qubit_depth,
+                &metrics.max_span_width,
+                &metrics.avg_span_width,
+                &format!("[{}]", idle_lanes.join(",")),
+            ),
+        )?;
+        for (kind, count) in &metrics.gate_counts {
+            self.execute(
+                "INSERT INTO gate_counts (program_id, kind, count) VALUES (?1, ?2, ?3)",
+                (&program_id, kind, count),
+            )?;
+        }
+        for (rank, count) in &metrics.rank_counts {
+            self.execute(
+                "INSERT INTO rank_counts (program_id, rank, count) VALUES (?1, ?2, ?3)",
+                (&program_id, rank, count),
+            )?;
+        }
+        for (lane_a, lane_b, count) in metrics.interaction_edges() {
+            self.execute(
+                "INSERT INTO interactions (program_id, lane_a, lane_b, count) VALUES (?1, ?2, ?3, ?4)",
+                (&program_id, &lane_a, &lane_b, &count),
+            )?;
+        }
+        trace!("Inserted circuit metrics");
+        Ok(())
+    }
+
+    #[instrument(skip(self, cost), level = "debug", fields(flops = cost.flops()))]
+    fn insert_cost_estimate(&self, cost: &CostEstimate, program_id: i64) -> Result<()> {
+        self.execute(
+            "INSERT INTO cost_estimates (program_id, mm_count, te_count, flops, sparse_flops, bytes_moved, sparse_bytes_moved, peak_memory, sparse_peak_memory, critical_path_length, critical_path_flops) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            (
+                &program_id,
+                &cost.mm_count,
+                &cost.te_count,
+                &cost.flops(),
+                &cost.sparse_flops(),
+                &cost.bytes_moved,
+                &cost.sparse_bytes_moved,
+                &cost.peak_memory,
+                &cost.sparse_peak_memory,
+                &cost.critical_path_length,
+                &cost.critical_path_flops,
+            ),
+        )?;
+        trace!("Inserted cost estimate");
+        Ok(())
+    }
+
     #[instrument(skip(self), level = "debug")]
     fn update_contraction_time(
         &self,
@@ -1,7 +1,9 @@
 use std::path::PathBuf;
 
+use clap::Parser;
 use qcs_bins::{BinFile, Matmul};
 use qcs_core::model::{
+    fixed::FixedFormat,
     gates::{
         Fredkin, Hadamard, Identity, PauliY, PauliZ, Phase, Swap, Toffoli, CRX, CRY, CRZ, CU, CX,
         CY, CZ, RX, RY, RZ, U, U1, U2, U3,
@@ -9,7 +11,19 @@ use qcs_core::model::{
     TensorProduct,
 };
 
+#[derive(Debug, Clone, Parser)]
+struct Cli {
+    /// Fixed-point format of the golden vectors, as the parameters W,I[,Q[,O]] of an ap_fixed
+    #[clap(short, long)]
+    fixed: Option<FixedFormat>,
+}
+
 fn main() {
+    let args = Cli::parse();
+    if let Some(format) = args.fixed {
+        println!("Format: {}", format);
+    }
+
     let u = U::new(1.0, 2.0, 3.0, 0);
     let massive_u = u
         .tensor_product(Identity::new(0))
@@ -37,6 +51,7 @@ fn main() {
     let mut bfile = BinFile::new(PathBuf::from("golden-vectors.dat")).unwrap();
 
     for (op, i) in ops.into_iter().zip(0..) {
+        let op = op.fixed(args.fixed);
         println!("{}: {}", i, op);
         bfile.add(op).unwrap();
     }
@@ -0,0 +1,105 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use clap::Parser;
+use qcs_circuit_parser::parse_program;
+use qcs_core::{
+    contractions::{
+        forest::ContractionForest,
+        patterns::{PatternStats, TreeShape},
+        strategy::Strategy,
+        tree::NodeKind,
+        TensorNetwork,
+    },
+    model::span::Span,
+};
+use rusqlite::{Connection, Result};
+
+#[derive(Debug, Clone, Parser)]
+struct Cli {
+    /// Input circuit files, contracted with the strategy
+    input: Vec<PathBuf>,
+
+    /// SQLite database written by export, whose stored trees are added to the statistics
+    #[clap(short, long)]
+    database: Option<PathBuf>,
+
+    /// Contraction order strategy (greedy, flops, peak)
+    #[clap(short = 'S', long, default_value = "greedy")]
+    strategy: Strategy,
+
+    /// Maximum rank of the contractions, the pieces left are combined on the host
+    #[clap(short = 'r', long)]
+    max_rank: Option<u8>,
+
+    /// Maximum number of leaves of the recurring subtrees
+    #[clap(short = 'l', long, default_value = "4")]
+    max_leaves: usize,
+}
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    let mut stats = PatternStats::new().max_leaves(args.max_leaves);
+
+    if let Some(path) = &args.database {
+        let conn = Connection::open(path)?;
+        for tree in stored_trees(&conn)? {
+            stats.add(&tree);
+        }
+    }
+
+    for input in &args.input {
+        let circuit = parse_program(input).unwrap();
+        let mut tensor_net = TensorNetwork::from(circuit);
+        if let Some(max_rank) = args.max_rank {
+            tensor_net = tensor_net.max_rank(max_rank);
+        }
+        let forest =
+            ContractionForest::new(tensor_net.contract_with(&args.strategy), args.max_rank);
+        stats.add_tensors(&forest.trees);
+    }
+
+    print!("{}", stats);
+    Ok(())
+}
+
+/// Rebuild the shapes of the trees stored in the `contractions` table. The
+/// children of a contraction are always inserted before it, so the rows are
+/// read in order of id and the rows left unused are the roots.
+fn stored_trees(conn: &Connection) -> Result<Vec<TreeShape>> {
+    let mut stmt =
+        conn.prepare("SELECT id, span, left_id, right_id, kind FROM contractions ORDER BY id")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, Option<i64>>(2)?,
+            row.get::<_, Option<i64>>(3)?,
+            row.get::<_, String>(4)?,
+        ))
+    })?;
+
+    let mut trees = BTreeMap::new();
+    for row in rows {
+        let (id, span, left_id, right_id, kind) = row?;
+        let shape = match (kind.as_str(), left_id, right_id) {
+            ("C", Some(left_id), Some(right_id)) => {
+                let lhs = trees.remove(&left_id).expect("Left operand not found");
+                let rhs = trees.remove(&right_id).expect("Right operand not found");
+                TreeShape::node(lhs, rhs)
+            }
+            _ => TreeShape::leaf(NodeKind::Gate, parse_span(&span)),
+        };
+        trees.insert(id, shape);
+    }
+    Ok(trees.into_values().collect())
+}
+
+/// Parse a span written as `[0,1,3]`.
+fn parse_span(text: &str) -> Span {
+    let lanes = text
+        .trim_matches(|c| c == '[' || c == ']')
+        .split(',')
+        .map(|lane| lane.trim().parse().expect("Invalid lane"))
+        .collect::<Vec<usize>>();
+    Span::new(lanes)
+}
@@ -0,0 +1,143 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use qcs_bins::{contraction_plan, NO_STATE_TENSORS};
+use qcs_circuit_parser::parse_program;
+use qcs_core::{
+    contractions::{forest::ContractionForest, TensorKind, TensorNetwork},
+    executor::CpuExecutor,
+    library,
+    model::{
+        blocks::SpannedBlock,
+        gates::QuantumGate,
+        sparse::{SparseBlock, DEFAULT_SPARSE_DENSITY},
+        QuantumCircuit,
+    },
+};
+
+#[derive(Debug, Clone, Parser)]
+struct Cli {
+    /// Input circuit files, contracted once with dense blocks only and once with sparse blocks
+    #[clap(required_unless_present = "library")]
+    input: Vec<PathBuf>,
+
+    /// Library circuits contracted like the input files, as name:size with the name one of qft,
+    /// ghz, bv (all ones secret) or adder (size in bits)
+    #[clap(short, long, value_parser = library_circuit)]
+    library: Vec<(String, QuantumCircuit)>,
+
+    /// Fraction of non-zero entries up to which a block is stored as sparse
+    #[clap(short, long, default_value_t = DEFAULT_SPARSE_DENSITY)]
+    density: f64,
+
+    /// Number of runs of each contraction, the fastest one is reported
+    #[clap(short, long, default_value = "3")]
+    repeat: usize,
+}
+
+fn main() {
+    let args = Cli::parse();
+
+    println!(
+        "{:<24} {:>6} {:>6} {:>12} {:>12} {:>12} {:>12} {:>8} {:>10}",
+        "circuit",
+        "qubits",
+        "gates",
+        "leaf density",
+        "out density",
+        "dense",
+        "sparse",
+        "speedup",
+        "deviation"
+    );
+    let inputs = args.input.iter().map(|input| {
+        let name = input.file_name().unwrap().to_string_lossy().to_string();
+        (name, parse_program(input).unwrap())
+    });
+    for (name, circuit) in inputs.chain(args.library.clone()) {
+        let leaf_density = circuit
+            .gates
+            .iter()
+            .map(|g| SparseBlock::from_dense(&g.matrix()).density())
+            .sum::<f64>()
+            / circuit.gates.len().max(1) as f64;
+
+        let (dense_time, dense) = fastest(&circuit, 0.0, args.repeat);
+        let (sparse_time, sparse) = fastest(&circuit, args.density, args.repeat);
+
+        let (out_density, deviation) = match (dense, sparse) {
+            (Some(dense), Some(sparse)) => (
+                SparseBlock::from_dense(dense.as_ref()).density(),
+                (dense.as_ref() - sparse.as_ref()).norm(),
+            ),
+            _ => (0.0, 0.0),
+        };
+        println!(
+            "{:<24} {:>6} {:>6} {:>12.4} {:>12.4} {:>12?} {:>12?} {:>7.2}x {:>10.2e}",
+            name,
+            circuit.n_qubits,
+            circuit.gates.len(),
+            leaf_density,
+            out_density,
+            dense_time,
+            sparse_time,
+            dense_time.as_secs_f64() / sparse_time.as_secs_f64(),
+            deviation
+        );
+    }
+}
+
+/// Parse a library circuit, as name:size.
+fn library_circuit(spec: &str) -> Result<(String, QuantumCircuit), String> {
+    let (name, size) = spec
+        .split_once(':')
+        .ok_or_else(|| format!("expected name:size, found {:?}", spec))?;
+    let size = size
+        .parse::<usize>()
+        .map_err(|e| format!("invalid size {:?}: {}", size, e))?;
+    let circuit = match name {
+        "qft" => library::qft(size),
+        "ghz" => library::ghz(size),
+        "bv" => library::bernstein_vazirani(size, (1 << size) - 1),
+        "adder" => library::ripple_carry_adder(size),
+        _ => return Err(format!("unknown circuit {:?}", name)),
+    };
+    Ok((spec.to_string(), circuit))
+}
+
+/// Contract the circuit a number of times with the given sparse density,
+/// returning the fastest time and the matrix of the circuit.
+fn fastest(
+    circuit: &QuantumCircuit,
+    density: f64,
+    repeat: usize,
+) -> (Duration, Option<SpannedBlock>) {
+    (0..repeat.max(1))
+        .map(|_| contract(circuit, density))
+        .min_by_key(|(time, _)| *time)
+        .unwrap()
+}
+
+/// Contract the circuit, timing the execution of the plans and the
+/// combination of the pieces on the host.
+fn contract(circuit: &QuantumCircuit, density: f64) -> (Duration, Option<SpannedBlock>) {
+    let tensors = TensorNetwork::from(circuit.clone()).contract();
+    let start = Instant::now();
+    let mut blocks = Vec::new();
+    for tensor in tensors {
+        match tensor {
+            TensorKind::Contraction(contr) => blocks.extend(
+                CpuExecutor::new()
+                    .sparse_density(density)
+                    .execute(contraction_plan::<SpannedBlock>(*contr)),
+            ),
+            TensorKind::Gate(g) => blocks.push((*g).spanned_block()),
+            TensorKind::State(_) => unreachable!("{}", NO_STATE_TENSORS),
+        }
+    }
+    let eval = ContractionForest::combine(blocks);
+    (start.elapsed(), eval)
+}
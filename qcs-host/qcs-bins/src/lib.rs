@@ -5,7 +5,30 @@ use std::{
 };
 
 use nalgebra::{Complex, DMatrix};
-use qcs_core::model::gates::QuantumGate;
+use qcs_core::{
+    contractions::TensorContraction,
+    model::{
+        blocks::ContractionBlock,
+        fixed::{self, FixedComplex, FixedFormat, Overflow, Rounding},
+        gates::QuantumGate,
+    },
+    op_tree::Operation,
+    scheduler::{ContractionPlan, OperationPlan},
+};
+
+/// The networks of circuits are open, so the plans of their contractions
+/// always build.
+pub const NO_STATE_TENSORS: &str = "Circuit networks have no state tensors";
+
+/// Build the contraction plan of a piece of the network of a circuit.
+pub fn contraction_plan<B: ContractionBlock>(contraction: TensorContraction) -> ContractionPlan<B> {
+    ContractionPlan::new(contraction).expect(NO_STATE_TENSORS)
+}
+
+/// Build the operation plan of a piece of the network of a circuit.
+pub fn operation_plan(contraction: TensorContraction) -> OperationPlan {
+    OperationPlan::from(Operation::from_contraction(contraction, false).expect(NO_STATE_TENSORS))
+}
 
 fn count_non_zero(matrix: &DMatrix<Complex<f64>>) -> usize {
     matrix
@@ -29,10 +52,60 @@ impl Serialize for DMatrix<Complex<f64>> {
     }
 }
 
+/// The format of a fixed-point matrix is written before its data, as its
+/// integer and fractional bits on 4 bytes each, then the indices of its
+/// rounding and overflow modes in `Rounding::ALL` and `Overflow::ALL` on one
+/// byte each.
+impl Serialize for FixedFormat {
+    fn serialize(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&self.int_bits.to_le_bytes());
+        bytes.extend_from_slice(&self.frac_bits.to_le_bytes());
+        let rounding = Rounding::ALL.iter().position(|r| *r == self.rounding);
+        let overflow = Overflow::ALL.iter().position(|o| *o == self.overflow);
+        bytes.push(rounding.unwrap() as u8);
+        bytes.push(overflow.unwrap() as u8);
+        bytes
+    }
+}
+
+/// The entries of fixed-point matrices are written as the raw words of their
+/// components, sign extended to 8 bytes, after the header of their format.
+///
+/// # Panics
+///
+/// Panics if the matrix is empty, as its format is the one of its entries.
+impl Serialize for DMatrix<FixedComplex> {
+    fn serialize(&self) -> Vec<u8> {
+        let format = self
+            .iter()
+            .next()
+            .expect("An empty fixed-point matrix has no format")
+            .format();
+        let mut bytes = format.serialize();
+        bytes.extend_from_slice(&(self.len().to_le_bytes()));
+        self.transpose().iter().for_each(|c| {
+            let (re, im) = c.bits();
+            bytes.extend_from_slice(&re.to_le_bytes());
+            bytes.extend_from_slice(&im.to_le_bytes());
+        });
+        bytes
+    }
+}
+
+/// Serialize a matrix, as raw words if a fixed-point format is given.
+fn serialize_in(matrix: &DMatrix<Complex<f64>>, format: Option<FixedFormat>) -> Vec<u8> {
+    match format {
+        Some(format) => fixed::quantize(matrix, format).serialize(),
+        None => matrix.serialize(),
+    }
+}
+
 pub struct TE {
     pub left: DMatrix<Complex<f64>>,
     pub right: DMatrix<Complex<f64>>,
     pub column_major: bool,
+    pub fixed: Option<FixedFormat>,
 }
 
 impl TE {
@@ -41,6 +114,7 @@ impl TE {
             left,
             right,
             column_major: false,
+            fixed: None,
         }
     }
 
@@ -49,6 +123,12 @@ impl TE {
         self
     }
 
+    /// Compute the result, and write the golden vectors, in a fixed-point format.
+    pub fn fixed(mut self, format: Option<FixedFormat>) -> Self {
+        self.fixed = format;
+        self
+    }
+
     pub fn id(size: usize) -> DMatrix<Complex<f64>> {
         let size = size * 2;
         DMatrix::from_iterator(
@@ -75,7 +155,13 @@ impl TE {
     }
 
     pub fn compute(&self) -> DMatrix<Complex<f64>> {
-        self.left.kronecker(&self.right)
+        match self.fixed {
+            Some(format) => fixed::dequantize(&fixed::kronecker(
+                &fixed::quantize(&self.left, format),
+                &fixed::quantize(&self.right, format),
+            )),
+            None => self.left.kronecker(&self.right),
+        }
     }
 }
 
@@ -85,15 +171,15 @@ impl Serialize for TE {
         bytes.push(0x00); // magic number for TE
         if !self.column_major {
             bytes.push(0x00); // magic number for row major
-            bytes.extend_from_slice(&self.left.serialize());
-            bytes.extend_from_slice(&self.right.serialize());
-            bytes.extend_from_slice(&self.compute().serialize());
+            bytes.extend_from_slice(&serialize_in(&self.left, self.fixed));
+            bytes.extend_from_slice(&serialize_in(&self.right, self.fixed));
+            bytes.extend_from_slice(&serialize_in(&self.compute(), self.fixed));
             bytes
         } else {
             bytes.push(0xff); // magic number for column major
-            bytes.extend_from_slice(&self.left.transpose().serialize());
-            bytes.extend_from_slice(&self.right.transpose().serialize());
-            bytes.extend_from_slice(&self.compute().transpose().serialize());
+            bytes.extend_from_slice(&serialize_in(&self.left.transpose(), self.fixed));
+            bytes.extend_from_slice(&serialize_in(&self.right.transpose(), self.fixed));
+            bytes.extend_from_slice(&serialize_in(&self.compute().transpose(), self.fixed));
             bytes
         }
     }
@@ -126,6 +212,7 @@ pub struct Matmul {
     pub left: DMatrix<Complex<f64>>,
     pub right: DMatrix<Complex<f64>>,
     pub column_major: bool,
+    pub fixed: Option<FixedFormat>,
 }
 
 impl Matmul {
@@ -134,6 +221,7 @@ impl Matmul {
             left,
             right,
             column_major: false,
+            fixed: None,
         }
     }
 
@@ -142,8 +230,20 @@ impl Matmul {
         self
     }
 
+    /// Compute the result, and write the golden vectors, in a fixed-point format.
+    pub fn fixed(mut self, format: Option<FixedFormat>) -> Self {
+        self.fixed = format;
+        self
+    }
+
     pub fn compute(&self) -> DMatrix<Complex<f64>> {
-        &self.left * &self.right
+        match self.fixed {
+            Some(format) => fixed::dequantize(&fixed::matmul(
+                &fixed::quantize(&self.left, format),
+                &fixed::quantize(&self.right, format),
+            )),
+            None => &self.left * &self.right,
+        }
     }
 }
 
@@ -153,15 +253,15 @@ impl Serialize for Matmul {
         bytes.push(0xff); // magic number for matmul
         if !self.column_major {
             bytes.push(0x00); // magic number for row major
-            bytes.extend_from_slice(&self.left.serialize());
-            bytes.extend_from_slice(&self.right.transpose().serialize());
-            bytes.extend_from_slice(&self.compute().serialize());
+            bytes.extend_from_slice(&serialize_in(&self.left, self.fixed));
+            bytes.extend_from_slice(&serialize_in(&self.right.transpose(), self.fixed));
+            bytes.extend_from_slice(&serialize_in(&self.compute(), self.fixed));
             bytes
         } else {
             bytes.push(0xff); // magic number for column major
-            bytes.extend_from_slice(&self.left.transpose().serialize());
-            bytes.extend_from_slice(&self.right.serialize());
-            bytes.extend_from_slice(&self.compute().transpose().serialize());
+            bytes.extend_from_slice(&serialize_in(&self.left.transpose(), self.fixed));
+            bytes.extend_from_slice(&serialize_in(&self.right, self.fixed));
+            bytes.extend_from_slice(&serialize_in(&self.compute().transpose(), self.fixed));
             bytes
         }
     }
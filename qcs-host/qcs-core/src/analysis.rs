@@ -0,0 +1,226 @@
+//! This module contains the structural analysis of quantum circuits.
+//!
+//! The metrics computed here only depend on the shape of the circuit (which
+//! gates are applied and on which lanes) and not on the numerical values of the
+//! gates. They are useful to correlate the shape of a circuit with the cost of
+//! contracting it.
+//!
+//! The circuit is decomposed in moments (or layers): a moment is a set of gates
+//! acting on disjoint lanes, that could be applied at the same time. Gates are
+//! placed in the earliest moment in which all their lanes are free.
+
+use std::collections::BTreeMap;
+
+use petgraph::graphmap::UnGraphMap;
+
+use crate::model::{
+    gates::{Gate, QuantumGate},
+    span::Span,
+    QuantumCircuit,
+};
+
+/// Structural metrics of a quantum circuit.
+#[derive(Debug, Clone)]
+pub struct CircuitMetrics {
+    /// The number of lanes of the circuit.
+    pub n_qubits: usize,
+    /// The total number of gates in the circuit.
+    pub n_gates: usize,
+    /// The number of gates of each kind, indexed by the name of the gate.
+    pub gate_counts: BTreeMap<&'static str, usize>,
+    /// The number of gates of each rank.
+    pub rank_counts: BTreeMap<u8, usize>,
+    /// The moment decomposition of the circuit, each moment contains the
+    /// indices of the gates (in the circuit) that belong to it.
+    pub moments: Vec<Vec<usize>>,
+    /// The depth of the circuit counting only gates acting on two or more lanes.
+    pub two_qubit_depth: usize,
+    /// The interaction graph between lanes, the weight of an edge is the number
+    /// of gates acting on both lanes.
+    pub interactions: UnGraphMap<usize, usize>,
+    /// The maximum width of the filled span of a gate.
+    pub max_span_width: usize,
+    /// The average width of the filled span of a gate.
+    pub avg_span_width: f64,
+    /// The lanes on which no gate is applied.
+    pub idle_lanes: Vec<usize>,
+}
+
+impl CircuitMetrics {
+    /// Return the depth of the circuit, that is the number of moments.
+    pub fn depth(&self) -> usize {
+        self.moments.len()
+    }
+
+    /// Return the pairs of interacting lanes with the number of gates
+    /// coupling them, sorted by lanes.
+    pub fn interaction_edges(&self) -> Vec<(usize, usize, usize)> {
+        let mut edges = self
+            .interactions
+            .all_edges()
+            .map(|(a, b, w)| (usize::min(a, b), usize::max(a, b), *w))
+            .collect::<Vec<_>>();
+        edges.sort_unstable();
+        edges
+    }
+}
+
+impl From<&QuantumCircuit> for CircuitMetrics {
+    fn from(circuit: &QuantumCircuit) -> Self {
+        let n_qubits = circuit.n_qubits;
+        let mut gate_counts = BTreeMap::new();
+        let mut rank_counts = BTreeMap::new();
+        let mut interactions = UnGraphMap::new();
+        let mut used_lanes = vec![false; n_qubits];
+        let mut total_span_width = 0;
+        let mut max_span_width = 0;
+
+        for gate in circuit.gates.iter() {
+            *gate_counts.entry(gate.name()).or_insert(0) += 1;
+            *rank_counts.entry(gate.rank()).or_insert(0) += 1;
+
+            let span = gate.span();
+            let width = span.filled().span_len();
+            total_span_width += width;
+            max_span_width = usize::max(max_span_width, width);
+
+            let lanes = span.into_iter().collect::<Vec<_>>();
+            for (i, &a) in lanes.iter().enumerate() {
+                used_lanes[a] = true;
+                for &b in lanes[i + 1..].iter() {
+                    match interactions.edge_weight_mut(a, b) {
+                        Some(w) => *w += 1,
+                        None => {
+                            interactions.add_edge(a, b, 1);
+                        }
+                    }
+                }
+            }
+        }
+
+        let avg_span_width = if circuit.gates.is_empty() {
+            0.0
+        } else {
+            total_span_width as f64 / circuit.gates.len() as f64
+        };
+        let idle_lanes = used_lanes
+            .into_iter()
+            .enumerate()
+            .filter(|(_, used)| !used)
+            .map(|(lane, _)| lane)
+            .collect();
+
+        Self {
+            n_qubits,
+            n_gates: circuit.gates.len(),
+            gate_counts,
+            rank_counts,
+            moments: moments(n_qubits, &circuit.gates, |_| true),
+            two_qubit_depth: moments(n_qubits, &circuit.gates, |g| g.rank() >= 2).len(),
+            interactions,
+            max_span_width,
+            avg_span_width,
+            idle_lanes,
+        }
+    }
+}
+
+/// Decompose the gates selected by the filter in moments, placing each gate in
+/// the earliest moment in which all its lanes are free.
+fn moments(n_qubits: usize, gates: &[Gate], filter: impl Fn(&Gate) -> bool) -> Vec<Vec<usize>> {
+    let mut lane_depth = vec![0; n_qubits];
+    let mut moments: Vec<Vec<usize>> = Vec::new();
+    for (ix, gate) in gates.iter().enumerate().filter(|(_, g)| filter(g)) {
+        let span = gate.span();
+        let moment = span
+            .clone()
+            .into_iter()
+            .map(|l| lane_depth[l])
+            .max()
+            .unwrap();
+        if moment == moments.len() {
+            moments.push(Vec::new());
+        }
+        moments[moment].push(ix);
+        span.into_iter().for_each(|l| lane_depth[l] = moment + 1);
+    }
+    moments
+}
+
+impl std::fmt::Display for CircuitMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Qubits: {}", self.n_qubits)?;
+        writeln!(f, "Gates: {}", self.n_gates)?;
+        writeln!(f, "Depth: {}", self.depth())?;
+        writeln!(f, "Two-qubit depth: {}", self.two_qubit_depth)?;
+        writeln!(
+            f,
+            "Span width: max {}, avg {:.2}",
+            self.max_span_width, self.avg_span_width
+        )?;
+        writeln!(f, "Idle lanes: {}", idle_span(&self.idle_lanes))?;
+        writeln!(f, "Gates by kind:")?;
+        for (name, count) in &self.gate_counts {
+            writeln!(f, "  {}: {}", name, count)?;
+        }
+        writeln!(f, "Gates by rank:")?;
+        for (rank, count) in &self.rank_counts {
+            writeln!(f, "  R{}: {}", rank, count)?;
+        }
+        writeln!(f, "Interactions:")?;
+        for (a, b, count) in self.interaction_edges() {
+            writeln!(f, "  {}-{}: {}", a, b, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Format the idle lanes as a span, or `[]` if there are none.
+fn idle_span(lanes: &[usize]) -> String {
+    if lanes.is_empty() {
+        "[]".to_string()
+    } else {
+        Span::new(lanes).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moments_and_depth() {
+        let mut circ = QuantumCircuit::new(4);
+        circ.g_h(0);
+        circ.g_h(1);
+        circ.g_cx(0, 1);
+        circ.g_x(2);
+        circ.g_cx(1, 2);
+        circ.g_h(0);
+
+        let metrics = CircuitMetrics::from(&circ);
+        assert_eq!(metrics.moments, vec![vec![0, 1, 3], vec![2], vec![4, 5]]);
+        assert_eq!(metrics.depth(), 3);
+        assert_eq!(metrics.two_qubit_depth, 2);
+        assert_eq!(metrics.idle_lanes, vec![3]);
+        assert_eq!(metrics.gate_counts.get("H"), Some(&3));
+        assert_eq!(metrics.rank_counts.get(&2), Some(&2));
+    }
+
+    #[test]
+    fn spans_and_interactions() {
+        let mut circ = QuantumCircuit::new(5);
+        circ.g_cx(0, 4);
+        circ.g_cx(4, 0);
+        circ.g_cxx(1, 2, 3);
+
+        let metrics = CircuitMetrics::from(&circ);
+        assert_eq!(metrics.max_span_width, 5);
+        assert!((metrics.avg_span_width - 13.0 / 3.0).abs() < 1e-10);
+        assert_eq!(
+            metrics.interaction_edges(),
+            vec![(0, 4, 2), (1, 2, 1), (1, 3, 1), (2, 3, 1)]
+        );
+        assert!(metrics.idle_lanes.is_empty());
+    }
+}
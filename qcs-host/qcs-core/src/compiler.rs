@@ -5,15 +5,16 @@ use std::{
 };
 
 use crate::{
-    model::blocks::Block,
+    model::{
+        blocks::Block,
+        scalar::{is_negligible, Scalar},
+    },
     scheduler::{
         operation::{Kernel, MatrixFormat, OperationInstruction},
         ExecutionOperand,
     },
 };
-use nalgebra::{Complex, DMatrix, Normed};
-
-const ZERO_THRESHOLD: f64 = 1e-10;
+use nalgebra::{Complex, DMatrix};
 
 pub struct QcfConfig {
     matrix_format: MatrixFormat,
@@ -53,12 +54,6 @@ pub trait ToQcf {
     fn to_qcf(&self, config: &QcfConfig) -> Vec<u8>;
 }
 
-fn count_non_zero(matrix: &DMatrix<Complex<f64>>) -> usize {
-    matrix
-        .iter()
-        .fold(0, |a, b| if b.norm() > ZERO_THRESHOLD { a + 1 } else { a })
-}
-
 /// Binary format for matrices (sparse COO format):
 /// - 4 bytes: number of non zero elements (u32)
 /// - 1 byte: rank of tensor (1: 2x2, 2: 4x4, 3:8x8, ...) (u8)
@@ -68,10 +63,14 @@ fn count_non_zero(matrix: &DMatrix<Complex<f64>>) -> usize {
 ///     - 4 bytes: column index (u32)
 ///     - 8 bytes: real part (f64)
 ///     - 8 bytes: imaginary part (f64)
-impl ToQcf for DMatrix<Complex<f64>> {
+///
+/// Single precision entries are widened, so the format does not depend on
+/// the precision of the matrix.
+impl<T: Scalar> ToQcf for DMatrix<Complex<T>> {
     fn to_qcf(&self, config: &QcfConfig) -> Vec<u8> {
         let mut bytes = vec![];
-        bytes.extend_from_slice(&(count_non_zero(self) as u32).to_le_bytes());
+        let nnz = self.iter().filter(|c| !is_negligible(*c)).count();
+        bytes.extend_from_slice(&(nnz as u32).to_le_bytes());
         bytes.push((self.nrows() as f64).log2().round() as u8);
         bytes.push(match config.matrix_format {
             MatrixFormat::RowMajor => 0x00,
@@ -82,12 +81,12 @@ impl ToQcf for DMatrix<Complex<f64>> {
                 self.row_iter().enumerate().for_each(|(i, row)| {
                     row.iter()
                         .enumerate()
-                        .filter(|(_, c)| c.norm() > ZERO_THRESHOLD)
+                        .filter(|(_, c)| !is_negligible(*c))
                         .for_each(|(j, c)| {
                             bytes.extend_from_slice(&(i as u32).to_le_bytes());
                             bytes.extend_from_slice(&(j as u32).to_le_bytes());
-                            bytes.extend_from_slice(&c.re.to_le_bytes());
-                            bytes.extend_from_slice(&c.im.to_le_bytes());
+                            bytes.extend_from_slice(&c.re.to_double().to_le_bytes());
+                            bytes.extend_from_slice(&c.im.to_double().to_le_bytes());
                         });
                 });
             }
@@ -95,12 +94,12 @@ impl ToQcf for DMatrix<Complex<f64>> {
                 self.column_iter().enumerate().for_each(|(j, col)| {
                     col.iter()
                         .enumerate()
-                        .filter(|(_, c)| c.norm() > ZERO_THRESHOLD)
+                        .filter(|(_, c)| !is_negligible(*c))
                         .for_each(|(i, c)| {
                             bytes.extend_from_slice(&(i as u32).to_le_bytes());
                             bytes.extend_from_slice(&(j as u32).to_le_bytes());
-                            bytes.extend_from_slice(&c.re.to_le_bytes());
-                            bytes.extend_from_slice(&c.im.to_le_bytes());
+                            bytes.extend_from_slice(&c.re.to_double().to_le_bytes());
+                            bytes.extend_from_slice(&c.im.to_double().to_le_bytes());
                         });
                 });
             }
@@ -109,7 +108,7 @@ impl ToQcf for DMatrix<Complex<f64>> {
     }
 }
 
-impl ToQcf for Block {
+impl<T: Scalar> ToQcf for Block<T> {
     fn to_qcf(&self, config: &QcfConfig) -> Vec<u8> {
         self.as_ref().to_qcf(config)
     }
@@ -126,6 +125,11 @@ impl ToQcf for Block {
 ///   - 0x05: MM(MxA)
 ///   - 0x06: MM(AxM)
 ///   - 0x07: MM(AxA)
+///   - 0x08: MV(MxM)
+///   - 0x09: MV(MxA)
+///   - 0x0A: MV(AxM)
+///   - 0x0B: MV(AxA)
+/// - only for MV: 1 byte: lane where the matrix starts to act on the state (u8)
 /// - left operand: can be a sparse COO matrix or an id of an operation
 ///   - if kind is matrix:
 ///     - matrix in sparse COO format
@@ -194,6 +198,36 @@ impl ToQcf for OperationInstruction {
                     bytes.extend((*a2 as u32).to_le_bytes());
                 }
             },
+            Kernel::MV {
+                left,
+                right,
+                offset,
+            } => match (left, right) {
+                (ExecutionOperand::Block(b1), ExecutionOperand::Block(b2)) => {
+                    bytes.push(0x08);
+                    bytes.push(*offset as u8);
+                    bytes.extend(b1.to_qcf(&config));
+                    bytes.extend(b2.to_qcf(&config.invert()));
+                }
+                (ExecutionOperand::Block(b1), ExecutionOperand::Address(a2)) => {
+                    bytes.push(0x09);
+                    bytes.push(*offset as u8);
+                    bytes.extend(b1.to_qcf(&config));
+                    bytes.extend((*a2 as u32).to_le_bytes());
+                }
+                (ExecutionOperand::Address(a1), ExecutionOperand::Block(b2)) => {
+                    bytes.push(0x0A);
+                    bytes.push(*offset as u8);
+                    bytes.extend((*a1 as u32).to_le_bytes());
+                    bytes.extend(b2.to_qcf(&config.invert()));
+                }
+                (ExecutionOperand::Address(a1), ExecutionOperand::Address(a2)) => {
+                    bytes.push(0x0B);
+                    bytes.push(*offset as u8);
+                    bytes.extend((*a1 as u32).to_le_bytes());
+                    bytes.extend((*a2 as u32).to_le_bytes());
+                }
+            },
         }
 
         bytes
@@ -2,15 +2,38 @@
 //! The tensor network is represented as a directed graph, where the nodes are tensors
 //! and the edges are tensor connections. The tensor connections are represented as spans.
 //!
-//! The contraction is done by contracting the tensors with the smallest rank first.
+//! The order of the contractions is decided by a `ContractionStrategy`, by default
+//! the tensors with the smallest rank are contracted first.
 //! The rank of a contraction is the length of the span that the contraction would cover.
 //!
 //! Not all arcs can be contracted, only the one that satisfy the following condition:
 //! The span of the source tensor and the target tensor is equal to the span of the arc.
+//! When the network has a maximum rank, the arcs whose contraction would cover more lanes
+//! are not contractable either, and the network is contracted into a forest of bounded
+//! trees (see `forest`).
+//!
+//! The contraction is deterministic: the edges are always visited in order of index, ties
+//! between equivalent edges are broken in favour of the lowest index, and the edges of a
+//! contracted tensor are added in order of the index of the linked tensor. Since the graph
+//! reuses the indices of the removed nodes and edges in a fixed order, the same circuit is
+//! always contracted into the same trees.
+
+pub mod closed;
+pub mod edit;
+pub mod einsum;
+pub mod forest;
+pub mod optimizer;
+pub mod patterns;
+pub mod slicing;
+pub mod state;
+pub mod strategy;
+pub mod tree;
+
+use std::collections::BTreeMap;
 
-use hashbrown::{HashMap, HashSet};
 use petgraph::{
-    stable_graph::{EdgeIndex, EdgeReference, StableDiGraph},
+    algo::toposort,
+    stable_graph::{EdgeIndex, EdgeReference, NodeIndex, StableDiGraph},
     visit::{EdgeRef, IntoEdgeReferences},
     Direction,
 };
@@ -21,71 +44,115 @@ use crate::model::{
     QuantumCircuit,
 };
 
+use self::{
+    closed::StateTensor,
+    strategy::{ContractionStrategy, GreedyLowestRank},
+};
+
 /// A tensor network is a directed graph where the nodes are tensors and the
 /// edges are tensor connections.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct TensorNetwork {
     graph: StableDiGraph<TensorKind, Span>,
+    max_rank: Option<u8>,
 }
 
 impl TensorNetwork {
+    /// Set the maximum rank of the contractions, counting all the lanes from
+    /// the first to the last one covered by the contracted tensor.
+    pub fn max_rank(mut self, max_rank: u8) -> Self {
+        self.max_rank = Some(max_rank);
+        self
+    }
+
     /// Contract the tensor network and return the contracted tensors.
-    pub fn contract(mut self) -> Vec<TensorKind> {
-        // println!("{}", self);
+    pub fn contract(self) -> Vec<TensorKind> {
+        self.contract_with(&GreedyLowestRank)
+    }
+
+    /// Contract the tensor network in the order decided by the strategy and
+    /// return the contracted tensors, in the order they are applied.
+    pub fn contract_with(
+        mut self,
+        strategy: &(impl ContractionStrategy + ?Sized),
+    ) -> Vec<TensorKind> {
         loop {
-            let lowest_rank = self
-                .contractable()
-                .into_iter()
-                .map(|e| self.contraction_rank(&e))
-                .min();
-            let Some(lowest_rank) = lowest_rank else {
+            let to_contract = strategy.select(&self);
+            if to_contract.is_empty() {
                 break;
-            };
-
-            let mut visited = HashSet::new();
-            let to_contract = self
-                .contractable()
-                .into_iter()
-                .filter(|e| self.contraction_rank(e) == lowest_rank)
-                .filter(|e| {
-                    if !visited.contains(&e.source()) && !visited.contains(&e.target()) {
-                        visited.insert(e.source());
-                        visited.insert(e.target());
-                        true
-                    } else {
-                        false
-                    }
-                })
-                .map(|e| e.id())
-                .collect::<Vec<_>>();
-
+            }
             to_contract.into_iter().for_each(|to_contract| {
-                self.contract_edge(to_contract);
+                self.contract_edge_unchecked(to_contract);
             });
-            // println!("{}", self);
         }
 
-        self.graph.node_weights().cloned().collect()
+        self.into_tensors()
+    }
+
+    /// Return the tensors of the network in topological order, that is in the
+    /// order they are applied.
+    pub fn into_tensors(mut self) -> Vec<TensorKind> {
+        toposort(&self.graph, None)
+            .expect("The tensor network is acyclic")
+            .into_iter()
+            .map(|n| self.graph.remove_node(n).unwrap())
+            .collect()
+    }
+
+    /// Return the graph of the tensor network.
+    pub fn graph(&self) -> &StableDiGraph<TensorKind, Span> {
+        &self.graph
     }
 
     /// Find the edges that can be contracted.
-    fn contractable(&self) -> Vec<EdgeReference<Span>> {
+    pub fn contractable(&self) -> Vec<EdgeReference<'_, Span>> {
         self.graph
             .edge_references()
-            .filter(|e| {
-                let source = self.graph.node_weight(e.source()).unwrap().span().filled();
-                let target = self.graph.node_weight(e.target()).unwrap().span().filled();
-                let max_span = source.intersection(&target).unwrap();
-                e.weight() == &max_span
-            })
+            .filter(|e| self.is_contractable(e))
             .collect()
     }
 
-    /// Contract an edge in the tensor network.
-    fn contract_edge(&mut self, edge: EdgeIndex) {
+    /// Check if an edge can be contracted, that is if its span is the
+    /// intersection of the filled spans of its endpoints and the contraction
+    /// does not exceed the maximum rank.
+    fn is_contractable(&self, edge: &EdgeReference<Span>) -> bool {
+        let source = self
+            .graph
+            .node_weight(edge.source())
+            .unwrap()
+            .span()
+            .filled();
+        let target = self
+            .graph
+            .node_weight(edge.target())
+            .unwrap()
+            .span()
+            .filled();
+        let max_span = source.intersection(&target).unwrap();
+        edge.weight() == &max_span
+            && self
+                .max_rank
+                .is_none_or(|max_rank| source.union(&target).span_len() <= max_rank as usize)
+    }
+
+    /// Contract the two nodes if they are linked by a contractable edge, and
+    /// return the new node.
+    fn contract_pair(&mut self, a: NodeIndex, b: NodeIndex) -> Option<NodeIndex> {
+        let edge = self
+            .graph
+            .edges_connecting(a, b)
+            .chain(self.graph.edges_connecting(b, a))
+            .find(|e| self.is_contractable(e))
+            .map(|e| e.id())?;
+        Some(self.contract_edge_unchecked(edge))
+    }
+
+    /// Contract an edge in the tensor network, without checking that it is
+    /// contractable (see `contract_edge`).
+    fn contract_edge_unchecked(&mut self, edge: EdgeIndex) -> NodeIndex {
         let (source, target) = self.graph.edge_endpoints(edge).unwrap();
 
-        let mut backlinks = HashMap::new();
+        let mut backlinks = BTreeMap::new();
         self.graph
             .edges_directed(source, Direction::Incoming)
             .chain(self.graph.edges_directed(target, Direction::Incoming))
@@ -98,7 +165,7 @@ impl TensorNetwork {
                     .unwrap_or_else(|| s.clone());
                 backlinks.insert(n, span);
             });
-        let mut frontlinks = HashMap::new();
+        let mut frontlinks = BTreeMap::new();
         self.graph
             .edges_directed(target, Direction::Outgoing)
             .chain(self.graph.edges_directed(source, Direction::Outgoing))
@@ -123,6 +190,7 @@ impl TensorNetwork {
         for (node, span) in frontlinks {
             self.graph.add_edge(new_node, node, span);
         }
+        new_node
     }
 
     /// Calculate the rank of a contraction.
@@ -133,23 +201,37 @@ impl TensorNetwork {
     }
 }
 
-impl From<QuantumCircuit> for TensorNetwork {
-    fn from(circuit: QuantumCircuit) -> Self {
-        let QuantumCircuit { gates, .. } = circuit;
+impl TensorNetwork {
+    /// Build the network of a sequence of tensors, linking each tensor to the
+    /// last tensors on its lanes. If `filled`, each tensor becomes the last one
+    /// on all the lanes of its filled span, not only on the lanes it acts on.
+    fn from_tensors(tensors: impl IntoIterator<Item = TensorKind>, filled: bool) -> Self {
         let mut graph = StableDiGraph::new();
         // This will be used as a vertical slice of the last gate in each qubit lane
         let mut span_register = GateSliceView::new();
-        for gate in gates.into_iter() {
-            let tensor = TensorKind::Gate(Box::new(gate));
+        for tensor in tensors {
             let current_span = tensor.span().clone();
             let new_node = graph.add_node(tensor);
             let linked_spans = span_register.get(&current_span.filled());
-            span_register.apply(current_span, new_node);
+            if filled {
+                span_register.apply(current_span.filled(), new_node);
+            } else {
+                span_register.apply(current_span, new_node);
+            }
             for (span, node) in linked_spans {
                 graph.add_edge(node, new_node, span);
             }
         }
-        Self { graph }
+        Self {
+            graph,
+            max_rank: None,
+        }
+    }
+}
+
+impl From<QuantumCircuit> for TensorNetwork {
+    fn from(circuit: QuantumCircuit) -> Self {
+        Self::from_tensors(circuit.gates.into_iter().map(TensorKind::from), false)
     }
 }
 
@@ -167,6 +249,8 @@ pub enum TensorKind {
     Contraction(Box<TensorContraction>),
     /// A single quantum gate.
     Gate(Box<Gate>),
+    /// A state closing a lane of the network.
+    State(Box<StateTensor>),
 }
 
 impl TensorKind {
@@ -175,6 +259,7 @@ impl TensorKind {
         match self {
             Self::Contraction(c) => c.span.clone(),
             Self::Gate(g) => g.span(),
+            Self::State(s) => s.span(),
         }
     }
 }
@@ -191,11 +276,18 @@ impl From<Gate> for TensorKind {
     }
 }
 
+impl From<StateTensor> for TensorKind {
+    fn from(value: StateTensor) -> Self {
+        Self::State(Box::new(value))
+    }
+}
+
 impl std::fmt::Display for TensorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self {
             Self::Contraction(c) => write!(f, "{}", c),
             Self::Gate(g) => write!(f, "{}", g),
+            Self::State(s) => write!(f, "{}", s),
         }
     }
 }
@@ -231,3 +323,24 @@ impl std::fmt::Display for TensorContraction {
         write!(f, "({} ~ {})", self.lhs, self.rhs)
     }
 }
+
+/// Fixtures shared by the tests of the crate.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::{TensorContraction, TensorKind, TensorNetwork};
+    use crate::model::QuantumCircuit;
+
+    /// Return the contraction of a tensor, panicking if it is a gate or a
+    /// state.
+    pub(crate) fn expect_contraction(tensor: TensorKind) -> TensorContraction {
+        match tensor {
+            TensorKind::Contraction(contraction) => *contraction,
+            tensor => panic!("Expected a contraction, found {}", tensor),
+        }
+    }
+
+    /// Return the contraction of the first tree of the network of a circuit.
+    pub(crate) fn circuit_contraction(circuit: QuantumCircuit) -> TensorContraction {
+        expect_contraction(TensorNetwork::from(circuit).contract().remove(0))
+    }
+}
@@ -0,0 +1,555 @@
+//! This module contains the networks closed by state tensors, used to compute
+//! single amplitudes ⟨x|C|ψ⟩ without building the unitary of the circuit.
+//!
+//! A closed network has an input qubit and, optionally, an output projector on
+//! each lane. Following the order of `QuantumCircuit::eval`, where the first
+//! gate is the leftmost factor, the output projectors are linked before the
+//! first tensor of their lane and the input qubits after the last one.
+//!
+//! Contracting a state into a tensor closes one of its lanes, so the tensors
+//! of a closed network are not square: an `IndexTensor` keeps track of the
+//! legs still open on the output side (rows) and on the input side
+//! (columns). When every lane is closed the result is a scalar, when only the
+//! inputs are closed it is a vector over the output lanes.
+
+use hashbrown::HashMap;
+use nalgebra::{Complex, DMatrix};
+use thiserror::Error;
+
+use crate::{
+    executor::CpuExecutor,
+    model::{
+        span::Span,
+        tensor::{difference, union, IndexTensor},
+        QuantumCircuit, Qubit,
+    },
+    scheduler::ContractionPlan,
+};
+
+use super::{strategy::ContractionStrategy, TensorKind, TensorNetwork};
+
+/// A rank-1 tensor closing a lane of the network.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateTensor {
+    /// The input qubit of a lane.
+    Input { lane: usize, qubit: Qubit },
+    /// The projector on a basis state of an output lane.
+    Output { lane: usize, bit: bool },
+}
+
+impl StateTensor {
+    /// Return the states of a product input register, one for each lane.
+    pub fn inputs(qubits: &[Qubit]) -> Vec<Self> {
+        qubits
+            .iter()
+            .enumerate()
+            .map(|(lane, qubit)| Self::Input {
+                lane,
+                qubit: qubit.clone(),
+            })
+            .collect()
+    }
+
+    /// Return the projectors on the basis state of a bitstring, where lane 0
+    /// is the most significant bit.
+    pub fn outputs(n_qubits: usize, bitstring: usize) -> Vec<Self> {
+        (0..n_qubits)
+            .map(|lane| Self::Output {
+                lane,
+                bit: bit(bitstring, n_qubits, lane),
+            })
+            .collect()
+    }
+
+    /// Return the lane closed by the state.
+    pub fn lane(&self) -> usize {
+        match self {
+            Self::Input { lane, .. } | Self::Output { lane, .. } => *lane,
+        }
+    }
+
+    /// Return the span of the state.
+    pub fn span(&self) -> Span {
+        Span::single(self.lane())
+    }
+}
+
+/// Error raised when a tensor of a closed network is converted into the
+/// operations of a plan: the plans only contract square matrices, closed
+/// networks are evaluated by `evaluate`.
+#[derive(Debug, Error, PartialEq)]
+#[error("The state tensor {0} is evaluated by `closed::evaluate`")]
+pub struct ClosedTensorError(pub StateTensor);
+
+impl std::fmt::Display for StateTensor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Input { lane, .. } => write!(f, "|ψ⟩[{}]", lane),
+            Self::Output { lane, bit } => write!(f, "⟨{}|[{}]", *bit as u8, lane),
+        }
+    }
+}
+
+impl TensorNetwork {
+    /// Build the network of a circuit closed by state tensors. The output
+    /// projectors are linked before the first gate of their lane and the input
+    /// qubits after the last one.
+    ///
+    /// In a closed network every tensor occupies all the lanes of its filled
+    /// span, so that the states close every lane of the gate matrices.
+    pub fn closed(circuit: QuantumCircuit, states: impl IntoIterator<Item = StateTensor>) -> Self {
+        let (outputs, inputs): (Vec<_>, Vec<_>) = states
+            .into_iter()
+            .inspect(|s| assert!(s.lane() < circuit.n_qubits, "Lane out of the circuit"))
+            .partition(|s| matches!(s, StateTensor::Output { .. }));
+        let tensors = outputs
+            .into_iter()
+            .map(TensorKind::from)
+            .chain(circuit.gates.into_iter().map(TensorKind::from))
+            .chain(inputs.into_iter().map(TensorKind::from));
+        Self::from_tensors(tensors, true)
+    }
+}
+
+impl From<&StateTensor> for IndexTensor {
+    /// An input qubit has an output leg, the projector of an output lane has
+    /// an input leg.
+    fn from(state: &StateTensor) -> Self {
+        match state {
+            StateTensor::Input { lane, qubit } => IndexTensor::new(
+                &[*lane],
+                &[],
+                DMatrix::from_column_slice(2, 1, qubit.as_ref().as_slice()),
+            ),
+            StateTensor::Output { lane, bit } => {
+                let mut matrix = DMatrix::zeros(1, 2);
+                matrix[(0, *bit as usize)] = Complex::new(1.0, 0.0);
+                IndexTensor::new(&[], &[*lane], matrix)
+            }
+        }
+    }
+}
+
+impl From<&TensorKind> for IndexTensor {
+    /// Evaluate a tree into a single tensor, the gates only have the legs of
+    /// the lanes they act on.
+    fn from(tensor: &TensorKind) -> Self {
+        match tensor {
+            TensorKind::Contraction(c) => Self::from(&c.lhs).einsum(&Self::from(&c.rhs)),
+            TensorKind::Gate(g) => Self::from(g.as_ref().clone()),
+            TensorKind::State(s) => Self::from(s.as_ref()),
+        }
+    }
+}
+
+/// Evaluate the tensors left by the contraction of a closed network, in the
+/// order they are applied, into a single tensor.
+pub fn evaluate(tensors: &[TensorKind]) -> IndexTensor {
+    tensors
+        .iter()
+        .map(IndexTensor::from)
+        .fold(IndexTensor::one(), |acc, tensor| acc.einsum(&tensor))
+}
+
+/// Evaluate the tensors like `evaluate`, executing the plan of every
+/// contraction on index tensors with the `CpuExecutor`.
+pub fn execute(tensors: &[TensorKind]) -> IndexTensor {
+    tensors
+        .iter()
+        .map(|tensor| match tensor {
+            TensorKind::Contraction(c) => {
+                let plan = ContractionPlan::<IndexTensor>::new(c.as_ref().clone())
+                    .expect("Index tensors hold the states");
+                CpuExecutor::new().execute(plan).pop().unwrap()
+            }
+            _ => IndexTensor::from(tensor),
+        })
+        .fold(IndexTensor::one(), |acc, tensor| acc.einsum(&tensor))
+}
+
+/// The elements of the tensors built while evaluating a contraction, with no
+/// tensor built. Footprints are compared by peak first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Footprint {
+    /// The number of elements of the largest tensor.
+    pub peak: usize,
+    /// The number of elements of all the results of the contractions.
+    pub total: usize,
+}
+
+impl Footprint {
+    /// Merge the footprints of two evaluations.
+    fn merge(self, other: Self) -> Self {
+        Self {
+            peak: self.peak.max(other.peak),
+            total: self.total + other.total,
+        }
+    }
+}
+
+/// Return the footprint of the evaluation of the tensors left by the
+/// contraction of a closed network.
+pub fn footprint(tensors: &[TensorKind]) -> Footprint {
+    let (_, footprint) = tensors.iter().map(open_lanes).fold(
+        ((vec![], vec![]), Footprint::default()),
+        |((outputs, inputs), footprint), (rhs, rhs_footprint)| {
+            let (lanes, contraction) = contract_lanes(&outputs, &inputs, &rhs.0, &rhs.1);
+            (lanes, footprint.merge(rhs_footprint).merge(contraction))
+        },
+    );
+    footprint
+}
+
+/// The output and input lanes left open by a tensor.
+type Lanes = (Vec<usize>, Vec<usize>);
+
+/// Return the lanes left open by a tree, with the footprint of its evaluation.
+fn open_lanes(tensor: &TensorKind) -> (Lanes, Footprint) {
+    match tensor {
+        TensorKind::Contraction(c) => {
+            let ((lhs_outputs, lhs_inputs), lhs) = open_lanes(&c.lhs);
+            let ((rhs_outputs, rhs_inputs), rhs) = open_lanes(&c.rhs);
+            let (lanes, contraction) =
+                contract_lanes(&lhs_outputs, &lhs_inputs, &rhs_outputs, &rhs_inputs);
+            (lanes, contraction.merge(lhs).merge(rhs))
+        }
+        _ => {
+            let leaf = IndexTensor::from(tensor);
+            let footprint = Footprint {
+                peak: leaf.as_ref().len(),
+                total: 0,
+            };
+            ((leaf.outputs().to_vec(), leaf.inputs().to_vec()), footprint)
+        }
+    }
+}
+
+/// Return the lanes left open by the contraction of two tensors, with its
+/// footprint: the largest among the operands and the result.
+fn contract_lanes(
+    lhs_outputs: &[usize],
+    lhs_inputs: &[usize],
+    rhs_outputs: &[usize],
+    rhs_inputs: &[usize],
+) -> (Lanes, Footprint) {
+    let outputs = union(lhs_outputs, &difference(rhs_outputs, lhs_inputs));
+    let inputs = union(rhs_inputs, &difference(lhs_inputs, rhs_outputs));
+    let left = lhs_outputs.len() + lhs_inputs.len();
+    let right = rhs_outputs.len() + rhs_inputs.len();
+    let result = outputs.len() + inputs.len();
+    let footprint = Footprint {
+        peak: 1 << left.max(right).max(result),
+        total: 1 << result,
+    };
+    ((outputs, inputs), footprint)
+}
+
+/// Compute the amplitude ⟨x|C|ψ⟩ of a circuit for a product input state and
+/// an output bitstring, where lane 0 is the most significant bit.
+pub fn amplitude(circuit: &QuantumCircuit, input: &[Qubit], bitstring: usize) -> Complex<f64> {
+    let states = StateTensor::inputs(input)
+        .into_iter()
+        .chain(StateTensor::outputs(circuit.n_qubits, bitstring));
+    let tensors = TensorNetwork::closed(circuit.clone(), states).contract();
+    evaluate(&tensors).scalar().unwrap()
+}
+
+/// A node of the contraction trees of an `AmplitudeBatch`.
+#[derive(Debug, Clone)]
+enum BatchNode {
+    /// A subtree without output projectors, evaluated once.
+    Fixed(IndexTensor),
+    /// The output projector of a lane.
+    Output(usize),
+    /// A contraction depending on the output bits of some lanes.
+    Contraction {
+        lhs: usize,
+        rhs: usize,
+        lanes: Vec<usize>,
+    },
+}
+
+/// Computes the amplitudes of a circuit for many output bitstrings, sharing
+/// the work between them.
+///
+/// The closed network is contracted once, then the subtrees without output
+/// projectors are evaluated once for all the bitstrings, and the subtrees
+/// with projectors are evaluated once for each assignment of their output
+/// lanes.
+#[derive(Debug, Clone)]
+pub struct AmplitudeBatch {
+    n_qubits: usize,
+    nodes: Vec<BatchNode>,
+    roots: Vec<usize>,
+}
+
+impl AmplitudeBatch {
+    /// Contract the network of the circuit closed by the input state, in the
+    /// order decided by the strategy.
+    pub fn new(
+        circuit: &QuantumCircuit,
+        input: &[Qubit],
+        strategy: &(impl ContractionStrategy + ?Sized),
+    ) -> Self {
+        let n_qubits = circuit.n_qubits;
+        let states = StateTensor::inputs(input)
+            .into_iter()
+            .chain(StateTensor::outputs(n_qubits, 0));
+        let tensors = TensorNetwork::closed(circuit.clone(), states).contract_with(strategy);
+
+        let mut batch = Self {
+            n_qubits,
+            nodes: vec![],
+            roots: vec![],
+        };
+        batch.roots = tensors.iter().map(|t| batch.add(t).0).collect();
+        batch
+    }
+
+    /// Add the nodes of a tree, returning its root and its output lanes.
+    fn add(&mut self, tensor: &TensorKind) -> (usize, Vec<usize>) {
+        let (node, lanes) = match tensor {
+            TensorKind::State(s) if matches!(s.as_ref(), StateTensor::Output { .. }) => {
+                (BatchNode::Output(s.lane()), vec![s.lane()])
+            }
+            TensorKind::Contraction(c) => {
+                let (lhs, lhs_lanes) = self.add(&c.lhs);
+                let (rhs, rhs_lanes) = self.add(&c.rhs);
+                let lanes = union(&lhs_lanes, &rhs_lanes);
+                if lanes.is_empty() {
+                    let (BatchNode::Fixed(l), BatchNode::Fixed(r)) =
+                        (self.nodes[lhs].clone(), self.nodes[rhs].clone())
+                    else {
+                        unreachable!("Subtrees without projectors are fixed");
+                    };
+                    self.nodes.truncate(lhs.min(rhs));
+                    (BatchNode::Fixed(l.einsum(&r)), lanes)
+                } else {
+                    (
+                        BatchNode::Contraction {
+                            lhs,
+                            rhs,
+                            lanes: lanes.clone(),
+                        },
+                        lanes,
+                    )
+                }
+            }
+            _ => (BatchNode::Fixed(IndexTensor::from(tensor)), vec![]),
+        };
+        self.nodes.push(node);
+        (self.nodes.len() - 1, lanes)
+    }
+
+    /// Evaluate a node for a bitstring, caching the results by the bits of its
+    /// output lanes.
+    fn evaluate(
+        &self,
+        node: usize,
+        bitstring: usize,
+        cache: &mut HashMap<(usize, usize), IndexTensor>,
+    ) -> IndexTensor {
+        match &self.nodes[node] {
+            BatchNode::Fixed(tensor) => tensor.clone(),
+            BatchNode::Output(lane) => IndexTensor::from(&StateTensor::Output {
+                lane: *lane,
+                bit: bit(bitstring, self.n_qubits, *lane),
+            }),
+            BatchNode::Contraction { lhs, rhs, lanes } => {
+                let key = lanes.iter().fold(0, |key, &lane| {
+                    (key << 1) | bit(bitstring, self.n_qubits, lane) as usize
+                });
+                if let Some(tensor) = cache.get(&(node, key)) {
+                    return tensor.clone();
+                }
+                let lhs = self.evaluate(*lhs, bitstring, cache);
+                let rhs = self.evaluate(*rhs, bitstring, cache);
+                let tensor = lhs.einsum(&rhs);
+                cache.insert((node, key), tensor.clone());
+                tensor
+            }
+        }
+    }
+
+    /// Compute the amplitude of a single bitstring.
+    pub fn amplitude(&self, bitstring: usize) -> Complex<f64> {
+        self.amplitudes(&[bitstring])[0]
+    }
+
+    /// Compute the amplitudes of the bitstrings, where lane 0 is the most
+    /// significant bit.
+    pub fn amplitudes(&self, bitstrings: &[usize]) -> Vec<Complex<f64>> {
+        let mut cache = HashMap::new();
+        bitstrings
+            .iter()
+            .map(|&bitstring| {
+                self.roots
+                    .iter()
+                    .map(|&root| self.evaluate(root, bitstring, &mut cache))
+                    .fold(IndexTensor::one(), |acc, tensor| acc.einsum(&tensor))
+                    .scalar()
+                    .unwrap()
+            })
+            .collect()
+    }
+}
+
+/// Return the bit of a lane in a bitstring, where lane 0 is the most
+/// significant bit.
+fn bit(bitstring: usize, n_qubits: usize, lane: usize) -> bool {
+    (bitstring >> (n_qubits - 1 - lane)) & 1 == 1
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        contractions::{fixtures::expect_contraction, strategy::Strategy},
+        library,
+        model::QRegister,
+        op_tree::Operation,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    /// A product input state with a different qubit on every lane.
+    pub(crate) fn inputs(n_qubits: usize) -> Vec<Qubit> {
+        (0..n_qubits)
+            .map(|i| {
+                let theta = 0.3 + 0.4 * i as f64;
+                Qubit::new(
+                    Complex::new(theta.cos(), 0.0),
+                    Complex::from_polar(theta.sin(), 0.7 * i as f64),
+                )
+            })
+            .collect()
+    }
+
+    fn circuits() -> Vec<QuantumCircuit> {
+        let mut far = QuantumCircuit::new(4);
+        far.g_h(0);
+        far.g_cx(0, 3);
+        far.g_h(2);
+        far.g_cp(0.4, 3, 1);
+        far.g_h(1);
+        vec![
+            far,
+            library::qft(4),
+            library::ripple_carry_adder(1),
+            library::grover(3, &library::phase_oracle(3, 5), 1),
+            library::hardware_efficient_ansatz(3, 1, &[0.2; 12]),
+        ]
+    }
+
+    fn assert_close(a: Complex<f64>, b: Complex<f64>) {
+        assert!((a - b).norm() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn amplitudes_match_state_vector() {
+        for circuit in circuits() {
+            let input = inputs(circuit.n_qubits);
+            let expected = circuit.eval_state(input.clone());
+            for x in 0..(1 << circuit.n_qubits) {
+                assert_close(amplitude(&circuit, &input, x), expected.qubits[x]);
+            }
+        }
+    }
+
+    #[test]
+    fn batch_matches_single_amplitudes() {
+        for circuit in circuits() {
+            let input = inputs(circuit.n_qubits);
+            let expected = circuit.eval_state(input.clone());
+            let bitstrings = (0..(1 << circuit.n_qubits)).rev().collect::<Vec<_>>();
+            for name in Strategy::NAMES {
+                let strategy = name.parse::<Strategy>().unwrap();
+                let batch = AmplitudeBatch::new(&circuit, &input, &strategy);
+                let amplitudes = batch.amplitudes(&bitstrings);
+                for (x, a) in bitstrings.iter().zip(amplitudes) {
+                    assert_close(a, expected.qubits[*x]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn partially_closed_network() {
+        let circuit = library::qft(4);
+        let input = inputs(4);
+        let expected = circuit.eval_state(input.clone());
+
+        // closing only the inputs gives the whole output state
+        let tensors =
+            TensorNetwork::closed(circuit.clone(), StateTensor::inputs(&input)).contract();
+        let state = evaluate(&tensors).vector().unwrap();
+        assert!((state.qubits - &expected.qubits).norm() < 1e-9);
+
+        // projecting lanes 0 and 2 on 1 and 0 leaves a vector over lanes 1 and 3
+        let projectors = [
+            StateTensor::Output { lane: 0, bit: true },
+            StateTensor::Output {
+                lane: 2,
+                bit: false,
+            },
+        ];
+        let states = StateTensor::inputs(&input).into_iter().chain(projectors);
+        let tensors = TensorNetwork::closed(circuit, states).contract();
+        let tensor = evaluate(&tensors);
+        assert_eq!(tensor.outputs(), &[1, 3]);
+        let vector = tensor.vector().unwrap();
+        for (i, x) in [0b1000, 0b1001, 0b1100, 0b1101].into_iter().enumerate() {
+            assert_close(vector.qubits[i], expected.qubits[x]);
+        }
+    }
+
+    #[test]
+    fn random_register_amplitudes() {
+        let mut rng = ChaCha8Rng::seed_from_u64(11);
+        let circuit = library::hardware_efficient_ansatz(4, 2, &[0.7; 24]);
+        let input = (0..4)
+            .map(|_| {
+                let register = QRegister::random(1, &mut rng);
+                Qubit::new(register.qubits[0], register.qubits[1])
+            })
+            .collect::<Vec<_>>();
+        let expected = circuit.eval_state(input.clone());
+        let batch = AmplitudeBatch::new(&circuit, &input, &Strategy::default());
+        for x in [0, 5, 9, 15] {
+            assert_close(batch.amplitude(x), expected.qubits[x]);
+        }
+    }
+
+    #[test]
+    fn plans_match_evaluation() {
+        for circuit in circuits() {
+            let n_qubits = circuit.n_qubits;
+            let states = StateTensor::inputs(&inputs(n_qubits))
+                .into_iter()
+                .chain(StateTensor::outputs(n_qubits, 3))
+                .collect::<Vec<_>>();
+            let tensors = TensorNetwork::closed(circuit.clone(), states).contract();
+            let expected = evaluate(&tensors).scalar().unwrap();
+            assert_close(execute(&tensors).scalar().unwrap(), expected);
+
+            let states = StateTensor::inputs(&inputs(n_qubits));
+            let tensors = TensorNetwork::closed(circuit, states).contract();
+            let (result, expected) = (execute(&tensors), evaluate(&tensors));
+            assert_eq!(result.outputs(), expected.outputs());
+            assert!((result.as_ref() - expected.as_ref()).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn closed_contractions_have_no_plan() {
+        let circuit = library::qft(3);
+        let states = StateTensor::inputs(&inputs(3));
+        let tensors = TensorNetwork::closed(circuit, states).contract();
+        let contraction = expect_contraction(tensors[0].clone());
+        assert!(matches!(
+            ContractionPlan::new(contraction.clone()),
+            Err::<ContractionPlan, _>(ClosedTensorError(StateTensor::Input { .. }))
+        ));
+        assert!(Operation::from_contraction(contraction, false).is_err());
+    }
+}
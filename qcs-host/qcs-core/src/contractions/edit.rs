@@ -0,0 +1,450 @@
+//! This module contains the editing of a tensor network, used by interactive
+//! tools and optimisers that build a network one tensor at a time and contract
+//! the edges they choose.
+//!
+//! Every edit keeps the rule the contraction relies on: each lane of the
+//! filled span of a tensor is linked to the previous and to the next tensor on
+//! that lane, and the span of an edge is covered by the filled spans of both
+//! its endpoints. Appending a tensor links it to the last tensors on its lanes,
+//! and removing a tensor links its predecessors to its successors on the lanes
+//! it was between them. The rule is checked by `TensorNetwork::check` after
+//! every edit, and an edit that leaves the network broken is undone and
+//! returns its error.
+
+use std::collections::BTreeMap;
+
+use petgraph::{
+    algo::is_cyclic_directed,
+    stable_graph::{EdgeIndex, NodeIndex},
+    visit::{EdgeRef, IntoEdgeReferences},
+    Direction,
+};
+use thiserror::Error;
+
+use crate::model::span::Span;
+
+use super::{TensorKind, TensorNetwork};
+
+/// Errors raised when editing a tensor network.
+#[derive(Debug, Error, PartialEq)]
+pub enum NetworkError {
+    #[error("The node {0:?} is not in the network")]
+    NodeNotFound(NodeIndex),
+    #[error("The edge {0:?} is not in the network")]
+    EdgeNotFound(EdgeIndex),
+    #[error("The edge {0:?} cannot be contracted")]
+    NotContractable(EdgeIndex),
+    #[error("The span {span} of edge {edge:?} is not covered by both its endpoints")]
+    UncoveredSpan { edge: EdgeIndex, span: Span },
+    #[error("The nodes {0:?} and {1:?} are linked by more than one edge")]
+    ParallelEdges(NodeIndex, NodeIndex),
+    #[error("The lane {lane} of node {node:?} is linked to more than one {direction} tensor")]
+    BranchingLane {
+        node: NodeIndex,
+        lane: usize,
+        direction: &'static str,
+    },
+    #[error("The network has a cycle")]
+    Cycle,
+}
+
+impl TensorNetwork {
+    /// Create an empty tensor network.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a tensor after the last tensors on the lanes of its filled span,
+    /// and return its node, or the error of the check of the network. On
+    /// error the network is left as it was.
+    pub fn push(&mut self, tensor: impl Into<TensorKind>) -> Result<NodeIndex, NetworkError> {
+        let tensor = tensor.into();
+        let mut links = BTreeMap::<NodeIndex, Vec<usize>>::new();
+        for lane in tensor.span().filled() {
+            if let Some(node) = self.last_on_lane(lane) {
+                links.entry(node).or_default().push(lane);
+            }
+        }
+        self.checked_edit(|network| {
+            let new_node = network.graph.add_node(tensor);
+            for (node, lanes) in links {
+                network.graph.add_edge(node, new_node, Span::new(lanes));
+            }
+            new_node
+        })
+    }
+
+    /// Remove a tensor, linking the tensors before it to the tensors after it
+    /// on the lanes it was between them, and return it, or the error of the
+    /// check of the network. On error the network is left as it was.
+    pub fn remove(&mut self, node: NodeIndex) -> Result<TensorKind, NetworkError> {
+        if !self.graph.contains_node(node) {
+            return Err(NetworkError::NodeNotFound(node));
+        }
+        let mut links = BTreeMap::<(NodeIndex, NodeIndex), Vec<usize>>::new();
+        for (before, before_span) in self.predecessors(node) {
+            for (after, after_span) in self.successors(node) {
+                if let Some(span) = before_span.intersection(&after_span) {
+                    links.entry((before, after)).or_default().extend(span);
+                }
+            }
+        }
+        self.checked_edit(|network| {
+            let tensor = network.graph.remove_node(node).unwrap();
+            for ((before, after), lanes) in links {
+                network.link(before, after, Span::new(lanes));
+            }
+            tensor
+        })
+    }
+
+    /// Return the tensor of a node.
+    pub fn tensor(&self, node: NodeIndex) -> Option<&TensorKind> {
+        self.graph.node_weight(node)
+    }
+
+    /// Return the nodes linked to a node by an incoming edge, with the span of
+    /// the edge, in order of index.
+    pub fn predecessors(&self, node: NodeIndex) -> Vec<(NodeIndex, Span)> {
+        self.linked(node, Direction::Incoming)
+    }
+
+    /// Return the nodes linked to a node by an outgoing edge, with the span of
+    /// the edge, in order of index.
+    pub fn successors(&self, node: NodeIndex) -> Vec<(NodeIndex, Span)> {
+        self.linked(node, Direction::Outgoing)
+    }
+
+    /// Return the nodes linked to a node in either direction, in order of
+    /// index.
+    pub fn neighbours(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        let mut neighbours = self.graph.neighbors_undirected(node).collect::<Vec<_>>();
+        neighbours.sort();
+        neighbours.dedup();
+        neighbours
+    }
+
+    /// Return the edge between two nodes, in either direction.
+    pub fn edge_between(&self, a: NodeIndex, b: NodeIndex) -> Option<EdgeIndex> {
+        self.graph
+            .find_edge(a, b)
+            .or_else(|| self.graph.find_edge(b, a))
+    }
+
+    /// Check if an edge exists and can be contracted.
+    pub fn can_contract(&self, edge: EdgeIndex) -> bool {
+        self.graph
+            .edge_references()
+            .find(|e| e.id() == edge)
+            .is_some_and(|e| self.is_contractable(&e))
+    }
+
+    /// Contract a single edge, checking that it can be contracted, and return
+    /// the node of the contraction, or the error of the check of the network.
+    /// On error the network is left as it was.
+    pub fn contract_edge(&mut self, edge: EdgeIndex) -> Result<NodeIndex, NetworkError> {
+        if self.graph.edge_weight(edge).is_none() {
+            return Err(NetworkError::EdgeNotFound(edge));
+        }
+        if !self.can_contract(edge) {
+            return Err(NetworkError::NotContractable(edge));
+        }
+        self.checked_edit(|network| network.contract_edge_unchecked(edge))
+    }
+
+    /// Check that every edge is covered by the filled spans of its endpoints,
+    /// that no lane branches and that the network is acyclic.
+    pub fn check(&self) -> Result<(), NetworkError> {
+        for edge in self.graph.edge_references() {
+            let source = self.graph[edge.source()].span().filled();
+            let target = self.graph[edge.target()].span().filled();
+            let covered = source
+                .intersection(&target)
+                .is_some_and(|s| s.intersection(edge.weight()).as_ref() == Some(edge.weight()));
+            if !covered {
+                return Err(NetworkError::UncoveredSpan {
+                    edge: edge.id(),
+                    span: edge.weight().clone(),
+                });
+            }
+            if self
+                .graph
+                .edges_connecting(edge.source(), edge.target())
+                .count()
+                > 1
+            {
+                return Err(NetworkError::ParallelEdges(edge.source(), edge.target()));
+            }
+        }
+        for node in self.graph.node_indices() {
+            for (direction, name) in [
+                (Direction::Incoming, "previous"),
+                (Direction::Outgoing, "next"),
+            ] {
+                let mut lanes = self
+                    .linked(node, direction)
+                    .into_iter()
+                    .flat_map(|(_, span)| span)
+                    .collect::<Vec<_>>();
+                lanes.sort();
+                if let Some(lane) = lanes.windows(2).find(|w| w[0] == w[1]) {
+                    return Err(NetworkError::BranchingLane {
+                        node,
+                        lane: lane[0],
+                        direction: name,
+                    });
+                }
+            }
+        }
+        if is_cyclic_directed(&self.graph) {
+            return Err(NetworkError::Cycle);
+        }
+        Ok(())
+    }
+
+    /// Return the last tensor on a lane, that is the tensor whose filled span
+    /// covers the lane and that has no outgoing edge on it.
+    fn last_on_lane(&self, lane: usize) -> Option<NodeIndex> {
+        self.graph.node_indices().find(|&n| {
+            self.graph[n].span().filled().contains(lane)
+                && self
+                    .graph
+                    .edges_directed(n, Direction::Outgoing)
+                    .all(|e| !e.weight().contains(lane))
+        })
+    }
+
+    /// Link two nodes on a span, merging it into the existing edge if any.
+    fn link(&mut self, source: NodeIndex, target: NodeIndex, span: Span) {
+        match self.graph.find_edge(source, target) {
+            Some(edge) => {
+                let merged = self.graph[edge].union(&span);
+                self.graph[edge] = merged;
+            }
+            None => {
+                self.graph.add_edge(source, target, span);
+            }
+        }
+    }
+
+    fn linked(&self, node: NodeIndex, direction: Direction) -> Vec<(NodeIndex, Span)> {
+        let mut linked = self
+            .graph
+            .edges_directed(node, direction)
+            .map(|e| {
+                let other = match direction {
+                    Direction::Incoming => e.source(),
+                    Direction::Outgoing => e.target(),
+                };
+                (other, e.weight().clone())
+            })
+            .collect::<Vec<_>>();
+        linked.sort_by_key(|(n, _)| *n);
+        linked
+    }
+
+    /// Apply an edit and return its result if the network passes the check,
+    /// restoring the graph as it was before the edit otherwise. The graph is
+    /// stable, so the restored nodes and edges keep their indices.
+    fn checked_edit<T>(&mut self, edit: impl FnOnce(&mut Self) -> T) -> Result<T, NetworkError> {
+        let graph = self.graph.clone();
+        let value = edit(self);
+        match self.check() {
+            Ok(()) => Ok(value),
+            Err(error) => {
+                self.graph = graph;
+                Err(error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::closed::{evaluate, StateTensor},
+        library,
+        model::{QuantumCircuit, Qubit},
+    };
+
+    /// Describe the edges by the tensors they link, so that networks built in
+    /// different ways can be compared.
+    fn edges(network: &TensorNetwork) -> Vec<(String, String, Span)> {
+        let graph = network.graph();
+        let mut edges = graph
+            .edge_references()
+            .map(|e| {
+                (
+                    graph[e.source()].to_string(),
+                    graph[e.target()].to_string(),
+                    e.weight().clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        edges.sort_by(|a, b| (&a.0, &a.1, a.2.start()).cmp(&(&b.0, &b.1, b.2.start())));
+        edges
+    }
+
+    fn circuits() -> Vec<QuantumCircuit> {
+        vec![
+            library::qft(4),
+            library::ripple_carry_adder(1),
+            library::grover(4, &library::phase_oracle(4, 5), 1),
+        ]
+    }
+
+    #[test]
+    fn pushing_gates_matches_circuit() {
+        for circuit in circuits() {
+            let mut network = TensorNetwork::new();
+            for gate in circuit.gates.clone() {
+                network.push(gate).unwrap();
+            }
+            let expected = TensorNetwork::from(circuit);
+            assert_eq!(expected.check(), Ok(()));
+            assert_eq!(edges(&network), edges(&expected));
+        }
+    }
+
+    #[test]
+    fn pushing_states_matches_closed_network() {
+        let circuit = library::qft(3);
+        let states = StateTensor::inputs(&[Qubit::zero(), Qubit::one(), Qubit::zero()]);
+        let mut network = TensorNetwork::new();
+        for gate in circuit.gates.clone() {
+            network.push(gate).unwrap();
+        }
+        for state in states.clone() {
+            network.push(state).unwrap();
+        }
+        let expected = TensorNetwork::closed(circuit, states);
+        assert_eq!(edges(&network), edges(&expected));
+    }
+
+    #[test]
+    fn removing_gate_matches_circuit_without_it() {
+        for circuit in circuits() {
+            for removed in [0, circuit.gates.len() / 2, circuit.gates.len() - 1] {
+                let mut network = TensorNetwork::from(circuit.clone());
+                let node = network.graph().node_indices().nth(removed).unwrap();
+                let tensor = network.remove(node).unwrap();
+                assert_eq!(tensor.to_string(), circuit.gates[removed].to_string());
+
+                let mut expected = QuantumCircuit::new(circuit.n_qubits);
+                expected.gates = circuit.gates.clone();
+                expected.gates.remove(removed);
+                assert_eq!(edges(&network), edges(&TensorNetwork::from(expected)));
+            }
+        }
+    }
+
+    #[test]
+    fn neighbours_and_links() {
+        let mut circuit = QuantumCircuit::new(3);
+        circuit.g_h(0);
+        circuit.g_cx(0, 2);
+        circuit.g_h(1);
+        let network = TensorNetwork::from(circuit);
+        let nodes = network.graph().node_indices().collect::<Vec<_>>();
+        assert_eq!(
+            network.successors(nodes[0]),
+            vec![(nodes[1], Span::single(0))]
+        );
+        assert_eq!(
+            network.predecessors(nodes[2]),
+            vec![(nodes[1], Span::single(1))]
+        );
+        assert_eq!(network.neighbours(nodes[1]), vec![nodes[0], nodes[2]]);
+        assert!(network.edge_between(nodes[2], nodes[1]).is_some());
+        assert!(network.edge_between(nodes[0], nodes[2]).is_none());
+        assert_eq!(network.tensor(nodes[2]).unwrap().to_string(), "H[1]");
+    }
+
+    #[test]
+    fn contract_chosen_edges() {
+        for circuit in circuits() {
+            let mut network = TensorNetwork::from(circuit.clone());
+            while let Some(edge) = network.contractable().last().map(|e| e.id()) {
+                let node = network.contract_edge(edge).unwrap();
+                assert!(network.tensor(node).is_some());
+                assert_eq!(network.check(), Ok(()));
+            }
+            let tensors = network.into_tensors();
+            let block = evaluate(&tensors);
+            let expected = circuit.eval();
+            assert!((block.as_ref() - expected.as_ref()).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn edit_errors() {
+        let mut network = TensorNetwork::from(library::qft(3)).max_rank(2);
+        let uncontractable = network
+            .graph()
+            .edge_indices()
+            .find(|&e| !network.can_contract(e))
+            .unwrap();
+        assert_eq!(
+            network.contract_edge(uncontractable),
+            Err(NetworkError::NotContractable(uncontractable))
+        );
+        let node = network.graph().node_indices().next().unwrap();
+        network.remove(node).unwrap();
+        assert!(matches!(network.remove(node), Err(NetworkError::NodeNotFound(n)) if n == node));
+        let missing = EdgeIndex::new(1000);
+        assert_eq!(
+            network.contract_edge(missing),
+            Err(NetworkError::EdgeNotFound(missing))
+        );
+    }
+
+    #[test]
+    fn check_finds_broken_edges() {
+        let mut network = TensorNetwork::from(library::qft(3));
+        let edge = network.graph.edge_indices().next().unwrap();
+        network.graph[edge] = Span::range(0..3);
+        assert!(matches!(
+            network.check(),
+            Err(NetworkError::UncoveredSpan { .. })
+        ));
+    }
+
+    #[test]
+    fn edits_reject_broken_networks() {
+        let broken = || {
+            let mut network = TensorNetwork::from(library::qft(3));
+            let edge = network.graph.edge_indices().next().unwrap();
+            network.graph[edge] = Span::range(0..3);
+            network
+        };
+        let uncovered =
+            |error: Option<NetworkError>| matches!(error, Some(NetworkError::UncoveredSpan { .. }));
+
+        // the failed edits leave the network as it was
+        let unchanged = |network: &TensorNetwork| {
+            let expected = broken();
+            edges(network) == edges(&expected)
+                && network
+                    .graph()
+                    .node_indices()
+                    .eq(expected.graph().node_indices())
+        };
+
+        let mut network = broken();
+        let mut circuit = QuantumCircuit::new(3);
+        circuit.g_h(2);
+        assert!(uncovered(network.push(circuit.gates.remove(0)).err()));
+        assert!(unchanged(&network));
+
+        let mut network = broken();
+        let last = network.graph().node_indices().next_back().unwrap();
+        assert!(uncovered(network.remove(last).err()));
+        assert!(unchanged(&network));
+
+        let mut network = broken();
+        let edge = network.contractable().last().map(|e| e.id()).unwrap();
+        assert!(uncovered(network.contract_edge(edge).err()));
+        assert!(unchanged(&network));
+    }
+}
@@ -0,0 +1,502 @@
+//! This module contains the exchange of tensor networks with the einsum based
+//! Python tools, such as opt_einsum and cotengra, to compare their contraction
+//! paths with ours.
+//!
+//! A network is exported as an einsum equation over its tensors, in the order
+//! they are applied, with a shape of 2 per index. Every tensor has the indices
+//! of its output legs followed by the ones of its input legs, in order of lane
+//! (see `IndexTensor`), so its data is its matrix reshaped in row-major order
+//! and can be written as a `.npy` file. The open indices of the equation are
+//! the outputs of the network followed by its inputs, and the lanes without
+//! any tensor are left out.
+//!
+//! A contraction path is a list of pairs of positions in the list of tensors:
+//! the two tensors are removed and their contraction is appended at the end, as
+//! in `opt_einsum.contract_path`. Since our contractions are products of
+//! operators, a pair can only be contracted if no other tensor lies between
+//! the two, and the tensor on the right of the other one in the product of the
+//! network becomes the right factor.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use nalgebra::{Complex, DMatrix};
+use petgraph::algo::toposort;
+use thiserror::Error;
+
+use crate::model::{
+    gates::QuantumGate,
+    tensor::{difference, union, IndexTensor},
+};
+
+use super::{TensorContraction, TensorKind, TensorNetwork};
+
+/// Errors raised when importing a contraction path.
+#[derive(Debug, Error, PartialEq)]
+pub enum EinsumError {
+    #[error("Invalid contraction path: {0}")]
+    InvalidPath(String),
+    #[error("Step {step} refers to the tensor {position}, but only {len} are left")]
+    PositionOutOfRange {
+        step: usize,
+        position: usize,
+        len: usize,
+    },
+    #[error("Step {step} contracts two tensors with other tensors between them")]
+    NotAdjacent { step: usize },
+}
+
+/// A tensor network as an einsum equation.
+#[derive(Debug, Clone)]
+pub struct Einsum {
+    /// The tensors, in the order of the operands of the equation.
+    pub tensors: Vec<TensorKind>,
+    /// The indices of each operand.
+    pub operands: Vec<Vec<usize>>,
+    /// The open indices, the outputs of the network followed by its inputs.
+    pub output: Vec<usize>,
+}
+
+impl Einsum {
+    /// Return the equation, with the symbols of opt_einsum.
+    pub fn equation(&self) -> String {
+        let word = |indices: &Vec<usize>| indices.iter().map(|&i| symbol(i)).collect::<String>();
+        let operands = self.operands.iter().map(word).collect::<Vec<_>>();
+        format!("{}->{}", operands.join(","), word(&self.output))
+    }
+
+    /// Return the shapes of the operands.
+    pub fn shapes(&self) -> Vec<Vec<usize>> {
+        self.operands.iter().map(|o| vec![2; o.len()]).collect()
+    }
+
+    /// Return the equation followed by the shapes of the operands as Python
+    /// tuples, one per line.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", self.equation());
+        for shape in self.shapes() {
+            text.push_str(&python_tuple(&shape));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Write the data of every operand in a `.npy` file of the directory,
+    /// named after its position, and return the paths of the files.
+    pub fn write_npy(&self, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+        std::fs::create_dir_all(dir)?;
+        self.tensors
+            .iter()
+            .enumerate()
+            .map(|(i, tensor)| {
+                let path = dir.join(format!("t{}.npy", i));
+                let tensor = IndexTensor::from(tensor);
+                let mut file = std::fs::File::create(&path)?;
+                file.write_all(&npy(tensor.as_ref(), &self.shapes()[i]))?;
+                Ok(path)
+            })
+            .collect()
+    }
+
+    /// Contract the tensors along a contraction path, returning the tensors
+    /// left in the order they are applied, as `TensorNetwork::contract_with`.
+    pub fn contract_path(&self, path: &[(usize, usize)]) -> Result<Vec<TensorKind>, EinsumError> {
+        let links = self.links();
+        // each tensor left is the set of operands it merges
+        let mut left = self
+            .tensors
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(i, tensor)| (BTreeSet::from([i]), tensor))
+            .collect::<Vec<_>>();
+        for (step, &(a, b)) in path.iter().enumerate() {
+            for position in [a, b] {
+                if position >= left.len() || a == b {
+                    return Err(EinsumError::PositionOutOfRange {
+                        step,
+                        position,
+                        len: left.len(),
+                    });
+                }
+            }
+            let (first, second) = (a.max(b), a.min(b));
+            let (x, x_tensor) = left.remove(first);
+            let (y, y_tensor) = left.remove(second);
+            let merged = x.union(&y).copied().collect::<BTreeSet<_>>();
+            if !is_convex(&merged, &links) {
+                return Err(EinsumError::NotAdjacent { step });
+            }
+            let tensor = if is_left_of(&x, &y, &links) {
+                TensorContraction::new(x_tensor, y_tensor)
+            } else {
+                TensorContraction::new(y_tensor, x_tensor)
+            };
+            left.push((merged, tensor.into()));
+        }
+
+        // sort the tensors left so that each comes before the ones on its right
+        let mut sorted = vec![];
+        while !left.is_empty() {
+            let next = (0..left.len())
+                .find(|&i| {
+                    (0..left.len()).all(|j| j == i || !is_left_of(&left[j].0, &left[i].0, &links))
+                })
+                .expect("The network is acyclic");
+            sorted.push(left.remove(next).1);
+        }
+        Ok(sorted)
+    }
+
+    /// Return the operands linked to the input legs of each operand, that is
+    /// the operands right of it in the product.
+    fn links(&self) -> Vec<Vec<usize>> {
+        let legs = self.tensors.iter().map(legs).collect::<Vec<_>>();
+        let mut outputs = BTreeMap::new();
+        for (i, (indices, (out, _))) in self.operands.iter().zip(&legs).enumerate() {
+            for index in &indices[..out.len()] {
+                outputs.insert(*index, i);
+            }
+        }
+        self.operands
+            .iter()
+            .zip(&legs)
+            .map(|(indices, (out, _))| {
+                indices[out.len()..]
+                    .iter()
+                    .filter_map(|index| outputs.get(index).copied())
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl TensorNetwork {
+    /// Export the network as an einsum equation over its tensors.
+    pub fn to_einsum(&self) -> Einsum {
+        let order = toposort(&self.graph, None).expect("The tensor network is acyclic");
+        let mut n_indices = 0;
+        let mut new_index = || {
+            n_indices += 1;
+            n_indices - 1
+        };
+        // the index open on the right of each lane, waiting for an output leg
+        let mut open = BTreeMap::new();
+        let mut outputs = BTreeMap::new();
+        let mut tensors = vec![];
+        let mut operands = vec![];
+        for node in order {
+            let tensor = self.graph[node].clone();
+            let (out, inp) = legs(&tensor);
+            let mut indices = vec![];
+            for lane in out {
+                let index = open.remove(&lane).unwrap_or_else(|| {
+                    let index = new_index();
+                    outputs.insert(lane, index);
+                    index
+                });
+                indices.push(index);
+            }
+            for lane in inp {
+                let index = new_index();
+                open.insert(lane, index);
+                indices.push(index);
+            }
+            tensors.push(tensor);
+            operands.push(indices);
+        }
+        let output = outputs.into_values().chain(open.into_values()).collect();
+        Einsum {
+            tensors,
+            operands,
+            output,
+        }
+    }
+}
+
+/// Parse a contraction path written as a Python or JSON list of pairs, such as
+/// `[(0, 1), (0, 1)]`.
+pub fn parse_path(text: &str) -> Result<Vec<(usize, usize)>, EinsumError> {
+    let invalid = |reason: &str| EinsumError::InvalidPath(reason.to_string());
+    let inner = text
+        .trim()
+        .strip_prefix('[')
+        .and_then(|t| t.strip_suffix(']'))
+        .ok_or_else(|| invalid("expected a list"))?;
+    let numbers = inner
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|n| !n.is_empty())
+        .map(|n| n.parse::<usize>().map_err(|_| invalid(n)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let groups = inner.matches(['(', '[']).count();
+    if numbers.len() != 2 * groups || inner.contains(|c: char| c.is_alphabetic()) {
+        return Err(invalid("expected pairs of positions"));
+    }
+    Ok(numbers.chunks(2).map(|p| (p[0], p[1])).collect())
+}
+
+/// Return the output and input lanes of a tensor.
+fn legs(tensor: &TensorKind) -> (Vec<usize>, Vec<usize>) {
+    match tensor {
+        TensorKind::Contraction(c) => {
+            let (lhs_out, lhs_in) = legs(&c.lhs);
+            let (rhs_out, rhs_in) = legs(&c.rhs);
+            (
+                union(&lhs_out, &difference(&rhs_out, &lhs_in)),
+                union(&rhs_in, &difference(&lhs_in, &rhs_out)),
+            )
+        }
+        TensorKind::Gate(g) => {
+            let lanes = g.span().into_iter().collect::<Vec<_>>();
+            (lanes.clone(), lanes)
+        }
+        TensorKind::State(s) => {
+            let tensor = IndexTensor::from(s.as_ref());
+            (tensor.outputs().to_vec(), tensor.inputs().to_vec())
+        }
+    }
+}
+
+/// Check that no operand outside of a set is both on the left and on the
+/// right of some operands of the set.
+fn is_convex(set: &BTreeSet<usize>, links: &[Vec<usize>]) -> bool {
+    // the operands outside of the set on the right of the set
+    let mut right = BTreeSet::new();
+    let mut stack = set.iter().copied().collect::<Vec<_>>();
+    while let Some(operand) = stack.pop() {
+        for &next in &links[operand] {
+            if !set.contains(&next) && right.insert(next) {
+                stack.push(next);
+            }
+        }
+    }
+    !right
+        .iter()
+        .any(|&operand| links[operand].iter().any(|next| set.contains(next)))
+}
+
+/// Check if an operand of `first` is linked to an operand of `second` on its
+/// right.
+fn is_left_of(first: &BTreeSet<usize>, second: &BTreeSet<usize>, links: &[Vec<usize>]) -> bool {
+    first
+        .iter()
+        .any(|&operand| links[operand].iter().any(|next| second.contains(next)))
+}
+
+/// Return the symbol of an index, as `opt_einsum.get_symbol`.
+fn symbol(index: usize) -> char {
+    const BASE: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let code = match index {
+        i if i < BASE.len() => return BASE[i] as char,
+        i if i >= 55296 => i + 2048,
+        i => i + 140,
+    };
+    char::from_u32(code as u32).expect("Valid unicode symbol")
+}
+
+fn python_tuple(values: &[usize]) -> String {
+    match values {
+        [value] => format!("({},)", value),
+        values => {
+            let values = values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+            format!("({})", values.join(", "))
+        }
+    }
+}
+
+/// Return the content of a `.npy` file (version 1.0) holding a matrix of
+/// complex numbers reshaped in row-major order.
+fn npy(matrix: &DMatrix<Complex<f64>>, shape: &[usize]) -> Vec<u8> {
+    let mut header = format!(
+        "{{'descr': '<c16', 'fortran_order': False, 'shape': {}, }}",
+        python_tuple(shape)
+    );
+    // the header is padded with spaces and ends with a newline, so that the
+    // data is aligned to 64 bytes
+    let unpadded = 10 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend((header.len() as u16).to_le_bytes());
+    bytes.extend(header.as_bytes());
+    for row in matrix.row_iter() {
+        for value in row.iter() {
+            bytes.extend(value.re.to_le_bytes());
+            bytes.extend(value.im.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::{closed::StateTensor, fixtures::expect_contraction},
+        executor::CpuExecutor,
+        library,
+        model::{QuantumCircuit, Qubit},
+        scheduler::ContractionPlan,
+    };
+
+    /// Evaluate an einsum equation by summing over all the values of its
+    /// indices, returning the entries in row-major order over the output.
+    fn brute_force(einsum: &Einsum) -> Vec<Complex<f64>> {
+        let data = einsum
+            .tensors
+            .iter()
+            .map(IndexTensor::from)
+            .collect::<Vec<_>>();
+        let n_indices = einsum.operands.iter().flatten().max().map_or(0, |m| m + 1);
+        let value = |indices: &[usize], x: usize| {
+            indices
+                .iter()
+                .fold(0, |acc, &i| (acc << 1) | ((x >> i) & 1))
+        };
+        let mut result = vec![Complex::new(0.0, 0.0); 1 << einsum.output.len()];
+        for x in 0..1usize << n_indices {
+            let term = einsum
+                .operands
+                .iter()
+                .zip(&data)
+                .map(|(indices, tensor)| {
+                    let flat = value(indices, x);
+                    let cols = tensor.as_ref().ncols();
+                    tensor.as_ref()[(flat / cols, flat % cols)]
+                })
+                .product::<Complex<f64>>();
+            result[value(&einsum.output, x)] += term;
+        }
+        result
+    }
+
+    #[test]
+    fn equation_of_small_circuit() {
+        let mut circuit = QuantumCircuit::new(3);
+        circuit.g_h(0);
+        circuit.g_cx(0, 1);
+        let einsum = TensorNetwork::from(circuit).to_einsum();
+        assert_eq!(einsum.equation(), "ab,bcde->acde");
+        assert_eq!(einsum.shapes(), vec![vec![2; 2], vec![2; 4]]);
+        assert_eq!(einsum.to_text(), "ab,bcde->acde\n(2, 2)\n(2, 2, 2, 2)\n");
+    }
+
+    #[test]
+    fn equation_evaluates_to_unitary() {
+        for circuit in [library::qft(3), library::ripple_carry_adder(1)] {
+            let n_qubits = circuit.n_qubits;
+            let einsum = TensorNetwork::from(circuit.clone()).to_einsum();
+            let result = brute_force(&einsum);
+            let unitary = circuit.eval().into_matrix();
+            let size = 1 << n_qubits;
+            let expected = (0..size * size).map(|i| unitary[(i / size, i % size)]);
+            for (value, expected) in result.into_iter().zip(expected) {
+                assert!((value - expected).norm() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn closed_network_equation() {
+        let circuit = library::qft(2);
+        let states = StateTensor::inputs(&[Qubit::zero(), Qubit::one()])
+            .into_iter()
+            .chain(StateTensor::outputs(2, 0b10));
+        let einsum = TensorNetwork::closed(circuit.clone(), states).to_einsum();
+        assert!(einsum.output.is_empty());
+        let amplitude = brute_force(&einsum)[0];
+        let expected =
+            crate::contractions::closed::amplitude(&circuit, &[Qubit::zero(), Qubit::one()], 0b10);
+        assert!((amplitude - expected).norm() < 1e-9);
+    }
+
+    #[test]
+    fn imported_path_runs_on_executor() {
+        let circuit = library::qft(4);
+        let einsum = TensorNetwork::from(circuit.clone()).to_einsum();
+        let n = einsum.tensors.len();
+        // from the right end of the product, and from the left one
+        let from_right = (2..=n)
+            .rev()
+            .map(|len| (len - 2, len - 1))
+            .collect::<Vec<_>>();
+        let from_left = std::iter::once((0, 1))
+            .chain((2..n).rev().map(|len| (0, len - 1)))
+            .collect::<Vec<_>>();
+        let expected = circuit.eval();
+        for path in [from_right, from_left] {
+            let mut trees = einsum.contract_path(&path).unwrap();
+            assert_eq!(trees.len(), 1);
+            let contraction = expect_contraction(trees.pop().unwrap());
+            let blocks =
+                CpuExecutor::new().execute(ContractionPlan::try_from(contraction).unwrap());
+            let block = blocks[0].clone().into_block();
+            assert!((block.as_ref() - expected.as_ref()).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn partial_path_keeps_order() {
+        let circuit = library::qft(3);
+        let einsum = TensorNetwork::from(circuit.clone()).to_einsum();
+        let trees = einsum.contract_path(&[(1, 2)]).unwrap();
+        assert_eq!(trees.len(), einsum.tensors.len() - 1);
+        let expected = crate::contractions::closed::evaluate(&trees);
+        let unitary = circuit.eval();
+        assert!((expected.as_ref() - unitary.as_ref()).norm() < 1e-9);
+    }
+
+    #[test]
+    fn path_errors() {
+        let mut circuit = QuantumCircuit::new(1);
+        circuit.g_h(0);
+        circuit.g_x(0);
+        circuit.g_h(0);
+        let einsum = TensorNetwork::from(circuit).to_einsum();
+        assert_eq!(
+            einsum.contract_path(&[(0, 2)]).unwrap_err(),
+            EinsumError::NotAdjacent { step: 0 }
+        );
+        assert_eq!(
+            einsum.contract_path(&[(0, 1), (0, 2)]).unwrap_err(),
+            EinsumError::PositionOutOfRange {
+                step: 1,
+                position: 2,
+                len: 2
+            }
+        );
+        assert!(einsum.contract_path(&[(0, 1), (0, 1)]).is_ok());
+    }
+
+    #[test]
+    fn parse_paths() {
+        assert_eq!(parse_path("[(0, 1), (2, 0)]"), Ok(vec![(0, 1), (2, 0)]));
+        assert_eq!(parse_path("[[3,4],[0,1]]"), Ok(vec![(3, 4), (0, 1)]));
+        assert_eq!(parse_path("[]"), Ok(vec![]));
+        assert!(parse_path("[(0, 1, 2)]").is_err());
+        assert!(parse_path("(0, 1)").is_err());
+    }
+
+    #[test]
+    fn npy_header_is_aligned() {
+        let matrix = DMatrix::from_element(2, 2, Complex::new(1.0, -1.0));
+        let bytes = npy(&matrix, &[2, 2]);
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        assert_eq!(bytes.len(), 10 + header_len + 4 * 16);
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.starts_with("{'descr': '<c16', 'fortran_order': False, 'shape': (2, 2), }"));
+        assert!(header.ends_with('\n'));
+    }
+
+    #[test]
+    fn symbols_match_opt_einsum() {
+        assert_eq!(symbol(0), 'a');
+        assert_eq!(symbol(51), 'Z');
+        assert_eq!(symbol(52), char::from_u32(192).unwrap());
+    }
+}
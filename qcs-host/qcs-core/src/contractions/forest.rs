@@ -0,0 +1,207 @@
+//! This module contains the forest of bounded trees left by a contraction with
+//! a maximum rank, used when the accelerator can only hold matrices up to a
+//! fixed rank.
+//!
+//! The contractions of the forest are computed on the accelerator, while the
+//! single gates, that need no computation, stay on the host. The host then
+//! combines the blocks of all the pieces, multiplying them in the order they
+//! are applied after expanding them to their common span.
+
+use crate::model::{blocks::SpannedBlock, scalar::Scalar};
+
+use super::TensorKind;
+
+/// Where a piece of the forest is computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Placement {
+    /// The piece is a contraction that fits in the accelerator buffers.
+    Accelerator,
+    /// The piece is computed, or only loaded, on the CPU.
+    Host,
+}
+
+impl std::fmt::Display for Placement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Accelerator => write!(f, "accelerator"),
+            Self::Host => write!(f, "host"),
+        }
+    }
+}
+
+/// The tensors left by the contraction of a network, in the order they are
+/// applied, with the maximum rank of the accelerator.
+#[derive(Debug, Clone)]
+pub struct ContractionForest {
+    /// The contracted tensors, in the order they are applied.
+    pub trees: Vec<TensorKind>,
+    /// The maximum rank of the accelerator, `None` if unbounded.
+    pub max_rank: Option<u8>,
+}
+
+impl ContractionForest {
+    /// Create a new forest from the contracted tensors of a network, in the
+    /// order returned by `TensorNetwork::contract_with`.
+    pub fn new(trees: Vec<TensorKind>, max_rank: Option<u8>) -> Self {
+        Self { trees, max_rank }
+    }
+
+    /// Return where a tensor of the forest is computed.
+    pub fn placement(&self, tensor: &TensorKind) -> Placement {
+        match tensor {
+            TensorKind::Contraction(c)
+                if self
+                    .max_rank
+                    .is_none_or(|max_rank| c.span.filled().span_len() <= max_rank as usize) =>
+            {
+                Placement::Accelerator
+            }
+            _ => Placement::Host,
+        }
+    }
+
+    /// Return the pieces of the forest with their placement, in the order
+    /// they are applied.
+    pub fn pieces(&self) -> impl Iterator<Item = (Placement, &TensorKind)> {
+        self.trees.iter().map(|t| (self.placement(t), t))
+    }
+
+    /// Return the number of pieces computed on the accelerator.
+    pub fn accelerator_pieces(&self) -> usize {
+        self.pieces()
+            .filter(|(p, _)| *p == Placement::Accelerator)
+            .count()
+    }
+
+    /// Return the number of pieces left on the host.
+    pub fn host_pieces(&self) -> usize {
+        self.trees.len() - self.accelerator_pieces()
+    }
+
+    /// Combine the blocks of the pieces on the host, given in the order they
+    /// are applied. Return `None` if there are no blocks.
+    pub fn combine<T: Scalar>(
+        blocks: impl IntoIterator<Item = SpannedBlock<T>>,
+    ) -> Option<SpannedBlock<T>> {
+        blocks.into_iter().reduce(|acc, block| {
+            let span = acc.merged_span(&block);
+            acc.adapt_to_span(span.clone()) * block.adapt_to_span(span)
+        })
+    }
+}
+
+impl std::fmt::Display for ContractionForest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max_rank {
+            Some(max_rank) => writeln!(
+                f,
+                "Forest of {} pieces (max rank {}):",
+                self.trees.len(),
+                max_rank
+            )?,
+            None => writeln!(f, "Forest of {} pieces (unbounded):", self.trees.len())?,
+        }
+        for (i, (placement, tensor)) in self.pieces().enumerate() {
+            let rank = match tensor {
+                TensorKind::Contraction(c) => c.rank as usize,
+                TensorKind::Gate(_) | TensorKind::State(_) => tensor.span().span_len(),
+            };
+            writeln!(
+                f,
+                "  #{} {} rank {} on {}",
+                i,
+                tensor.span(),
+                rank,
+                placement
+            )?;
+        }
+        write!(
+            f,
+            "{} on the accelerator, {} on the host, combined on the host",
+            self.accelerator_pieces(),
+            self.host_pieces()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::{strategy::Strategy, TensorNetwork},
+        equivalence::EquivalenceChecker,
+        executor::CpuExecutor,
+        library,
+        model::gates::QuantumGate,
+        scheduler::ContractionPlan,
+    };
+
+    fn max_rank(tensor: &TensorKind) -> usize {
+        match tensor {
+            TensorKind::Contraction(c) => c
+                .span
+                .filled()
+                .span_len()
+                .max(max_rank(&c.lhs))
+                .max(max_rank(&c.rhs)),
+            TensorKind::Gate(_) | TensorKind::State(_) => 0,
+        }
+    }
+
+    fn evaluate(forest: &ContractionForest) -> SpannedBlock {
+        let blocks = forest
+            .trees
+            .iter()
+            .cloned()
+            .flat_map(|tensor| match tensor {
+                TensorKind::Contraction(c) => {
+                    CpuExecutor::new().execute(ContractionPlan::try_from(*c).unwrap())
+                }
+                TensorKind::Gate(g) => vec![g.spanned_block()],
+                TensorKind::State(_) => unreachable!(),
+            });
+        ContractionForest::combine(blocks).unwrap()
+    }
+
+    #[test]
+    fn contractions_within_max_rank() {
+        let circuit = library::qft(5);
+        for name in Strategy::NAMES {
+            let strategy = name.parse::<Strategy>().unwrap();
+            for cap in 2..=5 {
+                let network = TensorNetwork::from(circuit.clone()).max_rank(cap);
+                let forest = ContractionForest::new(network.contract_with(&strategy), Some(cap));
+                assert!(forest.trees.iter().all(|t| max_rank(t) <= cap as usize));
+                assert!(forest
+                    .pieces()
+                    .filter(|(p, _)| *p == Placement::Host)
+                    .all(|(_, t)| matches!(t, TensorKind::Gate(_))));
+
+                let block = evaluate(&forest).into_block();
+                let report = EquivalenceChecker::new().check_block(&circuit, &block);
+                assert!(report.equivalent, "{} with max rank {}", name, cap);
+            }
+        }
+    }
+
+    #[test]
+    fn wide_gates_stay_on_host() {
+        // the multi-controlled gates of the oracle are wider than the cap
+        let circuit = library::grover(4, &library::phase_oracle(4, 5), 1);
+        let network = TensorNetwork::from(circuit.clone()).max_rank(2);
+        let forest = ContractionForest::new(network.contract(), Some(2));
+        assert!(forest.accelerator_pieces() > 0);
+        assert!(forest.host_pieces() > 0);
+        assert!(forest
+            .pieces()
+            .filter(|(p, _)| *p == Placement::Host)
+            .all(|(_, t)| matches!(t, TensorKind::Gate(_))));
+
+        let block = evaluate(&forest).into_block();
+        assert!(
+            EquivalenceChecker::new()
+                .check_block(&circuit, &block)
+                .equivalent
+        );
+    }
+}
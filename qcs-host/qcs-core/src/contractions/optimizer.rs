@@ -0,0 +1,374 @@
+//! This module contains the optimisation of contraction trees with simulated
+//! annealing.
+//!
+//! The optimisation starts from the tree built by a `ContractionStrategy` (by
+//! default the greedy one used by `TensorNetwork::contract`) and repeatedly
+//! mutates it. A mutation either rotates a subtree, turning (A ~ (B ~ C)) into
+//! ((A ~ B) ~ C), or re-pairs the children of two sibling subtrees, turning
+//! ((A ~ B) ~ (C ~ D)) into ((A ~ C) ~ (B ~ D)).
+//!
+//! A mutated tree is valid only if every contraction in it can be performed on
+//! the tensor network, that is if the two tensors are linked by a contractable
+//! edge when they are merged. Invalid mutations are rejected, valid ones are
+//! accepted if they lower the cost or, with a probability that decreases with
+//! the temperature, if they raise it.
+
+use std::time::{Duration, Instant};
+
+use hashbrown::HashMap;
+use petgraph::stable_graph::NodeIndex;
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::cost::dense_flops;
+
+use super::{
+    strategy::{ContractionStrategy, Strategy},
+    TensorKind, TensorNetwork,
+};
+
+/// The temperature reached at the end of the iteration budget, relative to the
+/// initial temperature.
+const FINAL_TEMPERATURE_RATIO: f64 = 1e-3;
+
+/// A contraction tree over the nodes of the initial tensor network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tree {
+    Leaf(NodeIndex),
+    Node(Box<Tree>, Box<Tree>),
+}
+
+/// The result of an optimisation.
+#[derive(Debug, Clone)]
+pub struct AnnealingResult {
+    /// The contracted tensors, in the same form returned by
+    /// `TensorNetwork::contract`.
+    pub tensors: Vec<TensorKind>,
+    /// The cost of the starting trees.
+    pub initial_cost: f64,
+    /// The cost of the optimised trees.
+    pub cost: f64,
+    /// The number of iterations performed.
+    pub iterations: usize,
+    /// The number of mutations that have been accepted.
+    pub accepted: usize,
+}
+
+/// Optimiser of contraction trees based on simulated annealing.
+#[derive(Debug, Clone)]
+pub struct AnnealingOptimizer {
+    strategy: Strategy,
+    iterations: usize,
+    time_limit: Option<Duration>,
+    initial_temperature: f64,
+    seed: u64,
+    cost: fn(&TensorKind) -> f64,
+}
+
+impl Default for AnnealingOptimizer {
+    fn default() -> Self {
+        Self {
+            strategy: Strategy::default(),
+            iterations: 1000,
+            time_limit: None,
+            initial_temperature: 1.0,
+            seed: 0,
+            cost: dense_flops,
+        }
+    }
+}
+
+impl AnnealingOptimizer {
+    /// Creates a new optimiser with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the strategy used to build the starting trees.
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Set the maximum number of iterations.
+    pub fn iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Set the maximum time spent in the optimisation.
+    pub fn time_limit(mut self, time_limit: Duration) -> Self {
+        self.time_limit = Some(time_limit);
+        self
+    }
+
+    /// Set the initial temperature. The temperature is compared with the
+    /// difference of the natural logarithm of the costs.
+    pub fn initial_temperature(mut self, temperature: f64) -> Self {
+        self.initial_temperature = temperature;
+        self
+    }
+
+    /// Set the seed of the random mutations.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Set the cost function of a contracted tensor, the cost of the whole
+    /// network is the sum of the costs of its contracted tensors. The default
+    /// is `crate::cost::dense_flops`, the model also provides `sparse_flops`.
+    pub fn cost(mut self, cost: fn(&TensorKind) -> f64) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    /// Optimise the contraction trees of the network.
+    pub fn optimize(&self, network: TensorNetwork) -> AnnealingResult {
+        let start = Instant::now();
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        let cooling = FINAL_TEMPERATURE_RATIO.powf(1.0 / self.iterations.max(1) as f64);
+
+        let mut current = initial_trees(&network, &self.strategy);
+        let mut current_tensors = replay(&network, &current).expect("Invalid initial trees");
+        let mut current_cost = self.network_cost(&current_tensors);
+        let initial_cost = current_cost;
+        let (mut best_tensors, mut best_cost) = (current_tensors.clone(), current_cost);
+
+        let mut temperature = self.initial_temperature;
+        let mut iterations = 0;
+        let mut accepted = 0;
+        while iterations < self.iterations
+            && self.time_limit.is_none_or(|limit| start.elapsed() < limit)
+        {
+            iterations += 1;
+            temperature *= cooling;
+
+            let Some(candidate) = mutate(&current, &mut rng) else {
+                continue;
+            };
+            let Some(tensors) = replay(&network, &candidate) else {
+                continue;
+            };
+            let cost = self.network_cost(&tensors);
+            let delta = cost.ln() - current_cost.ln();
+            if delta <= 0.0 || rng.gen::<f64>() < (-delta / temperature).exp() {
+                accepted += 1;
+                current = candidate;
+                current_tensors = tensors;
+                current_cost = cost;
+                if current_cost < best_cost {
+                    best_tensors = current_tensors.clone();
+                    best_cost = current_cost;
+                }
+            }
+        }
+
+        AnnealingResult {
+            tensors: best_tensors,
+            initial_cost,
+            cost: best_cost,
+            iterations,
+            accepted,
+        }
+    }
+
+    fn network_cost(&self, tensors: &[TensorKind]) -> f64 {
+        tensors.iter().map(self.cost).sum::<f64>().max(1.0)
+    }
+}
+
+/// Build the trees of the contraction decided by the strategy, tracking the
+/// nodes of the initial network merged by each contraction.
+fn initial_trees(network: &TensorNetwork, strategy: &impl ContractionStrategy) -> Vec<Tree> {
+    let mut network = network.clone();
+    let mut trees = network
+        .graph
+        .node_indices()
+        .map(|n| (n, Tree::Leaf(n)))
+        .collect::<HashMap<_, _>>();
+    loop {
+        let to_contract = strategy.select(&network);
+        if to_contract.is_empty() {
+            break;
+        }
+        for edge in to_contract {
+            let (source, target) = network.graph.edge_endpoints(edge).unwrap();
+            let lhs = trees.remove(&source).unwrap();
+            let rhs = trees.remove(&target).unwrap();
+            let new_node = network.contract_edge_unchecked(edge);
+            trees.insert(new_node, Tree::Node(Box::new(lhs), Box::new(rhs)));
+        }
+    }
+    network
+        .graph
+        .node_indices()
+        .map(|n| trees.remove(&n).unwrap())
+        .collect()
+}
+
+/// Perform the contractions of the trees on the network, returning the
+/// contracted tensors or `None` if some contraction is not possible.
+fn replay(network: &TensorNetwork, trees: &[Tree]) -> Option<Vec<TensorKind>> {
+    fn replay_tree(network: &mut TensorNetwork, tree: &Tree) -> Option<NodeIndex> {
+        match tree {
+            Tree::Leaf(n) => Some(*n),
+            Tree::Node(lhs, rhs) => {
+                let lhs = replay_tree(network, lhs)?;
+                let rhs = replay_tree(network, rhs)?;
+                network.contract_pair(lhs, rhs)
+            }
+        }
+    }
+
+    let mut network = network.clone();
+    for tree in trees {
+        replay_tree(&mut network, tree)?;
+    }
+    Some(network.into_tensors())
+}
+
+/// Apply a random mutation to a random internal node of the trees, returning
+/// `None` if the mutation can not be applied to the chosen node.
+fn mutate(trees: &[Tree], rng: &mut ChaCha8Rng) -> Option<Vec<Tree>> {
+    let mut paths = Vec::new();
+    for (i, tree) in trees.iter().enumerate() {
+        internal_paths(tree, &mut vec![], &mut |path| paths.push((i, path)));
+    }
+    let (i, path) = paths.choose(rng)?.clone();
+
+    let mut trees = trees.to_vec();
+    let node = path.iter().fold(&mut trees[i], |node, &right| match node {
+        Tree::Node(lhs, rhs) => {
+            if right {
+                rhs
+            } else {
+                lhs
+            }
+        }
+        Tree::Leaf(_) => unreachable!(),
+    });
+    let Tree::Node(lhs, rhs) = node.clone() else {
+        unreachable!()
+    };
+
+    let mutated = match (rng.gen_range(0..3), *lhs, *rhs) {
+        // (A ~ (B ~ C)) -> ((A ~ B) ~ C) or ((A ~ C) ~ B)
+        (0, a, Tree::Node(b, c)) => {
+            let (b, c) = if rng.gen() { (b, c) } else { (c, b) };
+            Tree::Node(Box::new(Tree::Node(Box::new(a), b)), c)
+        }
+        // ((A ~ B) ~ C) -> (A ~ (B ~ C)) or (B ~ (A ~ C))
+        (1, Tree::Node(a, b), c) => {
+            let (a, b) = if rng.gen() { (a, b) } else { (b, a) };
+            Tree::Node(a, Box::new(Tree::Node(b, Box::new(c))))
+        }
+        // ((A ~ B) ~ (C ~ D)) -> ((A ~ C) ~ (B ~ D)) or ((A ~ D) ~ (B ~ C))
+        (2, Tree::Node(a, b), Tree::Node(c, d)) => {
+            let (c, d) = if rng.gen() { (c, d) } else { (d, c) };
+            Tree::Node(Box::new(Tree::Node(a, c)), Box::new(Tree::Node(b, d)))
+        }
+        _ => return None,
+    };
+    *node = mutated;
+    Some(trees)
+}
+
+/// Call the callback with the path of every internal node of the tree, where
+/// each step of the path is true when going to the right child.
+fn internal_paths(tree: &Tree, path: &mut Vec<bool>, callback: &mut impl FnMut(Vec<bool>)) {
+    if let Tree::Node(lhs, rhs) = tree {
+        callback(path.clone());
+        path.push(false);
+        internal_paths(lhs, path, callback);
+        path.pop();
+        path.push(true);
+        internal_paths(rhs, path, callback);
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::fixtures::expect_contraction, equivalence::EquivalenceChecker,
+        executor::CpuExecutor, model::QuantumCircuit, scheduler::ContractionPlan,
+    };
+
+    fn n_gates(tensor: &TensorKind) -> usize {
+        match tensor {
+            TensorKind::Contraction(c) => n_gates(&c.lhs) + n_gates(&c.rhs),
+            TensorKind::Gate(_) => 1,
+            TensorKind::State(_) => 0,
+        }
+    }
+
+    /// A random circuit of CNOT gates on far lanes and Hadamard gates, on
+    /// which the greedy tree is not optimal.
+    fn circuit() -> QuantumCircuit {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let mut circuit = QuantumCircuit::new(6);
+        for _ in 0..40 {
+            let control = rng.gen_range(0..6);
+            let target = (control + rng.gen_range(1..6)) % 6;
+            if rng.gen() {
+                circuit.g_cx(control, target);
+            } else {
+                circuit.g_h(control);
+            }
+        }
+        circuit
+    }
+
+    #[test]
+    fn greedy_trees_replay() {
+        let network = TensorNetwork::from(circuit());
+        let trees = initial_trees(&network, &Strategy::default());
+        let replayed = replay(&network, &trees).unwrap();
+        let contracted = network.contract();
+        assert_eq!(replayed.len(), contracted.len());
+        for (a, b) in replayed.iter().zip(contracted.iter()) {
+            assert_eq!(a.to_string(), b.to_string());
+        }
+    }
+
+    #[test]
+    fn annealing_improves_and_is_reproducible() {
+        let circuit = circuit();
+        let network = TensorNetwork::from(circuit.clone());
+        let optimizer = AnnealingOptimizer::new().iterations(1000).seed(42);
+        let result = optimizer.optimize(network.clone());
+
+        assert!(result.cost < result.initial_cost);
+        assert_eq!(result.iterations, 1000);
+        assert_eq!(result.tensors.len(), 1);
+        assert_eq!(n_gates(&result.tensors[0]), circuit.gates.len());
+
+        let again = optimizer.optimize(network);
+        assert_eq!(again.cost, result.cost);
+        assert_eq!(again.tensors[0].to_string(), result.tensors[0].to_string());
+    }
+
+    #[test]
+    fn annealed_tree_is_equivalent() {
+        let circuit = circuit();
+        let result = AnnealingOptimizer::new()
+            .iterations(1000)
+            .seed(42)
+            .optimize(TensorNetwork::from(circuit.clone()));
+        assert!(result.cost < result.initial_cost);
+        let contraction = expect_contraction(result.tensors[0].clone());
+        let block = CpuExecutor::new()
+            .execute(ContractionPlan::try_from(contraction).unwrap())
+            .into_iter()
+            .next()
+            .unwrap()
+            .into_block();
+        assert!(
+            EquivalenceChecker::new()
+                .check_block(&circuit, &block)
+                .equivalent
+        );
+    }
+}
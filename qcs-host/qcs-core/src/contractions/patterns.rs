@@ -0,0 +1,423 @@
+//! This module contains the mining of the patterns of contracted trees, to
+//! choose the fixed-size kernels of the accelerator.
+//!
+//! The trees of a whole benchmark corpus are reduced to their `TreeShape`,
+//! that only keeps the kind and the lanes of every node, and accumulated in a
+//! `PatternStats`. The statistics count:
+//!
+//! - the ranks of the contractions, with the estimated FLOPs of their dense
+//!   products;
+//! - the kinds of the operand pairs (gate×gate, gate×contraction, ...) and
+//!   the ranks of the operands of each contraction;
+//! - the tensor expansions padding an operand with identities up to the span
+//!   of its contraction;
+//! - the recurring subtrees, up to a number of leaves, with their lanes taken
+//!   relative to the first lane of the subtree so that the same pattern on
+//!   different lanes is counted once.
+//!
+//! The rank of a contraction is the rank of its dense block, that is the
+//! number of lanes of its filled span, since this is the size of the kernel
+//! that executes it.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{cost::mm_flops, model::span::Span};
+
+use super::{tree::NodeKind, TensorKind};
+
+/// Default maximum number of leaves of the recurring subtrees.
+const DEFAULT_MAX_LEAVES: usize = 4;
+
+/// The shape of a contracted tree, the kind and the lanes of its nodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeShape {
+    /// A gate or a state tensor.
+    Leaf { kind: NodeKind, span: Span },
+    /// The contraction of two subtrees, over the union of their spans.
+    Node {
+        span: Span,
+        lhs: Box<TreeShape>,
+        rhs: Box<TreeShape>,
+    },
+}
+
+impl TreeShape {
+    /// Create the shape of a leaf.
+    pub fn leaf(kind: NodeKind, span: Span) -> Self {
+        Self::Leaf { kind, span }
+    }
+
+    /// Create the shape of the contraction of two subtrees.
+    pub fn node(lhs: TreeShape, rhs: TreeShape) -> Self {
+        Self::Node {
+            span: lhs.span().union(rhs.span()),
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        }
+    }
+
+    /// Get the kind of the root of the tree.
+    pub fn kind(&self) -> NodeKind {
+        match self {
+            Self::Leaf { kind, .. } => *kind,
+            Self::Node { .. } => NodeKind::Contraction,
+        }
+    }
+
+    /// Get the span of the root of the tree.
+    pub fn span(&self) -> &Span {
+        match self {
+            Self::Leaf { span, .. } | Self::Node { span, .. } => span,
+        }
+    }
+
+    /// Get the rank of the dense block of the root, the number of lanes of
+    /// its filled span.
+    pub fn rank(&self) -> usize {
+        self.span().filled().span_len()
+    }
+
+    /// Get the number of leaves of the tree.
+    pub fn leaves(&self) -> usize {
+        match self {
+            Self::Leaf { .. } => 1,
+            Self::Node { lhs, rhs, .. } => lhs.leaves() + rhs.leaves(),
+        }
+    }
+
+    /// Return the canonical form of the tree, with the lanes shifted so that
+    /// the tree starts at lane 0. Leaves are written as `g[0,1]` (or `s[0]`
+    /// for states) and contractions as `(lhs rhs)`.
+    pub fn pattern(&self) -> String {
+        self.pattern_from(self.span().start())
+    }
+
+    fn pattern_from(&self, offset: usize) -> String {
+        match self {
+            Self::Leaf { kind, span } => {
+                let prefix = match kind {
+                    NodeKind::State => 's',
+                    _ => 'g',
+                };
+                let lanes = span.clone().into_iter().map(|l| l - offset);
+                format!("{}{}", prefix, Span::new(lanes.collect::<Vec<_>>()))
+            }
+            Self::Node { lhs, rhs, .. } => format!(
+                "({} {})",
+                lhs.pattern_from(offset),
+                rhs.pattern_from(offset)
+            ),
+        }
+    }
+}
+
+impl From<&TensorKind> for TreeShape {
+    fn from(tensor: &TensorKind) -> Self {
+        match tensor {
+            TensorKind::Contraction(c) => Self::node((&c.lhs).into(), (&c.rhs).into()),
+            TensorKind::Gate(_) => Self::leaf(NodeKind::Gate, tensor.span()),
+            TensorKind::State(_) => Self::leaf(NodeKind::State, tensor.span()),
+        }
+    }
+}
+
+/// The contractions of a rank.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RankStats {
+    /// The number of contractions.
+    pub count: usize,
+    /// The estimated FLOPs of the dense products of the contractions.
+    pub flops: f64,
+}
+
+/// The statistics of the patterns of a set of contracted trees.
+#[derive(Debug, Clone)]
+pub struct PatternStats {
+    /// The number of trees added.
+    pub trees: usize,
+    /// The number of leaves of the trees.
+    pub leaves: usize,
+    /// The contractions of each rank.
+    pub ranks: BTreeMap<usize, RankStats>,
+    /// The number of contractions of each pair of operand kinds, left first.
+    pub operands: BTreeMap<(NodeKind, NodeKind), usize>,
+    /// The number of contractions of each shape, indexed by the ranks of the
+    /// left operand, of the right operand and of the result.
+    pub shapes: BTreeMap<(usize, usize, usize), usize>,
+    /// The number of tensor expansions padding an operand to the span of its
+    /// contraction, indexed by the rank before and after the padding.
+    pub padding: BTreeMap<(usize, usize), usize>,
+    /// The number of occurrences of each subtree pattern.
+    pub subtrees: HashMap<String, usize>,
+    /// The maximum number of leaves of the subtree patterns.
+    max_leaves: usize,
+}
+
+impl Default for PatternStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PatternStats {
+    /// Create empty statistics.
+    pub fn new() -> Self {
+        Self {
+            trees: 0,
+            leaves: 0,
+            ranks: BTreeMap::new(),
+            operands: BTreeMap::new(),
+            shapes: BTreeMap::new(),
+            padding: BTreeMap::new(),
+            subtrees: HashMap::new(),
+            max_leaves: DEFAULT_MAX_LEAVES,
+        }
+    }
+
+    /// Set the maximum number of leaves of the recurring subtrees.
+    pub fn max_leaves(mut self, max_leaves: usize) -> Self {
+        self.max_leaves = max_leaves;
+        self
+    }
+
+    /// Add a contracted tree to the statistics.
+    pub fn add(&mut self, tree: &TreeShape) {
+        self.trees += 1;
+        self.visit(tree);
+    }
+
+    /// Add the contracted tensors of a network to the statistics.
+    pub fn add_tensors(&mut self, tensors: &[TensorKind]) {
+        for tensor in tensors {
+            self.add(&tensor.into());
+        }
+    }
+
+    /// Accumulate the nodes of a subtree, returning its number of leaves.
+    fn visit(&mut self, tree: &TreeShape) -> usize {
+        let TreeShape::Node { lhs, rhs, .. } = tree else {
+            self.leaves += 1;
+            return 1;
+        };
+        let leaves = self.visit(lhs) + self.visit(rhs);
+
+        let rank = tree.rank();
+        let dim = 1 << rank;
+        let stats = self.ranks.entry(rank).or_default();
+        stats.count += 1;
+        stats.flops += mm_flops(dim, dim, dim);
+
+        *self.operands.entry((lhs.kind(), rhs.kind())).or_default() += 1;
+        *self
+            .shapes
+            .entry((lhs.rank(), rhs.rank(), rank))
+            .or_default() += 1;
+        for operand in [lhs, rhs] {
+            if operand.rank() != rank {
+                *self.padding.entry((operand.rank(), rank)).or_default() += 1;
+            }
+        }
+        if leaves <= self.max_leaves {
+            *self.subtrees.entry(tree.pattern()).or_default() += 1;
+        }
+        leaves
+    }
+
+    /// Get the total number of contractions.
+    pub fn contractions(&self) -> usize {
+        self.ranks.values().map(|s| s.count).sum()
+    }
+
+    /// Get the total estimated FLOPs of the contractions.
+    pub fn flops(&self) -> f64 {
+        self.ranks.values().map(|s| s.flops).sum()
+    }
+
+    /// Return the subtree patterns that occur more than once, the most
+    /// frequent first, at most `n` of them.
+    pub fn recurring(&self, n: usize) -> Vec<(&str, usize)> {
+        let mut patterns = self
+            .subtrees
+            .iter()
+            .filter(|(_, &count)| count > 1)
+            .map(|(pattern, &count)| (pattern.as_str(), count))
+            .collect::<Vec<_>>();
+        patterns.sort_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then(a.0.len().cmp(&b.0.len()))
+                .then(a.0.cmp(b.0))
+        });
+        patterns.truncate(n);
+        patterns
+    }
+
+    /// Return the smallest kernel rank that executes at least a fraction of
+    /// the contractions, padding the ones of lower rank.
+    pub fn kernel_rank(&self, fraction: f64) -> Option<usize> {
+        let total = self.contractions() as f64;
+        let mut covered = 0;
+        for (&rank, stats) in &self.ranks {
+            covered += stats.count;
+            if covered as f64 >= fraction * total {
+                return Some(rank);
+            }
+        }
+        None
+    }
+
+    /// Return the rank whose contractions take the most estimated FLOPs.
+    pub fn heaviest_rank(&self) -> Option<usize> {
+        self.ranks
+            .iter()
+            .max_by(|a, b| a.1.flops.total_cmp(&b.1.flops))
+            .map(|(&rank, _)| rank)
+    }
+}
+
+/// Return the percentage of a part over a total, zero for an empty total.
+fn percent(part: f64, total: f64) -> f64 {
+    if total > 0.0 {
+        100.0 * part / total
+    } else {
+        0.0
+    }
+}
+
+impl std::fmt::Display for PatternStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let contractions = self.contractions();
+        let total = contractions as f64;
+        let flops = self.flops();
+        writeln!(f, "Trees: {}", self.trees)?;
+        writeln!(f, "Leaves: {}", self.leaves)?;
+        writeln!(f, "Contractions: {}", contractions)?;
+        writeln!(f, "Contractions by rank:")?;
+        let mut covered = 0;
+        for (rank, stats) in &self.ranks {
+            covered += stats.count;
+            writeln!(
+                f,
+                "  R{}: {} ({:.1}%, cumulative {:.1}%), {:.3e} FLOPs ({:.1}%)",
+                rank,
+                stats.count,
+                percent(stats.count as f64, total),
+                percent(covered as f64, total),
+                stats.flops,
+                percent(stats.flops, flops)
+            )?;
+        }
+        writeln!(f, "Operand pairs:")?;
+        for ((lhs, rhs), count) in &self.operands {
+            writeln!(
+                f,
+                "  {} x {}: {} ({:.1}%)",
+                lhs,
+                rhs,
+                count,
+                percent(*count as f64, total)
+            )?;
+        }
+        writeln!(f, "Operand shapes:")?;
+        for ((lhs, rhs, rank), count) in &self.shapes {
+            writeln!(f, "  R{} x R{} -> R{}: {}", lhs, rhs, rank, count)?;
+        }
+        writeln!(f, "TE padding:")?;
+        for ((from, to), count) in &self.padding {
+            writeln!(f, "  R{} -> R{} (+{}): {}", from, to, to - from, count)?;
+        }
+        writeln!(f, "Recurring subtrees (up to {} leaves):", self.max_leaves)?;
+        for (pattern, count) in self.recurring(10) {
+            writeln!(f, "  {}: {}", pattern, count)?;
+        }
+        writeln!(f, "Kernels:")?;
+        for fraction in [0.9, 0.99] {
+            if let Some(rank) = self.kernel_rank(fraction) {
+                writeln!(
+                    f,
+                    "  {:.0}% of the contractions fit a R{} kernel",
+                    100.0 * fraction,
+                    rank
+                )?;
+            }
+        }
+        if let Some(rank) = self.heaviest_rank() {
+            writeln!(
+                f,
+                "  R{} contractions take the most FLOPs ({:.1}%)",
+                rank,
+                percent(self.ranks[&rank].flops, flops)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{contractions::TensorNetwork, library};
+
+    fn gate(lanes: &[usize]) -> TreeShape {
+        TreeShape::leaf(NodeKind::Gate, Span::new(lanes))
+    }
+
+    #[test]
+    fn pattern_is_relative_to_the_first_lane() {
+        let low = TreeShape::node(gate(&[0]), gate(&[0, 1]));
+        let high = TreeShape::node(gate(&[3]), gate(&[3, 4]));
+        assert_eq!(low.pattern(), "(g[0] g[0,1])");
+        assert_eq!(low.pattern(), high.pattern());
+        assert_eq!(high.rank(), 2);
+        assert_eq!(high.leaves(), 2);
+    }
+
+    #[test]
+    fn counts_operands_padding_and_subtrees() {
+        // ((H0 x CX01) x (H3 x CX34)) x CX14, the right operand is a gate
+        // with sparse lanes whose filled span covers lanes 1 to 4
+        let left = TreeShape::node(
+            TreeShape::node(gate(&[0]), gate(&[0, 1])),
+            TreeShape::node(gate(&[3]), gate(&[3, 4])),
+        );
+        let tree = TreeShape::node(left, gate(&[1, 4]));
+
+        let mut stats = PatternStats::new().max_leaves(2);
+        stats.add(&tree);
+
+        assert_eq!(stats.trees, 1);
+        assert_eq!(stats.leaves, 5);
+        assert_eq!(stats.contractions(), 4);
+        assert_eq!(stats.ranks[&2].count, 2);
+        assert_eq!(stats.ranks[&5].count, 2);
+        assert_eq!(stats.operands[&(NodeKind::Gate, NodeKind::Gate)], 2);
+        assert_eq!(
+            stats.operands[&(NodeKind::Contraction, NodeKind::Contraction)],
+            1
+        );
+        assert_eq!(stats.operands[&(NodeKind::Contraction, NodeKind::Gate)], 1);
+        assert_eq!(stats.shapes[&(1, 2, 2)], 2);
+        // H padded twice, the two pairs and the sparse gate up to five lanes
+        assert_eq!(stats.padding[&(1, 2)], 2);
+        assert_eq!(stats.padding[&(2, 5)], 2);
+        assert_eq!(stats.padding[&(4, 5)], 1);
+        assert_eq!(stats.recurring(10), vec![("(g[0] g[0,1])", 2)]);
+        assert_eq!(stats.kernel_rank(0.5), Some(2));
+        assert_eq!(stats.kernel_rank(0.9), Some(5));
+        assert_eq!(stats.heaviest_rank(), Some(5));
+    }
+
+    #[test]
+    fn contracted_network_counts_every_contraction() {
+        let circuit = library::qft(4);
+        let n_gates = circuit.gates.len();
+        let tensors = TensorNetwork::from(circuit).contract();
+
+        let mut stats = PatternStats::new();
+        stats.add_tensors(&tensors);
+
+        assert_eq!(stats.leaves, n_gates);
+        assert_eq!(stats.contractions(), n_gates - stats.trees);
+        assert_eq!(stats.operands.values().sum::<usize>(), stats.contractions());
+        assert!(stats.to_string().contains("Kernels:"));
+    }
+}
@@ -0,0 +1,402 @@
+//! This module contains the slicing of closed networks, used when the
+//! intermediate tensors of a contraction do not fit in memory.
+//!
+//! A bond is a lane between two consecutive gates. Since the identity on a
+//! lane is the sum of |b⟩⟨b| over b = 0, 1, a bond is sliced by fixing its
+//! value: an input state |b⟩ closes the earlier gate and an output projector
+//! ⟨b| closes the later one (see `closed`). The network is contracted once,
+//! then each assignment of the sliced bonds closes the leaves of the same
+//! trees, giving smaller intermediate tensors, and the results of all the
+//! slices are summed.
+//!
+//! The `SlicingPlanner` picks the bonds greedily: it slices the bond that
+//! lowers the peak memory of the contraction the most, until the peak fits in
+//! the memory limit. Since a single bond often leaves the peak unchanged, ties
+//! are broken by the total size of the intermediate tensors.
+//!
+//! The slices are evaluated in parallel, as many at the same time as fit in
+//! the memory limit of the planner, either by the reference evaluation of
+//! `closed::evaluate` or by the plans of `closed::execute`.
+//!
+//! Slicing only shrinks the tensors that hold one side of a bond: when the
+//! largest tensor of a contraction covers whole lanes, as the unitary of a
+//! circuit with long range gates does, no bond lowers the peak.
+
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+
+use crate::{
+    cost::DENSE_ELEMENT_BYTES,
+    model::{gates::QuantumGate, tensor::IndexTensor, QuantumCircuit, Qubit},
+};
+
+use super::{
+    closed::{evaluate, execute, footprint, Footprint, StateTensor},
+    strategy::Strategy,
+    TensorContraction, TensorKind, TensorNetwork,
+};
+
+/// Errors raised when slicing a network.
+#[derive(Debug, Error, PartialEq)]
+pub enum SlicingError {
+    #[error("The memory limit of {limit} bytes cannot be reached (best peak {peak} bytes)")]
+    LimitUnreachable { limit: usize, peak: usize },
+}
+
+/// The bond of a lane after its `position`-th gate, counting the gates whose
+/// filled span covers the lane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Bond {
+    pub lane: usize,
+    pub position: usize,
+}
+
+impl Bond {
+    /// Return all the bonds of a circuit, in order of lane and position.
+    pub fn all(circuit: &QuantumCircuit) -> Vec<Self> {
+        let mut gates = vec![0; circuit.n_qubits];
+        for gate in &circuit.gates {
+            let span = gate.span();
+            for count in &mut gates[span.start()..=span.end()] {
+                *count += 1;
+            }
+        }
+        gates
+            .into_iter()
+            .enumerate()
+            .flat_map(|(lane, n)| (0..n.max(1) - 1).map(move |position| Self { lane, position }))
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Bond {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.lane, self.position)
+    }
+}
+
+/// The contracted trees of a closed network with some sliced bonds.
+#[derive(Debug, Clone)]
+pub struct SlicedNetwork {
+    /// The trees left by the contraction of the network, in the order they
+    /// are applied.
+    pub trees: Vec<TensorKind>,
+    /// The sliced bonds, the first one is the most significant bit of the
+    /// index of a slice.
+    pub bonds: Vec<Bond>,
+    /// The memory in bytes taken by all the slices evaluated at the same time.
+    memory_limit: Option<usize>,
+}
+
+impl SlicedNetwork {
+    /// Create a sliced network from the trees left by the contraction of a
+    /// closed network.
+    pub fn new(trees: Vec<TensorKind>, bonds: Vec<Bond>) -> Self {
+        Self {
+            trees,
+            bonds,
+            memory_limit: None,
+        }
+    }
+
+    /// Set the memory limit in bytes of the slices evaluated at the same time.
+    pub fn memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = Some(memory_limit);
+        self
+    }
+
+    /// Return the number of slices.
+    pub fn n_slices(&self) -> usize {
+        1 << self.bonds.len()
+    }
+
+    /// Return the trees of a slice, where the bits of the index are the values
+    /// of the bonds.
+    pub fn slice(&self, index: usize) -> Vec<TensorKind> {
+        let n_bonds = self.bonds.len();
+        let values = self
+            .bonds
+            .iter()
+            .enumerate()
+            .map(|(i, bond)| (*bond, (index >> (n_bonds - 1 - i)) & 1 == 1))
+            .collect::<BTreeMap<_, _>>();
+        let mut positions = vec![0; self.n_lanes()];
+        self.trees
+            .iter()
+            .map(|tree| slice_tree(tree, &values, &mut positions))
+            .collect()
+    }
+
+    /// Return the footprint of the evaluation of a slice, the same for all the
+    /// slices.
+    pub fn footprint(&self) -> Footprint {
+        footprint(&self.slice(0))
+    }
+
+    /// Return the peak memory in bytes of the evaluation of a slice.
+    pub fn peak_memory(&self) -> usize {
+        bytes(self.footprint().peak)
+    }
+
+    /// Return the number of slices evaluated at the same time: one per CPU,
+    /// but no more than fit in the memory limit, and at least one.
+    pub fn n_threads(&self) -> usize {
+        let n_threads = num_cpus::get().min(self.n_slices());
+        match self.memory_limit {
+            Some(limit) => n_threads.min(limit / self.peak_memory().max(1)).max(1),
+            None => n_threads,
+        }
+    }
+
+    /// Evaluate all the slices in parallel, summing their results in order of
+    /// index.
+    pub fn execute(&self) -> IndexTensor {
+        self.execute_with(evaluate)
+    }
+
+    /// Evaluate the slices like `execute`, executing the plans of their
+    /// contractions with the `CpuExecutor`.
+    pub fn execute_plans(&self) -> IndexTensor {
+        self.execute_with(execute)
+    }
+
+    /// Evaluate all the slices in parallel with an evaluation of the trees,
+    /// summing their results in order of index.
+    fn execute_with(&self, evaluate: fn(&[TensorKind]) -> IndexTensor) -> IndexTensor {
+        let n_slices = self.n_slices();
+        let n_threads = self.n_threads();
+        let mut results = std::thread::scope(|scope| {
+            let handles = (0..n_threads)
+                .map(|thread| {
+                    scope.spawn(move || {
+                        (thread..n_slices)
+                            .step_by(n_threads)
+                            .map(|i| (i, evaluate(&self.slice(i))))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        results.sort_by_key(|(i, _)| *i);
+        results
+            .into_iter()
+            .map(|(_, tensor)| tensor)
+            .reduce(|acc, tensor| acc + tensor)
+            .unwrap()
+    }
+
+    /// Return the number of lanes covered by the trees.
+    fn n_lanes(&self) -> usize {
+        self.trees
+            .iter()
+            .map(|t| t.span().end() + 1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Close the gates of a tree around the sliced bonds, counting the gates on
+/// each lane in the order of the leaves, that is the order they are applied.
+fn slice_tree(
+    tensor: &TensorKind,
+    values: &BTreeMap<Bond, bool>,
+    positions: &mut [usize],
+) -> TensorKind {
+    match tensor {
+        TensorKind::Contraction(c) => {
+            let lhs = slice_tree(&c.lhs, values, positions);
+            let rhs = slice_tree(&c.rhs, values, positions);
+            TensorContraction::new(lhs, rhs).into()
+        }
+        TensorKind::Gate(g) => {
+            let mut leaf = tensor.clone();
+            let lanes = g.span().start()..=g.span().end();
+            for (lane, count) in lanes.clone().zip(&mut positions[lanes]) {
+                let position = *count;
+                *count += 1;
+                let before = position
+                    .checked_sub(1)
+                    .and_then(|position| values.get(&Bond { lane, position }));
+                if let Some(&bit) = before {
+                    let projector = StateTensor::Output { lane, bit };
+                    leaf = TensorContraction::new(projector.into(), leaf).into();
+                }
+                if let Some(&bit) = values.get(&Bond { lane, position }) {
+                    let qubit = if bit { Qubit::one() } else { Qubit::zero() };
+                    let state = StateTensor::Input { lane, qubit };
+                    leaf = TensorContraction::new(leaf, state.into()).into();
+                }
+            }
+            leaf
+        }
+        TensorKind::State(_) => tensor.clone(),
+    }
+}
+
+/// Picks the bonds to slice so that the contraction of each slice fits in a
+/// memory limit.
+#[derive(Debug, Clone)]
+pub struct SlicingPlanner {
+    memory_limit: usize,
+    strategy: Strategy,
+}
+
+impl SlicingPlanner {
+    /// Create a new planner with a memory limit in bytes.
+    pub fn new(memory_limit: usize) -> Self {
+        Self {
+            memory_limit,
+            strategy: Strategy::default(),
+        }
+    }
+
+    /// Set the strategy used to contract the network.
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Slice the network of a circuit closed by the states, adding the bond
+    /// that lowers the footprint the most until the peak fits in the limit.
+    pub fn plan(
+        &self,
+        circuit: &QuantumCircuit,
+        states: Vec<StateTensor>,
+    ) -> Result<SlicedNetwork, SlicingError> {
+        let trees = TensorNetwork::closed(circuit.clone(), states).contract_with(&self.strategy);
+        let mut network = SlicedNetwork::new(trees, vec![]).memory_limit(self.memory_limit);
+        let mut current = network.footprint();
+        let mut candidates = Bond::all(circuit);
+        while bytes(current.peak) > self.memory_limit {
+            let best = candidates
+                .iter()
+                .enumerate()
+                .map(|(i, bond)| {
+                    let mut sliced = network.clone();
+                    sliced.bonds.push(*bond);
+                    (sliced.footprint(), i)
+                })
+                .min();
+            match best {
+                Some((footprint, i)) if footprint < current => {
+                    network.bonds.push(candidates.remove(i));
+                    current = footprint;
+                }
+                _ => {
+                    return Err(SlicingError::LimitUnreachable {
+                        limit: self.memory_limit,
+                        peak: bytes(current.peak),
+                    })
+                }
+            }
+        }
+        Ok(network)
+    }
+}
+
+/// Return the bytes taken by a number of dense elements.
+fn bytes(elements: usize) -> usize {
+    (elements as f64 * DENSE_ELEMENT_BYTES) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{contractions::closed::tests::inputs, library};
+
+    fn contract(circuit: &QuantumCircuit, states: Vec<StateTensor>) -> Vec<TensorKind> {
+        TensorNetwork::closed(circuit.clone(), states).contract()
+    }
+
+    #[test]
+    fn slices_sum_to_unsliced_result() {
+        let circuit = library::qft(4);
+        let states = StateTensor::inputs(&inputs(4))
+            .into_iter()
+            .chain(StateTensor::outputs(4, 0b1011))
+            .collect::<Vec<_>>();
+        let trees = contract(&circuit, states);
+        let expected = evaluate(&trees).scalar().unwrap();
+        let bonds = Bond::all(&circuit);
+        for sliced in [
+            vec![bonds[0]],
+            vec![bonds[1], bonds[5]],
+            vec![bonds[2], bonds[4], bonds[7]],
+        ] {
+            let network = SlicedNetwork::new(trees.clone(), sliced);
+            let result = network.execute().scalar().unwrap();
+            assert!((result - expected).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn planner_fits_memory_limit() {
+        for (circuit, divisor) in [
+            (library::qft(5), 4),
+            (library::hardware_efficient_ansatz(8, 3, &[0.4; 64]), 16),
+        ] {
+            let n_qubits = circuit.n_qubits;
+            let states = StateTensor::inputs(&inputs(n_qubits))
+                .into_iter()
+                .chain(StateTensor::outputs(n_qubits, 5))
+                .collect::<Vec<_>>();
+            let trees = contract(&circuit, states.clone());
+            let expected = evaluate(&trees).scalar().unwrap();
+            let limit = SlicedNetwork::new(trees, vec![]).peak_memory() / divisor;
+
+            let network = SlicingPlanner::new(limit)
+                .plan(&circuit, states.clone())
+                .unwrap();
+            assert!(!network.bonds.is_empty());
+            assert!(network.peak_memory() <= limit);
+            assert!(network.n_threads() * network.peak_memory() <= limit);
+            let result = network.execute().scalar().unwrap();
+            assert!((result - expected).norm() < 1e-9);
+            let result = network.execute_plans().scalar().unwrap();
+            assert!((result - expected).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn threads_fit_memory_limit() {
+        let circuit = library::qft(4);
+        let trees = contract(&circuit, StateTensor::inputs(&inputs(4)));
+        let network = SlicedNetwork::new(trees, Bond::all(&circuit)[..4].to_vec());
+        let peak = network.peak_memory();
+        assert_eq!(network.n_threads(), num_cpus::get().min(16));
+        assert_eq!(network.clone().memory_limit(peak / 2).n_threads(), 1);
+        assert!(network.clone().memory_limit(2 * peak).n_threads() <= 2);
+        let result = network.clone().memory_limit(peak).execute_plans();
+        let expected = network.execute();
+        assert!((result.as_ref() - expected.as_ref()).norm() < 1e-9);
+    }
+
+    #[test]
+    fn partially_closed_slices() {
+        // only the inputs are closed, the result is the whole output state
+        let circuit = library::grover(4, &library::phase_oracle(4, 6), 1);
+        let states = StateTensor::inputs(&inputs(circuit.n_qubits));
+        let trees = contract(&circuit, states);
+        let expected = evaluate(&trees);
+        let network = SlicedNetwork::new(trees, Bond::all(&circuit)[..3].to_vec());
+        let result = network.execute();
+        assert_eq!(result.outputs(), expected.outputs());
+        assert!((result.as_ref() - expected.as_ref()).norm() < 1e-9);
+    }
+
+    #[test]
+    fn unreachable_limit() {
+        let circuit = library::qft(3);
+        let states = StateTensor::inputs(&inputs(3));
+        let result = SlicingPlanner::new(0).plan(&circuit, states);
+        assert!(matches!(
+            result,
+            Err(SlicingError::LimitUnreachable { limit: 0, .. })
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+//! This module contains the contraction of the input state into the network.
+//!
+//! Instead of building the unitary of the circuit and multiplying it by the
+//! input state at the end, the contracted tensors are applied to the state one
+//! after the other, from the last to the first, with matrix-vector products
+//! (see `Operand::from_state_contraction`). The intermediate results are then
+//! vectors of the size of the state, and each tensor only holds the matrix of
+//! its own span, so idle lanes are kept untouched.
+
+use crate::{executor::CpuExecutor, model::QRegister, op_tree::Operand, scheduler::OperationPlan};
+
+use super::{closed::ClosedTensorError, TensorKind, TensorNetwork};
+
+/// Apply the contracted tensors of a network, in the order they are applied,
+/// to an input state. The tensors of closed networks cannot be applied.
+pub fn apply(tensors: Vec<TensorKind>, input: QRegister) -> Result<QRegister, ClosedTensorError> {
+    match Operand::from_state_contraction(tensors, input)? {
+        Operand::State(state) => Ok(*state),
+        Operand::Operation(operation) => {
+            let plan = OperationPlan::from(*operation);
+            let mut blocks = CpuExecutor::new().execute(plan);
+            assert_eq!(blocks.len(), 1, "The plan must leave only the state");
+            Ok(blocks.pop().unwrap().into_register())
+        }
+        Operand::Gate(_) => unreachable!("The innermost leaf is the input state"),
+    }
+}
+
+impl TensorNetwork {
+    /// Contract the network and apply it to an input state, without building
+    /// the unitary of the circuit.
+    pub fn contract_state(self, input: QRegister) -> Result<QRegister, ClosedTensorError> {
+        apply(self.contract(), input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::strategy::Strategy, library, model::QuantumCircuit,
+        scheduler::operation::Kernel,
+    };
+
+    fn circuits() -> Vec<QuantumCircuit> {
+        // lane 2 is idle
+        let mut idle = QuantumCircuit::new(4);
+        idle.g_h(0);
+        idle.g_cx(0, 3);
+        idle.g_ry(0.3, 1);
+        idle.g_cp(0.4, 3, 1);
+        vec![
+            idle,
+            library::qft(4),
+            library::ripple_carry_adder(1),
+            library::grover(4, &library::phase_oracle(4, 5), 1),
+        ]
+    }
+
+    fn assert_close(a: &QRegister, b: &QRegister) {
+        assert!((&a.qubits - &b.qubits).norm() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn state_contraction_matches_unitary() {
+        for circuit in circuits() {
+            let input = QRegister::haar_random(circuit.n_qubits, 5);
+            let expected = circuit.clone().eval() * input.clone();
+            for name in Strategy::NAMES {
+                let strategy = name.parse::<Strategy>().unwrap();
+                for max_rank in [None, Some(2)] {
+                    let mut network = TensorNetwork::from(circuit.clone());
+                    if let Some(max_rank) = max_rank {
+                        network = network.max_rank(max_rank);
+                    }
+                    let state = apply(network.contract_with(&strategy), input.clone()).unwrap();
+                    assert_close(&state, &expected);
+                    assert!((state.distr() - expected.distr()).norm() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn plan_applies_pieces_in_reverse() {
+        let circuit = library::qft(3);
+        let tensors = TensorNetwork::from(circuit).max_rank(2).contract();
+        let expected = tensors
+            .iter()
+            .rev()
+            .map(|t| t.span().start())
+            .collect::<Vec<_>>();
+        let Operand::Operation(operation) =
+            Operand::from_state_contraction(tensors, QRegister::basis(3, 0)).unwrap()
+        else {
+            panic!("Expected a chain of products");
+        };
+        let plan = OperationPlan::from(*operation);
+        let offsets = plan
+            .instructions()
+            .filter_map(|i| match &i.kernel {
+                Kernel::MV { offset, .. } => Some(*offset),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, expected);
+    }
+
+    #[test]
+    fn empty_network_returns_input() {
+        let input = QRegister::basis(2, 3);
+        let state = TensorNetwork::from(QuantumCircuit::new(2))
+            .contract_state(input.clone())
+            .unwrap();
+        assert_close(&state, &input);
+    }
+}
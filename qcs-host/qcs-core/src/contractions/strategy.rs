@@ -0,0 +1,259 @@
+//! This module contains the strategies that decide the order in which the
+//! edges of a tensor network are contracted.
+//!
+//! A strategy is asked repeatedly for the next edges to contract, until it
+//! returns no edge. The selected edges must be contractable and must not share
+//! any endpoint, so that they can be contracted one after the other.
+//!
+//! The costs used by the strategies are the estimates of `crate::cost` on
+//! dense matrices: a tensor is a square matrix over the lanes of its filled
+//! span, and a contraction is a product of two matrices over the filled span
+//! of the result.
+
+use enum_dispatch::enum_dispatch;
+use hashbrown::HashSet;
+use petgraph::{stable_graph::EdgeIndex, visit::EdgeRef};
+
+use crate::{
+    cost::{contraction_flops, tensor_size},
+    model::span::Span,
+};
+
+use super::TensorNetwork;
+
+/// An interface for the contraction order strategies.
+#[enum_dispatch]
+pub trait ContractionStrategy {
+    /// Return the name of the strategy.
+    fn name(&self) -> &'static str;
+
+    /// Select the next edges to contract in the network. An empty selection
+    /// ends the contraction.
+    fn select(&self, network: &TensorNetwork) -> Vec<EdgeIndex>;
+}
+
+/// This represents all the available contraction strategies, that can be
+/// selected by name.
+#[enum_dispatch(ContractionStrategy)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Strategy {
+    /// Contract all the disjoint edges of the lowest rank at once.
+    GreedyLowestRank,
+    /// Contract the edge with the lowest estimated FLOPs.
+    MinimumFlops,
+    /// Contract the edge that keeps the peak intermediate size lowest.
+    MinimumPeak,
+}
+
+impl Strategy {
+    /// The names of all the available strategies.
+    pub const NAMES: [&'static str; 3] = ["greedy", "flops", "peak"];
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        GreedyLowestRank.into()
+    }
+}
+
+impl std::str::FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "greedy" => Ok(GreedyLowestRank.into()),
+            "flops" => Ok(MinimumFlops.into()),
+            "peak" => Ok(MinimumPeak.into()),
+            _ => Err(format!(
+                "Unknown strategy {:?}, expected one of: {}",
+                s,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Strategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Contract every edge of the current lowest rank, first come first served in
+/// order of index, skipping the edges that share an endpoint with an already
+/// selected one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GreedyLowestRank;
+
+impl ContractionStrategy for GreedyLowestRank {
+    fn name(&self) -> &'static str {
+        "greedy"
+    }
+
+    fn select(&self, network: &TensorNetwork) -> Vec<EdgeIndex> {
+        let contractable = network.contractable();
+        let Some(lowest_rank) = contractable
+            .iter()
+            .map(|e| network.contraction_rank(e))
+            .min()
+        else {
+            return Vec::new();
+        };
+
+        let mut visited = HashSet::new();
+        contractable
+            .into_iter()
+            .filter(|e| network.contraction_rank(e) == lowest_rank)
+            .filter(|e| {
+                if !visited.contains(&e.source()) && !visited.contains(&e.target()) {
+                    visited.insert(e.source());
+                    visited.insert(e.target());
+                    true
+                } else {
+                    false
+                }
+            })
+            .map(|e| e.id())
+            .collect()
+    }
+}
+
+/// Contract one edge at a time, choosing the one with the lowest estimated
+/// FLOPs and, among those, the one that grows the rank of its endpoints the
+/// least.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MinimumFlops;
+
+impl ContractionStrategy for MinimumFlops {
+    fn name(&self) -> &'static str {
+        "flops"
+    }
+
+    fn select(&self, network: &TensorNetwork) -> Vec<EdgeIndex> {
+        network
+            .contractable()
+            .into_iter()
+            .map(|e| {
+                let (source, target) = endpoint_spans(network, e.id());
+                let span = source.union(&target);
+                let rank = |span: &Span| span.filled().span_len();
+                let growth = rank(&span) - usize::max(rank(&source), rank(&target));
+                (contraction_flops(&span), growth, e.id())
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)))
+            .map(|(_, _, e)| e)
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Contract one edge at a time, choosing the one that keeps the size of the
+/// largest live tensor lowest and, among those, the one that frees the most
+/// memory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MinimumPeak;
+
+impl ContractionStrategy for MinimumPeak {
+    fn name(&self) -> &'static str {
+        "peak"
+    }
+
+    fn select(&self, network: &TensorNetwork) -> Vec<EdgeIndex> {
+        let peak = network
+            .graph()
+            .node_weights()
+            .map(|t| tensor_size(&t.span()))
+            .fold(0.0, f64::max);
+
+        network
+            .contractable()
+            .into_iter()
+            .map(|e| {
+                let (source, target) = endpoint_spans(network, e.id());
+                let size = tensor_size(&source.union(&target));
+                let freed = tensor_size(&source) + tensor_size(&target);
+                (f64::max(peak, size), size - freed, e.id())
+            })
+            .min_by(|a, b| {
+                a.0.total_cmp(&b.0)
+                    .then(a.1.total_cmp(&b.1))
+                    .then(a.2.cmp(&b.2))
+            })
+            .map(|(_, _, e)| e)
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Return the spans of the endpoints of an edge.
+fn endpoint_spans(network: &TensorNetwork, edge: EdgeIndex) -> (Span, Span) {
+    let graph = network.graph();
+    let (source, target) = graph.edge_endpoints(edge).unwrap();
+    let span = |n| graph.node_weight(n).unwrap().span();
+    (span(source), span(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{contractions::TensorKind, cost::CostEstimate, library, model::QuantumCircuit};
+
+    fn max_rank(tensor: &TensorKind) -> u8 {
+        match tensor {
+            TensorKind::Contraction(c) => c.rank.max(max_rank(&c.lhs)).max(max_rank(&c.rhs)),
+            TensorKind::Gate(_) | TensorKind::State(_) => tensor.span().span_len() as u8,
+        }
+    }
+
+    fn n_gates(tensor: &TensorKind) -> usize {
+        match tensor {
+            TensorKind::Contraction(c) => n_gates(&c.lhs) + n_gates(&c.rhs),
+            TensorKind::Gate(_) => 1,
+            TensorKind::State(_) => 0,
+        }
+    }
+
+    #[test]
+    fn strategies_by_name() {
+        for name in Strategy::NAMES {
+            let strategy = name.parse::<Strategy>().unwrap();
+            assert_eq!(strategy.to_string(), name);
+        }
+        assert!("unknown".parse::<Strategy>().is_err());
+        assert_eq!(Strategy::default(), GreedyLowestRank.into());
+    }
+
+    #[test]
+    fn strategies_contract_every_gate() {
+        let circuits: [QuantumCircuit; 3] = [
+            library::qft(4),
+            library::ripple_carry_adder(2),
+            library::hardware_efficient_ansatz(4, 2, &[0.5; 24]),
+        ];
+        for circuit in circuits {
+            for name in Strategy::NAMES {
+                let strategy = name.parse::<Strategy>().unwrap();
+                let network = TensorNetwork::from(circuit.clone());
+                let tensors = network.contract_with(&strategy);
+                assert_eq!(tensors.len(), 1, "{} left more than one tensor", name);
+                assert_eq!(n_gates(&tensors[0]), circuit.gates.len());
+                assert!(max_rank(&tensors[0]) as usize <= circuit.n_qubits);
+            }
+        }
+    }
+
+    #[test]
+    fn strategies_beat_greedy() {
+        let cost = |name: &str| {
+            let strategy = name.parse::<Strategy>().unwrap();
+            let tensors = TensorNetwork::from(library::qft(4)).contract_with(&strategy);
+            tensors
+                .iter()
+                .map(|t| CostEstimate::try_from(t).unwrap())
+                .sum::<CostEstimate>()
+        };
+        let (greedy, flops, peak) = (cost("greedy"), cost("flops"), cost("peak"));
+        assert!(flops.flops() < greedy.flops());
+        assert!(peak.peak_memory < greedy.peak_memory);
+    }
+}
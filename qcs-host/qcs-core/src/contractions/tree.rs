@@ -0,0 +1,479 @@
+//! This module contains the export of contracted trees, so that the order of a
+//! contraction can be inspected and compared between strategies.
+//!
+//! The trees are flattened into a `ContractionTree`, whose nodes carry the
+//! span and the rank of the tensor, the estimated FLOPs of the contraction
+//! that produces it and, once measured, the time it took in the reference
+//! contraction. The tree can then be written as a Graphviz DOT graph, a JSON
+//! document or a self-contained SVG image.
+//!
+//! The reference contraction contracts the index tensors of the nodes one
+//! pair at a time (see `IndexTensor::einsum`), as `closed::evaluate` does. It
+//! does not run the kernels of the executor, so its times only compare the
+//! nodes of a tree between them, not with the timings of the plans.
+//!
+//! The estimated cost of a node is the cost of its own contraction, without
+//! the contractions of its children. It is only known for the contractions of
+//! gates, since the cost model does not cover the state tensors.
+
+use std::{
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cost::dense_flops,
+    model::{span::Span, tensor::IndexTensor},
+};
+
+use super::TensorKind;
+
+/// Horizontal distance between two leaves of the SVG image.
+const SVG_LEAF_WIDTH: f64 = 110.0;
+/// Vertical distance between two levels of the SVG image.
+const SVG_LEVEL_HEIGHT: f64 = 80.0;
+/// Width and height of the box of a node in the SVG image.
+const SVG_NODE_SIZE: (f64, f64) = (100.0, 50.0);
+/// Margin around the SVG image.
+const SVG_MARGIN: f64 = 20.0;
+
+/// The kind of a node of a contraction tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NodeKind {
+    Contraction,
+    Gate,
+    State,
+}
+
+impl std::fmt::Display for NodeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Contraction => write!(f, "contraction"),
+            Self::Gate => write!(f, "gate"),
+            Self::State => write!(f, "state"),
+        }
+    }
+}
+
+/// A node of a contraction tree.
+#[derive(Debug, Clone)]
+pub struct TreeNode {
+    pub kind: NodeKind,
+    /// The gate or the state of a leaf, `~` for a contraction.
+    pub label: String,
+    pub span: Span,
+    pub rank: usize,
+    /// The estimated FLOPs of the contraction of the node, zero for the
+    /// leaves and `None` if the subtree holds state tensors.
+    pub flops: Option<f64>,
+    /// The time of the contraction of the node in the reference contraction,
+    /// `None` until the tree is measured.
+    pub reference_time: Option<Duration>,
+    /// The indices of the left and right children of a contraction.
+    pub children: Option<(usize, usize)>,
+}
+
+/// The contracted trees of a network, flattened into a list of nodes.
+#[derive(Debug, Clone)]
+pub struct ContractionTree {
+    /// The nodes of all the trees, every node comes after its children.
+    pub nodes: Vec<TreeNode>,
+    /// The indices of the roots, in the order they are applied.
+    pub roots: Vec<usize>,
+    /// The contracted tensors, kept to measure the contractions.
+    tensors: Vec<TensorKind>,
+}
+
+impl ContractionTree {
+    /// Create a tree from the contracted tensors of a network, in the order
+    /// returned by `TensorNetwork::contract_with`.
+    pub fn new(trees: &[TensorKind]) -> Self {
+        let mut tree = Self {
+            nodes: vec![],
+            roots: vec![],
+            tensors: trees.to_vec(),
+        };
+        for tensor in trees {
+            let (root, _) = tree.push(tensor);
+            tree.roots.push(root);
+        }
+        tree
+    }
+
+    /// Add the nodes of a tensor, returning the index of its root and the
+    /// estimated FLOPs of the whole subtree.
+    fn push(&mut self, tensor: &TensorKind) -> (usize, Option<f64>) {
+        let (kind, label, rank, flops, children) = match tensor {
+            TensorKind::Contraction(c) => {
+                let (lhs, lhs_flops) = self.push(&c.lhs);
+                let (rhs, rhs_flops) = self.push(&c.rhs);
+                let subtree = lhs_flops
+                    .zip(rhs_flops)
+                    .map(|(l, r)| (dense_flops(tensor), l + r));
+                let kind = NodeKind::Contraction;
+                (
+                    kind,
+                    "~".to_string(),
+                    c.rank as usize,
+                    subtree,
+                    Some((lhs, rhs)),
+                )
+            }
+            TensorKind::Gate(g) => {
+                let rank = tensor.span().span_len();
+                (NodeKind::Gate, g.to_string(), rank, Some((0.0, 0.0)), None)
+            }
+            TensorKind::State(s) => {
+                let rank = tensor.span().span_len();
+                (NodeKind::State, s.to_string(), rank, None, None)
+            }
+        };
+        self.nodes.push(TreeNode {
+            kind,
+            label,
+            span: tensor.span(),
+            rank,
+            flops: flops.map(|(subtree, children)| (subtree - children).max(0.0)),
+            reference_time: None,
+            children,
+        });
+        (self.nodes.len() - 1, flops.map(|(subtree, _)| subtree))
+    }
+
+    /// Evaluate the trees with the reference contraction, measuring the time
+    /// of the contraction of every node. The leaves are only loaded and take
+    /// no time.
+    pub fn measure_reference(mut self) -> Self {
+        let mut next = 0;
+        for tensor in &self.tensors.clone() {
+            self.measure_tensor(tensor, &mut next);
+        }
+        self
+    }
+
+    /// Evaluate a tensor whose nodes start at `next`, in the order they were
+    /// added by `push`.
+    fn measure_tensor(&mut self, tensor: &TensorKind, next: &mut usize) -> IndexTensor {
+        let (result, time) = match tensor {
+            TensorKind::Contraction(c) => {
+                let lhs = self.measure_tensor(&c.lhs, next);
+                let rhs = self.measure_tensor(&c.rhs, next);
+                let start = Instant::now();
+                let result = lhs.einsum(&rhs);
+                (result, start.elapsed())
+            }
+            TensorKind::Gate(_) | TensorKind::State(_) => {
+                (IndexTensor::from(tensor), Duration::ZERO)
+            }
+        };
+        self.nodes[*next].reference_time = Some(time);
+        *next += 1;
+        result
+    }
+
+    /// Return the tree as a Graphviz DOT graph, with an edge from every
+    /// contraction to its children.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph contraction {\n    node [shape=box];\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = std::iter::once(node.label.clone())
+                .chain(node.annotations())
+                .map(|line| escape_dot(&line))
+                .collect::<Vec<_>>()
+                .join("\\n");
+            writeln!(dot, "    n{} [label=\"{}\"];", i, label).unwrap();
+        }
+        for (i, node) in self.nodes.iter().enumerate() {
+            if let Some((lhs, rhs)) = node.children {
+                writeln!(dot, "    n{} -> n{} [label=\"lhs\"];", i, lhs).unwrap();
+                writeln!(dot, "    n{} -> n{} [label=\"rhs\"];", i, rhs).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Return the tree as a JSON document, with the trees nested in the
+    /// `trees` array in the order they are applied.
+    pub fn to_json(&self) -> String {
+        let trees = self
+            .roots
+            .iter()
+            .map(|&root| self.json_node(root))
+            .collect::<Vec<_>>();
+        format!("{{\"trees\":[{}]}}\n", trees.join(","))
+    }
+
+    fn json_node(&self, index: usize) -> String {
+        let node = &self.nodes[index];
+        let span = node
+            .span
+            .clone()
+            .into_iter()
+            .map(|lane| lane.to_string())
+            .collect::<Vec<_>>();
+        let mut json = format!(
+            "{{\"kind\":\"{}\",\"label\":\"{}\",\"span\":[{}],\"rank\":{}",
+            node.kind,
+            escape_json(&node.label),
+            span.join(","),
+            node.rank
+        );
+        match node.flops {
+            Some(flops) => write!(json, ",\"flops\":{}", flops).unwrap(),
+            None => json.push_str(",\"flops\":null"),
+        }
+        match node.reference_time {
+            Some(time) => {
+                write!(json, ",\"reference_time_us\":{}", time.as_secs_f64() * 1e6).unwrap()
+            }
+            None => json.push_str(",\"reference_time_us\":null"),
+        }
+        if let Some((lhs, rhs)) = node.children {
+            write!(
+                json,
+                ",\"lhs\":{},\"rhs\":{}",
+                self.json_node(lhs),
+                self.json_node(rhs)
+            )
+            .unwrap();
+        }
+        json.push('}');
+        json
+    }
+
+    /// Return the tree as a self-contained SVG image, with the leaves on the
+    /// bottom row in the order they are applied and the trees side by side.
+    pub fn to_svg(&self) -> String {
+        let mut positions = vec![(0.0, 0); self.nodes.len()];
+        let mut next_leaf = 0;
+        for &root in &self.roots {
+            self.layout(root, 0, &mut next_leaf, &mut positions);
+        }
+        let depth = positions.iter().map(|(_, d)| *d).max().unwrap_or(0);
+        let width = next_leaf.max(1) as f64 * SVG_LEAF_WIDTH + 2.0 * SVG_MARGIN;
+        let height = (depth + 1) as f64 * SVG_LEVEL_HEIGHT + 2.0 * SVG_MARGIN;
+        let center = |index: usize| {
+            let (x, depth) = positions[index];
+            let x = SVG_MARGIN + (x + 0.5) * SVG_LEAF_WIDTH;
+            let y = SVG_MARGIN + depth as f64 * SVG_LEVEL_HEIGHT + SVG_NODE_SIZE.1 / 2.0;
+            (x, y)
+        };
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+             viewBox=\"0 0 {w} {h}\" font-family=\"monospace\" font-size=\"10\">\n",
+            w = width,
+            h = height
+        );
+        for (i, node) in self.nodes.iter().enumerate() {
+            if let Some((lhs, rhs)) = node.children {
+                let (x1, y1) = center(i);
+                for child in [lhs, rhs] {
+                    let (x2, y2) = center(child);
+                    writeln!(
+                        svg,
+                        "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"black\"/>",
+                        x1, y1, x2, y2
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        for (i, node) in self.nodes.iter().enumerate() {
+            let (x, y) = center(i);
+            let (w, h) = SVG_NODE_SIZE;
+            let fill = match node.kind {
+                NodeKind::Contraction => "#dde8f7",
+                NodeKind::Gate => "#ffffff",
+                NodeKind::State => "#f7eadd",
+            };
+            writeln!(
+                svg,
+                "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"black\"/>",
+                x - w / 2.0,
+                y - h / 2.0,
+                w,
+                h,
+                fill
+            )
+            .unwrap();
+            let lines = std::iter::once(node.label.clone())
+                .chain(node.annotations())
+                .collect::<Vec<_>>();
+            let top = y - (lines.len() as f64 - 1.0) * 5.5;
+            for (j, line) in lines.iter().enumerate() {
+                writeln!(
+                    svg,
+                    "  <text x=\"{}\" y=\"{}\" text-anchor=\"middle\" dominant-baseline=\"middle\">{}</text>",
+                    x,
+                    top + j as f64 * 11.0,
+                    escape_xml(line)
+                )
+                .unwrap();
+            }
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Place a node and its children, returning the horizontal position of
+    /// the node in units of leaves.
+    fn layout(
+        &self,
+        index: usize,
+        depth: usize,
+        next_leaf: &mut usize,
+        positions: &mut [(f64, usize)],
+    ) -> f64 {
+        let x = match self.nodes[index].children {
+            Some((lhs, rhs)) => {
+                let lhs = self.layout(lhs, depth + 1, next_leaf, positions);
+                let rhs = self.layout(rhs, depth + 1, next_leaf, positions);
+                (lhs + rhs) / 2.0
+            }
+            None => {
+                *next_leaf += 1;
+                (*next_leaf - 1) as f64
+            }
+        };
+        positions[index] = (x, depth);
+        x
+    }
+}
+
+impl TreeNode {
+    /// Return the annotations of the node, one per line.
+    fn annotations(&self) -> Vec<String> {
+        let mut lines = vec![format!("span {} rank {}", self.span, self.rank)];
+        match self.flops {
+            Some(flops) => lines.push(format!("flops {:.3e}", flops)),
+            None => lines.push("flops ?".to_string()),
+        }
+        if let Some(time) = self.reference_time {
+            lines.push(format!("ref. time {:?}", time));
+        }
+        lines
+    }
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_json(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '"' => vec!['\\', '"'],
+            '\\' => vec!['\\', '\\'],
+            c if c.is_control() => format!("\\u{:04x}", c as u32).chars().collect(),
+            c => vec![c],
+        })
+        .collect()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::{closed::StateTensor, TensorNetwork},
+        library,
+        model::Qubit,
+    };
+
+    fn qft_tree() -> ContractionTree {
+        ContractionTree::new(&TensorNetwork::from(library::qft(3)).contract())
+    }
+
+    #[test]
+    fn nodes_follow_children() {
+        let tree = qft_tree();
+        let n_gates = library::qft(3).gates.len();
+        let leaves = tree.nodes.iter().filter(|n| n.children.is_none()).count();
+        assert_eq!(leaves, n_gates);
+        assert_eq!(tree.nodes.len(), 2 * n_gates - tree.roots.len());
+        for (i, node) in tree.nodes.iter().enumerate() {
+            if let Some((lhs, rhs)) = node.children {
+                assert!(lhs < i && rhs < i);
+                let span = tree.nodes[lhs].span.union(&tree.nodes[rhs].span);
+                assert_eq!(node.span, span);
+                assert_eq!(node.rank, span.span_len());
+            }
+        }
+    }
+
+    #[test]
+    fn flops_sum_to_estimate() {
+        let trees = TensorNetwork::from(library::qft(4)).contract();
+        let expected = trees.iter().map(dense_flops).sum::<f64>();
+        let tree = ContractionTree::new(&trees);
+        let flops = tree.nodes.iter().map(|n| n.flops.unwrap()).sum::<f64>();
+        assert!((flops - expected).abs() < 1e-6 * expected);
+        assert!(tree
+            .nodes
+            .iter()
+            .filter(|n| n.children.is_none())
+            .all(|n| n.flops == Some(0.0)));
+    }
+
+    #[test]
+    fn measure_times_every_node() {
+        let tree = qft_tree();
+        assert!(tree.nodes.iter().all(|n| n.reference_time.is_none()));
+        let tree = tree.measure_reference();
+        assert!(tree.nodes.iter().all(|n| n.reference_time.is_some()));
+        assert!(tree.to_json().contains("\"reference_time_us\":"));
+    }
+
+    #[test]
+    fn closed_network_has_no_estimate() {
+        let circuit = library::qft(2);
+        let states = StateTensor::inputs(&[Qubit::zero(), Qubit::one()]);
+        let trees = TensorNetwork::closed(circuit, states).contract();
+        let tree = ContractionTree::new(&trees).measure_reference();
+        assert!(tree.nodes.iter().any(|n| n.kind == NodeKind::State));
+        assert!(tree
+            .nodes
+            .iter()
+            .filter(|n| n.kind == NodeKind::State)
+            .all(|n| n.flops.is_none()));
+        assert!(tree.to_json().contains("\"flops\":null"));
+    }
+
+    #[test]
+    fn exports() {
+        let tree = qft_tree().measure_reference();
+        let n_edges = 2 * tree.nodes.iter().filter(|n| n.children.is_some()).count();
+
+        let dot = tree.to_dot();
+        assert!(dot.starts_with("digraph contraction {"));
+        assert_eq!(dot.matches(" -> ").count(), n_edges);
+        assert_eq!(dot.matches("[label=").count(), tree.nodes.len() + n_edges);
+
+        let json = tree.to_json();
+        assert!(json.starts_with("{\"trees\":["));
+        assert_eq!(json.matches("\"rank\":").count(), tree.nodes.len());
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+        assert!(json.contains("\"label\":\"H[0]\""));
+
+        let svg = tree.to_svg();
+        assert!(svg.starts_with("<svg xmlns="));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert_eq!(svg.matches("<rect ").count(), tree.nodes.len());
+        assert_eq!(svg.matches("<line ").count(), n_edges);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(escape_json("a\"b\\\n"), "a\\\"b\\\\\\u000a");
+        assert_eq!(escape_xml("<ψ|&>"), "&lt;ψ|&amp;&gt;");
+        assert_eq!(escape_dot("a\"b"), "a\\\"b");
+    }
+}
@@ -0,0 +1,604 @@
+//! This module contains the cost model of contraction trees and operation
+//! plans, used to predict how expensive a tree is before running it.
+//!
+//! A tree is estimated through the operation plan it would produce (see
+//! `op_tree`): every contraction is a matrix multiplication (MM) of two
+//! operands, expanded to the span of the contraction with tensor expansions
+//! (TE) by identities when needed. When the input state is contracted into the
+//! network, the tensors are applied to it with matrix-vector products (MV),
+//! counted among the MMs. The estimate only depends on the dimensions
+//! of the blocks and on their number of non zero elements, so no matrix is
+//! built when estimating a tree.
+//!
+//! All the figures are estimates for a sequential execution of the plan in the
+//! order of the instruction ids:
+//! - the dense FLOPs count 8 real operations for each complex multiply-add of
+//!   a MM or a MV, and 6 for each complex product of a TE;
+//! - the sparse FLOPs only count the products between non zero elements, the
+//!   non zero elements of the result of a MM are estimated assuming that they
+//!   are uniformly distributed;
+//! - the bytes moved are the bytes of the operands read and of the result
+//!   written by each instruction, 16 bytes for each dense element and 24 bytes
+//!   for each sparse element (as in the sparse COO format of `compiler`);
+//! - the peak memory is the largest amount of memory taken at the same time by
+//!   the operands of an instruction and by the results waiting to be used;
+//! - the critical path is the longest chain of dependent instructions.
+//!
+//! The contraction strategies and the annealing optimizer of `contractions`
+//! rank the candidate contractions with the same model: a contraction over a
+//! span is a MM of two dense matrices over its filled span, see
+//! `contraction_flops` and `tensor_size`.
+
+use hashbrown::HashMap;
+use nalgebra::{Complex, DMatrix};
+
+use crate::{
+    contractions::{closed::ClosedTensorError, TensorContraction, TensorKind},
+    model::{
+        blocks::Block,
+        gates::{Gate, QuantumGate},
+        span::Span,
+        sparse::count_non_zero,
+        QRegister,
+    },
+    op_tree::{Operand, Operation, OperationKind},
+    scheduler::{
+        operation::{Kernel, OperationPlan},
+        ExecutionOperand,
+    },
+};
+
+/// The bytes taken by a dense complex element.
+pub const DENSE_ELEMENT_BYTES: f64 = 16.0;
+
+/// The bytes taken by a sparse complex element (row, column, real and
+/// imaginary parts).
+pub const SPARSE_ELEMENT_BYTES: f64 = 24.0;
+
+/// Return the FLOPs of a dense matrix multiplication of a rows × inner matrix
+/// by an inner × cols matrix.
+pub fn mm_flops(rows: usize, inner: usize, cols: usize) -> f64 {
+    8.0 * rows as f64 * inner as f64 * cols as f64
+}
+
+/// Return the FLOPs of a dense tensor expansion of two matrices, given their
+/// numbers of elements.
+pub fn te_flops(left_size: usize, right_size: usize) -> f64 {
+    6.0 * left_size as f64 * right_size as f64
+}
+
+/// Return the estimated FLOPs of a sparse matrix multiplication, given the non
+/// zero elements of the operands and the inner dimension.
+pub fn sparse_mm_flops(left_nnz: f64, right_nnz: f64, inner: usize) -> f64 {
+    8.0 * left_nnz * right_nnz / inner as f64
+}
+
+/// Return the FLOPs of a sparse tensor expansion, given the non zero elements
+/// of the operands.
+pub fn sparse_te_flops(left_nnz: f64, right_nnz: f64) -> f64 {
+    6.0 * left_nnz * right_nnz
+}
+
+/// Return the dense FLOPs of a contraction covering the span, a MM of two
+/// square matrices over the lanes of its filled span, as in the plans.
+pub fn contraction_flops(span: &Span) -> f64 {
+    let dim = 1 << span.filled().span_len();
+    mm_flops(dim, dim, dim)
+}
+
+/// Return the number of complex elements of the dense block of a tensor
+/// covering the span.
+pub fn tensor_size(span: &Span) -> f64 {
+    let dim = 1usize << span.filled().span_len();
+    (dim * dim) as f64
+}
+
+/// The estimated cost of the execution of a plan.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostEstimate {
+    /// The number of matrix multiplications, including the matrix-vector
+    /// products.
+    pub mm_count: usize,
+    /// The number of tensor expansions.
+    pub te_count: usize,
+    /// The dense FLOPs of the matrix multiplications.
+    pub mm_flops: f64,
+    /// The dense FLOPs of the tensor expansions.
+    pub te_flops: f64,
+    /// The sparse FLOPs of the matrix multiplications.
+    pub sparse_mm_flops: f64,
+    /// The sparse FLOPs of the tensor expansions.
+    pub sparse_te_flops: f64,
+    /// The bytes read and written with dense blocks.
+    pub bytes_moved: f64,
+    /// The bytes read and written with sparse blocks.
+    pub sparse_bytes_moved: f64,
+    /// The peak memory in bytes with dense blocks.
+    pub peak_memory: f64,
+    /// The peak memory in bytes with sparse blocks.
+    pub sparse_peak_memory: f64,
+    /// The number of instructions in the longest chain of dependencies.
+    pub critical_path_length: usize,
+    /// The dense FLOPs of the longest chain of dependencies.
+    pub critical_path_flops: f64,
+}
+
+impl CostEstimate {
+    /// Return the total dense FLOPs.
+    pub fn flops(&self) -> f64 {
+        self.mm_flops + self.te_flops
+    }
+
+    /// Return the total sparse FLOPs.
+    pub fn sparse_flops(&self) -> f64 {
+        self.sparse_mm_flops + self.sparse_te_flops
+    }
+
+    /// Estimate the cost of a list of instructions, sorted so that every
+    /// instruction comes after its dependencies.
+    fn from_steps(steps: &[Step]) -> Self {
+        let mut cost = Self::default();
+        let mut results: HashMap<usize, Shape> = HashMap::new();
+        let mut depth: HashMap<usize, (usize, f64)> = HashMap::new();
+        let mut last_use = HashMap::new();
+        for step in steps {
+            for address in [step.left, step.right].iter().filter_map(|o| o.address()) {
+                last_use.insert(address, step.id);
+            }
+        }
+
+        let (mut live, mut sparse_live) = (0.0, 0.0);
+        for step in steps {
+            let left = step.left.shape(&results);
+            let right = step.right.shape(&results);
+            let (result, flops) = match step.kind {
+                StepKind::TE => {
+                    cost.te_count += 1;
+                    let result = Shape {
+                        rows: left.rows * right.rows,
+                        cols: left.cols * right.cols,
+                        nnz: left.nnz * right.nnz,
+                    };
+                    let flops = te_flops(left.size(), right.size());
+                    cost.te_flops += flops;
+                    cost.sparse_te_flops += sparse_te_flops(left.nnz, right.nnz);
+                    (result, flops)
+                }
+                StepKind::MM => {
+                    cost.mm_count += 1;
+                    let inner = left.cols;
+                    let density = (left.density() * right.density()).min(1.0);
+                    let result = Shape {
+                        rows: left.rows,
+                        cols: right.cols,
+                        nnz: (left.rows * right.cols) as f64
+                            * (1.0 - (1.0 - density).powi(inner as i32)),
+                    };
+                    let flops = mm_flops(left.rows, inner, right.cols);
+                    cost.mm_flops += flops;
+                    cost.sparse_mm_flops += sparse_mm_flops(left.nnz, right.nnz, inner);
+                    (result, flops)
+                }
+                StepKind::MV => {
+                    // the matrix is applied to each slice of the state over its lanes
+                    cost.mm_count += 1;
+                    let slices = right.rows / left.cols;
+                    let result = Shape {
+                        rows: right.rows,
+                        cols: 1,
+                        nnz: right.rows as f64,
+                    };
+                    let flops = mm_flops(left.rows, left.cols, slices);
+                    cost.mm_flops += flops;
+                    cost.sparse_mm_flops += sparse_mm_flops(left.nnz, right.nnz, left.cols);
+                    (result, flops)
+                }
+            };
+
+            let operands = [left, right, result];
+            cost.bytes_moved += operands.iter().map(Shape::dense_bytes).sum::<f64>();
+            cost.sparse_bytes_moved += operands.iter().map(Shape::sparse_bytes).sum::<f64>();
+
+            // operands given as blocks are only alive during the instruction
+            let inline = [(step.left, left), (step.right, right)]
+                .into_iter()
+                .filter(|(o, _)| o.address().is_none())
+                .map(|(_, s)| s)
+                .collect::<Vec<_>>();
+            live += result.dense_bytes();
+            sparse_live += result.sparse_bytes();
+            cost.peak_memory = cost
+                .peak_memory
+                .max(live + inline.iter().map(Shape::dense_bytes).sum::<f64>());
+            cost.sparse_peak_memory = cost
+                .sparse_peak_memory
+                .max(sparse_live + inline.iter().map(Shape::sparse_bytes).sum::<f64>());
+            for (operand, shape) in [(step.left, left), (step.right, right)] {
+                if operand.address().is_some_and(|a| last_use[&a] == step.id) {
+                    live -= shape.dense_bytes();
+                    sparse_live -= shape.sparse_bytes();
+                }
+            }
+
+            let (length, path_flops) = [step.left, step.right]
+                .iter()
+                .filter_map(|o| o.address())
+                .map(|a| depth[&a])
+                .fold((0, 0.0), |(l, f), (dl, df)| (l.max(dl), f64::max(f, df)));
+            depth.insert(step.id, (length + 1, path_flops + flops));
+            cost.critical_path_length = cost.critical_path_length.max(length + 1);
+            cost.critical_path_flops = cost.critical_path_flops.max(path_flops + flops);
+
+            results.insert(step.id, result);
+        }
+        cost
+    }
+}
+
+impl std::iter::Sum for CostEstimate {
+    /// Sum the costs of plans executed one after the other: the peak memory and
+    /// the critical path are the largest among the plans.
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |acc, cost| Self {
+            mm_count: acc.mm_count + cost.mm_count,
+            te_count: acc.te_count + cost.te_count,
+            mm_flops: acc.mm_flops + cost.mm_flops,
+            te_flops: acc.te_flops + cost.te_flops,
+            sparse_mm_flops: acc.sparse_mm_flops + cost.sparse_mm_flops,
+            sparse_te_flops: acc.sparse_te_flops + cost.sparse_te_flops,
+            bytes_moved: acc.bytes_moved + cost.bytes_moved,
+            sparse_bytes_moved: acc.sparse_bytes_moved + cost.sparse_bytes_moved,
+            peak_memory: acc.peak_memory.max(cost.peak_memory),
+            sparse_peak_memory: acc.sparse_peak_memory.max(cost.sparse_peak_memory),
+            critical_path_length: acc.critical_path_length.max(cost.critical_path_length),
+            critical_path_flops: acc.critical_path_flops.max(cost.critical_path_flops),
+        })
+    }
+}
+
+impl From<&Operation> for CostEstimate {
+    fn from(operation: &Operation) -> Self {
+        let mut builder = StepsBuilder::default();
+        builder.populate(operation);
+        Self::from_steps(&builder.steps)
+    }
+}
+
+impl TryFrom<&TensorContraction> for CostEstimate {
+    type Error = ClosedTensorError;
+
+    fn try_from(contraction: &TensorContraction) -> Result<Self, Self::Error> {
+        Ok(Self::from(&Operation::from_contraction(
+            contraction.clone(),
+            false,
+        )?))
+    }
+}
+
+impl TryFrom<&TensorKind> for CostEstimate {
+    type Error = ClosedTensorError;
+
+    fn try_from(tensor: &TensorKind) -> Result<Self, Self::Error> {
+        match tensor {
+            TensorKind::Contraction(contraction) => Self::try_from(contraction.as_ref()),
+            TensorKind::Gate(_) | TensorKind::State(_) => Ok(Self::default()),
+        }
+    }
+}
+
+impl From<&OperationPlan> for CostEstimate {
+    fn from(plan: &OperationPlan) -> Self {
+        let steps = plan
+            .instructions()
+            .map(|instr| {
+                let (kind, left, right) = match &instr.kernel {
+                    Kernel::TE { left, right } => (StepKind::TE, left, right),
+                    Kernel::MM { left, right } => (StepKind::MM, left, right),
+                    Kernel::MV { left, right, .. } => (StepKind::MV, left, right),
+                };
+                let operand = |o: &ExecutionOperand<Block>| match o {
+                    ExecutionOperand::Block(block) => {
+                        let matrix: &DMatrix<Complex<f64>> = (**block).as_ref();
+                        StepOperand::Block(Shape {
+                            rows: matrix.nrows(),
+                            cols: matrix.ncols(),
+                            nnz: count_non_zero(matrix) as f64,
+                        })
+                    }
+                    ExecutionOperand::Address(id) => StepOperand::Address(*id),
+                };
+                Step {
+                    id: instr.id,
+                    kind,
+                    left: operand(left),
+                    right: operand(right),
+                }
+            })
+            .collect::<Vec<_>>();
+        Self::from_steps(&steps)
+    }
+}
+
+impl std::fmt::Display for CostEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Instructions: {} MM, {} TE",
+            self.mm_count, self.te_count
+        )?;
+        writeln!(
+            f,
+            "FLOPs: {:.3e} dense, {:.3e} sparse",
+            self.flops(),
+            self.sparse_flops()
+        )?;
+        writeln!(
+            f,
+            "Bytes moved: {:.3e} dense, {:.3e} sparse",
+            self.bytes_moved, self.sparse_bytes_moved
+        )?;
+        writeln!(
+            f,
+            "Peak memory: {:.3e} dense, {:.3e} sparse",
+            self.peak_memory, self.sparse_peak_memory
+        )?;
+        writeln!(
+            f,
+            "Critical path: {} instructions, {:.3e} FLOPs",
+            self.critical_path_length, self.critical_path_flops
+        )
+    }
+}
+
+/// Return the estimated dense FLOPs of a contracted tensor, to rank trees.
+/// The contractions of closed networks have no plan and cost infinite FLOPs.
+pub fn dense_flops(tensor: &TensorKind) -> f64 {
+    CostEstimate::try_from(tensor).map_or(f64::INFINITY, |cost| cost.flops())
+}
+
+/// Return the estimated sparse FLOPs of a contracted tensor, to rank trees.
+/// The contractions of closed networks have no plan and cost infinite FLOPs.
+pub fn sparse_flops(tensor: &TensorKind) -> f64 {
+    CostEstimate::try_from(tensor).map_or(f64::INFINITY, |cost| cost.sparse_flops())
+}
+
+/// The dimensions and the non zero elements of a block.
+#[derive(Debug, Clone, Copy)]
+struct Shape {
+    rows: usize,
+    cols: usize,
+    nnz: f64,
+}
+
+impl Shape {
+    fn identity(dim: usize) -> Self {
+        Self {
+            rows: dim,
+            cols: dim,
+            nnz: dim as f64,
+        }
+    }
+
+    fn gate(gate: &Gate) -> Self {
+        let matrix = gate.shared_matrix();
+        Self {
+            rows: matrix.nrows(),
+            cols: matrix.ncols(),
+            nnz: count_non_zero(&matrix) as f64,
+        }
+    }
+
+    fn state(state: &QRegister) -> Self {
+        Self {
+            rows: state.qubits.len(),
+            cols: 1,
+            nnz: count_non_zero(&DMatrix::from_column_slice(
+                state.qubits.len(),
+                1,
+                state.qubits.as_slice(),
+            )) as f64,
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.rows * self.cols
+    }
+
+    fn density(&self) -> f64 {
+        self.nnz / self.size() as f64
+    }
+
+    fn dense_bytes(&self) -> f64 {
+        self.size() as f64 * DENSE_ELEMENT_BYTES
+    }
+
+    fn sparse_bytes(&self) -> f64 {
+        self.nnz * SPARSE_ELEMENT_BYTES
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StepKind {
+    TE,
+    MM,
+    MV,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StepOperand {
+    Block(Shape),
+    Address(usize),
+}
+
+impl StepOperand {
+    fn address(&self) -> Option<usize> {
+        match self {
+            Self::Block(_) => None,
+            Self::Address(id) => Some(*id),
+        }
+    }
+
+    fn shape(&self, results: &HashMap<usize, Shape>) -> Shape {
+        match self {
+            Self::Block(shape) => *shape,
+            Self::Address(id) => results[id],
+        }
+    }
+}
+
+/// An instruction of a plan, described only by the shapes of its operands.
+#[derive(Debug, Clone)]
+struct Step {
+    id: usize,
+    kind: StepKind,
+    left: StepOperand,
+    right: StepOperand,
+}
+
+/// Builder of the steps of an operation tree, that follows the same rules of
+/// the builder of `OperationPlan` without building the blocks.
+#[derive(Debug, Default)]
+struct StepsBuilder {
+    steps: Vec<Step>,
+}
+
+impl StepsBuilder {
+    fn push(&mut self, kind: StepKind, left: StepOperand, right: StepOperand) -> usize {
+        let id = self.steps.len();
+        self.steps.push(Step {
+            id,
+            kind,
+            left,
+            right,
+        });
+        id
+    }
+
+    fn operand(&mut self, operand: &Operand) -> StepOperand {
+        match operand {
+            Operand::Operation(op) => StepOperand::Address(self.populate(op)),
+            Operand::Gate(gate) => StepOperand::Block(Shape::gate(gate)),
+            Operand::State(state) => StepOperand::Block(Shape::state(state)),
+        }
+    }
+
+    fn populate(&mut self, operation: &Operation) -> usize {
+        match &operation.inner {
+            OperationKind::TensorExpansion {
+                target_span,
+                operand,
+            } => {
+                let op_span = operand.span();
+                let mut op = self.operand(operand);
+                let mut id = None;
+
+                let id_dim = op_span.start() - target_span.start();
+                if id_dim != 0 {
+                    let left = StepOperand::Block(Shape::identity(1 << id_dim));
+                    let new_id = self.push(StepKind::TE, left, op);
+                    op = StepOperand::Address(new_id);
+                    id = Some(new_id);
+                }
+                let id_dim = target_span.end() - op_span.end();
+                if id_dim != 0 {
+                    let right = StepOperand::Block(Shape::identity(1 << id_dim));
+                    id = Some(self.push(StepKind::TE, op, right));
+                }
+                id.unwrap()
+            }
+            OperationKind::MatrixMultiplication { left, right } => {
+                let left = self.operand(left);
+                let right = self.operand(right);
+                self.push(StepKind::MM, left, right)
+            }
+            OperationKind::MatrixVector { matrix, state } => {
+                let matrix = self.operand(matrix);
+                let state = self.operand(state);
+                self.push(StepKind::MV, matrix, state)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::{fixtures::circuit_contraction, TensorNetwork},
+        library,
+        model::{gates::QuantumGate, QuantumCircuit},
+    };
+
+    fn contract(circuit: QuantumCircuit) -> TensorKind {
+        let mut tensors = TensorNetwork::from(circuit).contract();
+        assert_eq!(tensors.len(), 1);
+        tensors.pop().unwrap()
+    }
+
+    #[test]
+    fn hadamard_cnot() {
+        let mut circuit = QuantumCircuit::new(2);
+        circuit.g_h(0);
+        circuit.g_cx(0, 1);
+        let cost = CostEstimate::try_from(&contract(circuit)).unwrap();
+
+        // H ⊗ I (4x4) followed by a 4x4 MM
+        assert_eq!((cost.te_count, cost.mm_count), (1, 1));
+        assert_eq!(cost.te_flops, te_flops(4, 4));
+        assert_eq!(cost.mm_flops, mm_flops(4, 4, 4));
+        assert_eq!(cost.bytes_moved, 16.0 * (4 + 4 + 16 + 16 + 16 + 16) as f64);
+        assert_eq!(cost.peak_memory, 16.0 * (16 + 16 + 16) as f64);
+        assert_eq!(cost.critical_path_length, 2);
+        assert_eq!(cost.critical_path_flops, cost.flops());
+        // H ⊗ I has 8 non zero elements, CX has 4
+        assert_eq!(cost.sparse_te_flops, sparse_te_flops(4.0, 2.0));
+        assert_eq!(cost.sparse_mm_flops, sparse_mm_flops(8.0, 4.0, 4));
+    }
+
+    #[test]
+    fn tree_and_plan_agree() {
+        let contraction = circuit_contraction(library::qft(4));
+        let tensor = TensorKind::from(contraction.clone());
+        let plan = OperationPlan::from(Operation::from_contraction(contraction, false).unwrap());
+        let from_tree = CostEstimate::try_from(&tensor).unwrap();
+        let from_plan = CostEstimate::from(&plan);
+
+        assert_eq!(from_tree.mm_count, from_plan.mm_count);
+        assert_eq!(from_tree.te_count, from_plan.te_count);
+        assert_eq!(from_tree.flops(), from_plan.flops());
+        assert_eq!(from_tree.bytes_moved, from_plan.bytes_moved);
+        assert_eq!(from_tree.peak_memory, from_plan.peak_memory);
+        assert_eq!(
+            from_tree.critical_path_length,
+            from_plan.critical_path_length
+        );
+        // the gates of the plan are stored as blocks, the non zero elements
+        // of the intermediate results are estimated in both cases
+        assert!((from_tree.sparse_flops() - from_plan.sparse_flops()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sparsity_lowers_cost() {
+        let adder = CostEstimate::try_from(&contract(library::ripple_carry_adder(2))).unwrap();
+        assert!(adder.sparse_flops() < adder.flops() / 10.0);
+        assert!(adder.sparse_peak_memory < adder.peak_memory);
+
+        let params = vec![0.3; library::ansatz_parameters(4, 2)];
+        let ansatz = contract(library::hardware_efficient_ansatz(4, 2, &params));
+        let dense = CostEstimate::try_from(&ansatz).unwrap();
+        assert!(dense.sparse_flops() <= dense.flops());
+        assert_eq!(dense_flops(&ansatz), dense.flops());
+    }
+
+    #[test]
+    fn gate_has_no_cost() {
+        let mut circuit = QuantumCircuit::new(1);
+        circuit.g_x(0);
+        let tensor = contract(circuit);
+        assert!(matches!(tensor, TensorKind::Gate(ref g) if g.rank() == 1));
+        assert_eq!(
+            CostEstimate::try_from(&tensor).unwrap(),
+            CostEstimate::default()
+        );
+    }
+}
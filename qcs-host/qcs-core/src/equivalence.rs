@@ -0,0 +1,314 @@
+//! This module contains the equivalence checking of quantum circuits.
+//!
+//! Two circuits are equivalent if their matrices are equal up to a global
+//! phase, that is U = e^iφ V for some φ. For small circuits the check is exact:
+//! the matrices of both circuits are built and compared element by element.
+//! For larger circuits the check is randomized: both circuits are evaluated on
+//! random input states with the state vector path (`QuantumCircuit::eval_state`)
+//! and the output states are compared, using the global phase estimated on the
+//! first sample for all the others.
+
+use nalgebra::{Complex, DMatrix, DVector};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::model::{blocks::Block, QRegister, QuantumCircuit};
+
+/// The default tolerance on the maximum deviation between two circuits.
+const DEFAULT_TOLERANCE: f64 = 1e-8;
+
+/// Circuits up to this number of qubits are compared exactly by default.
+const DEFAULT_EXACT_MAX_QUBITS: usize = 10;
+
+/// The method that has been used to check the equivalence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EquivalenceMethod {
+    /// The full matrices have been compared.
+    Exact,
+    /// The circuits have been compared on a number of random input states.
+    Randomized { samples: usize },
+}
+
+/// The result of an equivalence check.
+#[derive(Debug, Clone)]
+pub struct EquivalenceReport {
+    /// True if the maximum deviation is within the tolerance.
+    pub equivalent: bool,
+    /// The method used for the check.
+    pub method: EquivalenceMethod,
+    /// The global phase e^iφ such that the first circuit equals e^iφ times the
+    /// second one.
+    pub global_phase: Complex<f64>,
+    /// The maximum absolute deviation between the amplitudes of the two
+    /// circuits, after removing the global phase.
+    pub max_deviation: f64,
+    /// The input state on which the deviation is maximum, if the circuits are
+    /// not equivalent.
+    pub counterexample: Option<QRegister>,
+}
+
+impl std::fmt::Display for EquivalenceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let method = match self.method {
+            EquivalenceMethod::Exact => "exact".to_string(),
+            EquivalenceMethod::Randomized { samples } => format!("randomized, {} samples", samples),
+        };
+        write!(
+            f,
+            "{} ({}): max deviation {:.3e}, global phase {:.4}",
+            if self.equivalent {
+                "Equivalent"
+            } else {
+                "Not equivalent"
+            },
+            method,
+            self.max_deviation,
+            self.global_phase.arg()
+        )
+    }
+}
+
+/// Checker of the equivalence of circuits up to a global phase.
+#[derive(Debug, Clone)]
+pub struct EquivalenceChecker {
+    tolerance: f64,
+    exact_max_qubits: usize,
+    samples: usize,
+    seed: u64,
+}
+
+impl Default for EquivalenceChecker {
+    fn default() -> Self {
+        Self {
+            tolerance: DEFAULT_TOLERANCE,
+            exact_max_qubits: DEFAULT_EXACT_MAX_QUBITS,
+            samples: 16,
+            seed: 0,
+        }
+    }
+}
+
+impl EquivalenceChecker {
+    /// Creates a new checker with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the tolerance on the maximum deviation.
+    pub fn tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Set the maximum number of qubits for which the check is exact.
+    pub fn exact_max_qubits(mut self, n_qubits: usize) -> Self {
+        self.exact_max_qubits = n_qubits;
+        self
+    }
+
+    /// Set the number of random states used by the randomized check.
+    pub fn samples(mut self, samples: usize) -> Self {
+        assert!(samples > 0, "At least one sample is required");
+        self.samples = samples;
+        self
+    }
+
+    /// Set the seed of the random states used by the randomized check.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Check if two circuits are equivalent up to a global phase.
+    pub fn check(&self, lhs: &QuantumCircuit, rhs: &QuantumCircuit) -> EquivalenceReport {
+        assert_eq!(lhs.n_qubits, rhs.n_qubits, "Different number of qubits");
+        if lhs.n_qubits <= self.exact_max_qubits {
+            self.check_matrices(lhs.clone().eval().as_ref(), rhs.clone().eval().as_ref())
+        } else {
+            self.check_states(lhs.n_qubits, |s| lhs.eval_state(s), |s| rhs.eval_state(s))
+        }
+    }
+
+    /// Check if a circuit is equivalent to a block up to a global phase.
+    pub fn check_block(&self, lhs: &QuantumCircuit, rhs: &Block) -> EquivalenceReport {
+        assert_eq!(
+            1 << lhs.n_qubits,
+            rhs.as_ref().nrows(),
+            "Incompatible block"
+        );
+        if lhs.n_qubits <= self.exact_max_qubits {
+            self.check_matrices(lhs.clone().eval().as_ref(), rhs.as_ref())
+        } else {
+            self.check_states(lhs.n_qubits, |s| lhs.eval_state(s), |s| rhs * s)
+        }
+    }
+
+    /// Compare two matrices element by element, after removing the global
+    /// phase estimated on the largest element of the second matrix.
+    fn check_matrices(
+        &self,
+        lhs: &DMatrix<Complex<f64>>,
+        rhs: &DMatrix<Complex<f64>>,
+    ) -> EquivalenceReport {
+        let (pivot, _) = rhs
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.norm_sqr().total_cmp(&b.norm_sqr()))
+            .unwrap();
+        let global_phase = phase_of(lhs.as_slice()[pivot] / rhs.as_slice()[pivot]);
+
+        let deviation = lhs - rhs * global_phase;
+        let (worst, max_deviation) = deviation
+            .iter()
+            .map(|c| c.norm())
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap();
+        let equivalent = max_deviation <= self.tolerance;
+        // column major storage, the column of the element is the basis input
+        let counterexample = (!equivalent)
+            .then(|| QRegister::basis(lhs.nrows().trailing_zeros() as usize, worst / lhs.nrows()));
+
+        EquivalenceReport {
+            equivalent,
+            method: EquivalenceMethod::Exact,
+            global_phase,
+            max_deviation,
+            counterexample,
+        }
+    }
+
+    /// Compare the output states of two evaluations on random input states.
+    fn check_states(
+        &self,
+        n_qubits: usize,
+        lhs: impl Fn(QRegister) -> QRegister,
+        rhs: impl Fn(QRegister) -> QRegister,
+    ) -> EquivalenceReport {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        let mut global_phase = None;
+        let mut max_deviation = 0.0;
+        let mut counterexample = None;
+
+        for _ in 0..self.samples {
+            let input = QRegister::random(n_qubits, &mut rng);
+            let a = lhs(input.clone()).qubits;
+            let b = rhs(input.clone()).qubits;
+            let phase = *global_phase.get_or_insert_with(|| phase_of(b.dotc(&a)));
+            let deviation = max_modulus(&(a - b * phase));
+            if deviation > max_deviation {
+                max_deviation = deviation;
+                counterexample = Some(input);
+            }
+        }
+
+        let equivalent = max_deviation <= self.tolerance;
+        EquivalenceReport {
+            equivalent,
+            method: EquivalenceMethod::Randomized {
+                samples: self.samples,
+            },
+            global_phase: global_phase.unwrap(),
+            max_deviation,
+            counterexample: counterexample.filter(|_| !equivalent),
+        }
+    }
+}
+
+/// Return the largest modulus of the amplitudes of a state.
+fn max_modulus(amplitudes: &DVector<Complex<f64>>) -> f64 {
+    amplitudes.iter().map(|c| c.norm()).fold(0.0, f64::max)
+}
+
+/// Return the unit complex number with the same argument, or 1 if the number
+/// is zero.
+fn phase_of(c: Complex<f64>) -> Complex<f64> {
+    if c.norm() == 0.0 {
+        Complex::new(1.0, 0.0)
+    } else {
+        c / c.norm()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn inverted_cnot() -> (QuantumCircuit, QuantumCircuit) {
+        let mut lhs = QuantumCircuit::new(2);
+        lhs.g_h(0);
+        lhs.g_h(1);
+        lhs.g_cx(0, 1);
+        lhs.g_h(0);
+        lhs.g_h(1);
+        let mut rhs = QuantumCircuit::new(2);
+        rhs.g_cx(1, 0);
+        (lhs, rhs)
+    }
+
+    #[test]
+    fn exact_equivalence() {
+        let (lhs, rhs) = inverted_cnot();
+        let report = EquivalenceChecker::new().check(&lhs, &rhs);
+        assert!(report.equivalent);
+        assert_eq!(report.method, EquivalenceMethod::Exact);
+        assert!(report.counterexample.is_none());
+    }
+
+    #[test]
+    fn global_phase() {
+        // RZ(θ) = e^(-iθ/2) P(θ)
+        let mut lhs = QuantumCircuit::new(1);
+        lhs.g_rz(0.5, 0);
+        let mut rhs = QuantumCircuit::new(1);
+        rhs.g_p(0.5, 0);
+
+        let report = EquivalenceChecker::new().check(&lhs, &rhs);
+        assert!(report.equivalent);
+        assert!((report.global_phase - Complex::cis(-0.25)).norm() < 1e-10);
+
+        let report = EquivalenceChecker::new()
+            .exact_max_qubits(0)
+            .check_block(&lhs, &rhs.eval());
+        assert!(report.equivalent);
+        assert!((report.global_phase - Complex::cis(-0.25)).norm() < 1e-10);
+    }
+
+    #[test]
+    fn deviation_is_the_modulus() {
+        // |1 + i| = √2, while its 1-norm is 2
+        let amplitudes = DVector::from_vec(vec![Complex::new(1.0, 1.0), Complex::new(0.0, 0.5)]);
+        assert!((max_modulus(&amplitudes) - 2f64.sqrt()).abs() < 1e-12);
+
+        // once the phase i of the pivot is removed, P(π/2) = diag(1, i)
+        // differs from the identity by 1 - i on the first entry
+        let mut lhs = QuantumCircuit::new(1);
+        lhs.g_p(FRAC_PI_2, 0);
+        let rhs = QuantumCircuit::new(1);
+        let report = EquivalenceChecker::new().check(&lhs, &rhs);
+        assert!((report.max_deviation - 2f64.sqrt()).abs() < 1e-12);
+        assert!(!report.equivalent);
+    }
+
+    #[test]
+    fn counterexample() {
+        let (lhs, _) = inverted_cnot();
+        let mut rhs = QuantumCircuit::new(2);
+        rhs.g_cx(0, 1);
+
+        let report = EquivalenceChecker::new().check(&lhs, &rhs);
+        assert!(!report.equivalent);
+        let input = report.counterexample.unwrap();
+        let deviation =
+            max_modulus(&(lhs.eval_state(input.clone()).qubits - rhs.eval_state(input).qubits));
+        assert!(deviation > 0.5);
+
+        let report = EquivalenceChecker::new()
+            .exact_max_qubits(0)
+            .check(&lhs, &rhs);
+        assert!(!report.equivalent);
+        assert!(report.counterexample.is_some());
+    }
+}
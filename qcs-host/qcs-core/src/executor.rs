@@ -4,13 +4,18 @@
 //! As for now there is only one executor, the `CpuExecutor`, which is responsible for
 //! executing the instructions on the CPU.
 
+pub mod diagnostics;
+
 use std::{fmt::Debug, sync::Arc, thread::JoinHandle};
 
 use crossbeam::channel::unbounded;
 use dashmap::{DashMap, DashSet};
+use nalgebra::{Complex, DMatrix};
 
 use crate::{model::blocks::BlockLike, scheduler::ExecutionOperand};
 
+use self::diagnostics::{DiagnosticStore, Diagnostics, RecordingStore};
+
 pub trait InstructionLike: Computation + Debug {
     fn id(&self) -> usize;
 }
@@ -25,18 +30,19 @@ pub trait ExecutorPlan {
 }
 
 pub trait BlockStore<B: BlockLike> {
-    fn load_block(&self, operand: ExecutionOperand<B>) -> B;
+    fn load_block(&self, operand: ExecutionOperand<B>) -> Arc<B>;
     fn save_block(&self, id: usize, block: B);
     // fn prepare_computation(&self, instruction: ContractionInstruction) -> Computation;
 }
 
 impl<B: BlockLike> BlockStore<B> for DashMap<usize, B> {
-    /// Loads a block from memory or from the instruction.
+    /// Loads a block from memory or from the instruction, without copying
+    /// the blocks shared with other instructions.
     #[inline]
-    fn load_block(&self, instruction: ExecutionOperand<B>) -> B {
+    fn load_block(&self, instruction: ExecutionOperand<B>) -> Arc<B> {
         match instruction {
             ExecutionOperand::Block(block) => block,
-            ExecutionOperand::Address(id) => self.remove(&id).unwrap().1,
+            ExecutionOperand::Address(id) => Arc::new(self.remove(&id).unwrap().1),
         }
     }
 
@@ -47,6 +53,30 @@ impl<B: BlockLike> BlockStore<B> for DashMap<usize, B> {
     }
 }
 
+/// A store that changes the storage of the blocks it loads and saves to the
+/// density of the executor.
+struct DensityStore<S> {
+    store: Arc<S>,
+    density: f64,
+}
+
+impl<B: BlockLike, S: BlockStore<B>> BlockStore<B> for DensityStore<S> {
+    /// Loads a block, copying the blocks of the instruction only if their
+    /// storage changes.
+    fn load_block(&self, operand: ExecutionOperand<B>) -> Arc<B> {
+        let block = self.store.load_block(operand);
+        match block.with_density(self.density) {
+            Some(stored) => Arc::new(stored),
+            None => block,
+        }
+    }
+
+    fn save_block(&self, id: usize, block: B) {
+        let block = block.with_density(self.density).unwrap_or(block);
+        self.store.save_block(id, block);
+    }
+}
+
 pub trait Computation {
     type BlockKind: BlockLike;
 
@@ -59,6 +89,8 @@ pub struct CpuExecutor<B: BlockLike> {
     /// The memory of the executor, which is a map from the id of the block to the block itself.
     memory: Arc<DashMap<usize, B>>,
     threads: Vec<JoinHandle<()>>,
+    /// The density the blocks are stored with, see `BlockLike::with_density`.
+    sparse_density: Option<f64>,
 }
 
 impl<B> CpuExecutor<B>
@@ -70,9 +102,18 @@ where
         Self {
             memory: Arc::new(DashMap::new()),
             threads: Vec::new(),
+            sparse_density: None,
         }
     }
 
+    /// Store the blocks loaded and computed by the executor as sparse if the
+    /// fraction of their non-zero entries is at most density, instead of
+    /// `DEFAULT_SPARSE_DENSITY`. A density of 0 keeps them dense.
+    pub fn sparse_density(mut self, density: f64) -> Self {
+        self.sparse_density = Some(density);
+        self
+    }
+
     /// Spawns a new thread and keeps track of it.
     pub fn spawn<F>(&mut self, f: F)
     where
@@ -89,10 +130,84 @@ where
     }
 
     /// Execute a contraction plan, in parallel.
-    pub fn execute<E, I>(mut self, mut plan: E) -> Vec<B>
+    pub fn execute<E, I>(mut self, plan: E) -> Vec<B>
     where
         I: Computation<BlockKind = B> + InstructionLike + Send + 'static,
         E: ExecutorPlan<Instruction = I> + Send + 'static,
+    {
+        let memory = Arc::clone(&self.memory);
+        self.run(plan, memory);
+        self.into_blocks()
+    }
+
+    /// Execute a plan like `execute`, checking the unitarity of the result of
+    /// every instruction.
+    pub fn diagnose<E, I>(self, plan: E) -> (Vec<B>, Diagnostics)
+    where
+        B: AsRef<DMatrix<Complex<B::Scalar>>>,
+        I: Computation<BlockKind = B> + InstructionLike + Send + 'static,
+        E: ExecutorPlan<Instruction = I> + Send + 'static,
+    {
+        self.run_diagnosed::<E, I, B>(plan, None)
+    }
+
+    /// Execute a plan like `diagnose`, comparing the result of every
+    /// instruction with the result of the instruction of the same id in a
+    /// reference plan, usually the same plan in a higher precision. The
+    /// reference plan is executed first, keeping all its intermediate results.
+    pub fn diagnose_against<E, I, RE, RI, R>(self, plan: E, reference: RE) -> (Vec<B>, Diagnostics)
+    where
+        B: AsRef<DMatrix<Complex<B::Scalar>>>,
+        I: Computation<BlockKind = B> + InstructionLike + Send + 'static,
+        E: ExecutorPlan<Instruction = I> + Send + 'static,
+        R: BlockLike + AsRef<DMatrix<Complex<R::Scalar>>> + Send + Sync + 'static,
+        RI: Computation<BlockKind = R> + InstructionLike + Send + 'static,
+        RE: ExecutorPlan<Instruction = RI> + Send + 'static,
+    {
+        let mut executor = CpuExecutor::<R>::new();
+        let store = Arc::new(RecordingStore::new(Arc::clone(&executor.memory)));
+        executor.run(reference, Arc::clone(&store));
+        drop(executor);
+        let reference = Arc::try_unwrap(store).ok().unwrap().into_record();
+        self.run_diagnosed(plan, Some(reference))
+    }
+
+    fn run_diagnosed<E, I, R>(
+        mut self,
+        plan: E,
+        reference: Option<DashMap<usize, R>>,
+    ) -> (Vec<B>, Diagnostics)
+    where
+        B: AsRef<DMatrix<Complex<B::Scalar>>>,
+        I: Computation<BlockKind = B> + InstructionLike + Send + 'static,
+        E: ExecutorPlan<Instruction = I> + Send + 'static,
+        R: BlockLike + AsRef<DMatrix<Complex<R::Scalar>>> + Send + Sync + 'static,
+    {
+        let store = Arc::new(DiagnosticStore::new(Arc::clone(&self.memory), reference));
+        self.run(plan, Arc::clone(&store));
+        let diagnostics = Arc::try_unwrap(store).ok().unwrap().into_diagnostics();
+        (self.into_blocks(), diagnostics)
+    }
+
+    /// Run the instructions of a plan in parallel, loading and saving the
+    /// blocks in a store.
+    fn run<E, I, S>(&mut self, plan: E, store: Arc<S>)
+    where
+        I: Computation<BlockKind = B> + InstructionLike + Send + 'static,
+        E: ExecutorPlan<Instruction = I> + Send + 'static,
+        S: BlockStore<B> + Send + Sync + 'static,
+    {
+        match self.sparse_density {
+            Some(density) => self.run_in(plan, Arc::new(DensityStore { store, density })),
+            None => self.run_in(plan, store),
+        }
+    }
+
+    fn run_in<E, I, S>(&mut self, mut plan: E, store: Arc<S>)
+    where
+        I: Computation<BlockKind = B> + InstructionLike + Send + 'static,
+        E: ExecutorPlan<Instruction = I> + Send + 'static,
+        S: BlockStore<B> + Send + Sync + 'static,
     {
         // create a channel to communicate the need of more instructions
         let (wtx, wrx) = unbounded();
@@ -125,7 +240,7 @@ where
         for _ in 0..num_cpus::get() {
             let wtx_clone = wtx.clone();
             let irx_clone = irx.clone();
-            let block_map = Arc::clone(&self.memory);
+            let block_map = Arc::clone(&store);
             self.spawn(move || {
                 while let Ok(instruction) = irx_clone.recv() {
                     let id = instruction.compute(&*block_map);
@@ -138,7 +253,10 @@ where
 
         // wait for all threads to finish
         self.join_all();
+    }
 
+    /// Return the blocks left in memory, the results of the plan.
+    fn into_blocks(self) -> Vec<B> {
         Arc::try_unwrap(self.memory)
             .unwrap()
             .into_iter()
@@ -0,0 +1,290 @@
+//! Module containing the diagnostics of the numerical error of an execution.
+//!
+//! Every intermediate result of a contraction is a product of unitary gates,
+//! so its distance from unitarity, ‖UU†−I‖, measures the error accumulated
+//! up to it without any reference. When the same plan is also executed in a
+//! higher precision, the distance between the two results of each instruction
+//! measures the error of the lower precision directly. Both are computed in
+//! double precision by `CpuExecutor::diagnose` and
+//! `CpuExecutor::diagnose_against`, as the blocks are saved.
+
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use nalgebra::{Complex, DMatrix};
+
+use crate::{
+    model::{
+        blocks::BlockLike,
+        scalar::{cast_matrix, deviation, Scalar},
+    },
+    scheduler::ExecutionOperand,
+};
+
+use super::BlockStore;
+
+/// The number of instructions shown for each measure by the report.
+const REPORTED: usize = 5;
+
+/// Return the distance from unitarity of a matrix, ‖UU†−I‖ in Frobenius norm.
+/// Matrices with more rows than columns, as the states, are checked with
+/// ‖U†U−I‖ instead, that is the distance of the norm of a state from one.
+pub fn unitarity_defect<T: Scalar>(matrix: &DMatrix<Complex<T>>) -> f64 {
+    let matrix = cast_matrix::<T, f64>(matrix);
+    let gram = if matrix.nrows() > matrix.ncols() {
+        matrix.adjoint() * &matrix
+    } else {
+        &matrix * matrix.adjoint()
+    };
+    let size = gram.nrows();
+    (gram - DMatrix::identity(size, size)).norm()
+}
+
+/// The diagnostics of the result of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstructionDiagnostics {
+    /// The id of the instruction.
+    pub id: usize,
+    /// The distance of the result from unitarity, see `unitarity_defect`.
+    pub unitarity: f64,
+    /// The Frobenius norm of the difference between the result and the
+    /// result of the reference, if the instruction has one.
+    pub deviation: Option<f64>,
+}
+
+/// The diagnostics of all the instructions of an execution, sorted by id.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    pub instructions: Vec<InstructionDiagnostics>,
+}
+
+impl Diagnostics {
+    /// Return the diagnostics of an instruction.
+    pub fn get(&self, id: usize) -> Option<&InstructionDiagnostics> {
+        self.instructions
+            .binary_search_by_key(&id, |d| d.id)
+            .ok()
+            .map(|index| &self.instructions[index])
+    }
+
+    /// Return the largest distance from unitarity.
+    pub fn max_unitarity(&self) -> f64 {
+        self.instructions
+            .iter()
+            .map(|d| d.unitarity)
+            .fold(0.0, f64::max)
+    }
+
+    /// Return the largest deviation from the reference, if there is one.
+    pub fn max_deviation(&self) -> Option<f64> {
+        self.instructions
+            .iter()
+            .filter_map(|d| d.deviation)
+            .reduce(f64::max)
+    }
+
+    /// Return the `count` instructions farthest from unitarity, the worst first.
+    pub fn worst_unitarity(&self, count: usize) -> Vec<&InstructionDiagnostics> {
+        self.worst_by(count, |d| Some(d.unitarity))
+    }
+
+    /// Return the `count` instructions with the largest deviation from the
+    /// reference, the worst first.
+    pub fn worst_deviation(&self, count: usize) -> Vec<&InstructionDiagnostics> {
+        self.worst_by(count, |d| d.deviation)
+    }
+
+    fn worst_by(
+        &self,
+        count: usize,
+        measure: impl Fn(&InstructionDiagnostics) -> Option<f64>,
+    ) -> Vec<&InstructionDiagnostics> {
+        let mut worst: Vec<_> = self
+            .instructions
+            .iter()
+            .filter_map(|d| measure(d).map(|m| (m, d)))
+            .collect();
+        // ties are broken by id, so that the report is deterministic
+        worst.sort_by(|(a, da), (b, db)| b.total_cmp(a).then(da.id.cmp(&db.id)));
+        worst.into_iter().take(count).map(|(_, d)| d).collect()
+    }
+}
+
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Diagnostics of {} instructions:",
+            self.instructions.len()
+        )?;
+        writeln!(f, "  max ‖UU†−I‖: {:.3e}", self.max_unitarity())?;
+        for d in self.worst_unitarity(REPORTED) {
+            writeln!(f, "    {:03}: {:.3e}", d.id, d.unitarity)?;
+        }
+        if let Some(max) = self.max_deviation() {
+            writeln!(f, "  max deviation: {:.3e}", max)?;
+            for d in self.worst_deviation(REPORTED) {
+                writeln!(f, "    {:03}: {:.3e}", d.id, d.deviation.unwrap())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A store that diagnoses every block saved, before saving it in the memory
+/// of the executor.
+pub(super) struct DiagnosticStore<B: BlockLike, R: BlockLike> {
+    memory: Arc<DashMap<usize, B>>,
+    reference: Option<DashMap<usize, R>>,
+    records: DashMap<usize, InstructionDiagnostics>,
+}
+
+impl<B: BlockLike, R: BlockLike> DiagnosticStore<B, R> {
+    pub(super) fn new(
+        memory: Arc<DashMap<usize, B>>,
+        reference: Option<DashMap<usize, R>>,
+    ) -> Self {
+        Self {
+            memory,
+            reference,
+            records: DashMap::new(),
+        }
+    }
+
+    pub(super) fn into_diagnostics(self) -> Diagnostics {
+        let mut instructions: Vec<_> = self.records.into_iter().map(|(_, d)| d).collect();
+        instructions.sort_by_key(|d| d.id);
+        Diagnostics { instructions }
+    }
+}
+
+impl<B, R> BlockStore<B> for DiagnosticStore<B, R>
+where
+    B: BlockLike + AsRef<DMatrix<Complex<B::Scalar>>>,
+    R: BlockLike + AsRef<DMatrix<Complex<R::Scalar>>>,
+{
+    fn load_block(&self, operand: ExecutionOperand<B>) -> Arc<B> {
+        self.memory.load_block(operand)
+    }
+
+    fn save_block(&self, id: usize, block: B) {
+        let deviation = self
+            .reference
+            .as_ref()
+            .and_then(|reference| reference.get(&id))
+            .map(|expected| deviation(block.as_ref(), expected.as_ref()));
+        let diagnostics = InstructionDiagnostics {
+            id,
+            unitarity: unitarity_defect(block.as_ref()),
+            deviation,
+        };
+        self.records.insert(id, diagnostics);
+        self.memory.save_block(id, block);
+    }
+}
+
+/// A store that keeps a copy of every block saved, to be used as the
+/// reference of a diagnosis.
+pub(super) struct RecordingStore<R: BlockLike> {
+    memory: Arc<DashMap<usize, R>>,
+    record: DashMap<usize, R>,
+}
+
+impl<R: BlockLike> RecordingStore<R> {
+    pub(super) fn new(memory: Arc<DashMap<usize, R>>) -> Self {
+        Self {
+            memory,
+            record: DashMap::new(),
+        }
+    }
+
+    pub(super) fn into_record(self) -> DashMap<usize, R> {
+        self.record
+    }
+}
+
+impl<R: BlockLike> BlockStore<R> for RecordingStore<R> {
+    fn load_block(&self, operand: ExecutionOperand<R>) -> Arc<R> {
+        self.memory.load_block(operand)
+    }
+
+    fn save_block(&self, id: usize, block: R) {
+        self.record.insert(id, block.clone());
+        self.memory.save_block(id, block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::fixtures::circuit_contraction,
+        executor::CpuExecutor,
+        library,
+        model::{
+            blocks::{Block, SpannedBlock},
+            fixed::FixedFormat,
+        },
+        op_tree::Operation,
+        scheduler::{ContractionPlan, OperationPlan},
+    };
+
+    #[test]
+    fn unitarity_of_blocks_and_states() {
+        let qft = library::qft(3).eval();
+        assert!(unitarity_defect(qft.as_ref()) < 1e-12);
+        let doubled = qft.as_ref() * Complex::new(2.0, 0.0);
+        // 8 eigenvalues of UU† equal to 4
+        assert!((unitarity_defect(&doubled) - 3.0 * 8f64.sqrt()).abs() < 1e-9);
+        let state = DMatrix::from_element(4, 1, Complex::new(0.5, 0.0));
+        assert!(unitarity_defect(&state) < 1e-12);
+        assert!((unitarity_defect(&(state * Complex::new(2.0, 0.0))) - 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn single_precision_against_double() {
+        let contraction = circuit_contraction(library::qft(5));
+        let plan = ContractionPlan::<SpannedBlock<f32>>::new(contraction.clone()).unwrap();
+        let reference = ContractionPlan::<SpannedBlock>::new(contraction.clone()).unwrap();
+        let (blocks, diagnostics) = CpuExecutor::new().diagnose_against(plan, reference);
+
+        assert_eq!(blocks.len(), 1);
+        assert!(diagnostics.instructions.len() > 1);
+        assert!(diagnostics
+            .instructions
+            .iter()
+            .all(|d| d.deviation.is_some()));
+        let max = diagnostics.max_deviation().unwrap();
+        assert!(max > 0.0 && max < 1e-4, "deviation {}", max);
+        assert!(diagnostics.max_unitarity() < 1e-4);
+        let worst = diagnostics.worst_deviation(3);
+        assert_eq!(worst[0].deviation, Some(max));
+        assert!(worst.windows(2).all(|w| w[0].deviation >= w[1].deviation));
+        assert_eq!(diagnostics.get(worst[0].id), Some(worst[0]));
+
+        // the same plan in double precision is its own reference
+        let plan = ContractionPlan::<SpannedBlock>::new(contraction.clone()).unwrap();
+        let reference = ContractionPlan::<SpannedBlock>::new(contraction).unwrap();
+        let (_, diagnostics) = CpuExecutor::new().diagnose_against(plan, reference);
+        assert_eq!(diagnostics.max_deviation(), Some(0.0));
+        assert!(diagnostics.max_unitarity() < 1e-12);
+    }
+
+    #[test]
+    fn fixed_point_error_budget() {
+        let operation =
+            Operation::from_contraction(circuit_contraction(library::qft(4)), false).unwrap();
+        let diagnose = |frac_bits| {
+            let plan = OperationPlan::from(operation.clone());
+            let fixed = plan.clone().fixed_point(FixedFormat::new(2, frac_bits));
+            CpuExecutor::<Block>::new().diagnose_against(fixed, plan).1
+        };
+        let (fine, coarse) = (diagnose(24), diagnose(10));
+        assert!(fine.max_deviation().unwrap() < coarse.max_deviation().unwrap());
+        assert!(fine.max_unitarity() < coarse.max_unitarity());
+
+        let (_, alone) = CpuExecutor::<Block>::new().diagnose(OperationPlan::from(operation));
+        assert_eq!(alone.max_deviation(), None);
+        assert!(!alone.to_string().contains("deviation"));
+    }
+}
@@ -1,6 +1,10 @@
+pub mod analysis;
 pub mod compiler;
 pub mod contractions;
+pub mod cost;
+pub mod equivalence;
 pub mod executor;
+pub mod library;
 pub mod model;
 pub mod op_tree;
 pub mod scheduler;
@@ -0,0 +1,431 @@
+//! This module contains generators of standard quantum circuits, used by the
+//! tests and the benchmarks.
+//!
+//! The circuits are built with the `g_*` methods of `QuantumCircuit` in the
+//! order in which the gates act on the register, and the gates are reversed
+//! before the circuit is returned, as `QuantumCircuit::eval` and
+//! `QuantumCircuit::eval_state` apply the last gate first. Registers follow
+//! the rest of the model: lane 0 is the most significant bit of a basis index.
+//!
+//! Multi-controlled gates are decomposed in Toffoli gates with a chain of
+//! ancilla lanes, that start and end in |0⟩.
+
+use std::f64::consts::PI;
+
+use crate::model::QuantumCircuit;
+
+/// Generates the quantum Fourier transform on n qubits, including the final
+/// swaps, so that |x⟩ is mapped to 1/√N Σ_y e^(2πixy/N) |y⟩.
+pub fn qft(n_qubits: usize) -> QuantumCircuit {
+    let mut circuit = QuantumCircuit::new(n_qubits);
+    let lanes = (0..n_qubits).collect::<Vec<_>>();
+    append_qft(&mut circuit, &lanes, false);
+    into_crate_order(circuit)
+}
+
+/// Generates the inverse of the quantum Fourier transform on n qubits.
+pub fn inverse_qft(n_qubits: usize) -> QuantumCircuit {
+    let mut circuit = QuantumCircuit::new(n_qubits);
+    let lanes = (0..n_qubits).collect::<Vec<_>>();
+    append_qft(&mut circuit, &lanes, true);
+    into_crate_order(circuit)
+}
+
+/// Generates the circuit preparing the GHZ state (|0…0⟩ + |1…1⟩)/√2 from
+/// |0…0⟩, with a Hadamard gate followed by a chain of CNOT gates.
+pub fn ghz(n_qubits: usize) -> QuantumCircuit {
+    assert!(n_qubits > 0, "GHZ circuit requires at least one qubit");
+    let mut circuit = QuantumCircuit::new(n_qubits);
+    circuit.g_h(0);
+    for lane in 1..n_qubits {
+        circuit.g_cx(lane - 1, lane);
+    }
+    into_crate_order(circuit)
+}
+
+/// Generates the Bernstein-Vazirani circuit for a secret string of n bits,
+/// where lane 0 holds the most significant bit of the secret.
+///
+/// The circuit has n + 1 lanes, the last one being the ancilla of the oracle.
+/// Starting from |0…0⟩ the search lanes end in |secret⟩ and the ancilla in |1⟩.
+pub fn bernstein_vazirani(n_qubits: usize, secret: usize) -> QuantumCircuit {
+    assert!(
+        secret < 1 << n_qubits,
+        "Secret does not fit in the register"
+    );
+    let ancilla = n_qubits;
+    let mut circuit = QuantumCircuit::new(n_qubits + 1);
+    circuit.g_x(ancilla);
+    (0..=n_qubits).for_each(|lane| circuit.g_h(lane));
+    for lane in 0..n_qubits {
+        if secret >> (n_qubits - 1 - lane) & 1 == 1 {
+            circuit.g_cx(lane, ancilla);
+        }
+    }
+    (0..=n_qubits).for_each(|lane| circuit.g_h(lane));
+    into_crate_order(circuit)
+}
+
+/// Returns the number of ancilla lanes used by the multi-controlled Z gates of
+/// a Grover circuit on n search lanes, that have n - 1 controls.
+pub fn grover_ancillas(n_qubits: usize) -> usize {
+    n_qubits.saturating_sub(3)
+}
+
+/// Returns the number of Grover iterations that maximises the probability of
+/// measuring a single marked state among 2^n.
+pub fn grover_iterations(n_qubits: usize) -> usize {
+    let theta = (1.0 / ((1 << n_qubits) as f64).sqrt()).asin();
+    (PI / (4.0 * theta) - 0.5).round() as usize
+}
+
+/// Generates the phase oracle flipping the sign of the basis state |marked⟩
+/// of n search lanes. The oracle acts on n + `grover_ancillas(n)` lanes.
+pub fn phase_oracle(n_qubits: usize, marked: usize) -> QuantumCircuit {
+    assert!(
+        marked < 1 << n_qubits,
+        "Marked state does not fit in the register"
+    );
+    let mut circuit = QuantumCircuit::new(n_qubits + grover_ancillas(n_qubits));
+    let zeros = (0..n_qubits)
+        .filter(|lane| marked >> (n_qubits - 1 - lane) & 1 == 0)
+        .collect::<Vec<_>>();
+    let lanes = (0..n_qubits).collect::<Vec<_>>();
+    let ancillas = (n_qubits..circuit.n_qubits).collect::<Vec<_>>();
+
+    zeros.iter().for_each(|&lane| circuit.g_x(lane));
+    append_mcz(&mut circuit, &lanes, &ancillas);
+    zeros.iter().for_each(|&lane| circuit.g_x(lane));
+    into_crate_order(circuit)
+}
+
+/// Generates Grover's search on n lanes with the given oracle, repeating the
+/// oracle and the diffusion operator for a number of iterations.
+///
+/// The oracle acts on the n search lanes followed by its ancilla lanes, which
+/// must be at least `grover_ancillas(n)` as they are shared with the diffusion
+/// operator.
+pub fn grover(n_qubits: usize, oracle: &QuantumCircuit, iterations: usize) -> QuantumCircuit {
+    assert!(
+        oracle.n_qubits >= n_qubits + grover_ancillas(n_qubits),
+        "Oracle does not have enough ancilla lanes"
+    );
+    let mut circuit = QuantumCircuit::new(oracle.n_qubits);
+    let lanes = (0..n_qubits).collect::<Vec<_>>();
+    let ancillas = (n_qubits..oracle.n_qubits).collect::<Vec<_>>();
+
+    lanes.iter().for_each(|&lane| circuit.g_h(lane));
+    for _ in 0..iterations {
+        // the oracle is in the order of the crate, the last gate first
+        circuit.gates.extend(oracle.gates.iter().rev().cloned());
+        lanes.iter().for_each(|&lane| circuit.g_h(lane));
+        lanes.iter().for_each(|&lane| circuit.g_x(lane));
+        append_mcz(&mut circuit, &lanes, &ancillas);
+        lanes.iter().for_each(|&lane| circuit.g_x(lane));
+        lanes.iter().for_each(|&lane| circuit.g_h(lane));
+    }
+    into_crate_order(circuit)
+}
+
+/// Generates the Cuccaro ripple-carry adder of two n-bit numbers.
+///
+/// The lanes are laid out as: the input carry on lane 0, the bits of a on
+/// lanes 1..=n, the bits of b on lanes n+1..=2n and the output carry on lane
+/// 2n+1, where the bits of each number are ordered from the least significant.
+/// The circuit maps |c, a, b, z⟩ to |c, a, a + b + c mod 2^n, z ⊕ carry⟩.
+pub fn ripple_carry_adder(n_bits: usize) -> QuantumCircuit {
+    assert!(n_bits > 0, "Adder requires at least one bit");
+    let mut circuit = QuantumCircuit::new(2 * n_bits + 2);
+    let a = |i: usize| 1 + i;
+    let b = |i: usize| 1 + n_bits + i;
+    let carry_in = |i: usize| if i == 0 { 0 } else { a(i - 1) };
+
+    for i in 0..n_bits {
+        // MAJ
+        circuit.g_cx(a(i), b(i));
+        circuit.g_cx(a(i), carry_in(i));
+        circuit.g_cxx(carry_in(i), b(i), a(i));
+    }
+    circuit.g_cx(a(n_bits - 1), 2 * n_bits + 1);
+    for i in (0..n_bits).rev() {
+        // UMA
+        circuit.g_cxx(carry_in(i), b(i), a(i));
+        circuit.g_cx(a(i), carry_in(i));
+        circuit.g_cx(carry_in(i), b(i));
+    }
+    into_crate_order(circuit)
+}
+
+/// Generates the QAOA circuit for MaxCut on the graph given by its edges, with
+/// one layer for each pair of angles (γ, β).
+///
+/// Each layer applies the cost unitary e^(-iγ Z_u Z_v) on every edge, followed
+/// by the mixer e^(-iβ X) on every lane, starting from the uniform
+/// superposition.
+pub fn qaoa(
+    n_qubits: usize,
+    edges: &[(usize, usize)],
+    gammas: &[f64],
+    betas: &[f64],
+) -> QuantumCircuit {
+    assert_eq!(gammas.len(), betas.len(), "Different number of angles");
+    let mut circuit = QuantumCircuit::new(n_qubits);
+    (0..n_qubits).for_each(|lane| circuit.g_h(lane));
+    for (&gamma, &beta) in gammas.iter().zip(betas) {
+        for &(u, v) in edges {
+            circuit.g_cx(u, v);
+            circuit.g_rz(2.0 * gamma, v);
+            circuit.g_cx(u, v);
+        }
+        (0..n_qubits).for_each(|lane| circuit.g_rx(2.0 * beta, lane));
+    }
+    into_crate_order(circuit)
+}
+
+/// Returns the number of parameters of a hardware-efficient ansatz.
+pub fn ansatz_parameters(n_qubits: usize, depth: usize) -> usize {
+    2 * n_qubits * (depth + 1)
+}
+
+/// Generates a hardware-efficient ansatz of the given depth: a layer of RY and
+/// RZ rotations on every lane, followed by `depth` repetitions of a linear
+/// chain of CNOT gates and another rotation layer.
+///
+/// The parameters are consumed layer by layer, lane by lane, RY first, and
+/// must be `ansatz_parameters(n_qubits, depth)`.
+pub fn hardware_efficient_ansatz(n_qubits: usize, depth: usize, params: &[f64]) -> QuantumCircuit {
+    assert_eq!(
+        params.len(),
+        ansatz_parameters(n_qubits, depth),
+        "Wrong number of parameters"
+    );
+    let mut circuit = QuantumCircuit::new(n_qubits);
+    let mut params = params.chunks(2);
+    let mut rotations = |circuit: &mut QuantumCircuit| {
+        for lane in 0..n_qubits {
+            let angles = params.next().unwrap();
+            circuit.g_ry(angles[0], lane);
+            circuit.g_rz(angles[1], lane);
+        }
+    };
+
+    rotations(&mut circuit);
+    for _ in 0..depth {
+        for lane in 1..n_qubits {
+            circuit.g_cx(lane - 1, lane);
+        }
+        rotations(&mut circuit);
+    }
+    into_crate_order(circuit)
+}
+
+/// Reverses the gates of a circuit built in the order in which they act, so
+/// that the last gate is the first one to be applied by the evaluators.
+fn into_crate_order(mut circuit: QuantumCircuit) -> QuantumCircuit {
+    circuit.gates.reverse();
+    circuit
+}
+
+/// Appends the quantum Fourier transform (or its inverse) on the lanes, the
+/// first lane being the most significant bit.
+fn append_qft(circuit: &mut QuantumCircuit, lanes: &[usize], inverse: bool) {
+    let n = lanes.len();
+    let swaps = |circuit: &mut QuantumCircuit| {
+        for i in 0..n / 2 {
+            circuit.g_swap(lanes[i], lanes[n - 1 - i]);
+        }
+    };
+
+    if inverse {
+        swaps(circuit);
+        for j in (0..n).rev() {
+            for k in (j + 1..n).rev() {
+                circuit.g_cp(-PI / (1 << (k - j)) as f64, lanes[k], lanes[j]);
+            }
+            circuit.g_h(lanes[j]);
+        }
+    } else {
+        for j in 0..n {
+            circuit.g_h(lanes[j]);
+            for k in j + 1..n {
+                circuit.g_cp(PI / (1 << (k - j)) as f64, lanes[k], lanes[j]);
+            }
+        }
+        swaps(circuit);
+    }
+}
+
+/// Appends a multi-controlled X gate, computing the conjunction of the
+/// controls on a chain of ancillas (at least controls - 2) and uncomputing it
+/// afterwards.
+fn append_mcx(circuit: &mut QuantumCircuit, controls: &[usize], target: usize, ancillas: &[usize]) {
+    match controls {
+        [] => circuit.g_x(target),
+        [c] => circuit.g_cx(*c, target),
+        [c1, c2] => circuit.g_cxx(*c1, *c2, target),
+        _ => {
+            let k = controls.len();
+            assert!(ancillas.len() >= k - 2, "Not enough ancilla lanes");
+            let chain = |circuit: &mut QuantumCircuit, i: usize| {
+                if i == 0 {
+                    circuit.g_cxx(controls[0], controls[1], ancillas[0]);
+                } else {
+                    circuit.g_cxx(controls[i + 1], ancillas[i - 1], ancillas[i]);
+                }
+            };
+            (0..k - 2).for_each(|i| chain(circuit, i));
+            circuit.g_cxx(controls[k - 1], ancillas[k - 3], target);
+            (0..k - 2).rev().for_each(|i| chain(circuit, i));
+        }
+    }
+}
+
+/// Appends a multi-controlled Z gate on the lanes, which is symmetric in all
+/// of them.
+fn append_mcz(circuit: &mut QuantumCircuit, lanes: &[usize], ancillas: &[usize]) {
+    match lanes {
+        [] => {}
+        [lane] => circuit.g_z(*lane),
+        [control, target] => circuit.g_cz(*control, *target),
+        [controls @ .., target] => {
+            circuit.g_h(*target);
+            append_mcx(circuit, controls, *target, ancillas);
+            circuit.g_h(*target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_4, FRAC_PI_8};
+
+    use nalgebra::Complex;
+
+    use super::*;
+    use crate::{contractions::TensorNetwork, model::QRegister};
+
+    /// Evaluates the circuit on the input with the state vector evaluator,
+    /// checking that the matrix of the circuit and the contraction of its
+    /// network give the same state.
+    fn run(circuit: &QuantumCircuit, input: QRegister) -> QRegister {
+        let state = circuit.eval_state(input.clone());
+        let matrix = circuit.clone().eval() * input.clone();
+        let contracted = TensorNetwork::from(circuit.clone())
+            .contract_state(input)
+            .unwrap();
+        assert!((&state.qubits - &matrix.qubits).norm() < 1e-9);
+        assert!((&state.qubits - &contracted.qubits).norm() < 1e-9);
+        state
+    }
+
+    fn probability(state: &QRegister, index: usize) -> f64 {
+        state.qubits[index].norm_sqr()
+    }
+
+    #[test]
+    fn qft_known_answer() {
+        let n = 4;
+        let size = 1 << n;
+        for x in [0, 5, 11] {
+            let state = run(&qft(n), QRegister::basis(n, x));
+            for y in 0..size {
+                let expected =
+                    Complex::cis(2.0 * PI * (x * y) as f64 / size as f64) / (size as f64).sqrt();
+                assert!((state.qubits[y] - expected).norm() < 1e-10);
+            }
+            let back = run(&inverse_qft(n), state);
+            assert!((probability(&back, x) - 1.0).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn ghz_known_answer() {
+        for n in 1..6 {
+            let state = run(&ghz(n), QRegister::basis(n, 0));
+            assert!((state.fidelity(&QRegister::ghz(n)) - 1.0).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn bernstein_vazirani_known_answer() {
+        for secret in [0b0000, 0b1011, 0b0110, 0b1111] {
+            let state = run(&bernstein_vazirani(4, secret), QRegister::basis(5, 0));
+            assert!((probability(&state, secret << 1 | 1) - 1.0).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn grover_known_answer() {
+        for (n, marked) in [(2, 2), (3, 5), (4, 9), (5, 17)] {
+            let iterations = grover_iterations(n);
+            let oracle = phase_oracle(n, marked);
+            let circuit = grover(n, &oracle, iterations);
+            let ancillas = grover_ancillas(n);
+            let state = run(&circuit, QRegister::basis(circuit.n_qubits, 0));
+
+            let theta = (1.0 / ((1 << n) as f64).sqrt()).asin();
+            let expected = ((2 * iterations + 1) as f64 * theta).sin().powi(2);
+            assert!((probability(&state, marked << ancillas) - expected).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn ripple_carry_adder_known_answer() {
+        let n = 2;
+        let circuit = ripple_carry_adder(n);
+        let width = circuit.n_qubits;
+        // lanes of each number are ordered from the least significant bit
+        let encode = |value: usize, offset: usize| {
+            (0..n)
+                .filter(|i| value >> i & 1 == 1)
+                .map(|i| 1 << (width - 1 - (offset + i)))
+                .sum::<usize>()
+        };
+        for (c, a, b) in
+            (0..2).flat_map(|c| (0..4).flat_map(move |a| (0..4).map(move |b| (c, a, b))))
+        {
+            let input = encode(c, 0) + encode(a, 1) + encode(b, 1 + n);
+            let sum = a + b + c;
+            let output = encode(c, 0) + encode(a, 1) + encode(sum % 4, 1 + n) + (sum >> n);
+            let state = run(&circuit, QRegister::basis(width, input));
+            assert!((probability(&state, output) - 1.0).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn qaoa_known_answer() {
+        // A single edge is cut with certainty at γ = π/4, β = -π/8
+        let state = run(
+            &qaoa(2, &[(0, 1)], &[FRAC_PI_4], &[-FRAC_PI_8]),
+            QRegister::basis(2, 0),
+        );
+        assert!((probability(&state, 0b01) + probability(&state, 0b10) - 1.0).abs() < 1e-10);
+
+        // With no rotation the state stays in the uniform superposition
+        let edges = [(0, 1), (1, 2), (2, 3), (3, 0)];
+        let state = run(
+            &qaoa(4, &edges, &[0.0; 3], &[0.0; 3]),
+            QRegister::basis(4, 0),
+        );
+        assert!((state.fidelity(&QRegister::uniform(4)) - 1.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn ansatz_known_answer() {
+        let (n, depth) = (4, 3);
+        let circuit = hardware_efficient_ansatz(n, depth, &vec![0.0; ansatz_parameters(n, depth)]);
+        assert_eq!(circuit.gates.len(), 2 * n * (depth + 1) + (n - 1) * depth);
+        let state = run(&circuit, QRegister::basis(n, 0));
+        assert!((probability(&state, 0) - 1.0).abs() < 1e-10);
+
+        // RY(π) on the first layer flips every lane, the CNOT chain then
+        // leaves 1010 and the remaining rotations are trivial
+        let mut params = vec![0.0; ansatz_parameters(n, 1)];
+        (0..n).for_each(|lane| params[2 * lane] = PI);
+        let state = run(
+            &hardware_efficient_ansatz(n, 1, &params),
+            QRegister::basis(n, 0),
+        );
+        assert!((probability(&state, 0b1010) - 1.0).abs() < 1e-10);
+    }
+}
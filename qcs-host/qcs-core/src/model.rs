@@ -3,21 +3,40 @@
 //! The `Qubit` struct represents a single qubit, which is a quantum system with two states.
 //! The `QRegister` struct represents a quantum register, which is a collection of qubits.
 //! The `QuantumCircuit` struct represents a quantum circuit, which is a sequence of quantum gates.
+//! The `DensityMatrix` struct represents the (possibly mixed) state of a subset of lanes of a register.
+//! The `IndexTensor` struct represents a tensor with an output and an input leg per lane.
+//! The `KroneckerBlock` struct represents a matrix padded with identities, without expanding it.
+//! The `SparseBlock` struct represents a matrix by its non-zero entries.
+//! The `MonomialBlock` struct represents a permutation matrix with phases.
+//! The `Scalar` trait is the real type of the entries of blocks and registers, `f32` or `f64`.
 
 pub mod blocks;
+pub mod cache;
+pub mod density;
+pub mod fixed;
 pub mod gates;
+pub mod kronecker;
+pub mod monomial;
+pub mod scalar;
 pub mod span;
+pub mod sparse;
+pub mod tensor;
 
 use nalgebra::{
     allocator::Allocator, Complex, DMatrix, DVector, DefaultAllocator, Dim, DimMul, DimProd, Dyn,
     Matrix, OMatrix, Storage, VecStorage, Vector2,
 };
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::StandardNormal;
+use thiserror::Error;
 
 use crate::model::gates::*;
 
 use self::{
     blocks::Block,
     gates::{Gate, QuantumGate},
+    scalar::{cast, Scalar},
 };
 
 // @@@@@@@@@@@@
@@ -85,26 +104,230 @@ impl From<Qubit> for DVector<Complex<f64>> {
     }
 }
 
+/// Tolerance on the norm of the amplitudes of a register.
+const NORM_TOLERANCE: f64 = 1e-8;
+
+/// The longest bitstring accepted by `QRegister::from_bitstring`, whose state
+/// of 2^32 amplitudes already takes 64 GiB.
+pub const MAX_BITSTRING_LEN: usize = 32;
+
+/// Errors raised when building a quantum register.
+#[derive(Debug, Error, PartialEq)]
+pub enum RegisterError {
+    #[error("The number of amplitudes ({0}) is not a power of two")]
+    InvalidLength(usize),
+    #[error("The amplitudes are not normalized (norm {0})")]
+    NotNormalized(f64),
+    #[error("Invalid character {0:?} in bitstring")]
+    InvalidBitstring(char),
+    #[error("The bitstring has {0} qubits, more than the {MAX_BITSTRING_LEN} supported")]
+    BitstringTooLong(usize),
+}
+
 /// A quantum register is a collection of qubits.
 #[derive(Debug, Clone, PartialEq)]
-pub struct QRegister {
-    pub qubits: DVector<Complex<f64>>,
+pub struct QRegister<T: Scalar = f64> {
+    pub qubits: DVector<Complex<T>>,
 }
 
 impl QRegister {
+    /// Creates the basis state |index⟩ of a register of n qubits, where lane 0
+    /// is the most significant bit of the index.
+    pub fn basis(n_qubits: usize, index: usize) -> Self {
+        assert!(index < 1 << n_qubits, "Index out of register");
+        let mut qubits = DVector::zeros(1 << n_qubits);
+        qubits[index] = Complex::new(1.0, 0.0);
+        QRegister { qubits }
+    }
+
+    /// Creates the basis state described by a bitstring, where the first
+    /// character is lane 0.
+    /// ```
+    /// # use qcs_core::model::{QRegister, Qubit};
+    /// let reg = QRegister::from_bitstring("10").unwrap();
+    /// assert_eq!(reg, QRegister::from([Qubit::one(), Qubit::zero()]));
+    /// ```
+    pub fn from_bitstring(bits: &str) -> Result<Self, RegisterError> {
+        let n_qubits = bits.chars().count();
+        if n_qubits > MAX_BITSTRING_LEN {
+            return Err(RegisterError::BitstringTooLong(n_qubits));
+        }
+        let index = bits.chars().try_fold(0, |acc, c| match c {
+            '0' => Ok(acc << 1),
+            '1' => Ok((acc << 1) | 1),
+            c => Err(RegisterError::InvalidBitstring(c)),
+        })?;
+        Ok(Self::basis(n_qubits, index))
+    }
+
+    /// Creates a register from its amplitudes, checking that their number is
+    /// a power of two and that they are normalized.
+    pub fn from_amplitudes(amplitudes: Vec<Complex<f64>>) -> Result<Self, RegisterError> {
+        if !amplitudes.len().is_power_of_two() {
+            return Err(RegisterError::InvalidLength(amplitudes.len()));
+        }
+        let qubits = DVector::from_vec(amplitudes);
+        let norm = qubits.norm();
+        if (norm - 1.0).abs() > NORM_TOLERANCE {
+            return Err(RegisterError::NotNormalized(norm));
+        }
+        Ok(QRegister { qubits })
+    }
+
+    /// Creates the uniform superposition of all basis states of n qubits.
+    pub fn uniform(n_qubits: usize) -> Self {
+        let amplitude = Complex::new(1.0 / ((1 << n_qubits) as f64).sqrt(), 0.0);
+        QRegister {
+            qubits: DVector::from_element(1 << n_qubits, amplitude),
+        }
+    }
+
+    /// Creates the GHZ state (|0…0⟩ + |1…1⟩)/√2 of n qubits.
+    pub fn ghz(n_qubits: usize) -> Self {
+        assert!(n_qubits > 0, "GHZ state requires at least one qubit");
+        let amplitude = Complex::new(1.0 / 2.0_f64.sqrt(), 0.0);
+        let mut qubits = DVector::zeros(1 << n_qubits);
+        qubits[0] = amplitude;
+        qubits[(1 << n_qubits) - 1] = amplitude;
+        QRegister { qubits }
+    }
+
+    /// Creates the W state (|10…0⟩ + |01…0⟩ + … + |0…01⟩)/√n of n qubits.
+    pub fn w(n_qubits: usize) -> Self {
+        assert!(n_qubits > 0, "W state requires at least one qubit");
+        let amplitude = Complex::new(1.0 / (n_qubits as f64).sqrt(), 0.0);
+        let mut qubits = DVector::zeros(1 << n_qubits);
+        for lane in 0..n_qubits {
+            qubits[1 << lane] = amplitude;
+        }
+        QRegister { qubits }
+    }
+
+    /// Creates a random state of n qubits distributed according to the Haar
+    /// measure, using the given random number generator.
+    pub fn random(n_qubits: usize, rng: &mut impl Rng) -> Self {
+        let qubits = DVector::from_fn(1 << n_qubits, |_, _| {
+            Complex::new(rng.sample(StandardNormal), rng.sample(StandardNormal))
+        });
+        QRegister { qubits }.normalize()
+    }
+
+    /// Creates a random state of n qubits distributed according to the Haar
+    /// measure. The same seed always gives the same state.
+    pub fn haar_random(n_qubits: usize, seed: u64) -> Self {
+        Self::random(n_qubits, &mut ChaCha8Rng::seed_from_u64(seed))
+    }
+
+    /// Returns the register scaled to unit norm.
+    pub fn normalize(self) -> Self {
+        QRegister {
+            qubits: self.qubits.normalize(),
+        }
+    }
+
+    /// Returns the inner product ⟨self|other⟩.
+    pub fn inner_product(&self, other: &QRegister) -> Complex<f64> {
+        self.qubits.dotc(&other.qubits)
+    }
+
+    /// Returns the fidelity |⟨self|other⟩|² between two pure states.
+    pub fn fidelity(&self, other: &QRegister) -> f64 {
+        self.inner_product(other).norm_sqr()
+    }
+
+    /// Returns the bitstring of a basis index of the register, where the first
+    /// character is lane 0.
+    pub fn bitstring(&self, index: usize) -> String {
+        format!("{:0width$b}", index, width = self.n_qubits())
+    }
+
+    /// Returns the k amplitudes with the highest probability, as pairs of
+    /// basis index and amplitude, sorted by decreasing probability.
+    pub fn top_amplitudes(&self, k: usize) -> Vec<(usize, Complex<f64>)> {
+        let mut amplitudes = self.qubits.iter().copied().enumerate().collect::<Vec<_>>();
+        amplitudes
+            .sort_by(|(ia, a), (ib, b)| b.norm_sqr().total_cmp(&a.norm_sqr()).then(ia.cmp(ib)));
+        amplitudes.truncate(k);
+        amplitudes
+    }
+
+    /// Formats the k amplitudes with the highest probability, one per line,
+    /// with their bitstring and probability.
+    pub fn format_top(&self, k: usize) -> String {
+        self.top_amplitudes(k)
+            .into_iter()
+            .map(|(i, a)| {
+                format!(
+                    "|{}⟩ {:+.6}{:+.6}i (p = {:.6})",
+                    self.bitstring(i),
+                    a.re,
+                    a.im,
+                    a.norm_sqr()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
     /// Returns the distribution of the register.
     pub fn distr(&self) -> DVector<f64> {
         let iter = self.qubits.iter().map(|x| x.norm_sqr());
         DVector::from_iterator(self.qubits.len(), iter)
     }
+
+    /// Multiplies the register by the matrix of a gate, acting only on the
+    /// amplitudes of the lanes covered by the filled span of the gate.
+    pub fn apply_gate(&mut self, gate: &Gate) {
+        self.apply_matrix(&gate.shared_matrix(), gate.span().start());
+    }
+
+    /// Apply a matrix to the contiguous lanes starting from `start`, in place,
+    /// without expanding it to the whole register.
+    pub fn apply_matrix(&mut self, matrix: &DMatrix<Complex<f64>>, start: usize) {
+        let n = self.n_qubits();
+        let width = matrix.nrows().ilog2() as usize;
+        let low_bits = n - start - width;
+        let mut amplitudes = DVector::zeros(1 << width);
+        for high in 0..(1usize << start) {
+            for low in 0..(1usize << low_bits) {
+                let base = (high << (n - start)) | low;
+                for k in 0..(1 << width) {
+                    amplitudes[k] = self.qubits[base | (k << low_bits)];
+                }
+                let result = matrix * &amplitudes;
+                for k in 0..(1 << width) {
+                    self.qubits[base | (k << low_bits)] = result[k];
+                }
+            }
+        }
+    }
 }
 
-impl std::fmt::Display for QRegister {
+impl<T: Scalar> QRegister<T> {
+    /// Converts the amplitudes to another precision.
+    pub fn cast<U: Scalar>(&self) -> QRegister<U> {
+        QRegister {
+            qubits: self.qubits.map(cast),
+        }
+    }
+}
+
+impl<T: Scalar> std::fmt::Display for QRegister<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.qubits)
     }
 }
 
+impl TensorProduct for QRegister {
+    type Output = QRegister;
+
+    fn tensor_product(&self, rhs: impl Into<QRegister>) -> Self::Output {
+        QRegister {
+            qubits: self.qubits.kronecker(&rhs.into().qubits),
+        }
+    }
+}
+
 impl From<Qubit> for QRegister {
     fn from(qubit: Qubit) -> Self {
         QRegister {
@@ -113,14 +336,14 @@ impl From<Qubit> for QRegister {
     }
 }
 
-impl AsRef<DVector<Complex<f64>>> for QRegister {
-    fn as_ref(&self) -> &DVector<Complex<f64>> {
+impl<T: Scalar> AsRef<DVector<Complex<T>>> for QRegister<T> {
+    fn as_ref(&self) -> &DVector<Complex<T>> {
         &self.qubits
     }
 }
 
-impl From<QRegister> for DVector<Complex<f64>> {
-    fn from(value: QRegister) -> Self {
+impl<T: Scalar> From<QRegister<T>> for DVector<Complex<T>> {
+    fn from(value: QRegister<T>) -> Self {
         value.qubits
     }
 }
@@ -375,24 +598,60 @@ impl QuantumCircuit {
     /// representation of the whole circuit.
     pub fn eval(self) -> Block {
         let Self { n_qubits, gates } = self;
-        let mut circuit =
-            (0..n_qubits).fold(Block::one(), |acc, i| acc.tensor_product(Identity::new(i)));
+        let mut circuit = Block::identity(1 << n_qubits);
         for gate in gates {
             let span = gate.span();
-            let mut gate_block = gate.block();
-            let mut new_block = Block::one();
-            for i in 0..span.start() {
-                new_block = new_block.tensor_product(Identity::new(i));
-            }
-            new_block = new_block.tensor_product(gate_block);
-            for i in (span.end() + 1)..self.n_qubits {
-                new_block = new_block.tensor_product(Identity::new(i));
-            }
-            gate_block = new_block;
+            let gate_block = Block::identity(1 << span.start())
+                .tensor_product(gate.block())
+                .tensor_product(Block::identity(1 << (n_qubits - span.end() - 1)));
             circuit = &circuit * &gate_block;
         }
         circuit
     }
+
+    /// Evaluates the circuit on an input register, applying the gates one at
+    /// a time on the state vector instead of building the matrix of the whole
+    /// circuit. The result is the same as `eval() * input`, so the last gate is
+    /// the first one to be applied.
+    pub fn eval_state(&self, input: impl Into<QRegister>) -> QRegister {
+        let mut state = input.into();
+        assert_eq!(state.n_qubits(), self.n_qubits, "Incompatible register");
+        for gate in self.gates.iter().rev() {
+            state.apply_gate(gate);
+        }
+        state
+    }
+
+    /// Evaluates the circuit on the basis state |input⟩ as a classical
+    /// reversible circuit, following the basis state through the monomial
+    /// matrix of each gate. Returns the output basis state and its phase, or
+    /// `None` if some gate is not monomial. As in `eval_state`, the last gate
+    /// is the first one to be applied.
+    pub fn eval_classical(&self, input: usize) -> Option<(usize, Complex<f64>)> {
+        assert!(input < 1 << self.n_qubits, "Index out of register");
+        let mut index = input;
+        let mut phase = Complex::new(1.0, 0.0);
+        for gate in self.gates.iter().rev() {
+            let block = gate.block();
+            let monomial = block.as_monomial()?;
+            let span = gate.span();
+            let low_bits = self.n_qubits - span.end() - 1;
+            let mask = (1 << (span.end() - span.start() + 1)) - 1;
+            let (local, value) = monomial.apply((index >> low_bits) & mask);
+            index = (index & !(mask << low_bits)) | (local << low_bits);
+            phase *= value;
+        }
+        Some((index, phase))
+    }
+
+    /// Check if every gate of the circuit is monomial, that is a permutation
+    /// of the basis states possibly with phases, so that `eval_classical`
+    /// evaluates the circuit.
+    pub fn is_monomial(&self) -> bool {
+        self.gates
+            .iter()
+            .all(|gate| gate.structure() != Structure::General)
+    }
 }
 
 // @@@@@@@@@@@@@@@@@
@@ -565,4 +824,81 @@ mod tests {
 
         assert!((t_eval - id).norm() < 1e-10);
     }
+
+    #[test]
+    fn state_vector_eval() {
+        let mut circ = QuantumCircuit::new(4);
+        circ.g_h(0);
+        circ.g_cx(0, 3);
+        circ.g_ry(0.3, 2);
+        circ.g_cxx(3, 1, 2);
+        circ.g_cp(0.7, 2, 0);
+        circ.g_swap(1, 3);
+
+        let input = QRegister::from([Qubit::one(), Qubit::zero(), Qubit::one(), Qubit::zero()]);
+        let state = circ.eval_state(input.clone());
+        let expected = circ.eval() * input;
+
+        assert!((state.qubits - expected.qubits).norm() < 1e-10);
+    }
+
+    #[test]
+    fn register_constructors() {
+        let ghz = QRegister::ghz(3);
+        // U = G1·G2·G3, so the first gate applied to the input is the last one
+        let mut circ = QuantumCircuit::new(3);
+        circ.g_cx(1, 2);
+        circ.g_cx(0, 1);
+        circ.g_h(0);
+        let prepared = circ.eval_state(QRegister::basis(3, 0));
+        assert!((ghz.fidelity(&prepared) - 1.0).abs() < 1e-10);
+
+        let w = QRegister::w(3);
+        assert_eq!(w.top_amplitudes(3).len(), 3);
+        assert!(w
+            .top_amplitudes(3)
+            .iter()
+            .all(|(i, _)| [1, 2, 4].contains(i)));
+        assert!(
+            (QRegister::uniform(3)
+                .inner_product(&QRegister::basis(3, 5))
+                .re
+                - 1.0 / 8.0_f64.sqrt())
+            .abs()
+                < 1e-10
+        );
+
+        let reg = QRegister::from_bitstring("011").unwrap();
+        assert_eq!(reg, QRegister::basis(3, 3));
+        assert_eq!(reg.bitstring(3), "011");
+        assert_eq!(
+            QRegister::from_bitstring("0x1"),
+            Err(RegisterError::InvalidBitstring('x'))
+        );
+        assert_eq!(
+            QRegister::from_bitstring(&"1".repeat(65)),
+            Err(RegisterError::BitstringTooLong(65))
+        );
+        assert_eq!(
+            QRegister::from_amplitudes(vec![Complex::new(1.0, 0.0); 3]),
+            Err(RegisterError::InvalidLength(3))
+        );
+        assert!(matches!(
+            QRegister::from_amplitudes(vec![Complex::new(1.0, 0.0); 2]),
+            Err(RegisterError::NotNormalized(_))
+        ));
+    }
+
+    #[test]
+    fn haar_random_register() {
+        let a = QRegister::haar_random(4, 7);
+        assert_eq!(a, QRegister::haar_random(4, 7));
+        assert_ne!(a, QRegister::haar_random(4, 8));
+        assert!((a.qubits.norm() - 1.0).abs() < 1e-10);
+        assert!((a.fidelity(&a) - 1.0).abs() < 1e-10);
+
+        let product = QRegister::basis(1, 1).tensor_product(a.clone());
+        assert_eq!(product.n_qubits(), 5);
+        assert_eq!(product.qubits.rows(16, 16), a.qubits);
+    }
 }
@@ -1,243 +1,499 @@
 //! Module containing the definition of the `Block` and `SpannedBlock` structs.
 //!
-//! The `Block` struct is a wrapper around a `DMatrix<Complex<f64>>` and provides
-//! methods for tensor product and matrix multiplication.
+//! The `Block` struct is a wrapper around a `DMatrix<Complex<T>>` and provides
+//! methods for tensor product and matrix multiplication. The real type `T` of
+//! the entries is `f64` unless a block is converted to another precision.
 //! The `SpannedBlock` struct is a wrapper around a `Block` and a `Span`, and provides
 //! methods for tensor product and matrix multiplication, while keeping track of
 //! the span of the block.
 
-use std::{fmt::Debug, ops::Mul};
+use std::{any::Any, borrow::Cow, fmt::Debug, ops::Mul};
 
 use nalgebra::{Complex, DMatrix};
 
-use crate::model::{gates::*, span::Span};
+use crate::{
+    contractions::closed::{ClosedTensorError, StateTensor},
+    model::{
+        cache::GateCache,
+        gates::*,
+        kronecker::KroneckerBlock,
+        monomial::MonomialBlock,
+        scalar::{cast_matrix, Scalar},
+        span::Span,
+        sparse::{count_non_zero, is_sparse, SparseBlock, DEFAULT_SPARSE_DENSITY},
+    },
+};
 
 use super::{QRegister, TensorProduct};
 
-/// A Block is a wrapper around a `DMatrix<Complex<f64>>` and provides methods for
+/// A Block is a wrapper around a `DMatrix<Complex<T>>` and provides methods for
 /// tensor product and matrix multiplication.
-#[derive(Debug, Clone, PartialEq)]
-pub struct Block {
-    matrix_repr: DMatrix<Complex<f64>>,
+///
+/// The padding with `Block::identity` is kept as a `KroneckerBlock`, the
+/// diagonal and monomial gates and their products as a `MonomialBlock`, and
+/// the matrices with few non-zeros as a `SparseBlock`, all only expanded when
+/// the block is borrowed or converted into a dense matrix.
+#[derive(Debug, Clone)]
+pub struct Block<T: Scalar = f64> {
+    matrix_repr: BlockRepr<T>,
     dim: usize,
 }
 
-impl Block {
-    /// Creates a new Block from a `DMatrix<Complex<f64>>` full of zeros.
+/// The storage of the matrix of a `Block`.
+#[derive(Debug, Clone)]
+enum BlockRepr<T: Scalar> {
+    Dense(DMatrix<Complex<T>>),
+    Kronecker(KroneckerBlock<T>),
+    Sparse(SparseBlock<T>),
+    Monomial(MonomialBlock<T>),
+}
+
+/// The monomial entries of the two operands of a product.
+type MonomialPair<'a, T> = (Cow<'a, MonomialBlock<T>>, Cow<'a, MonomialBlock<T>>);
+
+impl<T: Scalar> Block<T> {
+    /// Creates a new Block from a `DMatrix<Complex<T>>` full of zeros.
     pub fn empty(dim: usize) -> Self {
-        DMatrix::from_element(dim, dim, Complex::new(0.0, 0.0)).into()
+        DMatrix::from_element(dim, dim, T::complex(0.0, 0.0)).into()
     }
 
     /// Create a block of dimension 1x1 with value 1.
     pub fn one() -> Self {
-        DMatrix::from_row_slice(1, 1, &[1.0])
-            .map(|x| Complex::new(x, 0.0))
-            .into()
+        DMatrix::from_element(1, 1, T::complex(1.0, 0.0)).into()
     }
 
-    /// Create a block of identity matrix of dimension dim.
+    /// Create a block of identity matrix of dimension dim, which is never
+    /// materialised when it is tensored or multiplied with other blocks. The
+    /// identity is taken from the global `GateCache`.
     pub fn identity(dim: usize) -> Self {
-        DMatrix::identity(dim, dim).into()
+        GateCache::global().identity_block(dim).cast()
     }
 
-    /// Convert the Block into a `DMatrix<Complex<f64>>`.
-    pub fn into_matrix(self) -> DMatrix<Complex<f64>> {
-        self.matrix_repr
+    /// Return the dimension of the block.
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Return the factors of the block if it is a padded core.
+    pub fn as_kronecker(&self) -> Option<&KroneckerBlock<T>> {
+        match &self.matrix_repr {
+            BlockRepr::Kronecker(kron) => Some(kron),
+            _ => None,
+        }
     }
-}
 
-impl TensorProduct for Block {
-    type Output = Block;
+    /// Return the non-zero entries of the block if it is stored as sparse.
+    pub fn as_sparse(&self) -> Option<&SparseBlock<T>> {
+        match &self.matrix_repr {
+            BlockRepr::Sparse(sparse) => Some(sparse),
+            _ => None,
+        }
+    }
+
+    /// Return the non-zero entries of the block if it is monomial.
+    pub fn as_monomial(&self) -> Option<&MonomialBlock<T>> {
+        match &self.matrix_repr {
+            BlockRepr::Monomial(monomial) => Some(monomial),
+            _ => None,
+        }
+    }
+
+    /// Return the structure of the block. Only the blocks built from
+    /// structured gates, and their products, are known to be structured.
+    pub fn structure(&self) -> Structure {
+        match &self.matrix_repr {
+            BlockRepr::Monomial(monomial) => monomial.structure(),
+            BlockRepr::Kronecker(kron) if kron.is_identity() => Structure::Diagonal,
+            _ => Structure::General,
+        }
+    }
 
-    fn tensor_product(&self, rhs: impl Into<Block>) -> Self::Output {
-        let b = rhs.into();
+    /// Convert the Block into a `DMatrix<Complex<T>>`.
+    pub fn into_matrix(self) -> DMatrix<Complex<T>> {
+        match self.matrix_repr {
+            BlockRepr::Dense(matrix) => matrix,
+            BlockRepr::Kronecker(kron) => kron.into_dense(),
+            BlockRepr::Sparse(sparse) => sparse.into_dense(),
+            BlockRepr::Monomial(monomial) => monomial.into_dense(),
+        }
+    }
+
+    /// Convert a Block holding a column vector into a `QRegister`.
+    pub fn into_register(self) -> QRegister<T> {
+        assert_eq!(self.as_ref().ncols(), 1, "The block is not a vector");
+        QRegister {
+            qubits: self.as_ref().column(0).into_owned(),
+        }
+    }
+
+    /// Convert the entries of the block to another precision, keeping the
+    /// storage of the matrix.
+    pub fn cast<U: Scalar>(&self) -> Block<U> {
+        let matrix_repr = match &self.matrix_repr {
+            BlockRepr::Dense(matrix) => BlockRepr::Dense(cast_matrix(matrix)),
+            BlockRepr::Kronecker(kron) => BlockRepr::Kronecker(kron.cast()),
+            BlockRepr::Sparse(sparse) => BlockRepr::Sparse(sparse.cast()),
+            BlockRepr::Monomial(monomial) => BlockRepr::Monomial(monomial.cast()),
+        };
         Block {
-            matrix_repr: self.as_ref().kronecker(b.as_ref()),
-            dim: self.dim * b.dim,
+            matrix_repr,
+            dim: self.dim,
+        }
+    }
+
+    /// Convert the block to another precision, without copying it if the
+    /// precision is the same.
+    pub fn into_precision<U: Scalar>(self) -> Block<U> {
+        match (Box::new(self) as Box<dyn Any>).downcast::<Block<U>>() {
+            Ok(block) => *block,
+            Err(block) => block.downcast::<Self>().unwrap().cast(),
+        }
+    }
+
+    /// Return the factors of the block, seeing a dense block as a core
+    /// without padding.
+    fn factors(&self) -> KroneckerBlock<T> {
+        match &self.matrix_repr {
+            BlockRepr::Dense(matrix) => KroneckerBlock::from(matrix.clone()),
+            BlockRepr::Kronecker(kron) => kron.clone(),
+            BlockRepr::Sparse(sparse) => KroneckerBlock::from(sparse.as_ref().clone()),
+            BlockRepr::Monomial(monomial) => KroneckerBlock::from(monomial.as_ref().clone()),
+        }
+    }
+
+    /// Return the non-zero entries of the block.
+    fn sparse(&self) -> Cow<'_, SparseBlock<T>> {
+        match &self.matrix_repr {
+            BlockRepr::Dense(matrix) => Cow::Owned(SparseBlock::from_dense(matrix)),
+            BlockRepr::Kronecker(kron) => Cow::Owned(SparseBlock::from(kron)),
+            BlockRepr::Sparse(sparse) => Cow::Borrowed(sparse),
+            BlockRepr::Monomial(monomial) => Cow::Owned(SparseBlock::from(monomial)),
+        }
+    }
+
+    /// Return the non-zero entries of the block if it is monomial, scanning
+    /// dense matrices and padded cores.
+    fn monomial(&self) -> Option<Cow<'_, MonomialBlock<T>>> {
+        match &self.matrix_repr {
+            BlockRepr::Monomial(monomial) => Some(Cow::Borrowed(monomial)),
+            BlockRepr::Dense(matrix) => MonomialBlock::from_dense(matrix).map(Cow::Owned),
+            BlockRepr::Kronecker(kron) => MonomialBlock::from_kronecker(kron).map(Cow::Owned),
+            _ => None,
+        }
+    }
+
+    /// Return the non-zero entries of both blocks if they are monomial and
+    /// at least one of them is stored as such.
+    fn monomials<'a>(&'a self, rhs: &'a Block<T>) -> Option<MonomialPair<'a, T>> {
+        if self.as_monomial().is_none() && rhs.as_monomial().is_none() {
+            return None;
+        }
+        Some((self.monomial()?, rhs.monomial()?))
+    }
+
+    /// Multiply two blocks, following the columns of monomial blocks, using
+    /// strided loops when one of them is padded and sparse kernels when one of
+    /// them is sparse.
+    fn product(&self, rhs: &Block<T>) -> Block<T> {
+        if let Some((lhs, rhs)) = self.monomials(rhs) {
+            return lhs.mul(&rhs).into();
+        }
+        match (&self.matrix_repr, &rhs.matrix_repr) {
+            (BlockRepr::Dense(lhs), BlockRepr::Monomial(rhs)) => {
+                MonomialBlock::dense_mul(lhs, rhs).into()
+            }
+            (BlockRepr::Monomial(lhs), BlockRepr::Dense(rhs)) => lhs.mul_dense(rhs).into(),
+            (BlockRepr::Dense(lhs), BlockRepr::Sparse(rhs)) => {
+                SparseBlock::dense_mul(lhs, rhs).into()
+            }
+            (BlockRepr::Sparse(lhs), BlockRepr::Dense(rhs)) => lhs.mul_dense(rhs).into(),
+            (BlockRepr::Sparse(_) | BlockRepr::Monomial(_), _)
+            | (_, BlockRepr::Sparse(_) | BlockRepr::Monomial(_)) => {
+                self.sparse().mul(&rhs.sparse()).into()
+            }
+            (BlockRepr::Dense(lhs), BlockRepr::Dense(rhs)) => (lhs * rhs).into(),
+            (BlockRepr::Dense(lhs), BlockRepr::Kronecker(rhs)) => {
+                KroneckerBlock::dense_mul(lhs, rhs).into()
+            }
+            (BlockRepr::Kronecker(lhs), BlockRepr::Dense(rhs)) => lhs.mul_dense(rhs).into(),
+            (BlockRepr::Kronecker(lhs), BlockRepr::Kronecker(rhs)) => lhs.mul(rhs).into(),
         }
     }
 }
 
-impl From<DMatrix<Complex<f64>>> for Block {
-    fn from(matrix_repr: DMatrix<Complex<f64>>) -> Self {
-        let dim = matrix_repr.nrows();
-        Self { matrix_repr, dim }
+impl<T: Scalar> TensorProduct for Block<T> {
+    type Output = Block<T>;
+
+    fn tensor_product(&self, rhs: impl Into<Block<T>>) -> Self::Output {
+        self.kronecker(&rhs.into())
     }
 }
 
-impl<G: QuantumGate> From<G> for Block {
-    fn from(gate: G) -> Self {
-        gate.block()
+impl<T: Scalar> Block<T> {
+    /// Compute the tensor product with a borrowed block, following the
+    /// structure of both blocks as `tensor_product` does.
+    pub fn kronecker(&self, b: &Block<T>) -> Block<T> {
+        if let Some((lhs, rhs)) = self.monomials(b) {
+            return lhs.tensor_product(&rhs).into();
+        }
+        match (&self.matrix_repr, &b.matrix_repr) {
+            (BlockRepr::Dense(lhs), BlockRepr::Dense(rhs)) => lhs.kronecker(rhs).into(),
+            (BlockRepr::Sparse(_) | BlockRepr::Monomial(_), _)
+            | (_, BlockRepr::Sparse(_) | BlockRepr::Monomial(_)) => {
+                self.sparse().tensor_product(&b.sparse()).into()
+            }
+            _ => self.factors().tensor_product(&b.factors()).into(),
+        }
     }
 }
 
-impl From<Block> for DMatrix<Complex<f64>> {
-    fn from(block: Block) -> Self {
-        block.matrix_repr
+impl<T: Scalar> From<DMatrix<Complex<T>>> for Block<T> {
+    /// Store the matrix as sparse if its density is under
+    /// `DEFAULT_SPARSE_DENSITY`.
+    fn from(matrix_repr: DMatrix<Complex<T>>) -> Self {
+        let dim = matrix_repr.nrows();
+        let nnz = count_non_zero(&matrix_repr);
+        if is_sparse(nnz, matrix_repr.len(), DEFAULT_SPARSE_DENSITY) {
+            return SparseBlock::from_dense(&matrix_repr).into();
+        }
+        Self {
+            matrix_repr: BlockRepr::Dense(matrix_repr),
+            dim,
+        }
     }
 }
 
-impl AsRef<DMatrix<Complex<f64>>> for Block {
-    fn as_ref(&self) -> &DMatrix<Complex<f64>> {
-        &self.matrix_repr
+impl<T: Scalar> From<SparseBlock<T>> for Block<T> {
+    /// Expand the matrix if its density is over `DEFAULT_SPARSE_DENSITY`.
+    fn from(sparse: SparseBlock<T>) -> Self {
+        let len = sparse.nrows() * sparse.ncols();
+        if !is_sparse(sparse.nnz(), len, DEFAULT_SPARSE_DENSITY) {
+            return Self {
+                dim: sparse.nrows(),
+                matrix_repr: BlockRepr::Dense(sparse.into_dense()),
+            };
+        }
+        Self {
+            dim: sparse.nrows(),
+            matrix_repr: BlockRepr::Sparse(sparse),
+        }
     }
 }
 
-impl Mul<&Block> for &Block {
-    type Output = Block;
+impl<T: Scalar> From<MonomialBlock<T>> for Block<T> {
+    fn from(monomial: MonomialBlock<T>) -> Self {
+        Self {
+            dim: monomial.dim(),
+            matrix_repr: BlockRepr::Monomial(monomial),
+        }
+    }
+}
 
-    fn mul(self, rhs: &Block) -> Self::Output {
-        Block {
-            matrix_repr: self.as_ref() * rhs.as_ref(),
-            dim: self.dim,
+impl<T: Scalar> From<KroneckerBlock<T>> for Block<T> {
+    /// Keep the factors, unless there is no padding to save.
+    fn from(kron: KroneckerBlock<T>) -> Self {
+        if kron.left() == 1 && kron.right() == 1 {
+            return kron.into_dense().into();
+        }
+        let dim = kron.nrows();
+        Self {
+            matrix_repr: BlockRepr::Kronecker(kron),
+            dim,
         }
     }
 }
 
-impl Mul<Block> for &Block {
-    type Output = Block;
+impl<T: Scalar, G: QuantumGate> From<G> for Block<T> {
+    /// Build the block of the gate, rounded to the precision of the block.
+    fn from(gate: G) -> Self {
+        gate.block().into_precision()
+    }
+}
+
+impl<T: Scalar> From<QRegister<T>> for Block<T> {
+    fn from(register: QRegister<T>) -> Self {
+        let n = register.qubits.len();
+        DMatrix::from_column_slice(n, 1, register.qubits.as_slice()).into()
+    }
+}
 
-    fn mul(self, rhs: Block) -> Self::Output {
-        Block {
-            matrix_repr: self.as_ref() * rhs.as_ref(),
-            dim: self.dim,
+impl<T: Scalar> From<Block<T>> for DMatrix<Complex<T>> {
+    fn from(block: Block<T>) -> Self {
+        block.into_matrix()
+    }
+}
+
+impl<T: Scalar> AsRef<DMatrix<Complex<T>>> for Block<T> {
+    fn as_ref(&self) -> &DMatrix<Complex<T>> {
+        match &self.matrix_repr {
+            BlockRepr::Dense(matrix) => matrix,
+            BlockRepr::Kronecker(kron) => kron.as_ref(),
+            BlockRepr::Sparse(sparse) => sparse.as_ref(),
+            BlockRepr::Monomial(monomial) => monomial.as_ref(),
         }
     }
 }
 
-impl Mul<Block> for Block {
-    type Output = Block;
+impl<T: Scalar> PartialEq for Block<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.dim == other.dim && self.as_ref() == other.as_ref()
+    }
+}
 
-    fn mul(self, rhs: Block) -> Self::Output {
-        Block {
-            matrix_repr: self.as_ref() * rhs.as_ref(),
-            dim: self.dim,
-        }
+impl<T: Scalar> Mul<&Block<T>> for &Block<T> {
+    type Output = Block<T>;
+
+    fn mul(self, rhs: &Block<T>) -> Self::Output {
+        self.product(rhs)
     }
 }
 
-impl Mul<&Block> for Block {
-    type Output = Block;
+impl<T: Scalar> Mul<Block<T>> for &Block<T> {
+    type Output = Block<T>;
 
-    fn mul(self, rhs: &Block) -> Self::Output {
-        Block {
-            matrix_repr: self.as_ref() * rhs.as_ref(),
-            dim: self.dim,
-        }
+    fn mul(self, rhs: Block<T>) -> Self::Output {
+        self.product(&rhs)
+    }
+}
+
+impl<T: Scalar> Mul<Block<T>> for Block<T> {
+    type Output = Block<T>;
+
+    fn mul(self, rhs: Block<T>) -> Self::Output {
+        self.product(&rhs)
     }
 }
 
-impl<Q: Into<QRegister>> Mul<Q> for &Block {
-    type Output = QRegister;
+impl<T: Scalar> Mul<&Block<T>> for Block<T> {
+    type Output = Block<T>;
+
+    fn mul(self, rhs: &Block<T>) -> Self::Output {
+        self.product(rhs)
+    }
+}
+
+impl<T: Scalar, Q: Into<QRegister<T>>> Mul<Q> for &Block<T> {
+    type Output = QRegister<T>;
 
     fn mul(self, rhs: Q) -> Self::Output {
-        QRegister {
-            qubits: self.as_ref() * rhs.into().qubits,
-        }
+        self.product(&Block::from(rhs.into())).into_register()
     }
 }
 
-impl<Q: Into<QRegister>> Mul<Q> for Block {
-    type Output = QRegister;
+impl<T: Scalar, Q: Into<QRegister<T>>> Mul<Q> for Block<T> {
+    type Output = QRegister<T>;
 
     fn mul(self, rhs: Q) -> Self::Output {
-        QRegister {
-            qubits: self.as_ref() * rhs.into().qubits,
-        }
+        self.product(&Block::from(rhs.into())).into_register()
     }
 }
 
-impl std::ops::Add for Block {
+impl<T: Scalar> std::ops::Add for Block<T> {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
-        Block {
-            matrix_repr: self.as_ref() + rhs.as_ref(),
-            dim: self.dim,
-        }
+        (self.as_ref() + rhs.as_ref()).into()
     }
 }
 
-impl std::ops::Sub for Block {
+impl<T: Scalar> std::ops::Sub for Block<T> {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        Block {
-            matrix_repr: self.as_ref() - rhs.as_ref(),
-            dim: self.dim,
-        }
+        (self.as_ref() - rhs.as_ref()).into()
     }
 }
 
-impl std::fmt::Display for Block {
+impl<T: Scalar> std::fmt::Display for Block<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.matrix_repr)
+        write!(f, "{}", self.as_ref())
     }
 }
 
 /// A `SpannedBlock` is a wrapper around a `Block` and a `Span`, and provides methods for
 /// tensor product and matrix multiplication, while keeping track of the span of the block.
 #[derive(Debug, Clone, PartialEq)]
-pub struct SpannedBlock {
-    block: Block,
+pub struct SpannedBlock<T: Scalar = f64> {
+    block: Block<T>,
     span: Span,
 }
 
-impl SpannedBlock {
+impl<T: Scalar> SpannedBlock<T> {
     /// Creates a new SpannedBlock from a `Block` and a `Span`.
-    pub fn new(block: Block, span: Span) -> Self {
+    pub fn new(block: Block<T>, span: Span) -> Self {
         Self { block, span }
     }
 
     /// Returns the span that a contraction with another `SpannedBlock` would cover.
-    pub fn merged_span(&self, rhs: &SpannedBlock) -> Span {
+    pub fn merged_span(&self, rhs: &SpannedBlock<T>) -> Span {
         self.span.union(&rhs.span)
     }
 
     /// Adapts the span of the block to a new span, making tensor products
-    /// in the right order.
-    pub fn adapt_to_span(mut self, span: Span) -> Self {
-        let mut new_block = Block::one();
-        for i in span.start()..self.span.start() {
-            new_block = new_block.tensor_product(Identity::new(i));
-        }
-        new_block = new_block.tensor_product(self.block);
-        for i in (self.span.end() + 1)..(span.end() + 1) {
-            new_block = new_block.tensor_product(Identity::new(i));
+    /// in the right order. The identities on the new lanes are kept as the
+    /// padding of a `KroneckerBlock`, so they are never materialised.
+    pub fn adapt_to_span(self, span: Span) -> Self {
+        self.padded(span)
+    }
+
+    /// Return the block adapted to a new span, as `adapt_to_span` does,
+    /// without consuming it.
+    pub fn padded(&self, span: Span) -> Self {
+        let left = Block::identity(1 << (self.span.start() - span.start()));
+        let right = Block::identity(1 << (span.end() - self.span.end()));
+        let block = left.kronecker(&self.block).kronecker(&right);
+        Self { block, span }
+    }
+
+    /// Return the block adapted to a span covering its own, borrowing it if
+    /// the spans are the same.
+    fn covering(&self, span: &Span) -> Cow<'_, Self> {
+        match &self.span == span {
+            true => Cow::Borrowed(self),
+            false => Cow::Owned(self.padded(span.clone())),
         }
-        self.block = new_block;
-        self.span = span;
-        self
     }
 
     /// Return the inner block.
-    pub fn into_block(self) -> Block {
+    pub fn into_block(self) -> Block<T> {
         self.block
     }
+
+    /// Convert the block to another precision.
+    pub fn cast<U: Scalar>(&self) -> SpannedBlock<U> {
+        SpannedBlock::new(self.block.cast(), self.span.clone())
+    }
+
+    /// Convert the block to another precision, without copying it if the
+    /// precision is the same.
+    pub fn into_precision<U: Scalar>(self) -> SpannedBlock<U> {
+        SpannedBlock::new(self.block.into_precision(), self.span)
+    }
 }
 
-impl AsRef<DMatrix<Complex<f64>>> for SpannedBlock {
-    fn as_ref(&self) -> &DMatrix<Complex<f64>> {
+impl<T: Scalar> AsRef<DMatrix<Complex<T>>> for SpannedBlock<T> {
+    fn as_ref(&self) -> &DMatrix<Complex<T>> {
         self.block.as_ref()
     }
 }
 
-impl std::fmt::Display for SpannedBlock {
+impl<T: Scalar> std::fmt::Display for SpannedBlock<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "SpannedBlock{}:\n{}", self.span, self.block)
     }
 }
 
-impl From<Gate> for SpannedBlock {
+impl<T: Scalar> From<Gate> for SpannedBlock<T> {
+    /// Build the block of the gate, rounded to the precision of the block.
     fn from(gate: Gate) -> Self {
-        gate.spanned_block()
+        gate.spanned_block().into_precision()
     }
 }
 
-impl TensorProduct for SpannedBlock {
-    type Output = SpannedBlock;
+impl<T: Scalar> TensorProduct for SpannedBlock<T> {
+    type Output = SpannedBlock<T>;
 
-    fn tensor_product(&self, rhs: impl Into<SpannedBlock>) -> Self::Output {
+    fn tensor_product(&self, rhs: impl Into<SpannedBlock<T>>) -> Self::Output {
         let rhs = rhs.into();
         SpannedBlock {
             block: self.block.tensor_product(rhs.block),
@@ -246,10 +502,10 @@ impl TensorProduct for SpannedBlock {
     }
 }
 
-impl Mul<&SpannedBlock> for &SpannedBlock {
-    type Output = SpannedBlock;
+impl<T: Scalar> Mul<&SpannedBlock<T>> for &SpannedBlock<T> {
+    type Output = SpannedBlock<T>;
 
-    fn mul(self, rhs: &SpannedBlock) -> Self::Output {
+    fn mul(self, rhs: &SpannedBlock<T>) -> Self::Output {
         assert_eq!(self.span, rhs.span, "Incompatible spans");
         SpannedBlock {
             block: &self.block * &rhs.block,
@@ -258,10 +514,10 @@ impl Mul<&SpannedBlock> for &SpannedBlock {
     }
 }
 
-impl Mul<SpannedBlock> for SpannedBlock {
-    type Output = SpannedBlock;
+impl<T: Scalar> Mul<SpannedBlock<T>> for SpannedBlock<T> {
+    type Output = SpannedBlock<T>;
 
-    fn mul(self, rhs: SpannedBlock) -> Self::Output {
+    fn mul(self, rhs: SpannedBlock<T>) -> Self::Output {
         assert_eq!(self.span, rhs.span, "Incompatible spans");
         SpannedBlock {
             block: &self.block * &rhs.block,
@@ -270,18 +526,83 @@ impl Mul<SpannedBlock> for SpannedBlock {
     }
 }
 
-pub trait BlockLike: Debug + Clone + PartialEq + AsRef<DMatrix<Complex<f64>>> {
-    fn into_block(self) -> Block;
+pub trait BlockLike: Debug + Clone + PartialEq {
+    /// The real type of the entries of the block.
+    type Scalar: Scalar;
+
+    fn into_block(self) -> Block<Self::Scalar>;
+
+    /// Return the block stored as sparse if the fraction of its non-zero
+    /// entries is at most density, and as dense otherwise, or `None` if its
+    /// storage already follows the density or has no such choice.
+    fn with_density(&self, _density: f64) -> Option<Self> {
+        None
+    }
 }
 
-impl BlockLike for SpannedBlock {
-    fn into_block(self) -> Block {
+impl<T: Scalar> BlockLike for SpannedBlock<T> {
+    type Scalar = T;
+
+    fn into_block(self) -> Block<T> {
         self.into_block()
     }
+
+    fn with_density(&self, density: f64) -> Option<Self> {
+        let block = self.block.with_density(density)?;
+        Some(Self::new(block, self.span.clone()))
+    }
 }
 
-impl BlockLike for Block {
-    fn into_block(self) -> Block {
+impl<T: Scalar> BlockLike for Block<T> {
+    type Scalar = T;
+
+    fn into_block(self) -> Block<T> {
         self
     }
+
+    /// Change the storage of dense and sparse blocks, keeping the padded,
+    /// diagonal and monomial ones.
+    fn with_density(&self, density: f64) -> Option<Self> {
+        let matrix_repr = match &self.matrix_repr {
+            BlockRepr::Dense(matrix)
+                if is_sparse(count_non_zero(matrix), matrix.len(), density) =>
+            {
+                BlockRepr::Sparse(SparseBlock::from_dense(matrix))
+            }
+            BlockRepr::Sparse(sparse)
+                if !is_sparse(sparse.nnz(), sparse.nrows() * sparse.ncols(), density) =>
+            {
+                BlockRepr::Dense(sparse.to_dense())
+            }
+            _ => return None,
+        };
+        Some(Self {
+            matrix_repr,
+            dim: self.dim,
+        })
+    }
+}
+
+/// A block that can be built from a gate and contracted with another block of
+/// the same kind, used as the backend of a `ContractionPlan`.
+pub trait ContractionBlock:
+    BlockLike + From<Gate> + AsRef<DMatrix<Complex<<Self as BlockLike>::Scalar>>>
+{
+    /// Contract the block with the right factor of their product.
+    fn contract(&self, rhs: &Self) -> Self;
+
+    /// Build the block of a state tensor of a closed network. Only the blocks
+    /// that are not square can hold a state, the others fail.
+    fn from_state(state: StateTensor) -> Result<Self, ClosedTensorError> {
+        Err(ClosedTensorError(state))
+    }
+}
+
+impl<T: Scalar> ContractionBlock for SpannedBlock<T> {
+    /// Expand both blocks to their merged span and multiply them, padding
+    /// only the blocks that do not cover it.
+    fn contract(&self, rhs: &Self) -> Self {
+        let span = self.merged_span(rhs);
+        &*self.covering(&span) * &*rhs.covering(&span)
+    }
 }
@@ -0,0 +1,419 @@
+//! Module containing the cache of the matrices and blocks of the gates.
+//!
+//! The matrix of a gate only depends on its kind, its parameters and the
+//! layout of its lanes relative to the first one, so a CX on lanes (3, 5) has
+//! the same matrix as a CX on lanes (0, 2). The `GateCache` keeps the matrices
+//! and the blocks built for each `GateKey`, so that the tensor products of the
+//! multi-qubit gates are computed once, and the plans share a single copy of
+//! the blocks of identical gates.
+//!
+//! The single qubit gates are built directly, as their 2x2 matrices are
+//! cheaper to create than to look up.
+//!
+//! The cache holds at most `capacity` bytes, counting every matrix and block
+//! as the dense matrix it may be expanded to, and evicts the least recently
+//! used entries beyond it. The blocks are built with `DEFAULT_SPARSE_DENSITY`,
+//! the executors restore the storage of their own density when they load them
+//! (see `CpuExecutor::sparse_density`).
+
+use std::{
+    mem::size_of,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use nalgebra::{Complex, DMatrix};
+
+use super::{blocks::Block, gates::QuantumGate, kronecker::KroneckerBlock};
+
+/// Matrices and blocks of a larger dimension are built but not kept.
+pub const MAX_CACHED_DIM: usize = 1 << 10;
+
+/// The number of bytes kept by `GateCache::new`.
+pub const DEFAULT_CAPACITY: usize = 256 << 20;
+
+/// The bytes of a dense matrix of dimension dim.
+fn dense_bytes(dim: usize) -> usize {
+    dim * dim * size_of::<Complex<f64>>()
+}
+
+/// The key of a gate in the cache: its kind, its parameters and the lanes it
+/// acts on relative to the first lane of its span.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GateKey {
+    kind: &'static str,
+    /// The bits of the parameters, so that the key can be hashed.
+    params: Vec<u64>,
+    layout: Vec<usize>,
+}
+
+impl GateKey {
+    /// Create the key of a gate of the given kind acting on the given lanes,
+    /// in the order of the gate (controls before targets).
+    pub fn new(kind: &'static str, params: &[f64], lanes: &[usize]) -> Self {
+        let start = lanes.iter().min().copied().unwrap_or(0);
+        Self {
+            kind,
+            // -0.0 and 0.0 build the same matrix
+            params: params.iter().map(|p| (p + 0.0).to_bits()).collect(),
+            layout: lanes.iter().map(|lane| lane - start).collect(),
+        }
+    }
+
+    /// Create the key of the identity of dimension dim, distinct from the
+    /// key of the single qubit `Identity` gate.
+    pub fn identity(dim: usize) -> Self {
+        Self {
+            kind: "Identity",
+            params: vec![],
+            layout: vec![dim],
+        }
+    }
+
+    /// Return the kind of the gate.
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    /// Return the lanes of the gate relative to the first one.
+    pub fn layout(&self) -> &[usize] {
+        &self.layout
+    }
+}
+
+impl std::fmt::Display for GateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if !self.params.is_empty() {
+            let params: Vec<_> = self
+                .params
+                .iter()
+                .map(|p| format!("{:.2}", f64::from_bits(*p)))
+                .collect();
+            write!(f, "({})", params.join(","))?;
+        }
+        write!(f, "{:?}", self.layout)
+    }
+}
+
+/// The hits and the misses of a cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    /// The number of matrices and blocks kept.
+    pub entries: usize,
+    /// The bytes of the matrices and blocks kept.
+    pub bytes: usize,
+}
+
+impl CacheStats {
+    /// Return the fraction of the lookups that were hits.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+impl std::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses ({:.1}%), {} entries ({} KiB)",
+            self.hits,
+            self.misses,
+            100.0 * self.hit_rate(),
+            self.entries,
+            self.bytes >> 10
+        )
+    }
+}
+
+/// A value kept in the cache, with its size and the time of its last use.
+#[derive(Debug)]
+struct Cached<V> {
+    value: Arc<V>,
+    bytes: usize,
+    used: AtomicU64,
+}
+
+/// A cache of the matrices and the blocks of the gates, see the module
+/// documentation. The cache can be shared between threads.
+#[derive(Debug)]
+pub struct GateCache {
+    matrices: DashMap<GateKey, Cached<DMatrix<Complex<f64>>>>,
+    blocks: DashMap<GateKey, Cached<Block>>,
+    /// The maximum number of bytes kept.
+    capacity: usize,
+    /// The number of bytes kept.
+    bytes: AtomicUsize,
+    /// The counter of the lookups, which orders the uses of the entries.
+    clock: AtomicU64,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl Default for GateCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GateCache {
+    /// Create an empty cache of `DEFAULT_CAPACITY` bytes.
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Create an empty cache keeping at most capacity bytes.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            matrices: DashMap::new(),
+            blocks: DashMap::new(),
+            capacity,
+            bytes: AtomicUsize::new(0),
+            clock: AtomicU64::new(0),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Return the cache used by the gates and the plans.
+    pub fn global() -> &'static Self {
+        static GLOBAL: OnceLock<GateCache> = OnceLock::new();
+        GLOBAL.get_or_init(Self::new)
+    }
+
+    /// Look up a value, building it on a miss. The map is not locked while
+    /// the value is built, as building a matrix may use the cache again.
+    /// The value is kept if `bytes` returns its size and it fits the cache.
+    fn lookup<V>(
+        &self,
+        map: &DashMap<GateKey, Cached<V>>,
+        key: GateKey,
+        bytes: impl Fn(&V) -> Option<usize>,
+        build: impl FnOnce() -> V,
+    ) -> Arc<V> {
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        if let Some(cached) = map.get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            cached.used.store(now, Ordering::Relaxed);
+            return cached.value.clone();
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let value = Arc::new(build());
+        let Some(bytes) = bytes(&value).filter(|bytes| *bytes <= self.capacity) else {
+            return value;
+        };
+        match map.entry(key) {
+            // built by another thread in the meantime
+            Entry::Occupied(entry) => return entry.get().value.clone(),
+            Entry::Vacant(entry) => {
+                entry.insert(Cached {
+                    value: value.clone(),
+                    bytes,
+                    used: AtomicU64::new(now),
+                });
+            }
+        }
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.evict();
+        value
+    }
+
+    /// Return the key and the time of the least recently used entry of a map.
+    fn oldest<V>(map: &DashMap<GateKey, Cached<V>>) -> Option<(u64, GateKey)> {
+        map.iter()
+            .map(|entry| (entry.used.load(Ordering::Relaxed), entry.key().clone()))
+            .min_by_key(|(used, _)| *used)
+    }
+
+    /// Remove an entry of a map, returning its size.
+    fn remove<V>(map: &DashMap<GateKey, Cached<V>>, key: &GateKey) -> usize {
+        map.remove(key).map_or(0, |(_, cached)| cached.bytes)
+    }
+
+    /// Remove the least recently used entries until the cache fits its
+    /// capacity.
+    fn evict(&self) {
+        while self.bytes.load(Ordering::Relaxed) > self.capacity {
+            let removed = match (Self::oldest(&self.matrices), Self::oldest(&self.blocks)) {
+                (Some(matrix), Some(block)) if matrix.0 < block.0 => {
+                    Self::remove(&self.matrices, &matrix.1)
+                }
+                (_, Some(block)) => Self::remove(&self.blocks, &block.1),
+                (Some(matrix), None) => Self::remove(&self.matrices, &matrix.1),
+                (None, None) => break,
+            };
+            self.bytes.fetch_sub(removed, Ordering::Relaxed);
+        }
+    }
+
+    /// Return the matrix of the gate with the given key, built with `build`
+    /// on a miss.
+    pub fn matrix(
+        &self,
+        key: GateKey,
+        build: impl FnOnce() -> DMatrix<Complex<f64>>,
+    ) -> Arc<DMatrix<Complex<f64>>> {
+        let bytes = |m: &DMatrix<_>| (m.nrows() <= MAX_CACHED_DIM).then(|| dense_bytes(m.nrows()));
+        self.lookup(&self.matrices, key, bytes, build)
+    }
+
+    /// Return the dense identity of dimension dim.
+    pub fn identity(&self, dim: usize) -> Arc<DMatrix<Complex<f64>>> {
+        self.matrix(GateKey::identity(dim), || DMatrix::identity(dim, dim))
+    }
+
+    /// Return the block of a gate, shared by all the identical gates.
+    pub fn block<G: QuantumGate>(&self, key: GateKey, gate: &G) -> Arc<Block> {
+        let bytes = |b: &Block| (b.dim() <= MAX_CACHED_DIM).then(|| dense_bytes(b.dim()));
+        self.lookup(&self.blocks, key, bytes, || gate.block())
+    }
+
+    /// Return the lazy identity block of dimension dim, see `Block::identity`.
+    /// The identities are kept whatever their dimension, and only weigh their
+    /// 1x1 core, as the kernels never expand them.
+    pub fn identity_block(&self, dim: usize) -> Arc<Block> {
+        self.lookup(
+            &self.blocks,
+            GateKey::identity(dim),
+            |_| Some(dense_bytes(1)),
+            || Block::from(KroneckerBlock::identity(dim)),
+        )
+    }
+
+    /// Return the hits and the misses of the cache since it was created or
+    /// cleared.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.matrices.len() + self.blocks.len(),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Remove all the entries and reset the statistics.
+    pub fn clear(&self) {
+        self.matrices.clear();
+        self.blocks.clear();
+        self.bytes.store(0, Ordering::Relaxed);
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::fixtures::circuit_contraction,
+        model::{gates::Gate, QuantumCircuit},
+        op_tree::Operation,
+        scheduler::{operation::Kernel, ExecutionOperand, OperationPlan},
+    };
+
+    #[test]
+    fn keys_are_relative() {
+        let mut circuit = QuantumCircuit::new(6);
+        circuit.g_cx(3, 5);
+        circuit.g_cx(0, 2);
+        circuit.g_cx(2, 0);
+        circuit.g_rx(0.5, 1);
+        circuit.g_rx(-0.5, 1);
+        let keys: Vec<_> = circuit.gates.iter().map(Gate::key).collect();
+        assert_eq!(keys[0], keys[1]);
+        assert_ne!(keys[1], keys[2]);
+        assert_ne!(keys[3], keys[4]);
+        assert_eq!(keys[2].layout(), &[2, 0]);
+        assert_eq!(keys[3].to_string(), "RX(0.50)[0]");
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let cache = GateCache::new();
+        let mut circuit = QuantumCircuit::new(5);
+        circuit.g_cxx(0, 1, 2);
+        circuit.g_cxx(2, 3, 4);
+        let matrices: Vec<_> = circuit
+            .gates
+            .iter()
+            .map(|g| cache.matrix(g.key(), || g.matrix()))
+            .collect();
+        assert!(Arc::ptr_eq(&matrices[0], &matrices[1]));
+        assert_eq!(*matrices[0], circuit.gates[0].matrix());
+        assert!(Arc::ptr_eq(&cache.identity(4), &cache.identity(4)));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (2, 2, 2));
+        assert_eq!(stats.bytes, dense_bytes(8) + dense_bytes(4));
+        assert_eq!(stats.hit_rate(), 0.5);
+
+        // too large to be kept
+        let key = GateKey::new("X", &[], &[0]);
+        let tall = || DMatrix::zeros(2 * MAX_CACHED_DIM, 1);
+        cache.matrix(key.clone(), tall);
+        cache.matrix(key, tall);
+        assert_eq!(cache.stats().misses, 4);
+        cache.clear();
+        assert_eq!(cache.stats(), CacheStats::default());
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = GateCache::with_capacity(2 * dense_bytes(8));
+        let mut circuit = QuantumCircuit::new(3);
+        circuit.g_cxx(0, 1, 2);
+        circuit.g_cxx(2, 1, 0);
+        circuit.g_cswap(0, 1, 2);
+        let gates = &circuit.gates;
+        let lookup = |i: usize| cache.block(gates[i].key(), &gates[i]);
+        let first = lookup(0);
+        lookup(1);
+        // refresh the first gate, so that the second one is evicted
+        assert!(Arc::ptr_eq(&first, &lookup(0)));
+        lookup(2);
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes), (2, 2 * dense_bytes(8)));
+        assert!(Arc::ptr_eq(&first, &lookup(0)));
+        assert_eq!(cache.stats().misses, 3);
+        lookup(1);
+        assert_eq!(cache.stats().misses, 4);
+
+        // larger than the whole cache
+        let tiny = GateCache::with_capacity(dense_bytes(4));
+        tiny.block(gates[0].key(), &gates[0]);
+        assert_eq!(tiny.stats().entries, 0);
+    }
+
+    #[test]
+    fn plans_share_leaves() {
+        let mut circuit = QuantumCircuit::new(4);
+        for lane in 0..4 {
+            circuit.g_h(lane);
+        }
+        circuit.g_cx(0, 1);
+        circuit.g_cx(2, 3);
+        let contraction = circuit_contraction(circuit.clone());
+        let plan = OperationPlan::from(Operation::from_contraction(contraction, false).unwrap());
+        let leaves: Vec<_> = plan
+            .instructions()
+            .flat_map(|instr| match &instr.kernel {
+                Kernel::TE { left, right } | Kernel::MM { left, right } => vec![left, right],
+                Kernel::MV { left, right, .. } => vec![left, right],
+            })
+            .filter_map(|operand| match operand {
+                ExecutionOperand::Block(block) => Some(block),
+                ExecutionOperand::Address(_) => None,
+            })
+            .collect();
+        let hadamard = GateCache::global().block(circuit.gates[0].key(), &circuit.gates[0]);
+        let shared = leaves.iter().filter(|b| Arc::ptr_eq(b, &hadamard)).count();
+        assert!(shared >= 2, "{} shared Hadamard blocks", shared);
+    }
+}
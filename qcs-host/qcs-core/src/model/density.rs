@@ -0,0 +1,306 @@
+//! Module containing the definition of the `DensityMatrix` struct and the
+//! utilities to inspect subsystems of a quantum state.
+//!
+//! A density matrix ρ describes the (possibly mixed) state of a set of lanes.
+//! For a pure state |ψ⟩ it is the ketbra ρ = |ψ⟩⟨ψ|, while the state of a subset
+//! of lanes is obtained with the partial trace over the remaining lanes.
+//!
+//! Lanes are indexed as in the rest of the model: lane 0 is the leftmost factor
+//! of the tensor product, that is the most significant bit of a basis index.
+
+use nalgebra::{Complex, DMatrix, Vector3};
+use thiserror::Error;
+
+use super::{span::Span, Braket, QRegister, Qubit};
+
+/// Eigenvalues below this threshold are ignored when computing the entropy.
+const EIGENVALUE_THRESHOLD: f64 = 1e-12;
+
+/// Errors raised when the lanes of a subsystem are invalid.
+#[derive(Debug, Error, PartialEq)]
+pub enum LaneError {
+    #[error("Lane {lane} is out of a state of {n_qubits} lanes")]
+    OutOfRange { lane: usize, n_qubits: usize },
+    #[error("Lane {0} appears more than once")]
+    Duplicate(usize),
+}
+
+/// A density matrix ρ over a number of lanes, stored as a dense
+/// `DMatrix<Complex<f64>>` of dimension 2^n × 2^n.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DensityMatrix {
+    matrix_repr: DMatrix<Complex<f64>>,
+    n_qubits: usize,
+}
+
+impl DensityMatrix {
+    /// Return the number of lanes described by the density matrix.
+    pub fn n_qubits(&self) -> usize {
+        self.n_qubits
+    }
+
+    /// Return the purity Tr(ρ²) of the state, that is 1 for pure states and
+    /// 1/2^n for the maximally mixed state.
+    pub fn purity(&self) -> f64 {
+        (&self.matrix_repr * &self.matrix_repr).trace().re
+    }
+
+    /// Return the von Neumann entropy S(ρ) = -Tr(ρ log₂ ρ), in bits.
+    pub fn entropy(&self) -> f64 {
+        self.matrix_repr
+            .symmetric_eigenvalues()
+            .iter()
+            .filter(|&&l| l > EIGENVALUE_THRESHOLD)
+            .map(|&l| -l * l.log2())
+            .sum()
+    }
+
+    /// Return the Bloch vector (x, y, z) of a single lane density matrix, where
+    /// ρ = (I + xX + yY + zZ) / 2.
+    pub fn bloch(&self) -> Vector3<f64> {
+        assert_eq!(self.n_qubits, 1, "Bloch vector requires a single lane");
+        let rho = &self.matrix_repr;
+        Vector3::new(
+            2.0 * rho[(0, 1)].re,
+            -2.0 * rho[(0, 1)].im,
+            (rho[(0, 0)] - rho[(1, 1)]).re,
+        )
+    }
+
+    /// Trace out the lanes in the span, returning the density matrix of the
+    /// remaining lanes (in ascending order). The lanes must be distinct and
+    /// in the state.
+    ///
+    /// ```
+    /// # use qcs_core::model::{span::Span, QRegister, Qubit};
+    /// let reg = QRegister::from([Qubit::one(), Qubit::zero()]);
+    /// // Tracing out lane 0 leaves lane 1 in |0⟩
+    /// let rho = reg.density_matrix().partial_trace(&Span::single(0)).unwrap();
+    /// assert!((rho.as_ref()[(0, 0)].re - 1.0).abs() < 1e-10);
+    /// assert!(reg.density_matrix().partial_trace(&Span::single(2)).is_err());
+    /// ```
+    pub fn partial_trace(&self, traced: &Span) -> Result<DensityMatrix, LaneError> {
+        let n = self.n_qubits;
+        let kept = kept_lanes(n, traced)?;
+        let traced = traced_lanes(n, &kept);
+        let dim = 1 << kept.len();
+        let matrix_repr = DMatrix::from_fn(dim, dim, |i, j| {
+            (0..1 << traced.len())
+                .map(|t| {
+                    let row = compose_index(n, &kept, i, &traced, t);
+                    let col = compose_index(n, &kept, j, &traced, t);
+                    self.matrix_repr[(row, col)]
+                })
+                .sum()
+        });
+        Ok(DensityMatrix {
+            matrix_repr,
+            n_qubits: kept.len(),
+        })
+    }
+
+    /// Convert the density matrix into a `DMatrix<Complex<f64>>`.
+    pub fn into_matrix(self) -> DMatrix<Complex<f64>> {
+        self.matrix_repr
+    }
+}
+
+impl AsRef<DMatrix<Complex<f64>>> for DensityMatrix {
+    fn as_ref(&self) -> &DMatrix<Complex<f64>> {
+        &self.matrix_repr
+    }
+}
+
+impl From<DMatrix<Complex<f64>>> for DensityMatrix {
+    fn from(matrix_repr: DMatrix<Complex<f64>>) -> Self {
+        let n_qubits = matrix_repr.nrows().trailing_zeros() as usize;
+        Self {
+            matrix_repr,
+            n_qubits,
+        }
+    }
+}
+
+impl std::fmt::Display for DensityMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.matrix_repr)
+    }
+}
+
+impl QRegister {
+    /// Return the number of lanes of the register.
+    pub fn n_qubits(&self) -> usize {
+        self.qubits.len().trailing_zeros() as usize
+    }
+
+    /// Return the density matrix |ψ⟩⟨ψ| of the whole register.
+    pub fn density_matrix(&self) -> DensityMatrix {
+        self.ketbra().into()
+    }
+
+    /// Return the reduced density matrix of the lanes in the span, tracing out
+    /// all the other lanes of the register.
+    ///
+    /// This is computed directly from the amplitudes as ρ = MM†, where M is the
+    /// state reshaped to a (kept lanes) × (traced lanes) matrix, so the full
+    /// density matrix is never built.
+    pub fn reduced_density_matrix(&self, kept: &Span) -> Result<DensityMatrix, LaneError> {
+        let n = self.n_qubits();
+        let kept = validate_lanes(n, kept)?;
+        let traced = traced_lanes(n, &kept);
+        let reshaped = DMatrix::from_fn(1 << kept.len(), 1 << traced.len(), |k, t| {
+            self.qubits[compose_index(n, &kept, k, &traced, t)]
+        });
+        Ok(DensityMatrix {
+            matrix_repr: &reshaped * reshaped.adjoint(),
+            n_qubits: kept.len(),
+        })
+    }
+
+    /// Trace out the lanes in the span, returning the density matrix of the
+    /// remaining lanes.
+    pub fn partial_trace(&self, traced: &Span) -> Result<DensityMatrix, LaneError> {
+        let kept = kept_lanes(self.n_qubits(), traced)?;
+        self.reduced_density_matrix(&Span::new(kept))
+    }
+
+    /// Return the von Neumann entropy of the bipartition between the lanes in
+    /// the span and the rest of the register, in bits.
+    pub fn entanglement_entropy(&self, span: &Span) -> Result<f64, LaneError> {
+        Ok(self.reduced_density_matrix(span)?.entropy())
+    }
+
+    /// Return the Bloch vector of the reduced state of a single lane.
+    pub fn bloch(&self, lane: usize) -> Result<Vector3<f64>, LaneError> {
+        Ok(self.reduced_density_matrix(&Span::single(lane))?.bloch())
+    }
+}
+
+impl Qubit {
+    /// Return the density matrix |ψ⟩⟨ψ| of the qubit.
+    pub fn density_matrix(&self) -> DensityMatrix {
+        self.ketbra().into()
+    }
+
+    /// Return the Bloch vector (x, y, z) of the qubit.
+    pub fn bloch(&self) -> Vector3<f64> {
+        self.density_matrix().bloch()
+    }
+
+    /// Return the purity of the qubit, that is 1 for normalized qubits.
+    pub fn purity(&self) -> f64 {
+        self.density_matrix().purity()
+    }
+}
+
+/// Return the lanes of the span, checking that they are distinct and in 0..n.
+fn validate_lanes(n: usize, span: &Span) -> Result<Vec<usize>, LaneError> {
+    let lanes = span.clone().into_iter().collect::<Vec<_>>();
+    if let Some(&lane) = lanes.iter().find(|&&l| l >= n) {
+        return Err(LaneError::OutOfRange { lane, n_qubits: n });
+    }
+    // the lanes of a span are sorted, so duplicates are adjacent
+    if let Some(pair) = lanes.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(LaneError::Duplicate(pair[0]));
+    }
+    Ok(lanes)
+}
+
+/// Return the lanes in 0..n that are not in the traced span.
+fn kept_lanes(n: usize, traced: &Span) -> Result<Vec<usize>, LaneError> {
+    validate_lanes(n, traced)?;
+    Ok((0..n).filter(|&l| !traced.contains(l)).collect())
+}
+
+/// Return the lanes in 0..n that are not kept.
+fn traced_lanes(n: usize, kept: &[usize]) -> Vec<usize> {
+    (0..n).filter(|l| !kept.contains(l)).collect()
+}
+
+/// Build the index of a basis state over n lanes from the index of the kept
+/// lanes and the index of the traced lanes. In both sub-indices the first lane
+/// is the most significant bit.
+fn compose_index(n: usize, kept: &[usize], k: usize, traced: &[usize], t: usize) -> usize {
+    let mut index = 0;
+    for (i, &lane) in kept.iter().enumerate() {
+        index |= ((k >> (kept.len() - 1 - i)) & 1) << (n - 1 - lane);
+    }
+    for (i, &lane) in traced.iter().enumerate() {
+        index |= ((t >> (traced.len() - 1 - i)) & 1) << (n - 1 - lane);
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::DVector;
+
+    use super::*;
+
+    fn bell() -> QRegister {
+        let s = 1.0 / 2.0_f64.sqrt();
+        QRegister {
+            qubits: DVector::from_vec(vec![s.into(), 0.0.into(), 0.0.into(), s.into()]),
+        }
+    }
+
+    #[test]
+    fn bell_entropy() {
+        let reg = bell();
+        let entropy = |lane| reg.entanglement_entropy(&Span::single(lane)).unwrap();
+        assert!((entropy(0) - 1.0).abs() < 1e-10);
+        assert!((entropy(1) - 1.0).abs() < 1e-10);
+        let reduced = reg.reduced_density_matrix(&Span::single(0)).unwrap();
+        assert!((reduced.purity() - 0.5).abs() < 1e-10);
+        assert!(reg.bloch(1).unwrap().norm() < 1e-10);
+        assert!(reg.density_matrix().entropy().abs() < 1e-10);
+    }
+
+    #[test]
+    fn product_state_subsystems() {
+        let s = 1.0 / 2.0_f64.sqrt();
+        let plus = Qubit::new(s.into(), s.into());
+        let reg = QRegister::from([Qubit::one(), plus.clone(), Qubit::zero()]);
+
+        assert!((reg.bloch(0).unwrap() - Vector3::new(0.0, 0.0, -1.0)).norm() < 1e-10);
+        assert!((reg.bloch(1).unwrap() - Vector3::new(1.0, 0.0, 0.0)).norm() < 1e-10);
+        assert!((reg.bloch(2).unwrap() - Vector3::new(0.0, 0.0, 1.0)).norm() < 1e-10);
+        assert!((plus.bloch() - reg.bloch(1).unwrap()).norm() < 1e-10);
+        assert!(reg.entanglement_entropy(&Span::new([0, 2])).unwrap().abs() < 1e-10);
+
+        let traced = reg.partial_trace(&Span::single(1)).unwrap();
+        let expected = QRegister::from([Qubit::one(), Qubit::zero()]).density_matrix();
+        assert!((traced.as_ref() - expected.as_ref()).norm() < 1e-10);
+        let nested = reg
+            .density_matrix()
+            .partial_trace(&Span::single(1))
+            .unwrap();
+        assert!((nested.as_ref() - expected.as_ref()).norm() < 1e-10);
+    }
+
+    #[test]
+    fn invalid_lanes() {
+        let reg = bell();
+        let out = LaneError::OutOfRange {
+            lane: 2,
+            n_qubits: 2,
+        };
+        assert_eq!(reg.partial_trace(&Span::new([0, 2])), Err(out));
+        assert_eq!(
+            reg.density_matrix().partial_trace(&Span::new([1, 1])),
+            Err(LaneError::Duplicate(1))
+        );
+        assert_eq!(
+            reg.reduced_density_matrix(&Span::new([0, 0])),
+            Err(LaneError::Duplicate(0))
+        );
+        assert!(reg.entanglement_entropy(&Span::single(5)).is_err());
+        assert_eq!(
+            reg.bloch(3),
+            Err(LaneError::OutOfRange {
+                lane: 3,
+                n_qubits: 2
+            })
+        );
+    }
+}
@@ -0,0 +1,679 @@
+//! Module containing the emulation of the fixed-point arithmetic of the HLS
+//! datapath.
+//!
+//! The accelerator may compute on `std::complex<ap_fixed<W, I, Q, O>>` values,
+//! whose format is described by a `FixedFormat`: `I` integer bits (sign
+//! included) and `W - I` fractional bits, with a rounding mode `Q` applied
+//! when low bits are dropped and an overflow mode `O` applied when high bits
+//! are dropped. The functions of this module reproduce the results of the
+//! device bit by bit:
+//!
+//! - a complex product is computed at full precision, as
+//!   `re = a.re·b.re - a.im·b.im`, and quantized once per component;
+//! - the sums of a matrix product are accumulated in the same format, in
+//!   increasing order of the inner index, with the overflow mode applied
+//!   after each addition.
+//!
+//! The word length is limited to 53 bits, so that fixed-point values are
+//! exactly representable as `f64` and can be stored in the blocks of the host.
+
+use nalgebra::{Complex, DMatrix};
+
+/// The largest word length supported, so that every value fits in an `f64`.
+pub const MAX_WORD_BITS: u32 = 53;
+
+/// How the bits below the least significant bit are dropped, named after the
+/// `ap_q_mode` of the HLS library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Rounding {
+    /// Round toward minus infinity (`AP_TRN`).
+    #[default]
+    Truncate,
+    /// Round toward zero (`AP_TRN_ZERO`).
+    TruncateZero,
+    /// Round to nearest, halves toward plus infinity (`AP_RND`).
+    Round,
+    /// Round to nearest, halves toward zero (`AP_RND_ZERO`).
+    RoundZero,
+    /// Round to nearest, halves away from zero (`AP_RND_INF`).
+    RoundInf,
+    /// Round to nearest, halves toward minus infinity (`AP_RND_MIN_INF`).
+    RoundMinInf,
+    /// Round to nearest, halves to even (`AP_RND_CONV`).
+    Convergent,
+}
+
+impl Rounding {
+    /// All the rounding modes, in the order of `NAMES`.
+    pub const ALL: [Self; 7] = [
+        Self::Truncate,
+        Self::TruncateZero,
+        Self::Round,
+        Self::RoundZero,
+        Self::RoundInf,
+        Self::RoundMinInf,
+        Self::Convergent,
+    ];
+
+    /// The names of the rounding modes, without the `AP_` prefix.
+    pub const NAMES: [&'static str; 7] = [
+        "trn",
+        "trn_zero",
+        "rnd",
+        "rnd_zero",
+        "rnd_inf",
+        "rnd_min_inf",
+        "rnd_conv",
+    ];
+
+    /// Round `floor + rest` to an integer, where `floor` is the integer part
+    /// of the value and `rest` its fractional part.
+    fn apply(self, floor: i128, rest: Rest) -> i128 {
+        let negative = floor < 0;
+        let up = match (self, rest) {
+            (_, Rest::Zero) | (Self::Truncate, _) => false,
+            (Self::TruncateZero, _) => negative,
+            (_, Rest::BelowHalf) => false,
+            (_, Rest::AboveHalf) => true,
+            (Self::Round, Rest::Half) => true,
+            (Self::RoundZero, Rest::Half) => negative,
+            (Self::RoundInf, Rest::Half) => !negative,
+            (Self::RoundMinInf, Rest::Half) => false,
+            (Self::Convergent, Rest::Half) => floor & 1 == 1,
+        };
+        floor + up as i128
+    }
+}
+
+/// How the bits above the most significant bit are dropped, named after the
+/// `ap_o_mode` of the HLS library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Overflow {
+    /// Keep the low bits, in two's complement (`AP_WRAP`).
+    #[default]
+    Wrap,
+    /// Clamp to the largest or smallest value (`AP_SAT`).
+    Saturate,
+    /// Replace the value with zero (`AP_SAT_ZERO`).
+    SaturateZero,
+    /// Clamp to the largest value or its opposite (`AP_SAT_SYM`).
+    SaturateSymmetric,
+}
+
+impl Overflow {
+    /// All the overflow modes, in the order of `NAMES`.
+    pub const ALL: [Self; 4] = [
+        Self::Wrap,
+        Self::Saturate,
+        Self::SaturateZero,
+        Self::SaturateSymmetric,
+    ];
+
+    /// The names of the overflow modes, without the `AP_` prefix.
+    pub const NAMES: [&'static str; 4] = ["wrap", "sat", "sat_zero", "sat_sym"];
+}
+
+/// Parse the name of a mode, ignoring the case and the `AP_` prefix.
+fn parse_mode<M: Copy>(s: &str, names: &[&str], modes: &[M], kind: &str) -> Result<M, String> {
+    let name = s.trim().to_lowercase();
+    let name = name.strip_prefix("ap_").unwrap_or(&name);
+    names
+        .iter()
+        .position(|n| *n == name)
+        .map(|i| modes[i])
+        .ok_or_else(|| {
+            format!(
+                "Unknown {} mode {:?}, expected one of: {}",
+                kind,
+                s,
+                names.join(", ")
+            )
+        })
+}
+
+impl std::str::FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_mode(s, &Self::NAMES, &Self::ALL, "rounding")
+    }
+}
+
+impl std::fmt::Display for Rounding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let index = Self::ALL.iter().position(|m| m == self).unwrap();
+        write!(f, "AP_{}", Self::NAMES[index].to_uppercase())
+    }
+}
+
+impl std::str::FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_mode(s, &Self::NAMES, &Self::ALL, "overflow")
+    }
+}
+
+impl std::fmt::Display for Overflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let index = Self::ALL.iter().position(|m| m == self).unwrap();
+        write!(f, "AP_{}", Self::NAMES[index].to_uppercase())
+    }
+}
+
+/// The fractional part of a value, compared to one half.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rest {
+    Zero,
+    BelowHalf,
+    Half,
+    AboveHalf,
+}
+
+impl Rest {
+    /// Compare the fraction `rest / 2^shift` to one half.
+    fn from_bits(rest: i128, shift: u32) -> Self {
+        let half = 1i128 << (shift - 1);
+        match rest {
+            0 => Self::Zero,
+            r if r < half => Self::BelowHalf,
+            r if r == half => Self::Half,
+            _ => Self::AboveHalf,
+        }
+    }
+
+    /// Compare a fraction in [0, 1) to one half.
+    fn from_fraction(rest: f64) -> Self {
+        if rest == 0.0 {
+            Self::Zero
+        } else if rest < 0.5 {
+            Self::BelowHalf
+        } else if rest == 0.5 {
+            Self::Half
+        } else {
+            Self::AboveHalf
+        }
+    }
+}
+
+/// The format of a fixed-point number, as the parameters of an
+/// `ap_fixed<W, I, Q, O>` type of the HLS library.
+///
+/// The format can be parsed from the parameters of the template, as
+/// `"18,2,rnd,sat"` or `"ap_fixed<18,2,AP_RND,AP_SAT>"`, the modes defaulting
+/// to truncation and wrapping as in the HLS library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FixedFormat {
+    /// The number of bits above the binary point, sign included.
+    pub int_bits: u32,
+    /// The number of bits below the binary point.
+    pub frac_bits: u32,
+    /// The rounding mode applied when low bits are dropped.
+    pub rounding: Rounding,
+    /// The overflow mode applied when high bits are dropped.
+    pub overflow: Overflow,
+}
+
+impl FixedFormat {
+    /// Create a format with `int_bits` integer bits, sign included, and
+    /// `frac_bits` fractional bits, that truncates and wraps.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no sign bit or if the word is longer than
+    /// `MAX_WORD_BITS`.
+    pub fn new(int_bits: u32, frac_bits: u32) -> Self {
+        assert!(int_bits >= 1, "The integer bits must include the sign bit");
+        assert!(
+            int_bits + frac_bits <= MAX_WORD_BITS,
+            "Words of more than {} bits are not supported",
+            MAX_WORD_BITS
+        );
+        Self {
+            int_bits,
+            frac_bits,
+            rounding: Rounding::default(),
+            overflow: Overflow::default(),
+        }
+    }
+
+    /// Set the rounding mode.
+    pub fn rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// Set the overflow mode.
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// The total number of bits of a word.
+    pub fn word_bits(&self) -> u32 {
+        self.int_bits + self.frac_bits
+    }
+
+    /// The value of the least significant bit.
+    pub fn epsilon(&self) -> f64 {
+        (-(self.frac_bits as f64)).exp2()
+    }
+
+    /// The largest raw word.
+    fn max_bits(&self) -> i128 {
+        (1i128 << (self.word_bits() - 1)) - 1
+    }
+
+    /// The smallest raw word.
+    fn min_bits(&self) -> i128 {
+        -(1i128 << (self.word_bits() - 1))
+    }
+
+    /// Apply the overflow mode to a raw value.
+    fn fit(&self, bits: i128) -> i64 {
+        let (min, max) = (self.min_bits(), self.max_bits());
+        if (min..=max).contains(&bits) {
+            return bits as i64;
+        }
+        let bits = match self.overflow {
+            Overflow::Wrap => {
+                let shift = 128 - self.word_bits();
+                (bits << shift) >> shift
+            }
+            Overflow::Saturate if bits > max => max,
+            Overflow::Saturate => min,
+            Overflow::SaturateZero => 0,
+            Overflow::SaturateSymmetric if bits > max => max,
+            Overflow::SaturateSymmetric => -max,
+        };
+        bits as i64
+    }
+
+    /// Quantize a raw value with `shift` more fractional bits than the format.
+    fn quantize_bits(&self, bits: i128, shift: u32) -> i64 {
+        if shift == 0 {
+            return self.fit(bits);
+        }
+        let floor = bits >> shift;
+        let rest = Rest::from_bits(bits - (floor << shift), shift);
+        self.fit(self.rounding.apply(floor, rest))
+    }
+
+    /// Quantize a real value to a raw word of this format.
+    pub fn quantize(&self, value: f64) -> i64 {
+        // scaling by a power of two is exact
+        let scaled = value * (self.frac_bits as f64).exp2();
+        let floor = scaled.floor();
+        let rest = Rest::from_fraction(scaled - floor);
+        self.fit(self.rounding.apply(floor as i128, rest))
+    }
+
+    /// The real value of a raw word of this format.
+    pub fn value(&self, bits: i64) -> f64 {
+        bits as f64 * self.epsilon()
+    }
+}
+
+impl std::str::FromStr for FixedFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let params = s.trim();
+        let params = match params.strip_prefix("ap_fixed<") {
+            Some(rest) => rest
+                .strip_suffix('>')
+                .ok_or_else(|| format!("Unclosed template in {:?}", s))?,
+            None => params,
+        };
+        let params: Vec<&str> = params.split(',').map(str::trim).collect();
+        if params.len() < 2 || params.len() > 4 {
+            return Err(format!(
+                "Expected the parameters W,I[,Q[,O]] of an ap_fixed, found {:?}",
+                s
+            ));
+        }
+        let bits = |p: &str| {
+            p.parse::<u32>()
+                .map_err(|e| format!("Invalid number of bits {:?}: {}", p, e))
+        };
+        let (word_bits, int_bits) = (bits(params[0])?, bits(params[1])?);
+        if int_bits < 1 || int_bits > word_bits || word_bits > MAX_WORD_BITS {
+            return Err(format!(
+                "Expected 1 <= I <= W <= {}, found W = {} and I = {}",
+                MAX_WORD_BITS, word_bits, int_bits
+            ));
+        }
+        let mut format = Self::new(int_bits, word_bits - int_bits);
+        if let Some(rounding) = params.get(2) {
+            format = format.rounding(rounding.parse()?);
+        }
+        if let Some(overflow) = params.get(3) {
+            format = format.overflow(overflow.parse()?);
+        }
+        Ok(format)
+    }
+}
+
+impl std::fmt::Display for FixedFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ap_fixed<{},{},{},{}>",
+            self.word_bits(),
+            self.int_bits,
+            self.rounding,
+            self.overflow
+        )
+    }
+}
+
+/// A complex number with fixed-point components, stored as the raw words of
+/// its format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FixedComplex {
+    re: i64,
+    im: i64,
+    format: FixedFormat,
+}
+
+impl FixedComplex {
+    /// Quantize a complex number to the format.
+    pub fn new(value: Complex<f64>, format: FixedFormat) -> Self {
+        Self {
+            re: format.quantize(value.re),
+            im: format.quantize(value.im),
+            format,
+        }
+    }
+
+    /// Create a complex number from the raw words of its components.
+    pub fn from_bits(re: i64, im: i64, format: FixedFormat) -> Self {
+        Self {
+            re: format.fit(re as i128),
+            im: format.fit(im as i128),
+            format,
+        }
+    }
+
+    /// The zero of the format.
+    pub fn zero(format: FixedFormat) -> Self {
+        Self::from_bits(0, 0, format)
+    }
+
+    /// The raw words of the real and imaginary parts.
+    pub fn bits(&self) -> (i64, i64) {
+        (self.re, self.im)
+    }
+
+    /// The format of the components.
+    pub fn format(&self) -> FixedFormat {
+        self.format
+    }
+
+    /// The exact value of the number.
+    pub fn to_complex(&self) -> Complex<f64> {
+        Complex::new(self.format.value(self.re), self.format.value(self.im))
+    }
+}
+
+impl std::ops::Add for FixedComplex {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        assert_eq!(self.format, rhs.format, "Incompatible fixed-point formats");
+        let format = self.format;
+        Self {
+            re: format.fit(self.re as i128 + rhs.re as i128),
+            im: format.fit(self.im as i128 + rhs.im as i128),
+            format,
+        }
+    }
+}
+
+impl std::ops::Mul for FixedComplex {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        assert_eq!(self.format, rhs.format, "Incompatible fixed-point formats");
+        let format = self.format;
+        let (a, b) = (self.re as i128, self.im as i128);
+        let (c, d) = (rhs.re as i128, rhs.im as i128);
+        // the full products have twice the fractional bits
+        Self {
+            re: format.quantize_bits(a * c - b * d, format.frac_bits),
+            im: format.quantize_bits(a * d + b * c, format.frac_bits),
+            format,
+        }
+    }
+}
+
+impl std::fmt::Display for FixedComplex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_complex())
+    }
+}
+
+/// Quantize every entry of a matrix to the format.
+pub fn quantize(matrix: &DMatrix<Complex<f64>>, format: FixedFormat) -> DMatrix<FixedComplex> {
+    matrix.map(|value| FixedComplex::new(value, format))
+}
+
+/// Return the exact values of a fixed-point matrix.
+pub fn dequantize(matrix: &DMatrix<FixedComplex>) -> DMatrix<Complex<f64>> {
+    matrix.map(|value| value.to_complex())
+}
+
+/// Compute the Kronecker product of two fixed-point matrices, quantizing each
+/// product of entries.
+pub fn kronecker(
+    left: &DMatrix<FixedComplex>,
+    right: &DMatrix<FixedComplex>,
+) -> DMatrix<FixedComplex> {
+    let (rr, rc) = right.shape();
+    DMatrix::from_fn(left.nrows() * rr, left.ncols() * rc, |i, j| {
+        left[(i / rr, j / rc)] * right[(i % rr, j % rc)]
+    })
+}
+
+/// Compute the product of two fixed-point matrices, accumulating the quantized
+/// products of each entry in increasing order of the inner index.
+///
+/// # Panics
+///
+/// Panics if the matrices have incompatible dimensions or are empty.
+pub fn matmul(
+    left: &DMatrix<FixedComplex>,
+    right: &DMatrix<FixedComplex>,
+) -> DMatrix<FixedComplex> {
+    assert_eq!(left.ncols(), right.nrows(), "Incompatible dimensions");
+    let format = left[(0, 0)].format();
+    DMatrix::from_fn(left.nrows(), right.ncols(), |i, j| {
+        (0..left.ncols()).fold(FixedComplex::zero(format), |acc, k| {
+            acc + left[(i, k)] * right[(k, j)]
+        })
+    })
+}
+
+/// Apply a fixed-point matrix to the lanes of a fixed-point state vector,
+/// starting at lane `start`, as `QRegister::apply_matrix` does. Each group of
+/// amplitudes is multiplied as in `matmul`.
+///
+/// # Panics
+///
+/// Panics if the matrix does not fit in the state from lane `start`.
+pub fn apply_matrix(
+    matrix: &DMatrix<FixedComplex>,
+    state: &DMatrix<FixedComplex>,
+    start: usize,
+) -> DMatrix<FixedComplex> {
+    let n = state.nrows().ilog2() as usize;
+    let width = matrix.nrows().ilog2() as usize;
+    assert!(start + width <= n, "The matrix does not fit in the state");
+    let low_bits = n - start - width;
+    let mut result = state.clone();
+    for high in 0..(1usize << start) {
+        for low in 0..(1usize << low_bits) {
+            let base = (high << (n - start)) | low;
+            let amplitudes = DMatrix::from_fn(1 << width, 1, |k, _| state[base | (k << low_bits)]);
+            let applied = matmul(matrix, &amplitudes);
+            for k in 0..(1 << width) {
+                result[base | (k << low_bits)] = applied[k];
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::{fixtures::circuit_contraction, TensorNetwork},
+        executor::CpuExecutor,
+        library,
+        model::{blocks::Block, scalar::deviation, QRegister},
+        op_tree::{Operand, Operation},
+        scheduler::OperationPlan,
+    };
+
+    #[test]
+    fn rounding_modes() {
+        // values in units of the least significant bit
+        let values = [2.5, 3.5, -2.5, 2.25, -2.75, -3.0];
+        let expected = [
+            (Rounding::Truncate, [2, 3, -3, 2, -3, -3]),
+            (Rounding::TruncateZero, [2, 3, -2, 2, -2, -3]),
+            (Rounding::Round, [3, 4, -2, 2, -3, -3]),
+            (Rounding::RoundZero, [2, 3, -2, 2, -3, -3]),
+            (Rounding::RoundInf, [3, 4, -3, 2, -3, -3]),
+            (Rounding::RoundMinInf, [2, 3, -3, 2, -3, -3]),
+            (Rounding::Convergent, [2, 4, -2, 2, -3, -3]),
+        ];
+        for (rounding, bits) in expected {
+            let format = FixedFormat::new(8, 4).rounding(rounding);
+            for (value, bits) in values.iter().zip(bits) {
+                assert_eq!(
+                    format.quantize(value / 16.0),
+                    bits,
+                    "{} {}",
+                    rounding,
+                    value
+                );
+                // the products take the same path through raw words
+                let product = format.quantize_bits((value * 256.0) as i128, 8);
+                assert_eq!(product, bits, "{} {}", rounding, value);
+            }
+        }
+    }
+
+    #[test]
+    fn overflow_modes() {
+        // 4 bits words, from -8 to 7
+        let expected = [
+            (Overflow::Wrap, [7, -8, -8, 7, 0]),
+            (Overflow::Saturate, [7, 7, -8, -8, 7]),
+            (Overflow::SaturateZero, [7, 0, -8, 0, 0]),
+            (Overflow::SaturateSymmetric, [7, 7, -8, -7, 7]),
+        ];
+        for (overflow, bits) in expected {
+            let format = FixedFormat::new(4, 0).overflow(overflow);
+            let fitted = [7, 8, -8, -9, 16].map(|b| format.fit(b));
+            assert_eq!(fitted, bits, "{}", overflow);
+        }
+    }
+
+    #[test]
+    fn format_names() {
+        let format: FixedFormat = "18,2,rnd,sat".parse().unwrap();
+        assert_eq!(
+            format,
+            FixedFormat::new(2, 16)
+                .rounding(Rounding::Round)
+                .overflow(Overflow::Saturate)
+        );
+        assert_eq!(format.to_string(), "ap_fixed<18,2,AP_RND,AP_SAT>");
+        assert_eq!(format.to_string().parse::<FixedFormat>(), Ok(format));
+        assert_eq!("16,3".parse(), Ok(FixedFormat::new(3, 13)));
+        for name in Rounding::NAMES {
+            assert_eq!(
+                name.parse::<Rounding>().unwrap().to_string().to_lowercase(),
+                format!("ap_{}", name)
+            );
+        }
+        assert!("16".parse::<FixedFormat>().is_err());
+        assert!("16,0".parse::<FixedFormat>().is_err());
+        assert!("64,2".parse::<FixedFormat>().is_err());
+        assert!("18,2,round".parse::<FixedFormat>().is_err());
+    }
+
+    #[test]
+    fn kernels_follow_the_floating_point_ones() {
+        let format = FixedFormat::new(2, 20).rounding(Rounding::Convergent);
+        let u = library::qft(2).eval();
+        let h = library::qft(1).eval();
+        let (qu, qh) = (quantize(u.as_ref(), format), quantize(h.as_ref(), format));
+
+        let te = dequantize(&kronecker(&qu, &qh));
+        let mm = dequantize(&matmul(&qu, &qu));
+        assert!(deviation(&te, &u.as_ref().kronecker(h.as_ref())) < 16.0 * format.epsilon());
+        assert!(deviation(&mm, &(u.as_ref() * u.as_ref())) < 16.0 * format.epsilon());
+        // the results are on the grid of the format
+        assert_eq!(
+            quantize(&te, format).map(|v| v.bits()),
+            kronecker(&qu, &qh).map(|v| v.bits())
+        );
+
+        // the unit does not fit in a single integer bit
+        let narrow = FixedFormat::new(1, 4).overflow(Overflow::Saturate);
+        assert_eq!(
+            FixedComplex::new(Complex::new(1.0, -1.0), narrow).bits(),
+            (15, -16)
+        );
+    }
+
+    #[test]
+    fn fixed_point_plan() {
+        let operation =
+            Operation::from_contraction(circuit_contraction(library::qft(4)), false).unwrap();
+        let plan = OperationPlan::from(operation.clone());
+        let expected = CpuExecutor::new().execute(plan).pop().unwrap();
+
+        let format = FixedFormat::new(2, 24).rounding(Rounding::Round);
+        let plan = OperationPlan::from(operation.clone()).fixed_point(format);
+        let result = CpuExecutor::new().execute(plan).pop().unwrap();
+        let error = deviation(result.as_ref(), expected.as_ref());
+        assert!(error > 0.0 && error < 1e-5, "deviation {}", error);
+        let grid = quantize(result.as_ref(), format);
+        assert_eq!(&dequantize(&grid), result.as_ref());
+
+        // the blocks keep the least significant bit of wide formats
+        let wide = FixedFormat::new(2, 48);
+        let lsb = DMatrix::from_diagonal_element(4, 4, Complex::new(wide.epsilon(), 0.0));
+        assert_eq!(Block::from(lsb.clone()).as_ref(), &lsb);
+
+        let coarse = FixedFormat::new(2, 8).rounding(Rounding::Round);
+        let plan = OperationPlan::from(operation).fixed_point(coarse);
+        let coarse = CpuExecutor::new().execute(plan).pop().unwrap();
+        assert!(deviation(coarse.as_ref(), expected.as_ref()) > error);
+    }
+
+    #[test]
+    fn fixed_point_state_plan() {
+        let tensors = TensorNetwork::from(library::qft(3)).max_rank(2).contract();
+        let Operand::Operation(operation) =
+            Operand::from_state_contraction(tensors, QRegister::basis(3, 1)).unwrap()
+        else {
+            panic!("Expected a chain of products");
+        };
+        let plan = OperationPlan::from(*operation.clone());
+        let expected = CpuExecutor::new().execute(plan).pop().unwrap();
+
+        let format = FixedFormat::new(2, 12).rounding(Rounding::Round);
+        let plan = OperationPlan::from(*operation).fixed_point(format);
+        let result = CpuExecutor::new().execute(plan).pop().unwrap();
+        let error = deviation(result.as_ref(), expected.as_ref());
+        assert!(error > 0.0 && error < 1e-2, "deviation {}", error);
+        let grid = quantize(result.as_ref(), format);
+        assert_eq!(&dequantize(&grid), result.as_ref());
+    }
+}
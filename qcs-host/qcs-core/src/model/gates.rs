@@ -5,12 +5,21 @@
 //! matrix with norm 1. The gates are represented as matrices of complex
 //! numbers, and are used to perform operations on qubits.
 
-use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+use std::{
+    f64::consts::{FRAC_PI_2, FRAC_PI_4},
+    sync::Arc,
+};
 
 use enum_dispatch::enum_dispatch;
 use nalgebra::{Complex, DMatrix};
 
-use super::{blocks::SpannedBlock, span::Span, Block, Braket, Qubit, TensorProduct};
+use super::{
+    blocks::SpannedBlock,
+    cache::{GateCache, GateKey},
+    monomial::MonomialBlock,
+    span::Span,
+    Block, Braket, Qubit, TensorProduct,
+};
 
 /// An interface for quantum gates. Each gate has a matrix representation, a
 /// rank, a span, and a block representation.
@@ -27,6 +36,13 @@ pub trait QuantumGate {
     /// Return the matrix representation of the gate
     fn matrix(&self) -> DMatrix<Complex<f64>>;
 
+    /// Return the matrix representation of the gate, shared with the
+    /// `GateCache` by the gates whose matrix is cached, so that reading it
+    /// does not copy it.
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        Arc::new(self.matrix())
+    }
+
     /// Return the rank of the gate.
     /// Rank 1 gates are single qubit gates, rank 2 gates are two qubit gates, etc.
     fn rank(&self) -> u8;
@@ -34,9 +50,23 @@ pub trait QuantumGate {
     /// Return the span of the gate
     fn span(&self) -> Span;
 
-    /// Return the equivalent block representation of the gate
+    /// Return the structure of the matrix of the gate. Gates are general
+    /// unless they report a sparser structure.
+    fn structure(&self) -> Structure {
+        Structure::General
+    }
+
+    /// Return the equivalent block representation of the gate, keeping only
+    /// the non-zero entries of diagonal and monomial gates.
     fn block(&self) -> Block {
-        self.matrix().into()
+        match self.structure() {
+            Structure::General => Arc::unwrap_or_clone(self.shared_matrix()).into(),
+            Structure::Diagonal | Structure::Monomial => {
+                MonomialBlock::from_dense(&self.shared_matrix())
+                    .expect("The gate is not monomial")
+                    .into()
+            }
+        }
     }
 
     /// Return spanned block representation of the gate
@@ -45,6 +75,18 @@ pub trait QuantumGate {
     }
 }
 
+/// The structure of the matrix of a gate or of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Structure {
+    /// Only the entries on the diagonal are non-zero.
+    Diagonal,
+    /// Exactly one entry in each row and column is non-zero, that is a
+    /// permutation matrix with phases.
+    Monomial,
+    /// Any other matrix.
+    General,
+}
+
 /// This represents all available quantum gates in the system.
 #[enum_dispatch(QuantumGate)]
 #[derive(Debug, Clone)]
@@ -108,6 +150,75 @@ impl Gate {
     pub fn is_rank_one(&self) -> bool {
         self.rank() == 1
     }
+
+    /// Return the name of the kind of gate, without parameters or lanes.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Gate::Identity(_) => "I",
+            Gate::PauliX(_) => "X",
+            Gate::PauliY(_) => "Y",
+            Gate::PauliZ(_) => "Z",
+            Gate::Hadamard(_) => "H",
+            Gate::Phase(_) => "P",
+            Gate::SX(_) => "SX",
+            Gate::RX(_) => "RX",
+            Gate::RY(_) => "RY",
+            Gate::RZ(_) => "RZ",
+            Gate::CX(_) => "CX",
+            Gate::CY(_) => "CY",
+            Gate::CZ(_) => "CZ",
+            Gate::CP(_) => "CP",
+            Gate::CRX(_) => "CRX",
+            Gate::CRY(_) => "CRY",
+            Gate::CRZ(_) => "CRZ",
+            Gate::CH(_) => "CH",
+            Gate::Swap(_) => "SWAP",
+            Gate::Toffoli(_) => "CCX",
+            Gate::Fredkin(_) => "CSWAP",
+            Gate::CU(_) => "CU",
+            Gate::U1(_) => "U1",
+            Gate::U2(_) => "U2",
+            Gate::U3(_) => "U3",
+            Gate::U(_) => "U",
+        }
+    }
+
+    /// Return the key of the gate in the `GateCache`: its kind, its
+    /// parameters and its lanes relative to the first one.
+    pub fn key(&self) -> GateKey {
+        let (params, lanes): (&[f64], &[usize]) = match self {
+            Gate::Identity(i) => (&[], &[i.lane]),
+            Gate::PauliX(x) => (&[], &[x.lane]),
+            Gate::PauliY(y) => (&[], &[y.lane]),
+            Gate::PauliZ(z) => (&[], &[z.lane]),
+            Gate::Hadamard(h) => (&[], &[h.lane]),
+            Gate::Phase(p) => (&[p.phase], &[p.lane]),
+            Gate::SX(sx) => (&[], &[sx.lane]),
+            Gate::RX(rx) => (&[rx.theta], &[rx.lane]),
+            Gate::RY(ry) => (&[ry.theta], &[ry.lane]),
+            Gate::RZ(rz) => (&[rz.theta], &[rz.lane]),
+            Gate::CX(cx) => (&[], &[cx.control, cx.target]),
+            Gate::CY(cy) => (&[], &[cy.control, cy.target]),
+            Gate::CZ(cz) => (&[], &[cz.control, cz.target]),
+            Gate::CP(cp) => (&[cp.phase], &[cp.control, cp.target]),
+            Gate::CRX(crx) => (&[crx.theta], &[crx.control, crx.target]),
+            Gate::CRY(cry) => (&[cry.theta], &[cry.control, cry.target]),
+            Gate::CRZ(crz) => (&[crz.theta], &[crz.control, crz.target]),
+            Gate::CH(ch) => (&[], &[ch.control, ch.target]),
+            Gate::Swap(s) => (&[], &[s.lanes.0, s.lanes.1]),
+            Gate::Toffoli(t) => (&[], &[t.control.0, t.control.1, t.target]),
+            Gate::Fredkin(fk) => (&[], &[fk.control, fk.target.0, fk.target.1]),
+            Gate::CU(cu) => (
+                &[cu.theta, cu.phi, cu.lambda, cu.gamma],
+                &[cu.control, cu.target],
+            ),
+            Gate::U1(u1) => (&[u1.lambda], &[u1.lane]),
+            Gate::U2(u2) => (&[u2.phi, u2.lambda], &[u2.lane]),
+            Gate::U3(u3) => (&[u3.theta, u3.phi, u3.lambda], &[u3.lane]),
+            Gate::U(u) => (&[u.theta, u.phi, u.lambda], &[u.lane]),
+        };
+        GateKey::new(self.name(), params, lanes)
+    }
 }
 
 impl std::fmt::Display for Gate {
@@ -191,6 +302,10 @@ impl Identity {
 }
 
 impl QuantumGate for Identity {
+    fn structure(&self) -> Structure {
+        Structure::Diagonal
+    }
+
     fn rank(&self) -> u8 {
         1
     }
@@ -233,6 +348,10 @@ impl PauliX {
 }
 
 impl QuantumGate for PauliX {
+    fn structure(&self) -> Structure {
+        Structure::Monomial
+    }
+
     fn rank(&self) -> u8 {
         1
     }
@@ -275,6 +394,10 @@ impl PauliY {
 }
 
 impl QuantumGate for PauliY {
+    fn structure(&self) -> Structure {
+        Structure::Monomial
+    }
+
     fn rank(&self) -> u8 {
         1
     }
@@ -317,6 +440,10 @@ impl PauliZ {
 }
 
 impl QuantumGate for PauliZ {
+    fn structure(&self) -> Structure {
+        Structure::Diagonal
+    }
+
     fn rank(&self) -> u8 {
         1
     }
@@ -417,6 +544,10 @@ impl Phase {
 }
 
 impl QuantumGate for Phase {
+    fn structure(&self) -> Structure {
+        Structure::Diagonal
+    }
+
     fn rank(&self) -> u8 {
         1
     }
@@ -591,6 +722,10 @@ impl RZ {
 }
 
 impl QuantumGate for RZ {
+    fn structure(&self) -> Structure {
+        Structure::Diagonal
+    }
+
     fn rank(&self) -> u8 {
         1
     }
@@ -639,6 +774,10 @@ impl CX {
 }
 
 impl QuantumGate for CX {
+    fn structure(&self) -> Structure {
+        Structure::Monomial
+    }
+
     fn rank(&self) -> u8 {
         2
     }
@@ -648,7 +787,13 @@ impl QuantumGate for CX {
     }
 
     fn matrix(&self) -> DMatrix<Complex<f64>> {
-        controlled_gate_block(self.control, self.target, pauli_x_matrix())
+        Arc::unwrap_or_clone(self.shared_matrix())
+    }
+
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        cached_matrix(*self, || {
+            controlled_gate_block(self.control, self.target, pauli_x_matrix())
+        })
     }
 }
 
@@ -686,6 +831,10 @@ impl CY {
 }
 
 impl QuantumGate for CY {
+    fn structure(&self) -> Structure {
+        Structure::Monomial
+    }
+
     fn rank(&self) -> u8 {
         2
     }
@@ -695,7 +844,13 @@ impl QuantumGate for CY {
     }
 
     fn matrix(&self) -> DMatrix<Complex<f64>> {
-        controlled_gate_block(self.control, self.target, pauli_y_matrix())
+        Arc::unwrap_or_clone(self.shared_matrix())
+    }
+
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        cached_matrix(*self, || {
+            controlled_gate_block(self.control, self.target, pauli_y_matrix())
+        })
     }
 }
 
@@ -733,6 +888,10 @@ impl CZ {
 }
 
 impl QuantumGate for CZ {
+    fn structure(&self) -> Structure {
+        Structure::Diagonal
+    }
+
     fn rank(&self) -> u8 {
         2
     }
@@ -742,7 +901,13 @@ impl QuantumGate for CZ {
     }
 
     fn matrix(&self) -> DMatrix<Complex<f64>> {
-        controlled_gate_block(self.control, self.target, pauli_z_matrix())
+        Arc::unwrap_or_clone(self.shared_matrix())
+    }
+
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        cached_matrix(*self, || {
+            controlled_gate_block(self.control, self.target, pauli_z_matrix())
+        })
     }
 }
 
@@ -786,6 +951,10 @@ impl CP {
 }
 
 impl QuantumGate for CP {
+    fn structure(&self) -> Structure {
+        Structure::Diagonal
+    }
+
     fn rank(&self) -> u8 {
         2
     }
@@ -795,7 +964,13 @@ impl QuantumGate for CP {
     }
 
     fn matrix(&self) -> DMatrix<Complex<f64>> {
-        controlled_gate_block(self.control, self.target, phase_matrix(self.phase))
+        Arc::unwrap_or_clone(self.shared_matrix())
+    }
+
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        cached_matrix(*self, || {
+            controlled_gate_block(self.control, self.target, phase_matrix(self.phase))
+        })
     }
 }
 
@@ -846,7 +1021,13 @@ impl QuantumGate for CRX {
     }
 
     fn matrix(&self) -> DMatrix<Complex<f64>> {
-        controlled_gate_block(self.control, self.target, rotated_x_matrix(self.theta))
+        Arc::unwrap_or_clone(self.shared_matrix())
+    }
+
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        cached_matrix(*self, || {
+            controlled_gate_block(self.control, self.target, rotated_x_matrix(self.theta))
+        })
     }
 }
 
@@ -897,7 +1078,13 @@ impl QuantumGate for CRY {
     }
 
     fn matrix(&self) -> DMatrix<Complex<f64>> {
-        controlled_gate_block(self.control, self.target, rotated_y_matrix(self.theta))
+        Arc::unwrap_or_clone(self.shared_matrix())
+    }
+
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        cached_matrix(*self, || {
+            controlled_gate_block(self.control, self.target, rotated_y_matrix(self.theta))
+        })
     }
 }
 
@@ -939,6 +1126,10 @@ impl CRZ {
 }
 
 impl QuantumGate for CRZ {
+    fn structure(&self) -> Structure {
+        Structure::Diagonal
+    }
+
     fn rank(&self) -> u8 {
         2
     }
@@ -948,7 +1139,13 @@ impl QuantumGate for CRZ {
     }
 
     fn matrix(&self) -> DMatrix<Complex<f64>> {
-        controlled_gate_block(self.control, self.target, rotated_z_matrix(self.theta))
+        Arc::unwrap_or_clone(self.shared_matrix())
+    }
+
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        cached_matrix(*self, || {
+            controlled_gate_block(self.control, self.target, rotated_z_matrix(self.theta))
+        })
     }
 }
 
@@ -994,7 +1191,13 @@ impl QuantumGate for CH {
     }
 
     fn matrix(&self) -> DMatrix<Complex<f64>> {
-        controlled_gate_block(self.control, self.target, hadamard_matrix())
+        Arc::unwrap_or_clone(self.shared_matrix())
+    }
+
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        cached_matrix(*self, || {
+            controlled_gate_block(self.control, self.target, hadamard_matrix())
+        })
     }
 }
 
@@ -1032,6 +1235,10 @@ impl Swap {
 }
 
 impl QuantumGate for Swap {
+    fn structure(&self) -> Structure {
+        Structure::Monomial
+    }
+
     fn rank(&self) -> u8 {
         2
     }
@@ -1041,24 +1248,30 @@ impl QuantumGate for Swap {
     }
 
     fn matrix(&self) -> DMatrix<Complex<f64>> {
-        let uninvolved = usize::abs_diff(self.lanes.0, self.lanes.1) - 1;
-        let mut a00 = Qubit::zero().ketbra();
-        a00 = tensor_expand(a00, uninvolved);
-        a00 = a00.tensor_product(Qubit::zero().ketbra());
+        Arc::unwrap_or_clone(self.shared_matrix())
+    }
+
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        cached_matrix(*self, || {
+            let uninvolved = usize::abs_diff(self.lanes.0, self.lanes.1) - 1;
+            let mut a00 = Qubit::zero().ketbra();
+            a00 = tensor_expand(a00, uninvolved);
+            a00 = a00.tensor_product(Qubit::zero().ketbra());
 
-        let mut a01 = Qubit::zero().ket() * Qubit::one().bra();
-        a01 = tensor_expand(a01, uninvolved);
-        a01 = a01.tensor_product(Qubit::one().ket() * Qubit::zero().bra());
+            let mut a01 = Qubit::zero().ket() * Qubit::one().bra();
+            a01 = tensor_expand(a01, uninvolved);
+            a01 = a01.tensor_product(Qubit::one().ket() * Qubit::zero().bra());
 
-        let mut a10 = Qubit::one().ket() * Qubit::zero().bra();
-        a10 = tensor_expand(a10, uninvolved);
-        a10 = a10.tensor_product(Qubit::zero().ket() * Qubit::one().bra());
+            let mut a10 = Qubit::one().ket() * Qubit::zero().bra();
+            a10 = tensor_expand(a10, uninvolved);
+            a10 = a10.tensor_product(Qubit::zero().ket() * Qubit::one().bra());
 
-        let mut a11 = Qubit::one().ketbra();
-        a11 = tensor_expand(a11, uninvolved);
-        a11 = a11.tensor_product(Qubit::one().ketbra());
+            let mut a11 = Qubit::one().ketbra();
+            a11 = tensor_expand(a11, uninvolved);
+            a11 = a11.tensor_product(Qubit::one().ketbra());
 
-        a00 + a01 + a10 + a11
+            a00 + a01 + a10 + a11
+        })
     }
 }
 
@@ -1103,6 +1316,10 @@ impl Toffoli {
 }
 
 impl QuantumGate for Toffoli {
+    fn structure(&self) -> Structure {
+        Structure::Monomial
+    }
+
     fn rank(&self) -> u8 {
         3
     }
@@ -1112,43 +1329,49 @@ impl QuantumGate for Toffoli {
     }
 
     fn matrix(&self) -> DMatrix<Complex<f64>> {
-        let lanes = [self.control.0, self.control.1, self.target];
-        let start = lanes.iter().min().unwrap();
-        let end = lanes.iter().max().unwrap();
-
-        let mut res = Block::empty(2_usize.pow((end - start + 1) as u32)).into_matrix();
-
-        // case control <- 0* || *0
-        let cases = [
-            (Qubit::zero(), Qubit::zero()),
-            (Qubit::zero(), Qubit::one()),
-            (Qubit::one(), Qubit::zero()),
-        ];
-        for (c0, c1) in cases.into_iter() {
+        Arc::unwrap_or_clone(self.shared_matrix())
+    }
+
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        cached_matrix(*self, || {
+            let lanes = [self.control.0, self.control.1, self.target];
+            let start = lanes.iter().min().unwrap();
+            let end = lanes.iter().max().unwrap();
+
+            let mut res = Block::empty(2_usize.pow((end - start + 1) as u32)).into_matrix();
+
+            // case control <- 0* || *0
+            let cases = [
+                (Qubit::zero(), Qubit::zero()),
+                (Qubit::zero(), Qubit::one()),
+                (Qubit::one(), Qubit::zero()),
+            ];
+            for (c0, c1) in cases.into_iter() {
+                let mut a = Block::one().into_matrix();
+                for i in *start..=*end {
+                    a = match i {
+                        _ if i == self.control.0 => a.tensor_product(c0.ketbra()),
+                        _ if i == self.control.1 => a.tensor_product(c1.ketbra()),
+                        _ if i == self.target => a.tensor_product(Block::identity(2)),
+                        _ => a.tensor_product(Block::identity(2)),
+                    };
+                }
+                res += a;
+            }
+
+            // case control <- 11
             let mut a = Block::one().into_matrix();
             for i in *start..=*end {
                 a = match i {
-                    _ if i == self.control.0 => a.tensor_product(c0.ketbra()),
-                    _ if i == self.control.1 => a.tensor_product(c1.ketbra()),
-                    _ if i == self.target => a.tensor_product(Block::identity(2)),
+                    _ if i == self.control.0 => a.tensor_product(Qubit::one().ketbra()),
+                    _ if i == self.control.1 => a.tensor_product(Qubit::one().ketbra()),
+                    _ if i == self.target => a.tensor_product(pauli_x_matrix()),
                     _ => a.tensor_product(Block::identity(2)),
                 };
             }
-            res += a;
-        }
 
-        // case control <- 11
-        let mut a = Block::one().into_matrix();
-        for i in *start..=*end {
-            a = match i {
-                _ if i == self.control.0 => a.tensor_product(Qubit::one().ketbra()),
-                _ if i == self.control.1 => a.tensor_product(Qubit::one().ketbra()),
-                _ if i == self.target => a.tensor_product(pauli_x_matrix()),
-                _ => a.tensor_product(Block::identity(2)),
-            };
-        }
-
-        res + a
+            res + a
+        })
     }
 }
 
@@ -1192,6 +1415,10 @@ impl Fredkin {
 }
 
 impl QuantumGate for Fredkin {
+    fn structure(&self) -> Structure {
+        Structure::Monomial
+    }
+
     fn rank(&self) -> u8 {
         3
     }
@@ -1201,45 +1428,51 @@ impl QuantumGate for Fredkin {
     }
 
     fn matrix(&self) -> DMatrix<Complex<f64>> {
-        let lanes = [self.control, self.target.0, self.target.1];
-        let start = lanes.iter().min().unwrap();
-        let end = lanes.iter().max().unwrap();
-
-        let mut res = Block::empty(2_usize.pow((end - start + 1) as u32)).into_matrix();
-
-        // case control <- 0
-        let mut c = Block::one().into_matrix();
-        for i in *start..=*end {
-            c = match i {
-                _ if i == self.control => c.tensor_product(Qubit::zero().ketbra()),
-                _ if i == self.target.0 => c.tensor_product(Block::identity(2)),
-                _ if i == self.target.1 => c.tensor_product(Block::identity(2)),
-                _ => c.tensor_product(Block::identity(2)),
-            };
-        }
-        res += c;
-
-        // case control <- 1
-        let cases = [
-            (Qubit::zero(), Qubit::zero()),
-            (Qubit::zero(), Qubit::one()),
-            (Qubit::one(), Qubit::zero()),
-            (Qubit::one(), Qubit::one()),
-        ];
-        for (a, b) in cases.into_iter() {
+        Arc::unwrap_or_clone(self.shared_matrix())
+    }
+
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        cached_matrix(*self, || {
+            let lanes = [self.control, self.target.0, self.target.1];
+            let start = lanes.iter().min().unwrap();
+            let end = lanes.iter().max().unwrap();
+
+            let mut res = Block::empty(2_usize.pow((end - start + 1) as u32)).into_matrix();
+
+            // case control <- 0
             let mut c = Block::one().into_matrix();
             for i in *start..=*end {
                 c = match i {
-                    _ if i == self.control => c.tensor_product(Qubit::one().ketbra()),
-                    _ if i == self.target.0 => c.tensor_product(a.ket() * b.bra()),
-                    _ if i == self.target.1 => c.tensor_product(b.ket() * a.bra()),
+                    _ if i == self.control => c.tensor_product(Qubit::zero().ketbra()),
+                    _ if i == self.target.0 => c.tensor_product(Block::identity(2)),
+                    _ if i == self.target.1 => c.tensor_product(Block::identity(2)),
                     _ => c.tensor_product(Block::identity(2)),
                 };
             }
             res += c;
-        }
 
-        res
+            // case control <- 1
+            let cases = [
+                (Qubit::zero(), Qubit::zero()),
+                (Qubit::zero(), Qubit::one()),
+                (Qubit::one(), Qubit::zero()),
+                (Qubit::one(), Qubit::one()),
+            ];
+            for (a, b) in cases.into_iter() {
+                let mut c = Block::one().into_matrix();
+                for i in *start..=*end {
+                    c = match i {
+                        _ if i == self.control => c.tensor_product(Qubit::one().ketbra()),
+                        _ if i == self.target.0 => c.tensor_product(a.ket() * b.bra()),
+                        _ if i == self.target.1 => c.tensor_product(b.ket() * a.bra()),
+                        _ => c.tensor_product(Block::identity(2)),
+                    };
+                }
+                res += c;
+            }
+
+            res
+        })
     }
 }
 
@@ -1317,6 +1550,10 @@ impl U1 {
 }
 
 impl QuantumGate for U1 {
+    fn structure(&self) -> Structure {
+        Structure::Diagonal
+    }
+
     fn rank(&self) -> u8 {
         1
     }
@@ -1463,30 +1700,45 @@ impl QuantumGate for CU {
     }
 
     fn matrix(&self) -> DMatrix<Complex<f64>> {
-        let mut a = Block::one().into_matrix();
-        let start = usize::min(self.control, self.target);
-        let end = usize::max(self.control, self.target);
-
-        for i in start..=end {
-            a = match i {
-                _ if i == self.control => {
-                    a.tensor_product(phase_matrix(self.gamma - self.theta / 2.0))
-                }
-                _ if i == self.target => a.tensor_product(Block::identity(2)),
-                _ => a.tensor_product(Block::identity(2)),
-            };
-        }
+        Arc::unwrap_or_clone(self.shared_matrix())
+    }
 
-        let b = controlled_gate_block(
-            self.control,
-            self.target,
-            universal_matrix(self.theta, self.phi, self.lambda),
-        );
+    fn shared_matrix(&self) -> Arc<DMatrix<Complex<f64>>> {
+        cached_matrix(*self, || {
+            let mut a = Block::one().into_matrix();
+            let start = usize::min(self.control, self.target);
+            let end = usize::max(self.control, self.target);
 
-        a * b
+            for i in start..=end {
+                a = match i {
+                    _ if i == self.control => {
+                        a.tensor_product(phase_matrix(self.gamma - self.theta / 2.0))
+                    }
+                    _ if i == self.target => a.tensor_product(Block::identity(2)),
+                    _ => a.tensor_product(Block::identity(2)),
+                };
+            }
+
+            let b = controlled_gate_block(
+                self.control,
+                self.target,
+                universal_matrix(self.theta, self.phi, self.lambda),
+            );
+
+            a * b
+        })
     }
 }
 
+/// Return the matrix of a gate from the global `GateCache`, building it on a
+/// miss.
+fn cached_matrix(
+    gate: impl Into<Gate>,
+    build: impl FnOnce() -> DMatrix<Complex<f64>>,
+) -> Arc<DMatrix<Complex<f64>>> {
+    GateCache::global().matrix(gate.into().key(), build)
+}
+
 /// See [this link](https://quantumcomputing.stackexchange.com/questions/4252/how-to-derive-the-cnot-matrix-for-a-3-qubit-system-where-the-control-target-qu)
 /// for more information on how to derive this matrix.
 fn controlled_gate_block(
@@ -1575,13 +1827,9 @@ fn universal_matrix(theta: f64, phi: f64, lambda: f64) -> DMatrix<Complex<f64>>
 
 fn gphase_matrix(phase: f64, dim: usize) -> DMatrix<Complex<f64>> {
     let phase = Complex::cis(phase);
-    let id = Block::identity(dim).into_matrix();
-    id * phase
+    GateCache::global().identity(dim).as_ref() * phase
 }
 
-fn tensor_expand(mut val: DMatrix<Complex<f64>>, uninvolved: usize) -> DMatrix<Complex<f64>> {
-    for _ in 0..uninvolved {
-        val = val.tensor_product(Block::identity(2));
-    }
-    val
+fn tensor_expand(val: DMatrix<Complex<f64>>, uninvolved: usize) -> DMatrix<Complex<f64>> {
+    val.kronecker(&GateCache::global().identity(1 << uninvolved))
 }
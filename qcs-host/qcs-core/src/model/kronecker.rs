@@ -0,0 +1,319 @@
+//! Module containing the definition of the `KroneckerBlock` struct.
+//!
+//! A `KroneckerBlock` is the product I⊗C⊗I of a core matrix C padded with
+//! identities on both sides, stored as its factors. It is what a `Block` turns
+//! into when it is expanded with `Block::identity`, so that the padding of a
+//! gate to a wider span is never materialised: multiplying it with a dense
+//! matrix walks the dense operand with strided loops, touching only the
+//! non-identity part of the product.
+
+use std::sync::OnceLock;
+
+use nalgebra::{Complex, DMatrix};
+
+use super::{
+    blocks::{Block, BlockLike},
+    scalar::{cast_matrix, Scalar},
+};
+
+/// The product I_left ⊗ core ⊗ I_right, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct KroneckerBlock<T: Scalar = f64> {
+    /// The dimension of the identity on the left of the core.
+    left: usize,
+    /// The matrix between the two identities.
+    core: DMatrix<Complex<T>>,
+    /// The dimension of the identity on the right of the core.
+    right: usize,
+    /// The expanded matrix, only built when it is borrowed as a dense matrix.
+    dense: OnceLock<DMatrix<Complex<T>>>,
+}
+
+impl<T: Scalar> KroneckerBlock<T> {
+    /// Create the product I_left ⊗ core ⊗ I_right.
+    pub fn new(left: usize, core: DMatrix<Complex<T>>, right: usize) -> Self {
+        assert!(left > 0 && right > 0, "Empty identity padding");
+        Self {
+            left,
+            core,
+            right,
+            dense: OnceLock::new(),
+        }
+    }
+
+    /// Create the identity of dimension dim, with a 1x1 core.
+    pub fn identity(dim: usize) -> Self {
+        Self::new(dim, DMatrix::from_element(1, 1, T::complex(1.0, 0.0)), 1)
+    }
+
+    /// Return the dimension of the identity on the left of the core.
+    pub fn left(&self) -> usize {
+        self.left
+    }
+
+    /// Return the core matrix.
+    pub fn core(&self) -> &DMatrix<Complex<T>> {
+        &self.core
+    }
+
+    /// Return the dimension of the identity on the right of the core.
+    pub fn right(&self) -> usize {
+        self.right
+    }
+
+    /// Return the number of rows of the expanded matrix.
+    pub fn nrows(&self) -> usize {
+        self.left * self.core.nrows() * self.right
+    }
+
+    /// Return the number of columns of the expanded matrix.
+    pub fn ncols(&self) -> usize {
+        self.left * self.core.ncols() * self.right
+    }
+
+    /// Check if the block is an identity, that is if its core is the 1x1 one.
+    pub fn is_identity(&self) -> bool {
+        self.core.shape() == (1, 1) && self.core[(0, 0)] == T::complex(1.0, 0.0)
+    }
+
+    /// Build the expanded matrix, writing the core on the diagonal blocks
+    /// without computing any kronecker product.
+    pub fn to_dense(&self) -> DMatrix<Complex<T>> {
+        let (p, q) = self.core.shape();
+        let r = self.right;
+        let mut dense = DMatrix::zeros(self.nrows(), self.ncols());
+        for a in 0..self.left {
+            for j in 0..q {
+                for i in 0..p {
+                    let value = self.core[(i, j)];
+                    for b in 0..r {
+                        dense[((a * p + i) * r + b, (a * q + j) * r + b)] = value;
+                    }
+                }
+            }
+        }
+        dense
+    }
+
+    /// Convert the block into its expanded matrix.
+    pub fn into_dense(mut self) -> DMatrix<Complex<T>> {
+        if self.left == 1 && self.right == 1 {
+            return self.core;
+        }
+        self.dense.take().unwrap_or_else(|| self.to_dense())
+    }
+
+    /// Compute the product lhs · self of a dense matrix with the block,
+    /// combining the columns of lhs picked by the core with strided loops.
+    pub fn dense_mul(lhs: &DMatrix<Complex<T>>, rhs: &Self) -> DMatrix<Complex<T>> {
+        assert_eq!(lhs.ncols(), rhs.nrows(), "Incompatible dimensions");
+        let (p, q) = rhs.core.shape();
+        let r = rhs.right;
+        let one = T::complex(1.0, 0.0);
+        let mut out = DMatrix::zeros(lhs.nrows(), rhs.ncols());
+        for a in 0..rhs.left {
+            for j in 0..q {
+                for b in 0..r {
+                    let mut column = out.column_mut((a * q + j) * r + b);
+                    for c in 0..p {
+                        let coeff = rhs.core[(c, j)];
+                        if coeff != T::complex(0.0, 0.0) {
+                            column.axpy(coeff, &lhs.column((a * p + c) * r + b), one);
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Compute the product self · rhs of the block with a dense matrix,
+    /// combining the rows of rhs picked by the core with strided loops.
+    pub fn mul_dense(&self, rhs: &DMatrix<Complex<T>>) -> DMatrix<Complex<T>> {
+        assert_eq!(self.ncols(), rhs.nrows(), "Incompatible dimensions");
+        let (p, q) = self.core.shape();
+        let r = self.right;
+        let mut out = DMatrix::zeros(self.nrows(), rhs.ncols());
+        for col in 0..rhs.ncols() {
+            for a in 0..self.left {
+                for b in 0..r {
+                    for i in 0..p {
+                        out[((a * p + i) * r + b, col)] = (0..q)
+                            .map(|c| self.core[(i, c)] * rhs[((a * q + c) * r + b, col)])
+                            .sum();
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Compute the product self · rhs of two blocks. The identities shared by
+    /// both operands stay as padding of the result, the rest of the operand
+    /// with the wider core is expanded and the other one is applied lazily.
+    pub fn mul(&self, rhs: &Self) -> Self {
+        assert_eq!(self.ncols(), rhs.nrows(), "Incompatible dimensions");
+        if self.is_identity() {
+            return rhs.clone();
+        }
+        if rhs.is_identity() {
+            return self.clone();
+        }
+        let left = self.left.min(rhs.left);
+        let right = self.right.min(rhs.right);
+        if [self.left, rhs.left].iter().any(|l| l % left != 0)
+            || [self.right, rhs.right].iter().any(|r| r % right != 0)
+        {
+            return Self::new(1, Self::dense_mul(&self.to_dense(), rhs), 1);
+        }
+        let lhs = Self::new(self.left / left, self.core.clone(), self.right / right);
+        let rhs = Self::new(rhs.left / left, rhs.core.clone(), rhs.right / right);
+        let core = if rhs.core.len() <= lhs.core.len() {
+            Self::dense_mul(&lhs.to_dense(), &rhs)
+        } else {
+            lhs.mul_dense(&rhs.to_dense())
+        };
+        Self::new(left, core, right)
+    }
+
+    /// Compute the tensor product self ⊗ rhs of two blocks. The outer
+    /// identities stay as padding, and the inner ones are absorbed by the
+    /// other core unless both cores are non trivial.
+    pub fn tensor_product(&self, rhs: &Self) -> Self {
+        if rhs.is_identity() {
+            Self::new(
+                self.left,
+                self.core.clone(),
+                self.right * rhs.left * rhs.right,
+            )
+        } else if self.is_identity() {
+            Self::new(
+                self.left * self.right * rhs.left,
+                rhs.core.clone(),
+                rhs.right,
+            )
+        } else {
+            let inner = Self::new(1, self.core.clone(), self.right * rhs.left);
+            let core = inner.to_dense().kronecker(&rhs.core);
+            Self::new(self.left, core, rhs.right)
+        }
+    }
+
+    /// Convert the core to another precision.
+    pub fn cast<U: Scalar>(&self) -> KroneckerBlock<U> {
+        KroneckerBlock::new(self.left, cast_matrix(&self.core), self.right)
+    }
+}
+
+impl<T: Scalar> From<DMatrix<Complex<T>>> for KroneckerBlock<T> {
+    fn from(matrix: DMatrix<Complex<T>>) -> Self {
+        Self::new(1, matrix, 1)
+    }
+}
+
+impl<T: Scalar> PartialEq for KroneckerBlock<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.left == other.left && self.right == other.right && self.core == other.core
+    }
+}
+
+impl<T: Scalar> AsRef<DMatrix<Complex<T>>> for KroneckerBlock<T> {
+    /// Borrow the expanded matrix, building it on the first call.
+    fn as_ref(&self) -> &DMatrix<Complex<T>> {
+        if self.left == 1 && self.right == 1 {
+            return &self.core;
+        }
+        self.dense.get_or_init(|| self.to_dense())
+    }
+}
+
+impl<T: Scalar> BlockLike for KroneckerBlock<T> {
+    type Scalar = T;
+
+    fn into_block(self) -> Block<T> {
+        Block::from(self)
+    }
+}
+
+impl<T: Scalar> std::fmt::Display for KroneckerBlock<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "I{} ⊗ {} ⊗ I{}", self.left, self.core, self.right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{gates::QuantumGate, QuantumCircuit, TensorProduct};
+
+    fn padded(left: usize, core: &DMatrix<Complex<f64>>, right: usize) -> DMatrix<Complex<f64>> {
+        DMatrix::identity(left, left)
+            .kronecker(core)
+            .kronecker(&DMatrix::identity(right, right))
+    }
+
+    fn random_matrix(rows: usize, cols: usize, seed: usize) -> DMatrix<Complex<f64>> {
+        DMatrix::from_fn(rows, cols, |r, c| {
+            let x = (r * 31 + c * 17 + seed * 7) as f64;
+            Complex::new(x.sin(), x.cos())
+        })
+    }
+
+    #[test]
+    fn expansion_matches_kronecker() {
+        let core = random_matrix(4, 2, 0);
+        let block = KroneckerBlock::new(2, core.clone(), 4);
+        assert_eq!(block.to_dense(), padded(2, &core, 4));
+        assert_eq!(block.as_ref(), &padded(2, &core, 4));
+        assert_eq!(block.into_dense(), padded(2, &core, 4));
+    }
+
+    #[test]
+    fn strided_products() {
+        for (left, right) in [(1, 1), (1, 4), (2, 1), (4, 2)] {
+            let core = random_matrix(2, 2, left + right);
+            let block = KroneckerBlock::new(left, core.clone(), right);
+            let dim = block.nrows();
+            let dense = random_matrix(dim, dim, 3);
+            let expected = padded(left, &core, right);
+            assert!((KroneckerBlock::dense_mul(&dense, &block) - &dense * &expected).norm() < 1e-9);
+            assert!((block.mul_dense(&dense) - &expected * &dense).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn structured_products() {
+        let a = KroneckerBlock::new(1, random_matrix(4, 4, 1), 4);
+        let b = KroneckerBlock::new(4, random_matrix(4, 4, 2), 1);
+        let c = KroneckerBlock::new(2, random_matrix(2, 2, 3), 4);
+        for (x, y) in [(&a, &b), (&b, &a), (&a, &c), (&c, &b), (&c, &c)] {
+            let product = x.mul(y);
+            assert!((product.to_dense() - x.to_dense() * y.to_dense()).norm() < 1e-9);
+            let tensor = x.tensor_product(y);
+            assert!((tensor.to_dense() - x.to_dense().kronecker(&y.to_dense())).norm() < 1e-9);
+        }
+        // the padding shared by both operands is kept
+        assert_eq!(c.mul(&c).left(), 2);
+        assert_eq!(c.mul(&c).core().shape(), (2, 2));
+        assert_eq!(KroneckerBlock::identity(16).mul(&c), c);
+    }
+
+    #[test]
+    fn identity_expansion_stays_lazy() {
+        let mut circuit = QuantumCircuit::new(8);
+        circuit.g_h(3);
+        circuit.g_cx(0, 7);
+        let gate = circuit.gates[0].clone();
+        let block = Block::identity(8)
+            .tensor_product(gate.block())
+            .tensor_product(Block::identity(16));
+        let structure = block.as_kronecker().expect("Lazy expansion");
+        assert_eq!((structure.left(), structure.right()), (8, 16));
+        assert_eq!(structure.core(), &gate.matrix());
+
+        let dense = circuit.gates[1].block();
+        let expected = dense.as_ref() * padded(8, &gate.matrix(), 16);
+        assert!(((&dense * &block).into_matrix() - &expected).norm() < 1e-9);
+        assert!((circuit.eval().into_matrix() - expected).norm() < 1e-9);
+    }
+}
@@ -0,0 +1,337 @@
+//! Module containing the definition of the `MonomialBlock` struct.
+//!
+//! A `MonomialBlock` is a square matrix with exactly one non-zero entry in
+//! each row and column, that is a permutation matrix with phases. Diagonal
+//! gates (Z, S, T, RZ, CZ, ...) and permutation gates (X, CX, Swap, Toffoli,
+//! ...) are monomial, and so are their products and tensor products, which
+//! cost O(2^n) on the `2^n` entries stored instead of O(8^n) on dense matrices.
+//!
+//! A monomial block with unit entries maps basis states to basis states, so a
+//! circuit made of permutation gates can be simulated as a classical
+//! reversible circuit.
+
+use std::sync::OnceLock;
+
+use nalgebra::{Complex, DMatrix};
+
+use super::{
+    blocks::{Block, BlockLike},
+    gates::Structure,
+    kronecker::KroneckerBlock,
+    scalar::{cast, is_negligible, is_zero, Scalar},
+    sparse::SparseBlock,
+};
+
+/// A permutation matrix with phases, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct MonomialBlock<T: Scalar = f64> {
+    /// The row of the non-zero entry of each column.
+    rows: Vec<usize>,
+    /// The value of the non-zero entry of each column.
+    values: Vec<Complex<T>>,
+    /// The expanded matrix, only built when it is borrowed as a dense matrix.
+    dense: OnceLock<DMatrix<Complex<T>>>,
+}
+
+impl<T: Scalar> MonomialBlock<T> {
+    /// Create the matrix with entry `values[c]` in row `rows[c]` of each
+    /// column c. The rows must be a permutation of the columns.
+    pub fn new(rows: Vec<usize>, values: Vec<Complex<T>>) -> Self {
+        assert_eq!(rows.len(), values.len(), "One value per column");
+        let mut seen = vec![false; rows.len()];
+        for &row in &rows {
+            assert!(
+                row < rows.len() && !std::mem::replace(&mut seen[row], true),
+                "The rows are not a permutation"
+            );
+        }
+        Self {
+            rows,
+            values,
+            dense: OnceLock::new(),
+        }
+    }
+
+    /// Create a diagonal matrix.
+    pub fn diagonal(values: Vec<Complex<T>>) -> Self {
+        Self::new((0..values.len()).collect(), values)
+    }
+
+    /// Create the identity matrix of dimension dim.
+    pub fn identity(dim: usize) -> Self {
+        Self::diagonal(vec![T::complex(1.0, 0.0); dim])
+    }
+
+    /// Create the matrix from a dense one, if it is square with exactly one
+    /// non-zero entry in each row and column.
+    pub fn from_dense(matrix: &DMatrix<Complex<T>>) -> Option<Self> {
+        if !matrix.is_square() {
+            return None;
+        }
+        let mut rows = Vec::with_capacity(matrix.ncols());
+        let mut values = Vec::with_capacity(matrix.ncols());
+        let mut seen = vec![false; matrix.nrows()];
+        for column in matrix.column_iter() {
+            let mut entries = column.iter().enumerate().filter(|(_, v)| !is_zero(*v));
+            let (row, value) = entries.next()?;
+            if entries.next().is_some() || std::mem::replace(&mut seen[row], true) {
+                return None;
+            }
+            rows.push(row);
+            values.push(*value);
+        }
+        Some(Self::new(rows, values))
+    }
+
+    /// Create the matrix from a padded core, if the core is monomial.
+    pub fn from_kronecker(kron: &KroneckerBlock<T>) -> Option<Self> {
+        let core = Self::from_dense(kron.core())?;
+        Some(
+            Self::identity(kron.left())
+                .tensor_product(&core)
+                .tensor_product(&Self::identity(kron.right())),
+        )
+    }
+
+    /// Return the dimension of the matrix.
+    pub fn dim(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Return the row of the non-zero entry of each column.
+    pub fn rows(&self) -> &[usize] {
+        &self.rows
+    }
+
+    /// Return the value of the non-zero entry of each column.
+    pub fn values(&self) -> &[Complex<T>] {
+        &self.values
+    }
+
+    /// Return the structure of the matrix, diagonal or monomial.
+    pub fn structure(&self) -> Structure {
+        if self.rows.iter().enumerate().all(|(c, &r)| r == c) {
+            Structure::Diagonal
+        } else {
+            Structure::Monomial
+        }
+    }
+
+    /// Check if the matrix is a permutation matrix, without phases.
+    pub fn is_permutation(&self) -> bool {
+        self.values
+            .iter()
+            .all(|v| is_negligible(&(v - T::complex(1.0, 0.0))))
+    }
+
+    /// Apply the matrix to the basis state |index⟩, returning the basis state
+    /// it is mapped to and its phase.
+    pub fn apply(&self, index: usize) -> (usize, Complex<T>) {
+        (self.rows[index], self.values[index])
+    }
+
+    /// Build the expanded matrix.
+    pub fn to_dense(&self) -> DMatrix<Complex<T>> {
+        let mut dense = DMatrix::zeros(self.dim(), self.dim());
+        for (col, (&row, &value)) in self.rows.iter().zip(&self.values).enumerate() {
+            dense[(row, col)] = value;
+        }
+        dense
+    }
+
+    /// Convert the block into its expanded matrix.
+    pub fn into_dense(mut self) -> DMatrix<Complex<T>> {
+        self.dense.take().unwrap_or_else(|| self.to_dense())
+    }
+
+    /// Compute the product self · rhs of two monomial matrices, following
+    /// each column of rhs through self.
+    pub fn mul(&self, rhs: &Self) -> Self {
+        assert_eq!(self.dim(), rhs.dim(), "Incompatible dimensions");
+        let (rows, values) = rhs
+            .rows
+            .iter()
+            .zip(&rhs.values)
+            .map(|(&k, &b)| (self.rows[k], self.values[k] * b))
+            .unzip();
+        Self {
+            rows,
+            values,
+            dense: OnceLock::new(),
+        }
+    }
+
+    /// Compute the tensor product self ⊗ rhs of two monomial matrices.
+    pub fn tensor_product(&self, rhs: &Self) -> Self {
+        let dim = rhs.dim();
+        let (rows, values) = self
+            .rows
+            .iter()
+            .zip(&self.values)
+            .flat_map(|(&r1, &v1)| {
+                rhs.rows
+                    .iter()
+                    .zip(&rhs.values)
+                    .map(move |(&r2, &v2)| (r1 * dim + r2, v1 * v2))
+            })
+            .unzip();
+        Self {
+            rows,
+            values,
+            dense: OnceLock::new(),
+        }
+    }
+
+    /// Compute the product self · rhs with a dense matrix, moving and scaling
+    /// the rows of rhs.
+    pub fn mul_dense(&self, rhs: &DMatrix<Complex<T>>) -> DMatrix<Complex<T>> {
+        assert_eq!(self.dim(), rhs.nrows(), "Incompatible dimensions");
+        let mut out = DMatrix::zeros(self.dim(), rhs.ncols());
+        for (k, (&row, &value)) in self.rows.iter().zip(&self.values).enumerate() {
+            out.row_mut(row).copy_from(&(rhs.row(k) * value));
+        }
+        out
+    }
+
+    /// Compute the product lhs · rhs of a dense matrix with a monomial one,
+    /// moving and scaling the columns of lhs.
+    pub fn dense_mul(lhs: &DMatrix<Complex<T>>, rhs: &Self) -> DMatrix<Complex<T>> {
+        assert_eq!(lhs.ncols(), rhs.dim(), "Incompatible dimensions");
+        let mut out = DMatrix::zeros(lhs.nrows(), rhs.dim());
+        for (col, (&k, &value)) in rhs.rows.iter().zip(&rhs.values).enumerate() {
+            out.column_mut(col).copy_from(&(lhs.column(k) * value));
+        }
+        out
+    }
+
+    /// Convert the entries to another precision.
+    pub fn cast<U: Scalar>(&self) -> MonomialBlock<U> {
+        MonomialBlock {
+            rows: self.rows.clone(),
+            values: self.values.iter().copied().map(cast).collect(),
+            dense: OnceLock::new(),
+        }
+    }
+}
+
+impl<T: Scalar> From<&MonomialBlock<T>> for SparseBlock<T> {
+    fn from(monomial: &MonomialBlock<T>) -> Self {
+        let triplets = monomial
+            .rows
+            .iter()
+            .zip(&monomial.values)
+            .enumerate()
+            .map(|(col, (&row, &value))| (row, col, value))
+            .collect();
+        SparseBlock::from_triplets(monomial.dim(), monomial.dim(), triplets)
+    }
+}
+
+impl<T: Scalar> PartialEq for MonomialBlock<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.rows == other.rows && self.values == other.values
+    }
+}
+
+impl<T: Scalar> AsRef<DMatrix<Complex<T>>> for MonomialBlock<T> {
+    /// Borrow the expanded matrix, building it on the first call.
+    fn as_ref(&self) -> &DMatrix<Complex<T>> {
+        self.dense.get_or_init(|| self.to_dense())
+    }
+}
+
+impl<T: Scalar> BlockLike for MonomialBlock<T> {
+    type Scalar = T;
+
+    fn into_block(self) -> Block<T> {
+        Block::from(self)
+    }
+}
+
+impl<T: Scalar> std::fmt::Display for MonomialBlock<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "MonomialBlock {}x{}:", self.dim(), self.dim())?;
+        for (col, (row, value)) in self.rows.iter().zip(&self.values).enumerate() {
+            writeln!(f, "  {} -> {} {}", col, row, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{gates::QuantumGate, QuantumCircuit, TensorProduct};
+
+    fn circuit() -> QuantumCircuit {
+        let mut circuit = QuantumCircuit::new(3);
+        circuit.g_cxx(0, 1, 2);
+        circuit.g_t(1);
+        circuit.g_y(2);
+        circuit.g_crz(0.3, 2, 0);
+        circuit.g_cswap(2, 0, 1);
+        circuit
+    }
+
+    #[test]
+    fn gates_report_structure() {
+        let circuit = circuit();
+        let structures = circuit
+            .gates
+            .iter()
+            .map(|g| g.structure())
+            .collect::<Vec<_>>();
+        use Structure::*;
+        assert_eq!(
+            structures,
+            vec![Monomial, Diagonal, Monomial, Diagonal, Monomial]
+        );
+        for gate in &circuit.gates {
+            let block = gate.block();
+            assert_eq!(block.structure(), gate.structure());
+            assert_eq!(block.as_ref(), &gate.matrix());
+        }
+        let mut general = QuantumCircuit::new(1);
+        general.g_h(0);
+        assert_eq!(general.gates[0].structure(), General);
+        assert_eq!(general.gates[0].block().structure(), General);
+    }
+
+    #[test]
+    fn products_stay_monomial() {
+        let circuit = circuit();
+        let mut expected = DMatrix::identity(8, 8);
+        let mut product = Block::identity(8);
+        for gate in &circuit.gates {
+            let span = gate.span();
+            let padded = Block::identity(1 << span.start())
+                .tensor_product(gate.block())
+                .tensor_product(Block::identity(1 << (2 - span.end())));
+            expected *= padded.as_ref();
+            product = &product * &padded;
+            assert_ne!(product.structure(), Structure::General);
+        }
+        assert!((product.as_ref() - &expected).norm() < 1e-9);
+        assert!((circuit.eval().into_matrix() - expected).norm() < 1e-9);
+
+        let dense = DMatrix::from_fn(8, 8, |r, c| Complex::new(r as f64, c as f64));
+        let monomial = product.as_monomial().unwrap();
+        assert_eq!(monomial.mul_dense(&dense), monomial.as_ref() * &dense);
+        assert_eq!(
+            MonomialBlock::dense_mul(&dense, monomial),
+            &dense * monomial.as_ref()
+        );
+    }
+
+    #[test]
+    fn from_dense_rejects_general_matrices() {
+        let one = Complex::new(1.0, 0.0);
+        let zero = Complex::new(0.0, 0.0);
+        let x = DMatrix::from_row_slice(2, 2, &[zero, one, one, zero]);
+        assert!(MonomialBlock::from_dense(&x).unwrap().is_permutation());
+        let two = DMatrix::from_row_slice(2, 2, &[one, one, zero, one]);
+        assert!(MonomialBlock::from_dense(&two).is_none());
+        let singular = DMatrix::from_row_slice(2, 2, &[one, one, zero, zero]);
+        assert!(MonomialBlock::from_dense(&singular).is_none());
+    }
+}
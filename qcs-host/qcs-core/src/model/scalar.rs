@@ -0,0 +1,202 @@
+//! Module containing the definition of the `Scalar` trait.
+//!
+//! The blocks and the registers are generic over the real type of their
+//! complex entries, so that the host can reproduce the single precision of the
+//! FPGA datapath as well as compute in double precision. Gates are always
+//! built in double precision, and rounded when they are turned into blocks of
+//! a lower precision.
+
+use nalgebra::{Complex, ComplexField, DMatrix, RealField};
+
+/// The real type of the entries of blocks and registers, `f32` or `f64`.
+pub trait Scalar: RealField + Copy + Default + std::fmt::Display {
+    /// The precision this type stands for.
+    const PRECISION: Precision;
+
+    /// Entries whose norm is at most this value are negligible, see
+    /// `is_negligible`.
+    const ZERO_THRESHOLD: f64;
+
+    /// Round a double precision value to this type.
+    fn from_double(value: f64) -> Self;
+
+    /// Widen the value to double precision.
+    fn to_double(self) -> f64;
+
+    /// Create the complex number re + i·im, rounded to this type.
+    fn complex(re: f64, im: f64) -> Complex<Self> {
+        Complex::new(Self::from_double(re), Self::from_double(im))
+    }
+}
+
+impl Scalar for f32 {
+    const PRECISION: Precision = Precision::Single;
+    const ZERO_THRESHOLD: f64 = 1e-6;
+
+    fn from_double(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_double(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Scalar for f64 {
+    const PRECISION: Precision = Precision::Double;
+    const ZERO_THRESHOLD: f64 = 1e-10;
+
+    fn from_double(value: f64) -> Self {
+        value
+    }
+
+    fn to_double(self) -> f64 {
+        self
+    }
+}
+
+/// Check if a complex number is exactly zero. The blocks only drop the exact
+/// zeros, so that changing their storage never changes their entries.
+pub(crate) fn is_zero<T: Scalar>(value: &Complex<T>) -> bool {
+    value.re == T::zero() && value.im == T::zero()
+}
+
+/// Check if the norm of a complex number is at most `T::ZERO_THRESHOLD`.
+pub(crate) fn is_negligible<T: Scalar>(value: &Complex<T>) -> bool {
+    value.modulus().to_double() <= T::ZERO_THRESHOLD
+}
+
+/// Convert a complex number to another precision.
+pub fn cast<T: Scalar, U: Scalar>(value: Complex<T>) -> Complex<U> {
+    Complex::new(
+        U::from_double(value.re.to_double()),
+        U::from_double(value.im.to_double()),
+    )
+}
+
+/// Convert a matrix to another precision.
+pub fn cast_matrix<T: Scalar, U: Scalar>(matrix: &DMatrix<Complex<T>>) -> DMatrix<Complex<U>> {
+    matrix.map(cast)
+}
+
+/// Return the Frobenius norm of the difference of two matrices of any
+/// precision, computed in double precision.
+pub fn deviation<T: Scalar, U: Scalar>(a: &DMatrix<Complex<T>>, b: &DMatrix<Complex<U>>) -> f64 {
+    assert_eq!(a.shape(), b.shape(), "Incompatible dimensions");
+    (cast_matrix::<T, f64>(a) - cast_matrix::<U, f64>(b)).norm()
+}
+
+/// The precisions the pipeline can run in, that can be selected by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Precision {
+    /// `Complex<f32>` entries, as in the FPGA datapath.
+    Single,
+    /// `Complex<f64>` entries.
+    Double,
+}
+
+impl Precision {
+    /// The names of all the available precisions.
+    pub const NAMES: [&'static str; 2] = ["single", "double"];
+}
+
+impl std::str::FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "single" | "f32" => Ok(Self::Single),
+            "double" | "f64" => Ok(Self::Double),
+            _ => Err(format!(
+                "Unknown precision {:?}, expected one of: {}",
+                s,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Precision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Single => write!(f, "single"),
+            Self::Double => write!(f, "double"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::{forest::ContractionForest, TensorKind, TensorNetwork},
+        executor::CpuExecutor,
+        library,
+        model::{
+            blocks::{Block, SpannedBlock},
+            gates::QuantumGate,
+            QRegister, QuantumCircuit, TensorProduct,
+        },
+        scheduler::ContractionPlan,
+    };
+
+    /// Contract a circuit with blocks of precision T.
+    fn contract<T: Scalar>(circuit: &QuantumCircuit) -> SpannedBlock<T> {
+        let mut blocks = Vec::new();
+        for tensor in TensorNetwork::from(circuit.clone()).contract() {
+            match tensor {
+                TensorKind::Contraction(c) => blocks.extend(
+                    CpuExecutor::new()
+                        .execute(ContractionPlan::<SpannedBlock<T>>::new(*c).unwrap()),
+                ),
+                TensorKind::Gate(g) => blocks.push(SpannedBlock::from(*g)),
+                TensorKind::State(_) => unreachable!(),
+            }
+        }
+        ContractionForest::combine(blocks).unwrap()
+    }
+
+    #[test]
+    fn single_precision_pipeline() {
+        for circuit in [library::qft(5), library::ripple_carry_adder(2)] {
+            let single = contract::<f32>(&circuit);
+            let double = contract::<f64>(&circuit);
+            let error = deviation(single.as_ref(), double.as_ref());
+            assert!(error < 1e-4, "deviation {}", error);
+            assert!(deviation(&cast_matrix::<f64, f32>(double.as_ref()), single.as_ref()) < 1e-4);
+
+            let input = QRegister::haar_random(circuit.n_qubits, 3);
+            let state = single.into_block() * input.cast::<f32>();
+            let expected = double.into_block() * input;
+            assert!((state.cast::<f64>().qubits - expected.qubits).norm() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn cast_keeps_storage() {
+        let mut circuit = QuantumCircuit::new(3);
+        circuit.g_h(0);
+        circuit.g_cx(1, 2);
+        circuit.g_cxx(0, 1, 2);
+        let padded = circuit.gates[0]
+            .block()
+            .tensor_product(Block::identity(4))
+            .cast::<f32>();
+        assert_eq!(padded.as_kronecker().map(|k| k.right()), Some(4));
+        let cx: Block<f32> = Block::from(circuit.gates[1].clone());
+        assert!(cx.as_monomial().is_some());
+        let sparse = Block::from(circuit.gates[2].matrix()).cast::<f32>();
+        assert_eq!(sparse.as_sparse().map(|s| s.nnz()), Some(8));
+        assert_eq!(cx.clone().into_precision::<f32>(), cx);
+    }
+
+    #[test]
+    fn precision_names() {
+        for name in Precision::NAMES {
+            let precision = name.parse::<Precision>().unwrap();
+            assert_eq!(precision.to_string(), name);
+        }
+        assert_eq!(f32::PRECISION, Precision::Single);
+        assert!("half".parse::<Precision>().is_err());
+    }
+}
@@ -0,0 +1,449 @@
+//! Module containing the definition of the `SparseBlock` struct.
+//!
+//! A `SparseBlock` stores only the non-zero entries of a matrix in CSR
+//! format: the entries are sorted by row, and the entries of row `i` are the
+//! ones between `row_offsets[i]` and `row_offsets[i + 1]`. Gate matrices and
+//! the first intermediates of a contraction have a handful of non-zeros per
+//! row, so their products and tensor products cost O(nnz) instead of O(n³).
+//!
+//! A `Block` picks between dense and sparse storage by the fraction of its
+//! entries that are non-zero, compared with `DEFAULT_SPARSE_DENSITY`, or with
+//! the density of the executor holding it (see `CpuExecutor::sparse_density`).
+//! Only the exact zeros are dropped, so the storage never changes the entries
+//! of a block.
+
+use std::sync::OnceLock;
+
+use nalgebra::{Complex, DMatrix};
+
+use super::{
+    blocks::{Block, BlockLike},
+    kronecker::KroneckerBlock,
+    scalar::{cast, is_zero, Scalar},
+};
+
+/// The fraction of non-zero entries up to which a block is stored as a
+/// `SparseBlock`, unless the executor sets another one.
+pub const DEFAULT_SPARSE_DENSITY: f64 = 0.25;
+
+/// Count the entries of a matrix that are not zero.
+pub(crate) fn count_non_zero<T: Scalar>(matrix: &DMatrix<Complex<T>>) -> usize {
+    matrix.iter().filter(|v| !is_zero(*v)).count()
+}
+
+/// Check if a matrix with nnz non-zeros out of len entries should be sparse
+/// with the given density. A density of 0 keeps every matrix with non-zeros
+/// dense.
+pub(crate) fn is_sparse(nnz: usize, len: usize, density: f64) -> bool {
+    len > 0 && nnz as f64 <= density * len as f64
+}
+
+/// A matrix in CSR format, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct SparseBlock<T: Scalar = f64> {
+    nrows: usize,
+    ncols: usize,
+    /// The position in `columns` and `values` of the first entry of each row,
+    /// followed by the number of entries.
+    row_offsets: Vec<usize>,
+    /// The column of each entry.
+    columns: Vec<usize>,
+    /// The value of each entry.
+    values: Vec<Complex<T>>,
+    /// The expanded matrix, only built when it is borrowed as a dense matrix.
+    dense: OnceLock<DMatrix<Complex<T>>>,
+}
+
+impl<T: Scalar> SparseBlock<T> {
+    /// Create a matrix from its entries in COO format, in any order. The
+    /// values of repeated entries are summed.
+    pub fn from_triplets(
+        nrows: usize,
+        ncols: usize,
+        mut triplets: Vec<(usize, usize, Complex<T>)>,
+    ) -> Self {
+        triplets.sort_by_key(|&(row, col, _)| (row, col));
+        let mut builder = CsrBuilder::new(nrows, ncols);
+        for (row, col, value) in triplets {
+            assert!(row < nrows && col < ncols, "Entry out of the matrix");
+            builder.push(row, col, value);
+        }
+        builder.build()
+    }
+
+    /// Create the identity matrix of dimension dim.
+    pub fn identity(dim: usize) -> Self {
+        let one = T::complex(1.0, 0.0);
+        Self::from_triplets(dim, dim, (0..dim).map(|i| (i, i, one)).collect())
+    }
+
+    /// Create a matrix from the non-zero entries of a dense one.
+    pub fn from_dense(matrix: &DMatrix<Complex<T>>) -> Self {
+        let mut builder = CsrBuilder::new(matrix.nrows(), matrix.ncols());
+        for (row, entries) in matrix.row_iter().enumerate() {
+            for (col, value) in entries.iter().enumerate() {
+                if !is_zero(value) {
+                    builder.push(row, col, *value);
+                }
+            }
+        }
+        builder.build()
+    }
+
+    /// Return the number of rows.
+    pub fn nrows(&self) -> usize {
+        self.nrows
+    }
+
+    /// Return the number of columns.
+    pub fn ncols(&self) -> usize {
+        self.ncols
+    }
+
+    /// Return the number of stored entries.
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Return the fraction of the entries of the matrix that are stored.
+    pub fn density(&self) -> f64 {
+        self.nnz() as f64 / (self.nrows * self.ncols) as f64
+    }
+
+    /// Return the stored entries of a row, as pairs of column and value.
+    pub fn row(&self, row: usize) -> impl Iterator<Item = (usize, Complex<T>)> + '_ {
+        let range = self.row_offsets[row]..self.row_offsets[row + 1];
+        self.columns[range.clone()]
+            .iter()
+            .copied()
+            .zip(self.values[range].iter().copied())
+    }
+
+    /// Return the stored entries in COO format, sorted by row and column.
+    pub fn triplets(&self) -> impl Iterator<Item = (usize, usize, Complex<T>)> + '_ {
+        (0..self.nrows).flat_map(move |row| self.row(row).map(move |(col, v)| (row, col, v)))
+    }
+
+    /// Build the expanded matrix.
+    pub fn to_dense(&self) -> DMatrix<Complex<T>> {
+        let mut dense = DMatrix::zeros(self.nrows, self.ncols);
+        for (row, col, value) in self.triplets() {
+            dense[(row, col)] = value;
+        }
+        dense
+    }
+
+    /// Convert the block into its expanded matrix.
+    pub fn into_dense(mut self) -> DMatrix<Complex<T>> {
+        self.dense.take().unwrap_or_else(|| self.to_dense())
+    }
+
+    /// Compute the product self · rhs of two sparse matrices, accumulating
+    /// each row of the result in a dense buffer.
+    pub fn mul(&self, rhs: &Self) -> Self {
+        assert_eq!(self.ncols, rhs.nrows, "Incompatible dimensions");
+        let zero = T::complex(0.0, 0.0);
+        let mut accumulator = vec![zero; rhs.ncols];
+        let mut touched = vec![false; rhs.ncols];
+        let mut pattern = Vec::new();
+        let mut builder = CsrBuilder::new(self.nrows, rhs.ncols);
+        for row in 0..self.nrows {
+            for (k, a) in self.row(row) {
+                for (col, b) in rhs.row(k) {
+                    if !touched[col] {
+                        touched[col] = true;
+                        pattern.push(col);
+                    }
+                    accumulator[col] += a * b;
+                }
+            }
+            pattern.sort_unstable();
+            for col in pattern.drain(..) {
+                builder.push(row, col, accumulator[col]);
+                accumulator[col] = zero;
+                touched[col] = false;
+            }
+        }
+        builder.build()
+    }
+
+    /// Compute the product self · rhs of the sparse matrix with a dense one.
+    pub fn mul_dense(&self, rhs: &DMatrix<Complex<T>>) -> DMatrix<Complex<T>> {
+        assert_eq!(self.ncols, rhs.nrows(), "Incompatible dimensions");
+        let mut out = DMatrix::zeros(self.nrows, rhs.ncols());
+        for col in 0..rhs.ncols() {
+            for row in 0..self.nrows {
+                out[(row, col)] = self.row(row).map(|(k, a)| a * rhs[(k, col)]).sum();
+            }
+        }
+        out
+    }
+
+    /// Compute the product lhs · rhs of a dense matrix with a sparse one,
+    /// adding up the columns of lhs picked by the entries of rhs.
+    pub fn dense_mul(lhs: &DMatrix<Complex<T>>, rhs: &Self) -> DMatrix<Complex<T>> {
+        assert_eq!(lhs.ncols(), rhs.nrows, "Incompatible dimensions");
+        let one = T::complex(1.0, 0.0);
+        let mut out = DMatrix::zeros(lhs.nrows(), rhs.ncols);
+        for (k, col, b) in rhs.triplets() {
+            out.column_mut(col).axpy(b, &lhs.column(k), one);
+        }
+        out
+    }
+
+    /// Compute the tensor product self ⊗ rhs of two sparse matrices.
+    pub fn tensor_product(&self, rhs: &Self) -> Self {
+        let mut builder = CsrBuilder::new(self.nrows * rhs.nrows, self.ncols * rhs.ncols);
+        for outer in 0..self.nrows {
+            for inner in 0..rhs.nrows {
+                for (outer_col, a) in self.row(outer) {
+                    for (inner_col, b) in rhs.row(inner) {
+                        builder.push(
+                            outer * rhs.nrows + inner,
+                            outer_col * rhs.ncols + inner_col,
+                            a * b,
+                        );
+                    }
+                }
+            }
+        }
+        builder.build()
+    }
+
+    /// Convert the entries to another precision.
+    pub fn cast<U: Scalar>(&self) -> SparseBlock<U> {
+        SparseBlock {
+            nrows: self.nrows,
+            ncols: self.ncols,
+            row_offsets: self.row_offsets.clone(),
+            columns: self.columns.clone(),
+            values: self.values.iter().copied().map(cast).collect(),
+            dense: OnceLock::new(),
+        }
+    }
+}
+
+impl<T: Scalar> From<&KroneckerBlock<T>> for SparseBlock<T> {
+    /// Expand the padding of the block without going through a dense matrix.
+    fn from(kron: &KroneckerBlock<T>) -> Self {
+        let core = SparseBlock::from_dense(kron.core());
+        SparseBlock::identity(kron.left())
+            .tensor_product(&core)
+            .tensor_product(&SparseBlock::identity(kron.right()))
+    }
+}
+
+impl<T: Scalar> PartialEq for SparseBlock<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.nrows == other.nrows
+            && self.ncols == other.ncols
+            && self.row_offsets == other.row_offsets
+            && self.columns == other.columns
+            && self.values == other.values
+    }
+}
+
+impl<T: Scalar> AsRef<DMatrix<Complex<T>>> for SparseBlock<T> {
+    /// Borrow the expanded matrix, building it on the first call.
+    fn as_ref(&self) -> &DMatrix<Complex<T>> {
+        self.dense.get_or_init(|| self.to_dense())
+    }
+}
+
+impl<T: Scalar> BlockLike for SparseBlock<T> {
+    type Scalar = T;
+
+    fn into_block(self) -> Block<T> {
+        Block::from(self)
+    }
+}
+
+impl<T: Scalar> std::fmt::Display for SparseBlock<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "SparseBlock {}x{} ({} non-zeros):",
+            self.nrows,
+            self.ncols,
+            self.nnz()
+        )?;
+        for (row, col, value) in self.triplets() {
+            writeln!(f, "  ({}, {}) {}", row, col, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Builder of a CSR matrix from entries pushed in order of row and column,
+/// dropping the ones that are zero.
+struct CsrBuilder<T: Scalar> {
+    nrows: usize,
+    ncols: usize,
+    row_offsets: Vec<usize>,
+    columns: Vec<usize>,
+    values: Vec<Complex<T>>,
+}
+
+impl<T: Scalar> CsrBuilder<T> {
+    fn new(nrows: usize, ncols: usize) -> Self {
+        Self {
+            nrows,
+            ncols,
+            row_offsets: vec![0],
+            columns: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Push an entry, summing it with the last one if they are in the same
+    /// position.
+    fn push(&mut self, row: usize, col: usize, value: Complex<T>) {
+        while self.row_offsets.len() <= row {
+            self.row_offsets.push(self.values.len());
+        }
+        let row_start = self.row_offsets[row];
+        if self.values.len() > row_start && self.columns.last() == Some(&col) {
+            *self.values.last_mut().unwrap() += value;
+        } else {
+            self.columns.push(col);
+            self.values.push(value);
+        }
+    }
+
+    fn build(mut self) -> SparseBlock<T> {
+        // drop the entries that are zero, compacting each row in place
+        let mut offsets = Vec::with_capacity(self.nrows + 1);
+        let mut kept = 0;
+        while self.row_offsets.len() <= self.nrows {
+            self.row_offsets.push(self.values.len());
+        }
+        for row in 0..self.nrows {
+            offsets.push(kept);
+            for i in self.row_offsets[row]..self.row_offsets[row + 1] {
+                if !is_zero(&self.values[i]) {
+                    self.columns[kept] = self.columns[i];
+                    self.values[kept] = self.values[i];
+                    kept += 1;
+                }
+            }
+        }
+        offsets.push(kept);
+        self.columns.truncate(kept);
+        self.values.truncate(kept);
+        SparseBlock {
+            nrows: self.nrows,
+            ncols: self.ncols,
+            row_offsets: offsets,
+            columns: self.columns,
+            values: self.values,
+            dense: OnceLock::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::fixtures::circuit_contraction,
+        executor::CpuExecutor,
+        model::{gates::QuantumGate, QuantumCircuit, TensorProduct},
+        op_tree::Operation,
+        scheduler::OperationPlan,
+    };
+
+    fn random_sparse(rows: usize, cols: usize, seed: usize) -> DMatrix<Complex<f64>> {
+        DMatrix::from_fn(rows, cols, |r, c| {
+            let x = (r * 31 + c * 17 + seed * 7) as f64;
+            if (r * 5 + c * 3 + seed).is_multiple_of(4) {
+                Complex::new(x.sin(), x.cos())
+            } else {
+                Complex::new(0.0, 0.0)
+            }
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        let dense = random_sparse(8, 4, 1);
+        let sparse = SparseBlock::from_dense(&dense);
+        assert_eq!(
+            sparse.nnz(),
+            dense.iter().filter(|v| v.norm() > 0.0).count()
+        );
+        assert_eq!(sparse.to_dense(), dense);
+        assert_eq!(sparse.as_ref(), &dense);
+        let triplets = sparse.triplets().collect::<Vec<_>>();
+        assert_eq!(SparseBlock::from_triplets(8, 4, triplets), sparse);
+    }
+
+    #[test]
+    fn products_match_dense() {
+        let a = random_sparse(8, 8, 1);
+        let b = random_sparse(8, 8, 2);
+        let (sa, sb) = (SparseBlock::from_dense(&a), SparseBlock::from_dense(&b));
+        assert!((sa.mul(&sb).to_dense() - &a * &b).norm() < 1e-9);
+        assert!((sa.mul_dense(&b) - &a * &b).norm() < 1e-9);
+        assert!((SparseBlock::dense_mul(&a, &sb) - &a * &b).norm() < 1e-9);
+        assert!((sa.tensor_product(&sb).to_dense() - a.kronecker(&b)).norm() < 1e-9);
+
+        let kron = KroneckerBlock::new(2, random_sparse(2, 2, 3), 4);
+        assert_eq!(SparseBlock::from(&kron).to_dense(), kron.to_dense());
+    }
+
+    #[test]
+    fn storage_follows_density() {
+        let mut circuit = QuantumCircuit::new(3);
+        circuit.g_h(0);
+        circuit.g_cxx(0, 1, 2);
+        // the toffoli gate is monomial, store its matrix as a generic one
+        let h = circuit.gates[0].block();
+        let toffoli = Block::from(circuit.gates[1].matrix());
+        assert!(h.as_sparse().is_none());
+        assert_eq!(toffoli.as_sparse().map(|s| s.nnz()), Some(8));
+
+        let padded = h.tensor_product(Block::identity(4));
+        let product = &toffoli * &padded;
+        let expected = toffoli.as_ref() * padded.as_ref();
+        assert!((product.as_ref() - &expected).norm() < 1e-9);
+        assert_eq!(
+            product.as_sparse().is_some(),
+            is_sparse(16, expected.len(), DEFAULT_SPARSE_DENSITY)
+        );
+    }
+
+    #[test]
+    fn storage_keeps_small_entries() {
+        let mut dense = random_sparse(8, 8, 1);
+        dense[(0, 1)] = Complex::new(1e-14, -1e-300);
+        let block = Block::from(dense.clone());
+        let sparse = block.with_density(1.0).unwrap_or(block);
+        assert_eq!(
+            sparse.as_sparse().map(|s| s.nnz()),
+            Some(count_non_zero(&dense))
+        );
+        assert_eq!(sparse.as_ref(), &dense);
+        let expanded = sparse.with_density(0.0).unwrap();
+        assert!(expanded.as_sparse().is_none());
+        assert_eq!(expanded.into_matrix(), dense);
+    }
+
+    #[test]
+    fn executor_density() {
+        let mut circuit = QuantumCircuit::new(4);
+        circuit.g_h(0);
+        for lane in 0..3 {
+            circuit.g_cx(lane, lane + 1);
+        }
+        let operation = Operation::from_contraction(circuit_contraction(circuit), false).unwrap();
+        let execute = |executor: CpuExecutor<Block>| {
+            let plan = OperationPlan::from(operation.clone());
+            executor.execute(plan).pop().unwrap()
+        };
+        let sparse = execute(CpuExecutor::new());
+        let dense = execute(CpuExecutor::new().sparse_density(0.0));
+        assert!(sparse.as_sparse().is_some());
+        assert!(dense.as_sparse().is_none());
+        assert_eq!(sparse.as_ref(), dense.as_ref());
+    }
+}
@@ -0,0 +1,404 @@
+//! Module containing the definition of the `IndexTensor` struct.
+//!
+//! An `IndexTensor` is a tensor with named qubit indices: an output leg and an
+//! input leg for each lane it acts on. Its entries are stored in a matrix whose
+//! rows run over the output legs and whose columns run over the input legs,
+//! the first lane being the most significant bit, so a tensor that only has
+//! output legs is a state and one that only has input legs is a projector.
+//!
+//! Two tensors are contracted einsum-style by `IndexTensor::einsum`, summing over the input legs of the
+//! left tensor that match an output leg of the right one, while all the other
+//! legs are kept. A gate on lanes {0, 5} is then a rank 2 tensor, and the lanes
+//! between them are never padded with identities as `SpannedBlock` does.
+//!
+//! The same tensors evaluate the closed networks of `contractions::closed`,
+//! where the states close some legs and the result is a scalar or a vector.
+
+use nalgebra::{Complex, DMatrix};
+
+use crate::contractions::closed::{ClosedTensorError, StateTensor};
+
+use super::{
+    blocks::{Block, BlockLike, ContractionBlock, SpannedBlock},
+    gates::{Gate, QuantumGate},
+    span::Span,
+    QRegister,
+};
+
+/// A leg of a tensor, the output or the input index of a lane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Leg {
+    Output(usize),
+    Input(usize),
+}
+
+impl std::fmt::Display for Leg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Output(lane) => write!(f, "o{}", lane),
+            Self::Input(lane) => write!(f, "i{}", lane),
+        }
+    }
+}
+
+/// A tensor with an output and an input leg per lane, see the module
+/// documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexTensor {
+    /// The lanes of the output legs, in ascending order.
+    outputs: Vec<usize>,
+    /// The lanes of the input legs, in ascending order.
+    inputs: Vec<usize>,
+    /// The entries, with a row per value of the output legs and a column per
+    /// value of the input legs.
+    matrix: DMatrix<Complex<f64>>,
+}
+
+impl IndexTensor {
+    /// Create a tensor from its legs and its entries. The lanes are sorted
+    /// and the matrix must have a row per value of the outputs and a column
+    /// per value of the inputs.
+    pub fn new(outputs: &[usize], inputs: &[usize], matrix: DMatrix<Complex<f64>>) -> Self {
+        let (outputs, inputs) = (sorted(outputs), sorted(inputs));
+        assert_eq!(matrix.nrows(), 1 << outputs.len(), "One row per output");
+        assert_eq!(matrix.ncols(), 1 << inputs.len(), "One column per input");
+        Self {
+            outputs,
+            inputs,
+            matrix,
+        }
+    }
+
+    /// Create the tensor of the scalar 1, with no leg.
+    pub fn one() -> Self {
+        Self::new(
+            &[],
+            &[],
+            DMatrix::from_element(1, 1, Complex::new(1.0, 0.0)),
+        )
+    }
+
+    /// Create an operator on a span from its matrix, with an output and an
+    /// input leg for each lane of the span.
+    pub fn operator(span: &Span, matrix: DMatrix<Complex<f64>>) -> Self {
+        let lanes = span.clone().into_iter().collect::<Vec<_>>();
+        Self::new(&lanes, &lanes, matrix)
+    }
+
+    /// Return the lanes of the output legs.
+    pub fn outputs(&self) -> &[usize] {
+        &self.outputs
+    }
+
+    /// Return the lanes of the input legs.
+    pub fn inputs(&self) -> &[usize] {
+        &self.inputs
+    }
+
+    /// Return all the legs, outputs first.
+    pub fn legs(&self) -> Vec<Leg> {
+        self.outputs
+            .iter()
+            .map(|&l| Leg::Output(l))
+            .chain(self.inputs.iter().map(|&l| Leg::Input(l)))
+            .collect()
+    }
+
+    /// Return the lanes covered by the legs of the tensor.
+    pub fn span(&self) -> Span {
+        Span::new(union(&self.outputs, &self.inputs))
+    }
+
+    /// Return the number of legs.
+    pub fn rank(&self) -> usize {
+        self.outputs.len() + self.inputs.len()
+    }
+
+    /// Return the value of the tensor if it has no leg.
+    pub fn scalar(&self) -> Option<Complex<f64>> {
+        (self.rank() == 0).then(|| self.matrix[(0, 0)])
+    }
+
+    /// Return the vector over the output legs of the tensor if it has no
+    /// input leg.
+    pub fn vector(&self) -> Option<QRegister> {
+        self.inputs.is_empty().then(|| QRegister {
+            qubits: self.matrix.column(0).into_owned(),
+        })
+    }
+
+    /// Contract the tensor with the right factor of their product, summing
+    /// over the input legs of `self` that match an output leg of `rhs`.
+    ///
+    /// The free output legs of `rhs` become output legs of the result and the
+    /// free input legs of `self` become input legs of the result, so they must
+    /// not collide with the legs of the other tensor.
+    pub fn einsum(&self, rhs: &Self) -> Self {
+        let contracted = intersection(&self.inputs, &rhs.outputs);
+        let free_inputs = difference(&self.inputs, &contracted);
+        let free_outputs = difference(&rhs.outputs, &contracted);
+        assert!(
+            intersection(&self.outputs, &free_outputs).is_empty()
+                && intersection(&rhs.inputs, &free_inputs).is_empty(),
+            "Open legs on the same lane"
+        );
+
+        // L[(o, f), c] * R[c, (g, i)] sums over the contracted legs c
+        let (n_free_inputs, n_inputs) = (free_inputs.len(), rhs.inputs.len());
+        let left = DMatrix::from_fn(
+            1 << (self.outputs.len() + n_free_inputs),
+            1 << contracted.len(),
+            |row, c| {
+                let (o, f) = split(row, n_free_inputs);
+                let col = compose(&self.inputs, &[(&contracted, c), (&free_inputs, f)]);
+                self.matrix[(o, col)]
+            },
+        );
+        let right = DMatrix::from_fn(
+            1 << contracted.len(),
+            1 << (free_outputs.len() + n_inputs),
+            |c, col| {
+                let (g, i) = split(col, n_inputs);
+                let row = compose(&rhs.outputs, &[(&contracted, c), (&free_outputs, g)]);
+                rhs.matrix[(row, i)]
+            },
+        );
+        let product = left * right;
+
+        let outputs = union(&self.outputs, &free_outputs);
+        let inputs = union(&rhs.inputs, &free_inputs);
+        let mut matrix = DMatrix::zeros(1 << outputs.len(), 1 << inputs.len());
+        for row in 0..product.nrows() {
+            let (o, f) = split(row, n_free_inputs);
+            for col in 0..product.ncols() {
+                let (g, i) = split(col, n_inputs);
+                let r = compose(&outputs, &[(&self.outputs, o), (&free_outputs, g)]);
+                let c = compose(&inputs, &[(&rhs.inputs, i), (&free_inputs, f)]);
+                matrix[(r, c)] = product[(row, col)];
+            }
+        }
+        Self {
+            outputs,
+            inputs,
+            matrix,
+        }
+    }
+}
+
+impl From<Gate> for IndexTensor {
+    /// Keep only the legs of the lanes the gate acts on, dropping the identity
+    /// on the idle lanes of its filled span.
+    fn from(gate: Gate) -> Self {
+        let lanes = gate.span().into_iter().collect::<Vec<_>>();
+        let filled = gate.span().filled().into_iter().collect::<Vec<_>>();
+        let full = gate.shared_matrix();
+        let size = 1 << lanes.len();
+        let matrix = DMatrix::from_fn(size, size, |r, c| {
+            full[(
+                compose(&filled, &[(&lanes, r)]),
+                compose(&filled, &[(&lanes, c)]),
+            )]
+        });
+        Self::new(&lanes, &lanes, matrix)
+    }
+}
+
+impl std::ops::Add for IndexTensor {
+    type Output = IndexTensor;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        assert_eq!(self.legs(), rhs.legs(), "Different legs");
+        Self {
+            matrix: self.matrix + rhs.matrix,
+            ..self
+        }
+    }
+}
+
+impl AsRef<DMatrix<Complex<f64>>> for IndexTensor {
+    fn as_ref(&self) -> &DMatrix<Complex<f64>> {
+        &self.matrix
+    }
+}
+
+impl BlockLike for IndexTensor {
+    /// Return the operator on the filled span of the tensor, with the identity
+    /// on the lanes without legs. Only operators, with an output and an input
+    /// leg on the same lanes, have a block.
+    type Scalar = f64;
+
+    fn into_block(self) -> Block {
+        assert_eq!(self.outputs, self.inputs, "Only operators have a block");
+        if self.outputs.is_empty() {
+            return Block::from(self.matrix);
+        }
+        let filled = self.span().filled().into_iter().collect::<Vec<_>>();
+        let idle = difference(&filled, &self.outputs);
+        let size = 1 << filled.len();
+        let mut matrix = DMatrix::zeros(size, size);
+        for r in 0..self.matrix.nrows() {
+            for c in 0..self.matrix.ncols() {
+                for bits in 0..1 << idle.len() {
+                    let row = compose(&filled, &[(&self.outputs, r), (&idle, bits)]);
+                    let col = compose(&filled, &[(&self.inputs, c), (&idle, bits)]);
+                    matrix[(row, col)] = self.matrix[(r, c)];
+                }
+            }
+        }
+        Block::from(matrix)
+    }
+}
+
+impl From<IndexTensor> for SpannedBlock {
+    fn from(tensor: IndexTensor) -> Self {
+        let span = tensor.span();
+        SpannedBlock::new(tensor.into_block(), span)
+    }
+}
+
+impl ContractionBlock for IndexTensor {
+    fn contract(&self, rhs: &Self) -> Self {
+        self.einsum(rhs)
+    }
+
+    fn from_state(state: StateTensor) -> Result<Self, ClosedTensorError> {
+        Ok(Self::from(&state))
+    }
+}
+
+impl std::fmt::Display for IndexTensor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let legs = self
+            .legs()
+            .iter()
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "IndexTensor[{}]:\n{}", legs.join(","), self.matrix)
+    }
+}
+
+/// Split an index into its high bits and its `low` low bits.
+fn split(index: usize, low: usize) -> (usize, usize) {
+    (index >> low, index & ((1 << low) - 1))
+}
+
+/// Compose an index over `lanes`, the first lane being the most significant
+/// bit, from the values of some of its lanes. The lanes without a value are 0.
+fn compose(lanes: &[usize], parts: &[(&[usize], usize)]) -> usize {
+    lanes.iter().fold(0, |index, lane| {
+        let bit = parts
+            .iter()
+            .find_map(|(part, value)| {
+                let position = part.iter().position(|l| l == lane)?;
+                Some((value >> (part.len() - 1 - position)) & 1)
+            })
+            .unwrap_or(0);
+        (index << 1) | bit
+    })
+}
+
+/// Return the lanes sorted and without duplicates.
+fn sorted(lanes: &[usize]) -> Vec<usize> {
+    let mut lanes = lanes.to_vec();
+    lanes.sort_unstable();
+    lanes.dedup();
+    lanes
+}
+
+/// Return the sorted union of two lists of lanes.
+pub(crate) fn union(a: &[usize], b: &[usize]) -> Vec<usize> {
+    sorted(&[a, b].concat())
+}
+
+/// Return the lanes of `a` that are also in `b`.
+pub(crate) fn intersection(a: &[usize], b: &[usize]) -> Vec<usize> {
+    a.iter().copied().filter(|l| b.contains(l)).collect()
+}
+
+/// Return the lanes of `a` that are not in `b`.
+pub(crate) fn difference(a: &[usize], b: &[usize]) -> Vec<usize> {
+    a.iter().copied().filter(|l| !b.contains(l)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contractions::{TensorKind, TensorNetwork},
+        executor::CpuExecutor,
+        library,
+        model::QuantumCircuit,
+        scheduler::ContractionPlan,
+    };
+
+    fn assert_close(a: &DMatrix<Complex<f64>>, b: &DMatrix<Complex<f64>>) {
+        assert_eq!(a.shape(), b.shape());
+        assert!((a - b).norm() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn gates_drop_idle_lanes() {
+        let mut circuit = QuantumCircuit::new(6);
+        circuit.g_cx(0, 5);
+        let gate = circuit.gates[0].clone();
+        let tensor = IndexTensor::from(gate.clone());
+        assert_eq!(tensor.outputs(), &[0, 5]);
+        assert_eq!(tensor.rank(), 4);
+        assert_eq!(tensor.as_ref().shape(), (4, 4));
+        assert_close(tensor.into_block().as_ref(), &gate.matrix());
+    }
+
+    #[test]
+    fn contraction_matches_padded_product() {
+        let mut circuit = QuantumCircuit::new(5);
+        circuit.g_cx(0, 4);
+        circuit.g_ry(0.4, 2);
+        circuit.g_cp(0.7, 4, 1);
+        circuit.g_h(4);
+        let pairs = [(0, 1), (1, 2), (2, 3), (0, 2), (1, 3)];
+        for (a, b) in pairs {
+            let (lhs, rhs) = (circuit.gates[a].clone(), circuit.gates[b].clone());
+            let expected =
+                SpannedBlock::from(lhs.clone()).contract(&SpannedBlock::from(rhs.clone()));
+            let tensor = IndexTensor::from(lhs).einsum(&IndexTensor::from(rhs));
+            assert_eq!(tensor.span(), expected.merged_span(&expected));
+            assert_close(tensor.into_block().as_ref(), expected.as_ref());
+        }
+    }
+
+    #[test]
+    fn states_and_projectors() {
+        let one = Complex::new(1.0, 0.0);
+        let h = IndexTensor::from(library::qft(1).gates[0].clone());
+        let zero = IndexTensor::new(&[0], &[], DMatrix::from_vec(2, 1, vec![one, 0.0.into()]));
+        let state = h.einsum(&zero);
+        assert_eq!(state.legs(), vec![Leg::Output(0)]);
+        let bra = IndexTensor::new(&[], &[0], DMatrix::from_vec(1, 2, vec![0.0.into(), one]));
+        let amplitude = bra.einsum(&state);
+        assert_eq!(amplitude.rank(), 0);
+        let expected = Complex::from(std::f64::consts::FRAC_1_SQRT_2);
+        assert!((amplitude.as_ref()[(0, 0)] - expected).norm() < 1e-9);
+    }
+
+    #[test]
+    fn plan_with_index_tensors() {
+        for circuit in [
+            library::qft(4),
+            library::grover(4, &library::phase_oracle(4, 5), 1),
+        ] {
+            let tensors = TensorNetwork::from(circuit.clone()).contract();
+            for tensor in tensors {
+                let TensorKind::Contraction(c) = tensor else {
+                    continue;
+                };
+                let expected =
+                    CpuExecutor::new().execute(ContractionPlan::try_from((*c).clone()).unwrap());
+                let plan = ContractionPlan::<IndexTensor>::new(*c).unwrap();
+                let result = CpuExecutor::new().execute(plan);
+                assert_eq!(result.len(), 1);
+                let block = result[0].clone().into_block();
+                assert_close(block.as_ref(), expected[0].as_ref());
+            }
+        }
+    }
+}
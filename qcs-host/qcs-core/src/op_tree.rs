@@ -1,8 +1,9 @@
 use crate::{
-    contractions::{TensorContraction, TensorKind},
+    contractions::{closed::ClosedTensorError, TensorContraction, TensorKind},
     model::{
         gates::{Gate, QuantumGate},
         span::Span,
+        QRegister,
     },
 };
 
@@ -16,14 +17,27 @@ pub struct Operation {
 
 #[derive(Debug, Clone)]
 pub enum OperationKind {
-    TensorExpansion { target_span: Span, operand: Operand },
-    MatrixMultiplication { left: Operand, right: Operand },
+    TensorExpansion {
+        target_span: Span,
+        operand: Operand,
+    },
+    MatrixMultiplication {
+        left: Operand,
+        right: Operand,
+    },
+    /// Apply a matrix, acting on the lanes of its span, to a state vector.
+    MatrixVector {
+        matrix: Operand,
+        state: Operand,
+    },
 }
 
 #[derive(Debug, Clone)]
 pub enum Operand {
     Operation(Box<Operation>),
     Gate(Box<Gate>),
+    /// A state vector over all the lanes of the circuit.
+    State(Box<QRegister>),
 }
 
 impl Operand {
@@ -31,17 +45,46 @@ impl Operand {
         match self {
             Self::Operation(op) => op.span(),
             Self::Gate(gate) => gate.span(),
+            Self::State(state) => Span::range(0..state.n_qubits()),
         }
     }
 
-    pub fn from_tensor_kind(kind: TensorKind, transposed_op: bool) -> Self {
+    /// Build the operand of a contracted tensor, failing on the state tensors
+    /// of closed networks.
+    pub fn from_tensor_kind(
+        kind: TensorKind,
+        transposed_op: bool,
+    ) -> Result<Self, ClosedTensorError> {
         match kind {
-            TensorKind::Contraction(contr) => {
-                Operand::Operation(Box::new(Operation::from_contraction(*contr, transposed_op)))
-            }
-            TensorKind::Gate(gate) => Operand::Gate(gate),
+            TensorKind::Contraction(contr) => Ok(Operand::Operation(Box::new(
+                Operation::from_contraction(*contr, transposed_op)?,
+            ))),
+            TensorKind::Gate(gate) => Ok(Operand::Gate(gate)),
+            TensorKind::State(state) => Err(ClosedTensorError(*state)),
         }
     }
+
+    /// Build the chain of matrix-vector products applying the contracted
+    /// tensors, given in the order they are applied, to an input state. The
+    /// last tensor is applied first, so the state is the innermost leaf, and
+    /// the operand is the state itself if there are no tensors.
+    pub fn from_state_contraction(
+        tensors: Vec<TensorKind>,
+        state: QRegister,
+    ) -> Result<Self, ClosedTensorError> {
+        tensors
+            .into_iter()
+            .rev()
+            .try_fold(Operand::State(Box::new(state)), |state, tensor| {
+                Ok(Operand::Operation(Box::new(Operation {
+                    inner: OperationKind::MatrixVector {
+                        matrix: Operand::from_tensor_kind(tensor, false)?,
+                        state,
+                    },
+                    transposed_op: false,
+                })))
+            })
+    }
 }
 
 impl OperationKind {
@@ -49,6 +92,7 @@ impl OperationKind {
         match self {
             Self::TensorExpansion { target_span, .. } => target_span.clone(),
             Self::MatrixMultiplication { left, right } => left.span().union(&right.span()),
+            Self::MatrixVector { state, .. } => state.span(),
         }
     }
 }
@@ -58,7 +102,12 @@ impl Operation {
         self.inner.span()
     }
 
-    pub fn from_contraction(contr: TensorContraction, transposed_op: bool) -> Self {
+    /// Build the operation of a tensor contraction, failing if it contracts
+    /// the state tensors of a closed network.
+    pub fn from_contraction(
+        contr: TensorContraction,
+        transposed_op: bool,
+    ) -> Result<Self, ClosedTensorError> {
         let TensorContraction {
             lhs: left,
             rhs: right,
@@ -70,29 +119,29 @@ impl Operation {
             Operand::Operation(Box::new(Operation {
                 inner: OperationKind::TensorExpansion {
                     target_span: span.clone(),
-                    operand: Operand::from_tensor_kind(left, transposed_op),
+                    operand: Operand::from_tensor_kind(left, transposed_op)?,
                 },
                 transposed_op,
             }))
         } else {
-            Operand::from_tensor_kind(left, transposed_op)
+            Operand::from_tensor_kind(left, transposed_op)?
         };
 
         let right = if span.filled() != right.span().filled() {
             Operand::Operation(Box::new(Operation {
                 inner: OperationKind::TensorExpansion {
                     target_span: span,
-                    operand: Operand::from_tensor_kind(right, !transposed_op),
+                    operand: Operand::from_tensor_kind(right, !transposed_op)?,
                 },
                 transposed_op: !transposed_op,
             }))
         } else {
-            Operand::from_tensor_kind(right, !transposed_op)
+            Operand::from_tensor_kind(right, !transposed_op)?
         };
 
-        Self {
+        Ok(Self {
             inner: OperationKind::MatrixMultiplication { left, right },
             transposed_op,
-        }
+        })
     }
 }
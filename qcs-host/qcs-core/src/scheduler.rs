@@ -4,16 +4,28 @@ pub mod operation;
 pub use contraction::ContractionPlan;
 pub use operation::OperationPlan;
 
+use std::sync::Arc;
+
+use nalgebra::{Complex, DMatrix};
+
 use crate::model::blocks::BlockLike;
 
+/// An operand of an instruction, either a block, that identical operands of
+/// a plan can share, or the id of the instruction computing it.
 #[derive(Debug, Clone, PartialEq)]
 pub enum ExecutionOperand<B: BlockLike> {
-    Block(B),
+    Block(Arc<B>),
     Address(usize),
 }
 
 impl<B: BlockLike> From<B> for ExecutionOperand<B> {
     fn from(block: B) -> Self {
+        Self::Block(Arc::new(block))
+    }
+}
+
+impl<B: BlockLike> From<Arc<B>> for ExecutionOperand<B> {
+    fn from(block: Arc<B>) -> Self {
         Self::Block(block)
     }
 }
@@ -24,14 +36,17 @@ impl<B: BlockLike> From<usize> for ExecutionOperand<B> {
     }
 }
 
-impl<B: BlockLike> std::fmt::Display for ExecutionOperand<B> {
+impl<B> std::fmt::Display for ExecutionOperand<B>
+where
+    B: BlockLike + AsRef<DMatrix<Complex<B::Scalar>>>,
+{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self {
             Self::Block(block) => write!(
                 f,
                 "M({:02}x{:02})",
-                block.as_ref().nrows(),
-                block.as_ref().ncols()
+                (**block).as_ref().nrows(),
+                (**block).as_ref().ncols()
             ),
             Self::Address(id) => write!(f, "I({:05})", id),
         }
@@ -6,32 +6,78 @@
 //! create a plan of instructions that can be executed in parallel, and will
 //! return the instructions in the order they can be executed.
 
+use std::{collections::BTreeMap, sync::Arc};
+
 use hashbrown::HashMap;
 
 use crate::{
-    contractions::{TensorContraction, TensorKind},
+    contractions::{closed::ClosedTensorError, TensorContraction, TensorKind},
     executor::{BlockStore, Computation, ExecutorPlan, InstructionLike},
-    model::blocks::SpannedBlock,
+    model::{
+        blocks::{ContractionBlock, SpannedBlock},
+        cache::GateKey,
+        gates::{Gate, QuantumGate},
+    },
 };
 
 use super::ExecutionOperand;
 
 /// A plan of instructions to be executed in the simulator
 /// The plan is a list of instructions that can be executed in parallel
-/// and the dependencies between them.
+/// and the dependencies between them. The instructions are kept sorted by id,
+/// so that they are always fetched in the same order.
+///
+/// The blocks of the plan are `SpannedBlock`s by default, any other
+/// `ContractionBlock` can be used as the backend with `ContractionPlan::new`.
 #[derive(Debug, Clone)]
-pub struct ContractionPlan {
+pub struct ContractionPlan<B: ContractionBlock = SpannedBlock> {
     /// The instructions to be executed.
-    instructions: HashMap<usize, ContractionInstruction>,
+    instructions: BTreeMap<usize, ContractionInstruction<B>>,
     /// The dependencies of each instruction.
-    waiting_dep: HashMap<usize, Vec<usize>>,
+    waiting_dep: BTreeMap<usize, Vec<usize>>,
     /// The dependants of each instruction.
-    dependants: HashMap<usize, Vec<usize>>,
+    dependants: BTreeMap<usize, Vec<usize>>,
 }
 
-impl ContractionPlan {
+impl<B: ContractionBlock> ContractionPlan<B> {
+    /// Create the plan of a tensor contraction, with the gates loaded as
+    /// blocks of the backend, shared by the identical gates of the plan. The
+    /// contractions of closed networks fail with the state tensor they
+    /// contract, unless the backend can hold states (see
+    /// `ContractionBlock::from_state`).
+    pub fn new(contraction: TensorContraction) -> Result<Self, ClosedTensorError> {
+        let (instruction, collaterals) =
+            ContractionInstruction::from_contraction(0, contraction, vec![], &mut HashMap::new())?;
+
+        let instructions: BTreeMap<_, _> = collaterals
+            .into_iter()
+            .chain(std::iter::once(instruction))
+            .map(|instr| (instr.id, instr))
+            .collect();
+        let waiting_dep: BTreeMap<usize, _> = instructions
+            .iter()
+            .map(|(id, instr)| (*id, instr.dependencies().to_vec()))
+            .collect();
+        let dependants = {
+            let mut dependants: BTreeMap<_, _> =
+                instructions.keys().map(|id| (*id, vec![])).collect();
+            for (id, deps) in &waiting_dep {
+                for dep in deps {
+                    dependants.get_mut(dep).unwrap().push(*id);
+                }
+            }
+            dependants
+        };
+
+        Ok(Self {
+            instructions,
+            waiting_dep,
+            dependants,
+        })
+    }
+
     /// Extract the instructions that are ready to be executed, these are the
-    /// instructions that have no dependencies.
+    /// instructions that have no dependencies, sorted by id.
     fn get_ready(&self) -> Vec<usize> {
         self.waiting_dep
             .iter()
@@ -55,9 +101,9 @@ impl ContractionPlan {
         }
     }
 
-    /// Fetch the instructions that are ready to be executed.
+    /// Fetch the instructions that are ready to be executed, sorted by id.
     /// The instructions are removed from the plan.
-    pub fn fetch_ready(&mut self) -> Vec<ContractionInstruction> {
+    pub fn fetch_ready(&mut self) -> Vec<ContractionInstruction<B>> {
         let ready = self.get_ready();
         ready
             .iter()
@@ -71,8 +117,8 @@ impl ContractionPlan {
     }
 }
 
-impl ExecutorPlan for ContractionPlan {
-    type Instruction = ContractionInstruction;
+impl<B: ContractionBlock> ExecutorPlan for ContractionPlan<B> {
+    type Instruction = ContractionInstruction<B>;
 
     fn get_ready(&self) -> Vec<usize> {
         self.get_ready()
@@ -91,43 +137,18 @@ impl ExecutorPlan for ContractionPlan {
     }
 }
 
-impl From<TensorContraction> for ContractionPlan {
-    fn from(contraction: TensorContraction) -> Self {
-        let (instruction, collaterals) =
-            ContractionInstruction::from_contraction(0, contraction, vec![]);
-
-        let instructions: HashMap<_, _> = collaterals
-            .into_iter()
-            .chain(std::iter::once(instruction))
-            .map(|instr| (instr.id, instr))
-            .collect();
-        let waiting_dep: HashMap<usize, _> = instructions
-            .iter()
-            .map(|(id, instr)| (*id, instr.dependencies().to_vec()))
-            .collect();
-        let dependants = {
-            let mut dependants: HashMap<_, _> =
-                instructions.iter().map(|(id, _)| (*id, vec![])).collect();
-            for (id, deps) in &waiting_dep {
-                for dep in deps {
-                    dependants.get_mut(dep).unwrap().push(*id);
-                }
-            }
-            dependants
-        };
+impl TryFrom<TensorContraction> for ContractionPlan {
+    type Error = ClosedTensorError;
 
-        Self {
-            instructions,
-            waiting_dep,
-            dependants,
-        }
+    fn try_from(contraction: TensorContraction) -> Result<Self, Self::Error> {
+        Self::new(contraction)
     }
 }
 
-impl std::fmt::Display for ContractionPlan {
+impl<B: ContractionBlock> std::fmt::Display for ContractionPlan<B> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Instructions:")?;
-        for (_, instr) in &self.instructions {
+        for instr in self.instructions.values() {
             writeln!(f, "{}", instr)?;
         }
         writeln!(f, "Waiting dependencies:")?;
@@ -142,11 +163,15 @@ impl std::fmt::Display for ContractionPlan {
     }
 }
 
+/// The blocks of the gates of a plan, by the key and the first lane of the
+/// gates.
+type Leaves<B> = HashMap<(GateKey, usize), Arc<B>>;
+
 /// An instruction to be executed in the simulator
 /// The instruction is a tensor contraction to be executed in the simulator
 /// and the dependencies of the instruction.
 #[derive(Debug, Clone)]
-pub struct ContractionInstruction {
+pub struct ContractionInstruction<B: ContractionBlock = SpannedBlock> {
     /// The id of the instruction
     pub id: usize,
     /// The dependencies on which this instruction depends
@@ -154,24 +179,26 @@ pub struct ContractionInstruction {
     /// The rank of the resulting tensor
     pub rank: u8,
     /// The left operand of the instruction
-    pub first: ExecutionOperand<SpannedBlock>,
+    pub first: ExecutionOperand<B>,
     /// The right operand of the instruction
-    pub second: ExecutionOperand<SpannedBlock>,
+    pub second: ExecutionOperand<B>,
 }
 
-impl ContractionInstruction {
+impl<B: ContractionBlock> ContractionInstruction<B> {
     /// Create an instruction from a tensor contraction
     /// The instruction will be created recursively from the tensor contraction
     /// and the collaterals will be returned as well.
     ///
     /// The id is the id of the instruction to be created, the contr is the
-    /// tensor contraction to be executed, and the collaterals are the
-    /// instructions that have been created so far.
+    /// tensor contraction to be executed, the collaterals are the
+    /// instructions that have been created so far, and the leaves are the
+    /// blocks of the gates loaded so far.
     fn from_contraction(
         id: usize,
         contr: TensorContraction,
-        collaterals: Vec<ContractionInstruction>,
-    ) -> (Self, Vec<Self>) {
+        collaterals: Vec<Self>,
+        leaves: &mut Leaves<B>,
+    ) -> Result<(Self, Vec<Self>), ClosedTensorError> {
         let mut collaterals = collaterals;
         let mut available_id = id + 1;
         let mut dependencies = Vec::new();
@@ -185,7 +212,8 @@ impl ContractionInstruction {
 
         let first = match left {
             TensorKind::Contraction(contr) => {
-                let (instr, col) = Self::from_contraction(available_id, *contr, collaterals);
+                let (instr, col) =
+                    Self::from_contraction(available_id, *contr, collaterals, leaves)?;
                 collaterals = col;
                 let instr_id = instr.id;
                 dependencies.push(instr_id);
@@ -193,19 +221,22 @@ impl ContractionInstruction {
                 available_id = collaterals.iter().map(|i| i.id).max().unwrap() + 1;
                 ExecutionOperand::from(instr_id)
             }
-            TensorKind::Gate(gate) => ExecutionOperand::Block(SpannedBlock::from(*gate)),
+            TensorKind::Gate(gate) => Self::gate_operand(*gate, leaves),
+            TensorKind::State(state) => ExecutionOperand::from(B::from_state(*state)?),
         };
 
         let second = match right {
             TensorKind::Contraction(contr) => {
-                let (instr, col) = Self::from_contraction(available_id, *contr, collaterals);
+                let (instr, col) =
+                    Self::from_contraction(available_id, *contr, collaterals, leaves)?;
                 collaterals = col;
                 let instr_id = instr.id;
                 dependencies.push(instr_id);
                 collaterals.push(instr);
                 ExecutionOperand::from(instr_id)
             }
-            TensorKind::Gate(gate) => ExecutionOperand::Block(SpannedBlock::from(*gate)),
+            TensorKind::Gate(gate) => Self::gate_operand(*gate, leaves),
+            TensorKind::State(state) => ExecutionOperand::from(B::from_state(*state)?),
         };
 
         let instruction = Self {
@@ -216,7 +247,16 @@ impl ContractionInstruction {
             second,
         };
 
-        (instruction, collaterals)
+        Ok((instruction, collaterals))
+    }
+
+    /// Return the operand of a gate, sharing the block of the identical gates
+    /// of the plan.
+    fn gate_operand(gate: Gate, leaves: &mut Leaves<B>) -> ExecutionOperand<B> {
+        let block = leaves
+            .entry((gate.key(), gate.span().start()))
+            .or_insert_with(|| Arc::new(B::from(gate)));
+        ExecutionOperand::from(block.clone())
     }
 
     /// Get the dependencies of the instruction
@@ -225,8 +265,8 @@ impl ContractionInstruction {
     }
 }
 
-impl Computation for ContractionInstruction {
-    type BlockKind = SpannedBlock;
+impl<B: ContractionBlock> Computation for ContractionInstruction<B> {
+    type BlockKind = B;
 
     fn compute(self, block_map: &impl BlockStore<Self::BlockKind>) -> usize {
         let ContractionInstruction {
@@ -236,31 +276,27 @@ impl Computation for ContractionInstruction {
         let first_block = block_map.load_block(first);
         let second_block = block_map.load_block(second);
 
-        let new_span = first_block.merged_span(&second_block);
-        let first_block = first_block.adapt_to_span(new_span.clone());
-        let second_block = second_block.adapt_to_span(new_span);
-
-        let out = first_block * second_block;
+        let out = first_block.contract(&second_block);
         block_map.save_block(id, out);
         id
     }
 }
 
-impl InstructionLike for ContractionInstruction {
+impl<B: ContractionBlock> InstructionLike for ContractionInstruction<B> {
     fn id(&self) -> usize {
         self.id
     }
 }
 
-impl PartialEq for ContractionInstruction {
+impl<B: ContractionBlock> PartialEq for ContractionInstruction<B> {
     fn eq(&self, other: &Self) -> bool {
         self.id == other.id
     }
 }
 
-impl Eq for ContractionInstruction {}
+impl<B: ContractionBlock> Eq for ContractionInstruction<B> {}
 
-impl std::fmt::Display for ContractionInstruction {
+impl<B: ContractionBlock> std::fmt::Display for ContractionInstruction<B> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
@@ -269,3 +305,34 @@ impl std::fmt::Display for ContractionInstruction {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hashbrown::HashSet;
+
+    use super::*;
+    use crate::{contractions::fixtures::circuit_contraction, model::QuantumCircuit};
+
+    #[test]
+    fn plan_shares_leaves() {
+        let mut circuit = QuantumCircuit::new(2);
+        for _ in 0..3 {
+            circuit.g_h(0);
+            circuit.g_cx(0, 1);
+        }
+        let plan = ContractionPlan::<SpannedBlock>::new(circuit_contraction(circuit)).unwrap();
+        let leaves: Vec<_> = plan
+            .instructions
+            .values()
+            .flat_map(|instr| [&instr.first, &instr.second])
+            .filter_map(|operand| match operand {
+                ExecutionOperand::Block(block) => Some(Arc::as_ptr(block)),
+                ExecutionOperand::Address(_) => None,
+            })
+            .collect();
+        assert_eq!(leaves.len(), 6);
+        // one block for the H and one for the CX
+        let distinct: HashSet<_> = leaves.into_iter().collect();
+        assert_eq!(distinct.len(), 2);
+    }
+}
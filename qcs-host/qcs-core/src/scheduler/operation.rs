@@ -6,11 +6,16 @@
 //! create a plan of instructions that can be executed in parallel, and will
 //! return the instructions in the order they can be executed.
 
-use hashbrown::HashMap;
+use std::{collections::BTreeMap, sync::Arc};
 
 use crate::{
     executor::{BlockStore, Computation, ExecutorPlan, InstructionLike},
-    model::{blocks::Block, gates::QuantumGate, TensorProduct},
+    model::{
+        blocks::Block,
+        cache::GateCache,
+        fixed::{self, FixedFormat},
+        gates::Gate,
+    },
     op_tree,
 };
 
@@ -18,20 +23,21 @@ use super::ExecutionOperand;
 
 /// A plan of instructions to be executed in the simulator
 /// The plan is a list of instructions that can be executed in parallel
-/// and the dependencies between them.
+/// and the dependencies between them. The instructions are kept sorted by id,
+/// so that they are always fetched in the same order.
 #[derive(Debug, Clone)]
 pub struct OperationPlan {
     /// The instructions to be executed.
-    instructions: HashMap<usize, OperationInstruction>,
+    instructions: BTreeMap<usize, OperationInstruction>,
     /// The dependencies of each instruction.
-    waiting_dep: HashMap<usize, Vec<usize>>,
+    waiting_dep: BTreeMap<usize, Vec<usize>>,
     /// The dependants of each instruction.
-    dependants: HashMap<usize, Vec<usize>>,
+    dependants: BTreeMap<usize, Vec<usize>>,
 }
 
 impl OperationPlan {
     /// Extract the instructions that are ready to be executed, these are the
-    /// instructions that have no dependencies.
+    /// instructions that have no dependencies, sorted by id.
     fn get_ready(&self) -> Vec<usize> {
         self.waiting_dep
             .iter()
@@ -55,7 +61,7 @@ impl OperationPlan {
         }
     }
 
-    /// Fetch the instructions that are ready to be executed.
+    /// Fetch the instructions that are ready to be executed, sorted by id.
     /// The instructions are removed from the plan.
     pub fn fetch_ready(&mut self) -> Vec<OperationInstruction> {
         let ready = self.get_ready();
@@ -69,6 +75,21 @@ impl OperationPlan {
     pub fn is_empty(&self) -> bool {
         self.instructions.is_empty()
     }
+
+    /// Return the instructions left in the plan, sorted by id.
+    pub fn instructions(&self) -> impl Iterator<Item = &OperationInstruction> {
+        self.instructions.values()
+    }
+
+    /// Evaluate the tensor expansions, the matrix multiplications and the
+    /// matrix-vector products of the plan in a fixed-point format, to emulate
+    /// the results of the device.
+    pub fn fixed_point(mut self, format: FixedFormat) -> Self {
+        for instruction in self.instructions.values_mut() {
+            instruction.fixed = Some(format);
+        }
+        self
+    }
 }
 
 impl ExecutorPlan for OperationPlan {
@@ -102,9 +123,7 @@ impl From<op_tree::Operation> for OperationPlan {
 impl std::fmt::Display for OperationPlan {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Instructions:")?;
-        let mut instructions = self.instructions.values().collect::<Vec<_>>();
-        instructions.sort_by_key(|instr| instr.id);
-        for instr in instructions {
+        for instr in self.instructions.values() {
             writeln!(f, "{}", instr)?;
         }
         writeln!(f, "Waiting dependencies:")?;
@@ -150,15 +169,16 @@ impl OperationPlanBuilder {
                         dependencies.push(dep);
                         ExecutionOperand::Address(dep)
                     }
-                    op_tree::Operand::Gate(gate) => {
-                        ExecutionOperand::from(Block::from(gate.matrix()))
-                    }
+                    op_tree::Operand::Gate(gate) => self.gate_operand(&gate),
+                    op_tree::Operand::State(state) => ExecutionOperand::from(Block::from(*state)),
                 };
 
                 // Create the first tensor expansion if needed
                 let id_dim = op_span.start() - target_span.start();
                 let (left_id, left, dependencies) = if id_dim != 0 {
-                    let left = ExecutionOperand::from(Block::identity(2usize.pow(id_dim as u32)));
+                    let left = ExecutionOperand::from(
+                        GateCache::global().identity_block(2usize.pow(id_dim as u32)),
+                    );
                     let id = self.new_id();
                     let first_te = OperationInstruction {
                         id,
@@ -172,6 +192,7 @@ impl OperationPlanBuilder {
                         } else {
                             MatrixFormat::RowMajor
                         },
+                        fixed: None,
                     };
                     self.instructions.push(first_te);
                     (Some(id), ExecutionOperand::Address(id), vec![id])
@@ -182,7 +203,9 @@ impl OperationPlanBuilder {
                 // Create the second tensor expansion if needed
                 let id_dim = target_span.end() - op_span.end();
                 let right_id = if id_dim != 0 {
-                    let right = ExecutionOperand::from(Block::identity(2usize.pow(id_dim as u32)));
+                    let right = ExecutionOperand::from(
+                        GateCache::global().identity_block(2usize.pow(id_dim as u32)),
+                    );
                     let id = self.new_id();
                     let second_te = OperationInstruction {
                         id,
@@ -193,6 +216,7 @@ impl OperationPlanBuilder {
                         } else {
                             MatrixFormat::RowMajor
                         },
+                        fixed: None,
                     };
                     self.instructions.push(second_te);
                     Some(id)
@@ -205,22 +229,8 @@ impl OperationPlanBuilder {
             }
             op_tree::OperationKind::MatrixMultiplication { left, right } => {
                 let mut dependencies = Vec::new();
-                let left = match left {
-                    op_tree::Operand::Operation(op) => {
-                        let dep = self.populate(*op);
-                        dependencies.push(dep);
-                        ExecutionOperand::Address(dep)
-                    }
-                    op_tree::Operand::Gate(gate) => ExecutionOperand::from(Block::from(*gate)),
-                };
-                let right = match right {
-                    op_tree::Operand::Operation(op) => {
-                        let dep = self.populate(*op);
-                        dependencies.push(dep);
-                        ExecutionOperand::Address(dep)
-                    }
-                    op_tree::Operand::Gate(gate) => ExecutionOperand::from(Block::from(*gate)),
-                };
+                let left = self.operand(left, &mut dependencies);
+                let right = self.operand(right, &mut dependencies);
                 let id = self.new_id();
                 let mm = OperationInstruction {
                     id,
@@ -231,26 +241,75 @@ impl OperationPlanBuilder {
                     } else {
                         MatrixFormat::RowMajor
                     },
+                    fixed: None,
                 };
                 self.instructions.push(mm);
                 id
             }
+            op_tree::OperationKind::MatrixVector { matrix, state } => {
+                let mut dependencies = Vec::new();
+                let offset = matrix.span().start();
+                let left = self.operand(matrix, &mut dependencies);
+                let right = self.operand(state, &mut dependencies);
+                let id = self.new_id();
+                let mv = OperationInstruction {
+                    id,
+                    dependencies,
+                    kernel: Kernel::MV {
+                        left,
+                        right,
+                        offset,
+                    },
+                    left_format: if transposed_op {
+                        MatrixFormat::ColumnMajor
+                    } else {
+                        MatrixFormat::RowMajor
+                    },
+                    fixed: None,
+                };
+                self.instructions.push(mv);
+                id
+            }
+        }
+    }
+
+    /// Return the execution operand of an operand of a multiplication,
+    /// populating its operation and adding it to the dependencies if needed.
+    fn operand(
+        &mut self,
+        operand: op_tree::Operand,
+        dependencies: &mut Vec<usize>,
+    ) -> ExecutionOperand<Block> {
+        match operand {
+            op_tree::Operand::Operation(op) => {
+                let dep = self.populate(*op);
+                dependencies.push(dep);
+                ExecutionOperand::Address(dep)
+            }
+            op_tree::Operand::Gate(gate) => self.gate_operand(&gate),
+            op_tree::Operand::State(state) => ExecutionOperand::from(Block::from(*state)),
         }
     }
 
+    /// Return the execution operand of a gate, sharing the block of the
+    /// identical gates through the global `GateCache`.
+    fn gate_operand(&self, gate: &Gate) -> ExecutionOperand<Block> {
+        ExecutionOperand::from(GateCache::global().block(gate.key(), gate))
+    }
+
     fn build(self) -> OperationPlan {
         let instructions = self
             .instructions
             .into_iter()
             .map(|instr| (instr.id, instr))
-            .collect::<HashMap<_, _>>();
-        let waiting_dep: HashMap<usize, _> = instructions
+            .collect::<BTreeMap<_, _>>();
+        let waiting_dep: BTreeMap<usize, _> = instructions
             .iter()
             .map(|(id, instr)| (*id, instr.dependencies.clone()))
             .collect();
         let dependants = {
-            let mut dependants: HashMap<_, _> =
-                instructions.iter().map(|(id, _)| (*id, vec![])).collect();
+            let mut dependants: BTreeMap<_, _> =
+                instructions.keys().map(|id| (*id, vec![])).collect();
             for (id, deps) in &waiting_dep {
                 for dep in deps {
                     dependants.get_mut(dep).unwrap().push(*id);
@@ -272,6 +331,8 @@ pub struct OperationInstruction {
     pub dependencies: Vec<usize>,
     pub kernel: Kernel,
     pub left_format: MatrixFormat,
+    /// The fixed-point format the kernels are evaluated in, if any.
+    pub fixed: Option<FixedFormat>,
 }
 
 impl Computation for OperationInstruction {
@@ -282,13 +343,46 @@ impl Computation for OperationInstruction {
             Kernel::TE { left, right } => {
                 let left = block_map.load_block(left);
                 let right = block_map.load_block(right);
-                let result = left.tensor_product(right);
+                let result = match self.fixed {
+                    Some(format) => Block::from(fixed::dequantize(&fixed::kronecker(
+                        &fixed::quantize((*left).as_ref(), format),
+                        &fixed::quantize((*right).as_ref(), format),
+                    ))),
+                    None => left.kronecker(&right),
+                };
                 block_map.save_block(self.id, result);
             }
             Kernel::MM { left, right } => {
                 let left = block_map.load_block(left);
                 let right = block_map.load_block(right);
-                let result = left * right;
+                let result = match self.fixed {
+                    Some(format) => Block::from(fixed::dequantize(&fixed::matmul(
+                        &fixed::quantize((*left).as_ref(), format),
+                        &fixed::quantize((*right).as_ref(), format),
+                    ))),
+                    None => &*left * &*right,
+                };
+                block_map.save_block(self.id, result);
+            }
+            Kernel::MV {
+                left,
+                right,
+                offset,
+            } => {
+                let matrix = block_map.load_block(left);
+                let state = block_map.load_block(right);
+                let result = match self.fixed {
+                    Some(format) => Block::from(fixed::dequantize(&fixed::apply_matrix(
+                        &fixed::quantize((*matrix).as_ref(), format),
+                        &fixed::quantize((*state).as_ref(), format),
+                        offset,
+                    ))),
+                    None => {
+                        let mut state = Arc::unwrap_or_clone(state).into_register();
+                        state.apply_matrix((*matrix).as_ref(), offset);
+                        Block::from(state)
+                    }
+                };
                 block_map.save_block(self.id, result);
             }
         }
@@ -336,6 +430,13 @@ pub enum Kernel {
         left: ExecutionOperand<Block>,
         right: ExecutionOperand<Block>,
     },
+    /// Matrix-vector product, where the matrix acts on the contiguous lanes of
+    /// the state starting from `offset` (lane 0 is the most significant bit).
+    MV {
+        left: ExecutionOperand<Block>,
+        right: ExecutionOperand<Block>,
+        offset: usize,
+    },
 }
 
 impl std::fmt::Display for Kernel {
@@ -343,6 +444,11 @@ impl std::fmt::Display for Kernel {
         match &self {
             Self::TE { left, right } => write!(f, "{}⊗ {}", left, right),
             Self::MM { left, right } => write!(f, "{}⊙ {}", left, right),
+            Self::MV {
+                left,
+                right,
+                offset,
+            } => write!(f, "{}⊙ {} @{}", left, right, offset),
         }
     }
 }
@@ -1,13 +1,18 @@
-use qcs_core::model::{QRegister, QuantumCircuit, Qubit};
+use qcs_core::model::{gates::Structure, QRegister, QuantumCircuit, Qubit};
 
-#[test]
-fn full_adder() {
+fn full_adder_circuit() -> QuantumCircuit {
     let mut circuit = QuantumCircuit::new(4);
     circuit.g_cxx(0, 1, 3);
     circuit.g_cx(0, 1);
     circuit.g_cxx(1, 2, 3);
     circuit.g_cx(1, 2);
     circuit.g_cx(0, 1);
+    circuit
+}
+
+#[test]
+fn full_adder() {
+    let circuit = full_adder_circuit();
 
     let t_eval = circuit.eval();
     println!("Circuit eval: {}", t_eval);
@@ -30,6 +35,27 @@ fn full_adder() {
     }
 }
 
+#[test]
+fn full_adder_is_a_permutation() {
+    let circuit = full_adder_circuit();
+    assert!(circuit.is_monomial());
+
+    let t_eval = circuit.clone().eval();
+    assert_eq!(t_eval.structure(), Structure::Monomial);
+    assert!(t_eval.as_monomial().unwrap().is_permutation());
+
+    for input in 0..16 {
+        let (output, phase) = circuit.eval_classical(input).unwrap();
+        assert!((phase.re - 1.0).abs() < 1e-10 && phase.im.abs() < 1e-10);
+        assert_eq!(t_eval.as_monomial().unwrap().apply(input).0, output);
+        let bits = (0..4).map(|lane| (input >> (3 - lane)) & 1 == 1);
+        let distr = circuit
+            .eval_state(new_reg(bits.collect::<Vec<_>>().try_into().unwrap()))
+            .distr();
+        assert!((distr[output] - 1.0).abs() < 1e-10);
+    }
+}
+
 fn new_reg(bits: [bool; 4]) -> QRegister {
     QRegister::from(
         bits.iter()
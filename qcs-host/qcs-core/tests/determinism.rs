@@ -0,0 +1,181 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use qcs_circuit_parser::parse_program;
+use qcs_core::{
+    compiler::ToQcf,
+    contractions::{
+        optimizer::AnnealingOptimizer, strategy::Strategy, TensorContraction, TensorKind,
+        TensorNetwork,
+    },
+    library,
+    model::QuantumCircuit,
+    op_tree::Operation,
+    scheduler::{ContractionPlan, OperationPlan},
+};
+
+const RUNS: usize = 8;
+
+fn circuits() -> Vec<QuantumCircuit> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .join("circuits");
+    let mut circuits = ["q5-00.txt", "full-adder.qasm", "qft.qasm"]
+        .iter()
+        .map(|f| parse_program(dir.join(f)).unwrap())
+        .collect::<Vec<_>>();
+    circuits.push(library::qft(5));
+    circuits.push(library::ripple_carry_adder(2));
+    circuits.push(library::grover(4, &library::phase_oracle(4, 6), 2));
+    circuits
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Return the 64-bit FNV-1a hash of the bytes, that unlike the hasher of the
+/// standard library is the same across releases and platforms.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Assert that all the runs give the same hash.
+fn assert_reproducible(mut run: impl FnMut() -> u64) {
+    let first = run();
+    for i in 1..RUNS {
+        assert_eq!(run(), first, "run {} differs from the first one", i);
+    }
+}
+
+fn contractions(tensors: Vec<TensorKind>) -> impl Iterator<Item = TensorContraction> {
+    tensors.into_iter().filter_map(|t| match t {
+        TensorKind::Contraction(c) => Some(*c),
+        TensorKind::Gate(_) | TensorKind::State(_) => None,
+    })
+}
+
+/// Emit the QCF instructions of the plans, in the order `qcs-compile` does.
+fn emit_qcf(tensors: Vec<TensorKind>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for contraction in contractions(tensors) {
+        let mut plan =
+            OperationPlan::from(Operation::from_contraction(contraction, false).unwrap());
+        while !plan.is_empty() {
+            let ready = plan.fetch_ready();
+            for instruction in &ready {
+                bytes.extend(instruction.to_qcf(&Default::default()));
+            }
+            plan.set_done(ready.into_iter().map(|i| i.id));
+        }
+    }
+    bytes
+}
+
+#[test]
+fn contraction_is_reproducible() {
+    for circuit in circuits() {
+        for name in Strategy::NAMES {
+            let strategy = name.parse::<Strategy>().unwrap();
+            for max_rank in [None, Some(3)] {
+                assert_reproducible(|| {
+                    let mut network = TensorNetwork::from(circuit.clone());
+                    if let Some(max_rank) = max_rank {
+                        network = network.max_rank(max_rank);
+                    }
+                    let tensors = network.contract_with(&strategy);
+                    hash(tensors.iter().map(|t| t.to_string()).collect::<Vec<_>>())
+                });
+            }
+        }
+    }
+}
+
+#[test]
+fn annealing_is_reproducible() {
+    let circuit = library::qft(4);
+    assert_reproducible(|| {
+        let result = AnnealingOptimizer::new()
+            .iterations(200)
+            .seed(7)
+            .optimize(TensorNetwork::from(circuit.clone()));
+        hash(
+            result
+                .tensors
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>(),
+        )
+    });
+}
+
+#[test]
+fn qcf_emission_is_reproducible() {
+    for circuit in circuits() {
+        for name in Strategy::NAMES {
+            let strategy = name.parse::<Strategy>().unwrap();
+            assert_reproducible(|| {
+                let tensors = TensorNetwork::from(circuit.clone()).contract_with(&strategy);
+                hash(emit_qcf(tensors))
+            });
+        }
+    }
+}
+
+#[test]
+fn plans_fetch_in_id_order() {
+    fn assert_sorted(ready: &[usize]) {
+        assert!(ready.windows(2).all(|w| w[0] < w[1]), "{:?}", ready);
+    }
+
+    for circuit in circuits() {
+        let tensors = TensorNetwork::from(circuit).contract();
+        for contraction in contractions(tensors) {
+            let mut plan = ContractionPlan::try_from(contraction.clone()).unwrap();
+            while !plan.is_empty() {
+                let ready = plan
+                    .fetch_ready()
+                    .into_iter()
+                    .map(|i| i.id)
+                    .collect::<Vec<_>>();
+                assert_sorted(&ready);
+                plan.set_done(ready);
+            }
+
+            let mut plan =
+                OperationPlan::from(Operation::from_contraction(contraction, false).unwrap());
+            while !plan.is_empty() {
+                let ready = plan
+                    .fetch_ready()
+                    .into_iter()
+                    .map(|i| i.id)
+                    .collect::<Vec<_>>();
+                assert_sorted(&ready);
+                plan.set_done(ready);
+            }
+        }
+    }
+}
+
+/// The other tests only compare the runs of a single process. The outputs of
+/// a fixed circuit are recorded here, so that a change between runs, or a
+/// change of behavior of the contraction or of the emission, is detected.
+#[test]
+fn outputs_match_recorded_values() {
+    let tensors = TensorNetwork::from(library::qft(3)).contract();
+    let trees = tensors.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+    assert_eq!(trees, [RECORDED_TREE]);
+    assert_eq!(fnv1a(&emit_qcf(tensors)), RECORDED_QCF_HASH);
+}
+
+const RECORDED_TREE: &str =
+    "(((SWAP[0, 2] ~ H[2]) ~ ((CP(1.57)[2,1] ~ H[1]) ~ CP(0.79)[2,0])) ~ (CP(1.57)[1,0] ~ H[0]))";
+const RECORDED_QCF_HASH: u64 = 0x7715_da13_3c82_b062;
@@ -3,9 +3,11 @@ use std::path::{Path, PathBuf};
 use anyhow::{Context, Result};
 use qcs_circuit_parser::parse_program;
 use qcs_core::{
-    contractions::{TensorKind, TensorNetwork},
+    contractions::{forest::ContractionForest, strategy::Strategy, TensorKind, TensorNetwork},
+    equivalence::EquivalenceChecker,
     executor::CpuExecutor,
-    model::{blocks::Block, gates::QuantumGate, QRegister, QuantumCircuit, Qubit, TensorProduct},
+    library,
+    model::{blocks::Block, gates::QuantumGate, QRegister, QuantumCircuit, Qubit},
     scheduler::ContractionPlan,
 };
 
@@ -18,12 +20,28 @@ macro_rules! test_circuit {
                 .context("Failed to check for zero register")?;
             check(&circ, &one_register(circ.n_qubits))
                 .context("Failed to check for one register")?;
+            check_equivalence(&circ).context("Failed to check equivalence")?;
             Ok(())
         }
     };
     () => {};
 }
 
+macro_rules! test_library {
+    ($name:ident, $circuit:expr) => {
+        #[test]
+        fn $name() -> Result<()> {
+            let circ = $circuit;
+            check(&circ, &zero_register(circ.n_qubits))
+                .context("Failed to check for zero register")?;
+            check(&circ, &one_register(circ.n_qubits))
+                .context("Failed to check for one register")?;
+            check_equivalence(&circ).context("Failed to check equivalence")?;
+            Ok(())
+        }
+    };
+}
+
 test_circuit!(q2_00, "q2-00.txt");
 test_circuit!(q2_01, "q2-01.qasm");
 test_circuit!(q2_02, "q2-02.qasm");
@@ -37,6 +55,38 @@ test_circuit!(full_adder, "full-adder.txt");
 test_circuit!(full_adder_qasm, "full-adder.qasm");
 test_circuit!(quantum_fourier_transform, "qft.qasm");
 
+test_library!(library_qft, library::qft(5));
+test_library!(library_inverse_qft, library::inverse_qft(4));
+test_library!(library_ghz, library::ghz(6));
+test_library!(
+    library_bernstein_vazirani,
+    library::bernstein_vazirani(4, 0b1011)
+);
+test_library!(
+    library_grover,
+    library::grover(4, &library::phase_oracle(4, 9), 2)
+);
+test_library!(library_ripple_carry_adder, library::ripple_carry_adder(2));
+test_library!(
+    library_qaoa,
+    library::qaoa(
+        4,
+        &[(0, 1), (1, 2), (2, 3), (3, 0)],
+        &[0.3, 0.6],
+        &[0.2, 0.4]
+    )
+);
+test_library!(
+    library_ansatz,
+    library::hardware_efficient_ansatz(
+        4,
+        2,
+        &(0..library::ansatz_parameters(4, 2))
+            .map(|i| 0.1 * i as f64)
+            .collect::<Vec<_>>()
+    )
+);
+
 fn zero_register(n_qubits: usize) -> QRegister {
     QRegister::from((0..n_qubits).map(|_| Qubit::zero()))
 }
@@ -47,7 +97,7 @@ fn one_register(n_qubits: usize) -> QRegister {
 
 fn check(circuit: &QuantumCircuit, input_register: &QRegister) -> Result<()> {
     let base_eval = circuit.clone().eval();
-    let contract_eval = contract(circuit).context("Failed to contract")?;
+    let contract_eval = contract(circuit, &Strategy::default()).context("Failed to contract")?;
 
     let base_output = base_eval * input_register.to_owned();
     let contract_output = contract_eval * input_register.to_owned();
@@ -59,16 +109,28 @@ fn check(circuit: &QuantumCircuit, input_register: &QRegister) -> Result<()> {
     Ok(())
 }
 
-fn contract(circuit: &QuantumCircuit) -> Result<Block> {
+fn check_equivalence(circuit: &QuantumCircuit) -> Result<()> {
+    for name in Strategy::NAMES {
+        let strategy = name.parse::<Strategy>().unwrap();
+        let contract_eval =
+            contract(circuit, &strategy).with_context(|| format!("Failed to contract ({name})"))?;
+        let report = EquivalenceChecker::new().check_block(circuit, &contract_eval);
+        println!("{}: {}", name, report);
+        assert!(report.equivalent);
+    }
+    Ok(())
+}
+
+fn contract(circuit: &QuantumCircuit, strategy: &Strategy) -> Result<Block> {
     let tensor_net = TensorNetwork::from(circuit.clone());
-    let contracted_nodes = tensor_net.contract().into_iter();
+    let contracted_nodes = tensor_net.contract_with(strategy).into_iter();
 
     let mut blocks = Vec::new();
 
     for node in contracted_nodes {
         match node {
             TensorKind::Contraction(contr) => {
-                let mut plan = ContractionPlan::from(*contr);
+                let mut plan = ContractionPlan::try_from(*contr).unwrap();
                 let plan_clone = plan.clone();
                 println!("{}", plan);
 
@@ -90,15 +152,12 @@ fn contract(circuit: &QuantumCircuit) -> Result<Block> {
                 println!("Time: {:?}", start.elapsed());
             }
             TensorKind::Gate(g) => blocks.push((*g).spanned_block()),
+            TensorKind::State(_) => unreachable!("Circuit networks have no state tensors"),
         }
     }
 
-    let eval = blocks.into_iter().fold(None, |acc, block| match acc {
-        None => Some(block),
-        Some(acc) => Some(acc.tensor_product(block)),
-    });
-
-    eval.map(|sb| sb.into_block())
+    ContractionForest::combine(blocks)
+        .map(|sb| sb.into_block())
         .ok_or(anyhow::anyhow!("Nothing to contract"))
 }
 
@@ -4,6 +4,9 @@ CREATE TABLE IF NOT EXISTS `programs` (
     `text` TEXT NOT NULL,
     `contraction_cpu_time_us` INTEGER DEFAULT NULL,
     `tree_building_time_us` INTEGER DEFAULT NULL,
+    -- name of the contraction order strategy (greedy, flops, peak), added to
+    -- existing databases by the export (see ADDED_COLUMNS)
+    `strategy` TEXT NOT NULL DEFAULT 'greedy',
     PRIMARY KEY(`id`)
 );
 CREATE TABLE IF NOT EXISTS `contractions` (
@@ -49,3 +52,55 @@ CREATE TABLE IF NOT EXISTS `experiments` (
     PRIMARY KEY(`id`),
     FOREIGN KEY(`program_id`) REFERENCES `programs`(`id`)
 );
+CREATE TABLE IF NOT EXISTS `circuit_metrics` (
+    `program_id` INTEGER NOT NULL,
+    `n_qubits` INTEGER NOT NULL,
+    `n_gates` INTEGER NOT NULL,
+    `depth` INTEGER NOT NULL,
+    `two_qubit_depth` INTEGER NOT NULL,
+    `max_span_width` INTEGER NOT NULL,
+    `avg_span_width` REAL NOT NULL,
+    -- idle lanes formatted as a span (e.g. [0,3])
+    `idle_lanes` TEXT NOT NULL,
+    PRIMARY KEY(`program_id`),
+    FOREIGN KEY(`program_id`) REFERENCES `programs`(`id`)
+);
+CREATE TABLE IF NOT EXISTS `gate_counts` (
+    `program_id` INTEGER NOT NULL,
+    `kind` TEXT NOT NULL,
+    `count` INTEGER NOT NULL,
+    PRIMARY KEY(`program_id`, `kind`),
+    FOREIGN KEY(`program_id`) REFERENCES `programs`(`id`)
+);
+CREATE TABLE IF NOT EXISTS `rank_counts` (
+    `program_id` INTEGER NOT NULL,
+    `rank` INTEGER NOT NULL,
+    `count` INTEGER NOT NULL,
+    PRIMARY KEY(`program_id`, `rank`),
+    FOREIGN KEY(`program_id`) REFERENCES `programs`(`id`)
+);
+CREATE TABLE IF NOT EXISTS `interactions` (
+    `program_id` INTEGER NOT NULL,
+    `lane_a` INTEGER NOT NULL,
+    `lane_b` INTEGER NOT NULL,
+    -- number of gates acting on both lanes
+    `count` INTEGER NOT NULL,
+    PRIMARY KEY(`program_id`, `lane_a`, `lane_b`),
+    FOREIGN KEY(`program_id`) REFERENCES `programs`(`id`)
+);
+CREATE TABLE IF NOT EXISTS `cost_estimates` (
+    `program_id` INTEGER NOT NULL,
+    `mm_count` INTEGER NOT NULL,
+    `te_count` INTEGER NOT NULL,
+    -- estimated real floating point operations, before and after sparsity
+    `flops` REAL NOT NULL,
+    `sparse_flops` REAL NOT NULL,
+    `bytes_moved` REAL NOT NULL,
+    `sparse_bytes_moved` REAL NOT NULL,
+    `peak_memory` REAL NOT NULL,
+    `sparse_peak_memory` REAL NOT NULL,
+    `critical_path_length` INTEGER NOT NULL,
+    `critical_path_flops` REAL NOT NULL,
+    PRIMARY KEY(`program_id`),
+    FOREIGN KEY(`program_id`) REFERENCES `programs`(`id`)
+);